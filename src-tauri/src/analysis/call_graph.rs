@@ -0,0 +1,101 @@
+//! Calls between functions and methods.
+//!
+//! While parsing, every Function/Method records the names it calls with their
+//! counts in `call_counts`. Once the whole directory has been parsed,
+//! [`link_calls`] resolves those names like `test_discovery` resolves test
+//! calls and returns `CALLS` edges carrying the count.
+
+use crate::analysis::test_discovery::{callables_by_name, collect_calls, resolve_call};
+use crate::models::{
+    CodeEntity, EntityType, LinkEntity, LinkType, LINK_CONFIDENCE_PROPERTY, LINK_COUNT_PROPERTY,
+};
+use std::collections::{BTreeMap, HashMap};
+use tree_sitter::Node;
+
+/// Property holding `name:count` pairs for the calls made by a function or
+/// method, e.g. `parse:2,emit:1`
+pub const CALL_COUNTS_PROPERTY: &str = "call_counts";
+
+/// Records the calls made in a function or method body as
+/// `CALL_COUNTS_PROPERTY`. Bodies without calls leave the property unset.
+pub fn write_call_properties(node: Node, source: &[u8], properties: &mut HashMap<String, String>) {
+    let Some(body) = node.child_by_field_name("body") else {
+        return;
+    };
+    let mut calls = BTreeMap::new();
+    collect_calls(body, source, &mut calls);
+    if calls.is_empty() {
+        return;
+    }
+    let pairs: Vec<String> = calls
+        .iter()
+        .map(|(name, count)| format!("{}:{}", name, count))
+        .collect();
+    properties.insert(CALL_COUNTS_PROPERTY.to_string(), pairs.join(","));
+}
+
+/// Resolves the calls recorded on functions and methods and returns `CALLS`
+/// links. A definition in the caller's own file wins; otherwise every
+/// definition with the called name is linked.
+pub fn link_calls(nodes: &[CodeEntity]) -> Vec<LinkEntity> {
+    let by_name = callables_by_name(nodes);
+
+    let mut links = Vec::new();
+    for caller in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::Function | EntityType::Method))
+    {
+        let Some(calls) = caller.properties.get(CALL_COUNTS_PROPERTY) else {
+            continue;
+        };
+        for (call, count) in calls.split(',').filter_map(|pair| pair.rsplit_once(':')) {
+            let (targets, confidence) = resolve_call(&by_name, caller, call);
+            for target in targets {
+                links.push(LinkEntity {
+                    from_name: caller.id.clone(),
+                    to_name: target.id.clone(),
+                    link_type: LinkType::Calls,
+                    properties: HashMap::from([
+                        (LINK_COUNT_PROPERTY.to_string(), count.to_string()),
+                        (LINK_CONFIDENCE_PROPERTY.to_string(), confidence.clone()),
+                    ]),
+                });
+            }
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    #[test]
+    fn test_function_calls_are_counted_and_linked() {
+        let source = "fn helper(x: i32) -> i32 { x }\n\
+                      fn run() { helper(1); let _y = helper(2); assert_eq!(helper(3), 3); }\n";
+        let mut parser = TSParser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let run = tree.root_node().named_child(1).unwrap();
+        let mut properties = HashMap::new();
+        write_call_properties(run, source.as_bytes(), &mut properties);
+        assert_eq!(properties[CALL_COUNTS_PROPERTY], "helper:3");
+
+        let entities = [
+            CodeEntity::fixture("a.rs::helper", "a.rs", EntityType::Function).with_name("helper"),
+            CodeEntity::fixture("b.rs::helper", "b.rs", EntityType::Function).with_name("helper"),
+            CodeEntity::fixture("a.rs::run", "a.rs", EntityType::Function)
+                .with_name("run")
+                .with_property(CALL_COUNTS_PROPERTY, &properties[CALL_COUNTS_PROPERTY]),
+        ];
+        let links = link_calls(&entities);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].from_name, "a.rs::run");
+        assert_eq!(links[0].to_name, "a.rs::helper");
+        assert_eq!(links[0].link_type.rel_type(), "CALLS");
+        assert_eq!(links[0].properties[LINK_COUNT_PROPERTY], "3");
+        assert_eq!(links[0].properties[LINK_CONFIDENCE_PROPERTY], "1.00");
+    }
+}
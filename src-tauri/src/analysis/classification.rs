@@ -0,0 +1,405 @@
+//! Classifies files as hand-written source, generated, vendored or minified
+//! before they are parsed, and decides which directories are never walked.
+//!
+//! Signals, strongest first: `linguist-generated` / `linguist-vendored` in the
+//! repository's `.gitattributes`, paths from the repository config, known
+//! generated/vendored paths, a generated-code marker in the file header and
+//! the average line length (minified bundles).
+//!
+//! Per-repository settings live in `.pear.toml` at the repository root:
+//!
+//! ```toml
+//! [files]
+//! generated_mode = "skip"          # or "flag" (default)
+//! ignore_dirs = ["tmp"]            # added to the built-in list
+//! generated = ["src/gen/**"]
+//! vendored = ["third_party/**"]
+//! max_average_line_length = 300
+//! ```
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Repository config file read at the repository root
+pub const REPO_CONFIG_FILE: &str = ".pear.toml";
+
+/// Directories that are never walked or watched
+const DEFAULT_IGNORED_DIRS: &[&str] = &[
+    "node_modules",
+    ".git",
+    "target",
+    "dist",
+    "build",
+    ".idea",
+    ".vscode",
+    "__pycache__",
+    ".next",
+    ".nuxt",
+];
+
+const KNOWN_GENERATED: &[&str] = &[
+    "**/__generated__/**",
+    "*.generated.*",
+    "*_pb2.py",
+    "*_pb2_grpc.py",
+    "*.pb.*",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lock",
+    "bun.lockb",
+    "Cargo.lock",
+    "poetry.lock",
+    "Pipfile.lock",
+];
+
+const KNOWN_VENDORED: &[&str] = &[
+    "vendor/",
+    "vendors/",
+    "third_party/",
+    "third-party/",
+    "bower_components/",
+];
+
+/// Marker anywhere in the header, as written by Buck, Relay and others
+const GENERATED_TAG: &str = "@generated";
+/// Go's convention: a whole line matching `^// Code generated .* DO NOT EDIT\.$`
+const GO_GENERATED_PREFIX: &str = "// Code generated ";
+const GO_GENERATED_SUFFIX: &str = " DO NOT EDIT.";
+
+/// Bytes of the file inspected for header markers
+const HEADER_BYTES: usize = 1024;
+/// Bytes of the file used for the average line length
+const CONTENT_BYTES: u64 = 256 * 1024;
+const DEFAULT_MAX_AVERAGE_LINE_LENGTH: usize = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileClass {
+    Source,
+    Generated,
+    Vendored,
+    Minified,
+}
+
+impl FileClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileClass::Source => "source",
+            FileClass::Generated => "generated",
+            FileClass::Vendored => "vendored",
+            FileClass::Minified => "minified",
+        }
+    }
+
+    /// Flags stored on the File node of a non-source file
+    pub fn write_properties(&self, properties: &mut HashMap<String, String>) {
+        properties.insert("classification".to_string(), self.as_str().to_string());
+        properties.insert(
+            "generated".to_string(),
+            matches!(self, FileClass::Generated | FileClass::Minified).to_string(),
+        );
+        properties.insert(
+            "vendored".to_string(),
+            matches!(self, FileClass::Vendored).to_string(),
+        );
+    }
+}
+
+/// What happens to files that aren't hand-written source
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GeneratedMode {
+    /// Leave them out of the graph
+    Skip,
+    /// Ingest a flagged File node without extracting entities
+    #[default]
+    Flag,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RepoConfig {
+    files: FilesConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FilesConfig {
+    generated_mode: GeneratedMode,
+    ignore_dirs: Vec<String>,
+    generated: Vec<String>,
+    vendored: Vec<String>,
+    max_average_line_length: Option<usize>,
+}
+
+pub struct FileClassifier {
+    root: PathBuf,
+    pub mode: GeneratedMode,
+    ignored_dirs: Vec<String>,
+    max_average_line_length: usize,
+    attributes_generated: Gitignore,
+    attributes_vendored: Gitignore,
+    configured_generated: Gitignore,
+    configured_vendored: Gitignore,
+    known_generated: Gitignore,
+    known_vendored: Gitignore,
+}
+
+impl FileClassifier {
+    /// Loads `.pear.toml` and `.gitattributes` from the repository root
+    pub fn load(root: &Path) -> Self {
+        let config: RepoConfig = std::fs::read_to_string(root.join(REPO_CONFIG_FILE))
+            .ok()
+            .and_then(|content| match toml::from_str(&content) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    warn!("Ignoring invalid {}: {}", REPO_CONFIG_FILE, e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        let files = config.files;
+
+        let (attributes_generated, attributes_vendored) = gitattributes(root);
+        let mut ignored_dirs: Vec<String> =
+            DEFAULT_IGNORED_DIRS.iter().map(|d| d.to_string()).collect();
+        ignored_dirs.extend(files.ignore_dirs);
+
+        FileClassifier {
+            root: root.to_path_buf(),
+            mode: files.generated_mode,
+            ignored_dirs,
+            max_average_line_length: files
+                .max_average_line_length
+                .unwrap_or(DEFAULT_MAX_AVERAGE_LINE_LENGTH),
+            attributes_generated,
+            attributes_vendored,
+            configured_generated: matcher(root, &files.generated),
+            configured_vendored: matcher(root, &files.vendored),
+            known_generated: matcher(root, KNOWN_GENERATED),
+            known_vendored: matcher(root, KNOWN_VENDORED),
+        }
+    }
+
+    /// True for paths inside a directory that is never walked or watched
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        relative.components().any(|c| {
+            let name = c.as_os_str().to_string_lossy();
+            self.ignored_dirs.iter().any(|d| d == name.as_ref())
+        })
+    }
+
+    /// Whether files of this class are left out entirely
+    pub fn skips(&self, class: FileClass) -> bool {
+        class != FileClass::Source && self.mode == GeneratedMode::Skip
+    }
+
+    /// Classifies a file from its path and, if needed, its content
+    pub fn classify(&self, path: &Path) -> FileClass {
+        if let Some(class) = self.classify_path(path) {
+            return class;
+        }
+        match std::fs::File::open(path) {
+            Ok(file) => self.classify_content(file),
+            Err(_) => FileClass::Source,
+        }
+    }
+
+    fn classify_path(&self, path: &Path) -> Option<FileClass> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let checks = [
+            (&self.attributes_generated, FileClass::Generated),
+            (&self.attributes_vendored, FileClass::Vendored),
+            (&self.configured_generated, FileClass::Generated),
+            (&self.configured_vendored, FileClass::Vendored),
+        ];
+        // An explicit `-linguist-generated` (whitelist) overrides the defaults
+        for (matcher, class) in checks {
+            let matched = matcher.matched_path_or_any_parents(relative, false);
+            if matched.is_ignore() {
+                return Some(class);
+            }
+            if matched.is_whitelist() {
+                return Some(FileClass::Source);
+            }
+        }
+
+        let name = relative.to_string_lossy();
+        if name.contains(".min.") {
+            return Some(FileClass::Minified);
+        }
+        if self
+            .known_vendored
+            .matched_path_or_any_parents(relative, false)
+            .is_ignore()
+        {
+            return Some(FileClass::Vendored);
+        }
+        if self
+            .known_generated
+            .matched_path_or_any_parents(relative, false)
+            .is_ignore()
+        {
+            return Some(FileClass::Generated);
+        }
+        None
+    }
+
+    fn classify_content(&self, file: std::fs::File) -> FileClass {
+        let mut content = Vec::new();
+        if file.take(CONTENT_BYTES).read_to_end(&mut content).is_err() {
+            return FileClass::Source;
+        }
+
+        let header = String::from_utf8_lossy(&content[..content.len().min(HEADER_BYTES)]);
+        if has_generated_marker(&header) {
+            return FileClass::Generated;
+        }
+
+        let (lines, total) = content
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.iter().all(|b| b.is_ascii_whitespace()))
+            .fold((0usize, 0usize), |(lines, total), line| {
+                (lines + 1, total + line.len())
+            });
+        if lines > 0 && total / lines > self.max_average_line_length {
+            return FileClass::Minified;
+        }
+        FileClass::Source
+    }
+}
+
+/// Whether a file header says the file is generated. Phrases such as "do not
+/// edit" alone are too common in hand-written comments to count.
+fn has_generated_marker(header: &str) -> bool {
+    header.contains(GENERATED_TAG)
+        || header.lines().any(|line| {
+            let line = line.trim_end();
+            line.starts_with(GO_GENERATED_PREFIX) && line.ends_with(GO_GENERATED_SUFFIX)
+        })
+}
+
+/// Builds gitignore-style matchers from the `linguist-generated` and
+/// `linguist-vendored` attributes. Unset attributes become whitelist entries.
+fn gitattributes(root: &Path) -> (Gitignore, Gitignore) {
+    let mut generated = GitignoreBuilder::new(root);
+    let mut vendored = GitignoreBuilder::new(root);
+    if let Ok(content) = std::fs::read_to_string(root.join(".gitattributes")) {
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let Some(pattern) = parts.next() else {
+                continue;
+            };
+            for attribute in parts {
+                let (builder, value) = match attribute.trim_start_matches(['-', '!']) {
+                    a if a.starts_with("linguist-generated") => (&mut generated, attribute),
+                    a if a.starts_with("linguist-vendored") => (&mut vendored, attribute),
+                    _ => continue,
+                };
+                let set = !value.starts_with('-')
+                    && !value.starts_with('!')
+                    && !value.ends_with("=false");
+                let line = if set {
+                    pattern.to_string()
+                } else {
+                    format!("!{}", pattern)
+                };
+                let _ = builder.add_line(None, &line);
+            }
+        }
+    }
+    (
+        generated.build().unwrap_or_else(|_| Gitignore::empty()),
+        vendored.build().unwrap_or_else(|_| Gitignore::empty()),
+    )
+}
+
+fn matcher<S: AsRef<str>>(root: &Path, patterns: &[S]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        let _ = builder.add_line(None, pattern.as_ref());
+    }
+    builder.build().unwrap_or_else(|_| Gitignore::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_classification_signals() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join(".gitattributes"),
+            "schemas/*.ts linguist-generated\nvendor/keep.js -linguist-vendored\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join(REPO_CONFIG_FILE),
+            "[files]\nvendored = [\"legacy/\"]\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("schemas")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+
+        let header = root.join("src/api.ts");
+        fs::write(
+            &header,
+            "// Code generated by protoc. DO NOT EDIT.\nexport {};\n",
+        )
+        .unwrap();
+        let bundle = root.join("src/bundle.js");
+        fs::write(&bundle, "var a=1;".repeat(100)).unwrap();
+        let source = root.join("src/main.ts");
+        fs::write(&source, "export const x = 1;\n").unwrap();
+
+        let classifier = FileClassifier::load(root);
+        let class = |p: &str| classifier.classify(&root.join(p));
+
+        assert_eq!(class("schemas/acl.ts"), FileClass::Generated);
+        assert_eq!(class("legacy/old.js"), FileClass::Vendored);
+        assert_eq!(class("third_party/lib.py"), FileClass::Vendored);
+        assert_eq!(class("vendor/keep.js"), FileClass::Source);
+        assert_eq!(class("app.min.js"), FileClass::Minified);
+        assert_eq!(class("src/api.ts"), FileClass::Generated);
+        assert_eq!(class("src/bundle.js"), FileClass::Minified);
+        assert_eq!(class("src/main.ts"), FileClass::Source);
+        assert!(classifier.is_ignored(&root.join("node_modules/react/index.js")));
+        assert!(!classifier.skips(FileClass::Generated));
+    }
+
+    #[test]
+    fn test_generated_markers_need_a_full_header_line() {
+        assert!(has_generated_marker(
+            "/**\n * @generated SignedSource<<abc>>\n */\n"
+        ));
+        assert!(has_generated_marker(
+            "// Code generated by stringer -type=Kind; DO NOT EDIT.\r\n\npackage kind\n"
+        ));
+
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let settings = root.join("settings.ts");
+        fs::write(
+            &settings,
+            "// Do not edit this value by hand, see docs/config.md\n\
+             // Code generated ids are unique per tenant\n\
+             // Code generated by hand. DO NOT EDIT. Unless you must.\n\
+             export const x = 1;\n",
+        )
+        .unwrap();
+        let classifier = FileClassifier::load(root);
+        assert_eq!(classifier.classify(&settings), FileClass::Source);
+    }
+}
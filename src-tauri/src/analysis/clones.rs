@@ -0,0 +1,625 @@
+//! Structural clone detection over functions and nested blocks.
+//!
+//! Each candidate subtree is normalized (identifiers and literals replaced by
+//! placeholders, comments dropped) and fingerprinted twice: a blake3 hash of
+//! the whole normalized tree finds exact clones, and a set of hashed token
+//! k-grams gives a Jaccard similarity for near duplicates.
+
+use crate::env_utils;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use tree_sitter::Node;
+
+/// Length of the token windows compared for near duplicates
+const SHINGLE_SIZE: usize = 5;
+
+const DEFAULT_MIN_TOKENS: usize = 40;
+const DEFAULT_MIN_SIMILARITY: f64 = 0.85;
+
+const FUNCTION_KINDS: &[&str] = &[
+    "function_item",
+    "function_definition",
+    "function_declaration",
+    "method_definition",
+    "arrow_function",
+    "function",
+    "closure_expression",
+];
+const BLOCK_KINDS: &[&str] = &["block", "statement_block"];
+const LITERAL_KINDS: &[&str] = &[
+    "string",
+    "template_string",
+    "string_literal",
+    "raw_string_literal",
+    "char_literal",
+    "concatenated_string",
+    "number",
+    "integer",
+    "float",
+    "integer_literal",
+    "float_literal",
+    "boolean_literal",
+    "true",
+    "false",
+];
+
+/// Thresholds for reporting clones
+#[derive(Clone, Debug)]
+pub struct CloneConfig {
+    /// Smallest candidate, in normalized tokens
+    pub min_tokens: usize,
+    /// Lowest Jaccard similarity reported as a near duplicate
+    pub min_similarity: f64,
+}
+
+impl Default for CloneConfig {
+    fn default() -> Self {
+        CloneConfig {
+            min_tokens: DEFAULT_MIN_TOKENS,
+            min_similarity: DEFAULT_MIN_SIMILARITY,
+        }
+    }
+}
+
+impl CloneConfig {
+    /// Reads `PEAR_CLONE_MIN_TOKENS` and `PEAR_CLONE_MIN_SIMILARITY`
+    pub fn from_env() -> Self {
+        CloneConfig {
+            min_tokens: env_utils::get_parsed("PEAR_CLONE_MIN_TOKENS")
+                .unwrap_or(DEFAULT_MIN_TOKENS),
+            min_similarity: env_utils::get_parsed("PEAR_CLONE_MIN_SIMILARITY")
+                .unwrap_or(DEFAULT_MIN_SIMILARITY),
+        }
+    }
+}
+
+/// A fingerprinted function or block
+#[derive(Clone, Debug)]
+pub struct CloneCandidate {
+    /// Graph id for functions and methods; blocks have none
+    pub entity_id: Option<String>,
+    pub name: String,
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub token_count: usize,
+    hash: String,
+    shingles: HashSet<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CloneSpan {
+    pub entity_id: Option<String>,
+    pub name: String,
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub token_count: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CloneGroup {
+    pub id: String,
+    /// True when every member has the same normalized tree
+    pub exact: bool,
+    /// Lowest similarity between linked members of the group
+    pub similarity: f64,
+    pub members: Vec<CloneSpan>,
+}
+
+/// A `DUPLICATE_OF` edge between two graph entities
+#[derive(Clone, Debug)]
+pub struct ClonePair {
+    pub from_id: String,
+    pub to_id: String,
+    pub similarity: f64,
+    pub group: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CloneReport {
+    pub groups: Vec<CloneGroup>,
+    pub pairs: Vec<ClonePair>,
+}
+
+/// Fingerprints a function or method node that is stored as `entity_id`
+pub fn function_candidate(
+    node: Node,
+    source: &[u8],
+    path: &str,
+    name: &str,
+    entity_id: &str,
+) -> CloneCandidate {
+    fingerprint(node, source, path, name, Some(entity_id.to_string()))
+}
+
+/// Fingerprints the blocks nested inside function bodies (loop bodies,
+/// branches, ...). Function bodies themselves are covered by the function.
+pub fn block_candidates(root: Node, source: &[u8], path: &str) -> Vec<CloneCandidate> {
+    let mut candidates = Vec::new();
+    collect_blocks(root, source, path, None, &mut candidates);
+    candidates
+}
+
+fn collect_blocks(
+    node: Node,
+    source: &[u8],
+    path: &str,
+    function_name: Option<&str>,
+    candidates: &mut Vec<CloneCandidate>,
+) {
+    let mut function_name = function_name;
+    if FUNCTION_KINDS.contains(&node.kind()) {
+        function_name = node
+            .child_by_field_name("name")
+            .and_then(|n| n.utf8_text(source).ok())
+            .or(Some("<anonymous>"));
+    }
+
+    let is_function_body = node
+        .parent()
+        .map(|p| FUNCTION_KINDS.contains(&p.kind()))
+        .unwrap_or(false);
+    if BLOCK_KINDS.contains(&node.kind()) && !is_function_body {
+        if let Some(function) = function_name {
+            let name = format!("block in {}", function);
+            candidates.push(fingerprint(node, source, path, &name, None));
+        }
+    }
+
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect_blocks(child, source, path, function_name, candidates);
+    }
+}
+
+fn fingerprint(
+    node: Node,
+    source: &[u8],
+    path: &str,
+    name: &str,
+    entity_id: Option<String>,
+) -> CloneCandidate {
+    let mut structure = Vec::new();
+    let mut tokens = Vec::new();
+    normalize(node, source, &mut structure, &mut tokens);
+
+    let mut hasher = blake3::Hasher::new();
+    for part in &structure {
+        hasher.update(part.as_bytes());
+        hasher.update(b" ");
+    }
+
+    let shingles = tokens
+        .windows(SHINGLE_SIZE.min(tokens.len().max(1)))
+        .map(|window| {
+            let mut h = DefaultHasher::new();
+            window.hash(&mut h);
+            h.finish()
+        })
+        .collect();
+
+    CloneCandidate {
+        entity_id,
+        name: name.to_string(),
+        path: path.to_string(),
+        start_line: node.start_position().row + 1,
+        end_line: node.end_position().row + 1,
+        token_count: tokens.len(),
+        hash: hasher.finalize().to_hex().to_string(),
+        shingles,
+    }
+}
+
+/// Flattens the subtree into structural markers and normalized leaf tokens
+fn normalize<'a>(
+    node: Node,
+    source: &'a [u8],
+    structure: &mut Vec<String>,
+    tokens: &mut Vec<&'a str>,
+) {
+    let kind = node.kind();
+    if kind.contains("comment") {
+        return;
+    }
+    if LITERAL_KINDS.contains(&kind) {
+        structure.push("$lit".to_string());
+        tokens.push("$lit");
+        return;
+    }
+    if node.child_count() == 0 {
+        let token = if kind.ends_with("identifier") || kind == "self" {
+            "$id"
+        } else {
+            node.utf8_text(source).unwrap_or_default()
+        };
+        structure.push(token.to_string());
+        tokens.push(token);
+        return;
+    }
+
+    if node.is_named() {
+        structure.push(format!("{}(", kind));
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        normalize(child, source, structure, tokens);
+    }
+    if node.is_named() {
+        structure.push(")".to_string());
+    }
+}
+
+fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let shared = a.intersection(b).count();
+    shared as f64 / (a.len() + b.len() - shared) as f64
+}
+
+fn overlaps(a: &CloneCandidate, b: &CloneCandidate) -> bool {
+    a.path == b.path && a.start_line <= b.end_line && b.start_line <= a.end_line
+}
+
+/// Groups exact and near-duplicate candidates and derives the edges between
+/// the ones that are graph entities.
+pub fn detect(candidates: &[CloneCandidate], config: &CloneConfig) -> CloneReport {
+    let candidates: Vec<&CloneCandidate> = candidates
+        .iter()
+        .filter(|c| c.token_count >= config.min_tokens)
+        .collect();
+
+    // Union-find over candidate indices; every union records the edge
+    let mut parent: Vec<usize> = (0..candidates.len()).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+
+    let mut edges: Vec<(usize, usize, f64)> = Vec::new();
+
+    // Exact clones share the normalized hash
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        by_hash.entry(candidate.hash.as_str()).or_default().push(i);
+    }
+    for members in by_hash.values() {
+        let first = members[0];
+        for &other in &members[1..] {
+            if overlaps(candidates[first], candidates[other]) {
+                continue;
+            }
+            let (a, b) = (find(&mut parent, first), find(&mut parent, other));
+            parent[b] = a;
+            edges.push((first, other, 1.0));
+        }
+    }
+
+    // Near duplicates: the similarity can't exceed the ratio of the sizes, so
+    // in size order each candidate is only compared with the ones after it
+    // until they get too large
+    let mut by_size: Vec<usize> = (0..candidates.len()).collect();
+    by_size.sort_by_key(|&i| candidates[i].token_count);
+    for (n, &first) in by_size.iter().enumerate() {
+        for &second in &by_size[n + 1..] {
+            let (small, large) = (candidates[first], candidates[second]);
+            if (small.token_count as f64) < (large.token_count as f64) * config.min_similarity {
+                break;
+            }
+            let (i, j) = (first.min(second), first.max(second));
+            let (a, b) = (candidates[i], candidates[j]);
+            if a.hash == b.hash || overlaps(a, b) {
+                continue;
+            }
+            let similarity = jaccard(&a.shingles, &b.shingles);
+            if similarity >= config.min_similarity {
+                let (ra, rb) = (find(&mut parent, i), find(&mut parent, j));
+                if ra != rb {
+                    parent[rb] = ra;
+                }
+                edges.push((i, j, similarity));
+            }
+        }
+    }
+
+    // Assemble groups keyed by their union-find root
+    let mut grouped: HashMap<usize, (Vec<usize>, f64, bool)> = HashMap::new();
+    for &(a, b, similarity) in &edges {
+        let root = find(&mut parent, a);
+        let entry = grouped.entry(root).or_insert((Vec::new(), 1.0, true));
+        for member in [a, b] {
+            if !entry.0.contains(&member) {
+                entry.0.push(member);
+            }
+        }
+        entry.1 = entry.1.min(similarity);
+        entry.2 &= similarity >= 1.0;
+    }
+
+    let mut report = CloneReport::default();
+    let mut group_of: HashMap<usize, String> = HashMap::new();
+    let mut roots: Vec<usize> = grouped.keys().copied().collect();
+    roots.sort_by_key(|root| {
+        let c = candidates[*root];
+        (c.path.clone(), c.start_line)
+    });
+
+    for (index, root) in roots.iter().enumerate() {
+        let (mut members, similarity, exact) = grouped.remove(root).unwrap_or_default();
+        members.sort_by_key(|&m| (candidates[m].path.clone(), candidates[m].start_line));
+        let id = format!("clone-{}", index + 1);
+        for &member in &members {
+            group_of.insert(member, id.clone());
+        }
+        report.groups.push(CloneGroup {
+            id,
+            exact,
+            similarity,
+            members: members
+                .iter()
+                .map(|&m| {
+                    let c = candidates[m];
+                    CloneSpan {
+                        entity_id: c.entity_id.clone(),
+                        name: c.name.clone(),
+                        path: c.path.clone(),
+                        start_line: c.start_line,
+                        end_line: c.end_line,
+                        token_count: c.token_count,
+                    }
+                })
+                .collect(),
+        });
+    }
+
+    for (a, b, similarity) in edges {
+        let (Some(from_id), Some(to_id)) = (&candidates[b].entity_id, &candidates[a].entity_id)
+        else {
+            continue;
+        };
+        report.pairs.push(ClonePair {
+            from_id: from_id.clone(),
+            to_id: to_id.clone(),
+            similarity,
+            group: group_of.get(&a).cloned().unwrap_or_default(),
+        });
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    const CONFIG: CloneConfig = CloneConfig {
+        min_tokens: 10,
+        min_similarity: 0.85,
+    };
+
+    // Candidates for the top-level functions of a file, and for the blocks
+    // nested in them
+    fn parse(
+        language: tree_sitter::Language,
+        path: &str,
+        source: &str,
+    ) -> (Vec<CloneCandidate>, Vec<CloneCandidate>) {
+        let mut parser = TSParser::new();
+        parser.set_language(language).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+        let functions: Vec<Node> = root.named_children(&mut cursor).collect();
+        let functions = functions
+            .into_iter()
+            .map(|f| {
+                let name = f
+                    .child_by_field_name("name")
+                    .unwrap()
+                    .utf8_text(source.as_bytes())
+                    .unwrap();
+                function_candidate(f, source.as_bytes(), path, name, name)
+            })
+            .collect();
+        (functions, block_candidates(root, source.as_bytes(), path))
+    }
+
+    fn candidates(source: &str) -> Vec<CloneCandidate> {
+        parse(tree_sitter_rust::language(), "lib.rs", source).0
+    }
+
+    #[test]
+    fn test_renamed_copy_is_exact_clone() {
+        let source = r#"
+fn total(items: &[u32]) -> u32 {
+    let mut sum = 0;
+    for item in items { if *item > 10 { sum += item; } }
+    sum
+}
+
+fn weight(parts: &[u32]) -> u32 {
+    let mut acc = 0;
+    for part in parts { if *part > 99 { acc += part; } }
+    acc
+}
+
+fn unrelated(name: &str) -> String {
+    format!("hello {}", name)
+}
+"#;
+        let report = detect(&candidates(source), &CONFIG);
+
+        assert_eq!(report.groups.len(), 1);
+        assert!(report.groups[0].exact);
+        assert_eq!(report.groups[0].members.len(), 2);
+        assert_eq!(report.pairs.len(), 1);
+        assert_eq!(report.pairs[0].similarity, 1.0);
+    }
+
+    #[test]
+    fn test_near_duplicate_with_extra_statement() {
+        let source = r#"
+fn load(path: &str) -> Vec<String> {
+    let text = std::fs::read_to_string(path).unwrap();
+    let mut lines = Vec::new();
+    for line in text.lines() { if !line.is_empty() { lines.push(line.trim().to_string()); } }
+    lines.sort();
+    lines.dedup();
+    lines
+}
+
+fn read(file: &str) -> Vec<String> {
+    let body = std::fs::read_to_string(file).unwrap();
+    let mut rows = Vec::new();
+    for row in body.lines() { if !row.is_empty() { rows.push(row.trim().to_string()); } }
+    rows.sort();
+    rows.dedup();
+    rows.truncate(10);
+    rows
+}
+
+fn short(a: u32) -> u32 { a + 1 }
+"#;
+        let report = detect(&candidates(source), &CONFIG);
+        assert_eq!(report.groups.len(), 1);
+        let group = &report.groups[0];
+        assert!(!group.exact);
+        assert!(group.similarity >= 0.85 && group.similarity < 1.0);
+        let names: Vec<&str> = group.members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["load", "read"]);
+        assert_eq!(report.pairs.len(), 1);
+        assert_eq!(
+            (
+                report.pairs[0].from_id.as_str(),
+                report.pairs[0].to_id.as_str()
+            ),
+            ("read", "load")
+        );
+
+        // Stricter than their similarity, they're no longer reported
+        let strict = CloneConfig {
+            min_similarity: 0.99,
+            ..CONFIG
+        };
+        assert!(detect(&candidates(source), &strict).groups.is_empty());
+    }
+
+    #[test]
+    fn test_blocks_are_grouped_without_edges() {
+        let source = r#"
+fn first(items: &[u32]) -> u32 {
+    let mut total = 0;
+    if items.len() > 2 {
+        for item in items { total += item * 2; }
+        total += items[0] + items[1];
+    }
+    total
+}
+
+fn second(values: &[u32], scale: u32) -> u32 {
+    let mut sum = scale;
+    while sum < 100 {
+        for value in values { sum += value * 3; }
+        sum += values[0] + values[1];
+    }
+    sum
+}
+"#;
+        let (functions, blocks) = parse(tree_sitter_rust::language(), "lib.rs", source);
+        let report = detect(&blocks, &CONFIG);
+        assert_eq!(report.groups.len(), 1);
+        let members = &report.groups[0].members;
+        assert!(members.iter().all(|m| m.entity_id.is_none()));
+        assert_eq!(members[0].name, "block in first");
+        assert_eq!(members[1].name, "block in second");
+        // Blocks aren't graph entities, so there's nothing to link
+        assert!(report.pairs.is_empty());
+
+        // Functions and blocks together: the functions differ, the blocks
+        // still match
+        let all: Vec<CloneCandidate> = functions.into_iter().chain(blocks).collect();
+        assert_eq!(detect(&all, &CONFIG).groups.len(), 1);
+    }
+
+    #[test]
+    fn test_overlapping_candidates_are_skipped() {
+        let source = r#"
+fn total(items: &[u32]) -> u32 {
+    let mut sum = 0;
+    for item in items { if *item > 10 { sum += item; } }
+    sum
+}
+"#;
+        // The same file fingerprinted twice matches itself exactly
+        let mut twice = candidates(source);
+        twice.extend(candidates(source));
+        assert_eq!(twice.len(), 2);
+        assert!(detect(&twice, &CONFIG).groups.is_empty());
+
+        // The same code in another file is a clone
+        let (other, _) = parse(tree_sitter_rust::language(), "other.rs", source);
+        twice.extend(other);
+        let report = detect(&twice, &CONFIG);
+        assert_eq!(report.groups.len(), 1);
+        let paths: Vec<&str> = report.groups[0]
+            .members
+            .iter()
+            .map(|m| m.path.as_str())
+            .collect();
+        assert_eq!(paths, ["lib.rs", "other.rs"]);
+    }
+
+    #[test]
+    fn test_python_and_typescript_clones() {
+        let python = r#"
+def total(items):
+    result = 0
+    for item in items:
+        if item > 10:
+            result += item * 2
+    return result
+
+def weight(parts):
+    acc = 0
+    for part in parts:
+        if part > 99:
+            acc += part * 3
+    return acc
+"#;
+        let (functions, _) = parse(tree_sitter_python::language(), "shapes.py", python);
+        let report = detect(&functions, &CONFIG);
+        assert_eq!(report.groups.len(), 1);
+        assert!(report.groups[0].exact);
+
+        let typescript = r#"
+function total(items: number[]): number {
+  let sum = 0;
+  for (const item of items) { if (item > 10) { sum += item; } }
+  return sum;
+}
+
+function weight(parts: number[]): number {
+  let acc = 0;
+  for (const part of parts) { if (part > 99) { acc += part; } }
+  return acc;
+}
+"#;
+        let (functions, _) = parse(
+            tree_sitter_typescript::language_typescript(),
+            "sum.ts",
+            typescript,
+        );
+        let report = detect(&functions, &CONFIG);
+        assert_eq!(report.groups.len(), 1);
+        assert!(report.groups[0].exact);
+        assert_eq!(report.pairs.len(), 1);
+    }
+}
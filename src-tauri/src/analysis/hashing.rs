@@ -0,0 +1,148 @@
+//! Content hashes for whole files and for individual entities.
+//!
+//! Entity hashes let re-ingestion skip nodes whose source did not change. In
+//! the default normalized mode the hash covers the token sequence and tree
+//! shape only, so reformatting or editing comments leaves it unchanged.
+
+use crate::env_utils;
+use std::io::Read;
+use tree_sitter::Node;
+
+/// Property holding an entity's content hash
+pub const CONTENT_HASH_PROPERTY: &str = "content_hash";
+
+/// Property holding the hash of a whole file on its File node
+pub const FILE_HASH_PROPERTY: &str = "hash";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashMode {
+    /// Hash the exact source text of the entity
+    Exact,
+    /// Ignore whitespace and comments
+    Normalized,
+}
+
+impl HashMode {
+    /// Reads `PEAR_HASH_MODE` (`exact` or `normalized`, the default)
+    pub fn from_env() -> Self {
+        match env_utils::get("PEAR_HASH_MODE").as_deref() {
+            Some("exact") => HashMode::Exact,
+            _ => HashMode::Normalized,
+        }
+    }
+}
+
+/// Hash of a whole file's bytes, shared by the parser and the file tracker
+pub fn file_hash(content: &[u8]) -> String {
+    blake3::hash(content).to_hex().to_string()
+}
+
+/// Same as `file_hash`, streamed from `reader` instead of a buffer
+pub fn reader_hash(reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(reader)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Hash of the source covered by `node`
+pub fn entity_hash(node: Node, source: &[u8], mode: HashMode) -> String {
+    match mode {
+        HashMode::Exact => blake3::hash(&source[node.byte_range()])
+            .to_hex()
+            .to_string(),
+        HashMode::Normalized => {
+            let mut hasher = blake3::Hasher::new();
+            hash_tokens(node, source, &mut hasher);
+            hasher.finalize().to_hex().to_string()
+        }
+    }
+}
+
+/// `hash` combined with the entity's doc comment, which lies outside its
+/// node, so that editing the doc re-ingests the entity with the new text
+pub fn with_doc(hash: &str, doc: Option<&str>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(hash.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(doc.unwrap_or_default().as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+fn hash_tokens(node: Node, source: &[u8], hasher: &mut blake3::Hasher) {
+    if node.kind().contains("comment") {
+        return;
+    }
+    if node.child_count() == 0 {
+        hasher.update(&source[node.byte_range()]);
+        hasher.update(b" ");
+        return;
+    }
+
+    // Named nodes contribute their kind so that moving a statement in or out
+    // of a block (e.g. Python indentation) still changes the hash
+    if node.is_named() {
+        hasher.update(node.kind().as_bytes());
+        hasher.update(b"(");
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        hash_tokens(child, source, hasher);
+    }
+    if node.is_named() {
+        hasher.update(b")");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    fn hash(source: &str, mode: HashMode) -> String {
+        let mut parser = TSParser::new();
+        parser.set_language(tree_sitter_python::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let function = tree.root_node().named_child(0).unwrap();
+        entity_hash(function, source.as_bytes(), mode)
+    }
+
+    const ORIGINAL: &str = "def f(x):\n    if x:\n        y = x + 1\n    return y\n";
+
+    #[test]
+    fn test_normalized_hash_ignores_whitespace_and_comments() {
+        let reformatted =
+            "def f( x ):  # entry\n    if x:\n\n        y = x+1  # bump\n    return y\n";
+        assert_eq!(
+            hash(ORIGINAL, HashMode::Normalized),
+            hash(reformatted, HashMode::Normalized)
+        );
+        assert_ne!(
+            hash(ORIGINAL, HashMode::Exact),
+            hash(reformatted, HashMode::Exact)
+        );
+    }
+
+    #[test]
+    fn test_normalized_hash_changes_on_tokens_and_nesting() {
+        let token = "def f(x):\n    if x:\n        y = x + 2\n    return y\n";
+        assert_ne!(
+            hash(ORIGINAL, HashMode::Normalized),
+            hash(token, HashMode::Normalized)
+        );
+        // Same tokens, but `return` moved into the `if` block
+        let nested = "def f(x):\n    if x:\n        y = x + 1\n        return y\n";
+        assert_ne!(
+            hash(ORIGINAL, HashMode::Normalized),
+            hash(nested, HashMode::Normalized)
+        );
+    }
+
+    #[test]
+    fn test_doc_edits_change_the_hash() {
+        let body = hash(ORIGINAL, HashMode::Normalized);
+        let documented = with_doc(&body, Some("/// Adds one"));
+        assert_ne!(documented, with_doc(&body, Some("/// Adds one to x")));
+        assert_ne!(documented, with_doc(&body, None));
+        assert_eq!(documented, with_doc(&body, Some("/// Adds one")));
+    }
+}
@@ -0,0 +1,529 @@
+//! Circular imports between files.
+//!
+//! Runs over the resolved file-to-file `IMPORTS` edges: every strongly
+//! connected component of more than one file is a `CycleGroup`, reported with
+//! its shortest loop (the one to break first) and every import line between
+//! its files. Imports that only bring in types (`import type`, TypeScript
+//! `{ type A }` specifiers, Python imports under `if TYPE_CHECKING:`) are
+//! erased before runtime, so they can be left out.
+
+use crate::models::{CodeEntity, EntityType, LinkEntity, LinkType, LINK_LINE_PROPERTY};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use tree_sitter::Node;
+
+/// Import entity and `IMPORTS` edge property set to `true` when the import
+/// only brings in types
+pub const TYPE_ONLY_PROPERTY: &str = "type_only";
+
+/// `IMPORTS` edge property listing, comma-separated, the line of every import
+/// behind the edge, with a `t` after type-only ones, e.g. `3t,8`
+pub const IMPORT_LINES_PROPERTY: &str = "lines";
+
+/// Whether a TypeScript or Python import node only brings in types
+pub fn is_type_only(node: Node, source: &[u8]) -> bool {
+    match node.kind() {
+        // Python's `import x` shares the kind with TypeScript's imports
+        "import_statement" => type_only_statement(node) || under_type_checking(node, source),
+        "export_statement" => type_only_statement(node),
+        "import_from_statement" => under_type_checking(node, source),
+        _ => false,
+    }
+}
+
+/// `import type …`, `export type … from`, or only `type` specifiers
+fn type_only_statement(node: Node) -> bool {
+    if has_type_keyword(node) {
+        return true;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.children(&mut cursor).collect();
+    // `import { type A, type B }`: a default or namespace import is a value
+    let named = children.iter().find_map(|c| match c.kind() {
+        "import_clause" if c.named_child_count() == 1 => c.named_child(0),
+        "export_clause" => Some(*c),
+        _ => None,
+    });
+    let Some(named) = named.filter(|n| matches!(n.kind(), "named_imports" | "export_clause"))
+    else {
+        return false;
+    };
+    let specifiers: Vec<Node> = named.named_children(&mut cursor).collect();
+    !specifiers.is_empty() && specifiers.into_iter().all(has_type_keyword)
+}
+
+/// Has a `type` keyword among its direct children
+fn has_type_keyword(node: Node) -> bool {
+    (0..node.child_count())
+        .filter_map(|i| node.child(i))
+        .any(|c| c.kind() == "type" && !c.is_named())
+}
+
+/// Inside the body of `if TYPE_CHECKING:` or `if typing.TYPE_CHECKING:`
+fn under_type_checking(node: Node, source: &[u8]) -> bool {
+    let mut child = node;
+    while let Some(parent) = child.parent() {
+        if parent.kind() == "if_statement"
+            && parent.child_by_field_name("consequence") == Some(child)
+        {
+            let condition = parent
+                .child_by_field_name("condition")
+                .and_then(|c| c.utf8_text(source).ok())
+                .unwrap_or_default();
+            if condition == "TYPE_CHECKING" || condition.ends_with(".TYPE_CHECKING") {
+                return true;
+            }
+        }
+        child = parent;
+    }
+    false
+}
+
+fn type_only_link(link: &LinkEntity) -> bool {
+    link.properties
+        .get(TYPE_ONLY_PROPERTY)
+        .is_some_and(|v| v == "true")
+}
+
+// Entry of a link's import in `IMPORT_LINES_PROPERTY`
+fn import_line(link: &LinkEntity) -> Option<String> {
+    let line = link.properties.get(LINK_LINE_PROPERTY)?;
+    Some(match type_only_link(link) {
+        true => format!("{}t", line),
+        false => line.clone(),
+    })
+}
+
+/// Adds an `IMPORTS` link, or folds it into the one already linking the same
+/// files. The edge stays type-only while every import behind it is, and then
+/// points at the line of the first import that isn't; the lines of all of
+/// them are kept in `IMPORT_LINES_PROPERTY`.
+pub fn merge_import_link(
+    links: &mut Vec<LinkEntity>,
+    seen: &mut HashMap<(String, String), usize>,
+    mut link: LinkEntity,
+) {
+    let key = (link.from_name.clone(), link.to_name.clone());
+    let line = import_line(&link);
+    match seen.get(&key) {
+        Some(&existing) => {
+            let existing = &mut links[existing];
+            let mut lines = existing
+                .properties
+                .remove(IMPORT_LINES_PROPERTY)
+                .unwrap_or_default();
+            if let Some(line) = line {
+                if !lines.is_empty() {
+                    lines.push(',');
+                }
+                lines.push_str(&line);
+            }
+            if type_only_link(existing) && !type_only_link(&link) {
+                existing.properties = link.properties;
+            }
+            if !lines.is_empty() {
+                existing
+                    .properties
+                    .insert(IMPORT_LINES_PROPERTY.to_string(), lines);
+            }
+        }
+        None => {
+            if let Some(line) = line {
+                link.properties
+                    .insert(IMPORT_LINES_PROPERTY.to_string(), line);
+            }
+            seen.insert(key, links.len());
+            links.push(link);
+        }
+    }
+}
+
+/// A file-to-file import
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImportEdge {
+    pub from: String,
+    pub to: String,
+    /// 1-based line of the import in `from`
+    pub line: Option<usize>,
+    pub type_only: bool,
+}
+
+impl ImportEdge {
+    /// An `IMPORTS` edge with its stored properties
+    pub fn new(from: &str, to: &str, properties: &HashMap<String, String>) -> Self {
+        ImportEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            line: properties
+                .get(LINK_LINE_PROPERTY)
+                .and_then(|line| line.parse().ok()),
+            type_only: properties
+                .get(TYPE_ONLY_PROPERTY)
+                .is_some_and(|v| v == "true"),
+        }
+    }
+
+    /// One import per line listed on a stored `IMPORTS` edge, or the edge
+    /// itself when it has no list
+    pub fn all(from: &str, to: &str, properties: &HashMap<String, String>) -> Vec<Self> {
+        let Some(lines) = properties
+            .get(IMPORT_LINES_PROPERTY)
+            .filter(|lines| !lines.is_empty())
+        else {
+            return vec![ImportEdge::new(from, to, properties)];
+        };
+        lines
+            .split(',')
+            .map(|entry| {
+                let line = entry.trim_end_matches('t');
+                ImportEdge {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    line: line.parse().ok(),
+                    type_only: line.len() < entry.len(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CycleGroup {
+    pub id: String,
+    /// Files that import each other, sorted
+    pub files: Vec<String>,
+    /// Shortest loop through the group, from a file back to it
+    pub cycle: Vec<ImportEdge>,
+    /// Every import between files of the group
+    pub imports: Vec<ImportEdge>,
+}
+
+/// Groups of files that import each other, sorted by their first file.
+/// Type-only imports are skipped when `ignore_type_only` is set.
+pub fn detect(edges: &[ImportEdge], ignore_type_only: bool) -> Vec<CycleGroup> {
+    let edges: Vec<&ImportEdge> = edges
+        .iter()
+        .filter(|e| e.from != e.to && !(ignore_type_only && e.type_only))
+        .collect();
+    let files: Vec<&str> = edges
+        .iter()
+        .flat_map(|e| [e.from.as_str(), e.to.as_str()])
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let index: HashMap<&str, usize> = files.iter().enumerate().map(|(i, f)| (*f, i)).collect();
+
+    // Every import per pair; the first one stands for the pair in loops
+    let mut by_pair: HashMap<(usize, usize), Vec<&ImportEdge>> = HashMap::new();
+    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); files.len()];
+    for &edge in &edges {
+        let pair = (index[edge.from.as_str()], index[edge.to.as_str()]);
+        match by_pair.entry(pair) {
+            Entry::Occupied(mut slot) => slot.get_mut().push(edge),
+            Entry::Vacant(slot) => {
+                slot.insert(vec![edge]);
+                adjacency[pair.0].push(pair.1);
+            }
+        }
+    }
+    for targets in &mut adjacency {
+        targets.sort_unstable();
+    }
+
+    let mut components: Vec<Vec<usize>> = strongly_connected(&adjacency)
+        .into_iter()
+        .filter(|c| c.len() > 1)
+        .map(|mut c| {
+            c.sort_unstable();
+            c
+        })
+        .collect();
+    components.sort();
+
+    components
+        .iter()
+        .enumerate()
+        .map(|(n, members)| {
+            let in_group: HashSet<usize> = members.iter().copied().collect();
+            let loop_files = shortest_cycle(&adjacency, members, &in_group);
+            let mut imports: Vec<ImportEdge> = by_pair
+                .iter()
+                .filter(|((a, b), _)| in_group.contains(a) && in_group.contains(b))
+                .flat_map(|(_, pair_edges)| pair_edges.iter().map(|edge| (*edge).clone()))
+                .collect();
+            imports.sort_by(|a, b| (&a.from, a.line, &a.to).cmp(&(&b.from, b.line, &b.to)));
+            CycleGroup {
+                id: format!("cycle-{}", n + 1),
+                files: members.iter().map(|&m| files[m].to_string()).collect(),
+                cycle: loop_files
+                    .windows(2)
+                    .map(|step| by_pair[&(step[0], step[1])][0].clone())
+                    .collect(),
+                imports,
+            }
+        })
+        .collect()
+}
+
+/// Tarjan's algorithm, iterative so long import chains can't overflow the stack
+fn strongly_connected(adjacency: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; adjacency.len()];
+    let mut low = vec![0; adjacency.len()];
+    let mut on_stack = vec![false; adjacency.len()];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut next = 0;
+
+    for root in 0..adjacency.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // (node, next target to look at)
+        let mut work = vec![(root, 0)];
+        while let Some((node, child)) = work.pop() {
+            if child == 0 {
+                index[node] = next;
+                low[node] = next;
+                next += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+            if let Some(&target) = adjacency[node].get(child) {
+                work.push((node, child + 1));
+                if index[target] == UNVISITED {
+                    work.push((target, 0));
+                } else if on_stack[target] {
+                    low[node] = low[node].min(index[target]);
+                }
+                continue;
+            }
+
+            if low[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+        }
+    }
+    components
+}
+
+/// Shortest loop within a group as file indices, first file repeated at the
+/// end; ties go to the loop starting at the lowest file
+fn shortest_cycle(
+    adjacency: &[Vec<usize>],
+    members: &[usize],
+    in_group: &HashSet<usize>,
+) -> Vec<usize> {
+    let mut best: Option<Vec<usize>> = None;
+    for &start in members {
+        let mut previous: HashMap<usize, usize> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        let mut closing = None;
+        'search: while let Some(node) = queue.pop_front() {
+            for &target in &adjacency[node] {
+                if target == start {
+                    closing = Some(node);
+                    break 'search;
+                }
+                if in_group.contains(&target) && !previous.contains_key(&target) {
+                    previous.insert(target, node);
+                    queue.push_back(target);
+                }
+            }
+        }
+        let Some(mut node) = closing else {
+            continue;
+        };
+
+        let mut path = vec![start];
+        while node != start {
+            path.push(node);
+            node = previous[&node];
+        }
+        path.push(start);
+        path.reverse();
+        if best.as_ref().is_none_or(|b| path.len() < b.len()) {
+            best = Some(path);
+        }
+    }
+    best.unwrap_or_default()
+}
+
+/// `CycleGroup` nodes for the groups, with an `IN_CYCLE` edge from each of
+/// their files; files on the shortest loop get the line of their import in it
+pub fn marker_nodes(groups: &[CycleGroup]) -> (Vec<CodeEntity>, Vec<LinkEntity>) {
+    let mut nodes = Vec::new();
+    let mut links = Vec::new();
+    for group in groups {
+        let loop_files: Vec<&str> = group
+            .cycle
+            .iter()
+            .map(|step| step.from.as_str())
+            .chain(group.cycle.first().map(|step| step.from.as_str()))
+            .collect();
+        let mut properties = HashMap::new();
+        properties.insert("name".to_string(), group.id.clone());
+        properties.insert("size".to_string(), group.files.len().to_string());
+        properties.insert("cycle".to_string(), loop_files.join(" -> "));
+        nodes.push(CodeEntity {
+            id: group.id.clone(),
+            path: String::new(),
+            entity_type: EntityType::CycleGroup,
+            start_line: None,
+            end_line: None,
+            span: None,
+            name_span: None,
+            properties,
+            children: None,
+        });
+
+        for file in &group.files {
+            let line = group
+                .cycle
+                .iter()
+                .find(|step| &step.from == file)
+                .and_then(|step| step.line);
+            links.push(LinkEntity {
+                from_name: file.clone(),
+                to_name: group.id.clone(),
+                link_type: LinkType::InCycle,
+                properties: line
+                    .map(|line| (LINK_LINE_PROPERTY.to_string(), line.to_string()))
+                    .into_iter()
+                    .collect(),
+            });
+        }
+    }
+    (nodes, links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    fn import(from: &str, to: &str, line: usize, type_only: bool) -> ImportEdge {
+        ImportEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            line: Some(line),
+            type_only,
+        }
+    }
+
+    #[test]
+    fn test_detect_reports_shortest_loop() {
+        // a -> b -> c -> a and the shortcut b -> a; d only imports into it
+        let edges = [
+            import("a.ts", "b.ts", 1, false),
+            import("b.ts", "c.ts", 2, false),
+            import("c.ts", "a.ts", 3, false),
+            import("b.ts", "a.ts", 4, true),
+            import("d.ts", "a.ts", 5, false),
+        ];
+
+        let groups = detect(&edges, false);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].files, ["a.ts", "b.ts", "c.ts"]);
+        assert_eq!(groups[0].cycle, [edges[0].clone(), edges[3].clone()]);
+        assert_eq!(groups[0].imports.len(), 4);
+
+        let groups = detect(&edges, true);
+        let lines: Vec<Option<usize>> = groups[0].cycle.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [Some(1), Some(2), Some(3)]);
+
+        assert!(detect(&edges[..2], false).is_empty());
+    }
+
+    #[test]
+    fn test_merged_links_keep_every_import_between_two_files() {
+        let link = |from: &str, to: &str, line: usize, type_only: bool| LinkEntity {
+            from_name: from.to_string(),
+            to_name: to.to_string(),
+            link_type: LinkType::Import,
+            properties: HashMap::from([
+                (LINK_LINE_PROPERTY.to_string(), line.to_string()),
+                (TYPE_ONLY_PROPERTY.to_string(), type_only.to_string()),
+            ]),
+        };
+        let mut links = Vec::new();
+        let mut seen = HashMap::new();
+        for l in [
+            link("a.py", "b.py", 1, true),
+            link("b.py", "a.py", 2, false),
+            link("a.py", "b.py", 7, false),
+        ] {
+            merge_import_link(&mut links, &mut seen, l);
+        }
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].properties[LINK_LINE_PROPERTY], "7");
+        assert_eq!(links[0].properties[IMPORT_LINES_PROPERTY], "1t,7");
+
+        let edges: Vec<ImportEdge> = links
+            .iter()
+            .flat_map(|l| ImportEdge::all(&l.from_name, &l.to_name, &l.properties))
+            .collect();
+        assert_eq!(
+            edges[..2],
+            [
+                import("a.py", "b.py", 1, true),
+                import("a.py", "b.py", 7, false)
+            ]
+        );
+        let groups = detect(&edges, false);
+        assert_eq!(groups.len(), 1);
+        let lines: Vec<(&str, Option<usize>)> = groups[0]
+            .imports
+            .iter()
+            .map(|e| (e.from.as_str(), e.line))
+            .collect();
+        assert_eq!(
+            lines,
+            [("a.py", Some(1)), ("a.py", Some(7)), ("b.py", Some(2))]
+        );
+        // Without type-only imports the loop goes through the runtime one
+        let groups = detect(&edges, true);
+        assert_eq!(groups[0].cycle[0], edges[1]);
+        assert_eq!(groups[0].imports.len(), 2);
+    }
+
+    #[test]
+    fn test_type_only_imports() {
+        let type_only = |language: tree_sitter::Language, source: &str| {
+            let mut parser = TSParser::new();
+            parser.set_language(language).unwrap();
+            let tree = parser.parse(source, None).unwrap();
+            let mut node = tree.root_node();
+            while !matches!(
+                node.kind(),
+                "import_statement" | "export_statement" | "import_from_statement"
+            ) {
+                node = node.named_child(node.named_child_count() - 1).unwrap();
+            }
+            is_type_only(node, source.as_bytes())
+        };
+
+        let ts = tree_sitter_typescript::language_typescript;
+        assert!(type_only(ts(), "import type { A } from './a';"));
+        assert!(type_only(ts(), "import { type A, type B } from './a';"));
+        assert!(!type_only(ts(), "import { type A, b } from './a';"));
+        assert!(!type_only(ts(), "import A, { type B } from './a';"));
+        assert!(type_only(ts(), "export type { A } from './a';"));
+
+        let py = tree_sitter_python::language;
+        assert!(type_only(py(), "if TYPE_CHECKING:\n    from .a import A\n"));
+        assert!(!type_only(py(), "from .a import A\n"));
+    }
+}
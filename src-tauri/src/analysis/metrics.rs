@@ -0,0 +1,445 @@
+//! Code metrics computed from the syntax tree while a file is parsed.
+//!
+//! Node kinds are listed for all supported grammars at once; the kinds that
+//! don't exist in a grammar simply never match.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use tree_sitter::Node;
+
+/// Control-flow structures that add a path through the code
+const BRANCH_KINDS: &[&str] = &[
+    "if_statement",
+    "if_expression",
+    "elif_clause",
+    "ternary_expression",
+    "conditional_expression",
+    "match_arm",
+    "switch_case",
+    "case_clause",
+    "catch_clause",
+    "except_clause",
+    "for_statement",
+    "for_in_statement",
+    "for_expression",
+    "while_statement",
+    "while_expression",
+    "do_statement",
+    "loop_expression",
+];
+
+/// Structures that increase nesting for cognitive complexity and nesting depth
+const NESTING_KINDS: &[&str] = &[
+    "if_statement",
+    "if_expression",
+    "ternary_expression",
+    "conditional_expression",
+    "switch_statement",
+    "match_statement",
+    "match_expression",
+    "catch_clause",
+    "except_clause",
+    "for_statement",
+    "for_in_statement",
+    "for_expression",
+    "while_statement",
+    "while_expression",
+    "do_statement",
+    "loop_expression",
+    "try_statement",
+];
+
+/// Nested functions add nesting but no increment of their own
+const FUNCTION_KINDS: &[&str] = &[
+    "arrow_function",
+    "function",
+    "function_expression",
+    "closure_expression",
+    "lambda",
+    "function_item",
+    "function_definition",
+    "function_declaration",
+    "method_definition",
+];
+
+/// Bodies whose named children are statements
+const BLOCK_KINDS: &[&str] = &["block", "statement_block", "declaration_list"];
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LineCounts {
+    pub total: usize,
+    pub code: usize,
+    pub comment: usize,
+    pub blank: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EntityMetrics {
+    pub cyclomatic_complexity: usize,
+    pub cognitive_complexity: usize,
+    pub statement_count: usize,
+    pub max_nesting_depth: usize,
+    pub parameter_count: usize,
+    pub lines: LineCounts,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FileMetrics {
+    pub lines: LineCounts,
+    pub function_count: usize,
+    pub total_cyclomatic_complexity: usize,
+    pub max_cyclomatic_complexity: usize,
+    pub total_cognitive_complexity: usize,
+}
+
+impl EntityMetrics {
+    /// Stores the metrics in an entity's property map
+    pub fn write_properties(&self, properties: &mut HashMap<String, String>) {
+        let values = [
+            ("cyclomatic_complexity", self.cyclomatic_complexity),
+            ("cognitive_complexity", self.cognitive_complexity),
+            ("statement_count", self.statement_count),
+            ("max_nesting_depth", self.max_nesting_depth),
+            ("parameter_count", self.parameter_count),
+            ("total_lines", self.lines.total),
+            ("code_lines", self.lines.code),
+            ("comment_lines", self.lines.comment),
+            ("blank_lines", self.lines.blank),
+        ];
+        for (key, value) in values {
+            properties.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+impl FileMetrics {
+    /// Starts the file aggregate from the line counts of the whole tree
+    pub fn new(root: Node) -> Self {
+        FileMetrics {
+            lines: line_counts(root),
+            ..Default::default()
+        }
+    }
+
+    /// Folds one function or method into the file totals
+    pub fn add_function(&mut self, metrics: &EntityMetrics) {
+        self.function_count += 1;
+        self.total_cyclomatic_complexity += metrics.cyclomatic_complexity;
+        self.total_cognitive_complexity += metrics.cognitive_complexity;
+        self.max_cyclomatic_complexity = self
+            .max_cyclomatic_complexity
+            .max(metrics.cyclomatic_complexity);
+    }
+
+    /// Stores the aggregates in the File node's property map
+    pub fn write_properties(&self, properties: &mut HashMap<String, String>) {
+        let values = [
+            ("total_lines", self.lines.total),
+            ("code_lines", self.lines.code),
+            ("comment_lines", self.lines.comment),
+            ("blank_lines", self.lines.blank),
+            ("function_count", self.function_count),
+            (
+                "total_cyclomatic_complexity",
+                self.total_cyclomatic_complexity,
+            ),
+            ("max_cyclomatic_complexity", self.max_cyclomatic_complexity),
+            (
+                "total_cognitive_complexity",
+                self.total_cognitive_complexity,
+            ),
+        ];
+        for (key, value) in values {
+            properties.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+/// Computes all metrics for a Function, Method or Class declaration node
+pub fn compute(node: Node, source: &[u8]) -> EntityMetrics {
+    // `const f = () => {}` is captured as the declarator; measure the function
+    let function = match node.kind() {
+        "variable_declarator" => node.child_by_field_name("value").unwrap_or(node),
+        _ => node,
+    };
+
+    let mut metrics = EntityMetrics {
+        cyclomatic_complexity: 1,
+        parameter_count: parameter_count(function, source),
+        lines: line_counts(node),
+        ..Default::default()
+    };
+
+    let mut cursor = function.walk();
+    for child in function.named_children(&mut cursor) {
+        walk(child, source, 0, &mut metrics);
+    }
+    metrics
+}
+
+fn walk(node: Node, source: &[u8], nesting: usize, metrics: &mut EntityMetrics) {
+    let kind = node.kind();
+
+    if BRANCH_KINDS.contains(&kind) || is_boolean_operator(node, source) {
+        metrics.cyclomatic_complexity += 1;
+    }
+
+    if BLOCK_KINDS.contains(&kind) {
+        let mut cursor = node.walk();
+        metrics.statement_count += node
+            .named_children(&mut cursor)
+            .filter(|child| !is_comment(*child))
+            .count();
+    }
+
+    // `else if` is scored like `else`: a flat increment without extra nesting
+    let is_else_if = node
+        .parent()
+        .map(|p| p.kind() == "else_clause")
+        .unwrap_or(false)
+        && kind.starts_with("if_");
+
+    let mut child_nesting = nesting;
+    if is_else_if {
+        metrics.cognitive_complexity += 1;
+    } else if NESTING_KINDS.contains(&kind) {
+        metrics.cognitive_complexity += 1 + nesting;
+        child_nesting = nesting + 1;
+        metrics.max_nesting_depth = metrics.max_nesting_depth.max(child_nesting);
+    } else if FUNCTION_KINDS.contains(&kind) {
+        child_nesting = nesting + 1;
+    } else if matches!(kind, "else_clause" | "elif_clause") {
+        let wraps_if = node
+            .named_child(0)
+            .map(|c| c.kind().starts_with("if_"))
+            .unwrap_or(false);
+        if !wraps_if {
+            metrics.cognitive_complexity += 1;
+        }
+    }
+
+    // A run of the same boolean operator counts once: `a && b && c`
+    if is_boolean_operator(node, source) {
+        let continues_run = node
+            .parent()
+            .map(|p| operator(p, source) == operator(node, source))
+            .unwrap_or(false);
+        if !continues_run {
+            metrics.cognitive_complexity += 1;
+        }
+    }
+
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        walk(child, source, child_nesting, metrics);
+    }
+}
+
+fn operator<'a>(node: Node, source: &'a [u8]) -> Option<&'a str> {
+    match node.kind() {
+        "binary_expression" | "boolean_operator" => node
+            .child_by_field_name("operator")
+            .and_then(|op| op.utf8_text(source).ok()),
+        _ => None,
+    }
+}
+
+fn is_boolean_operator(node: Node, source: &[u8]) -> bool {
+    matches!(operator(node, source), Some("&&" | "||" | "and" | "or"))
+}
+
+fn is_comment(node: Node) -> bool {
+    node.kind().contains("comment")
+}
+
+fn parameter_count(function: Node, source: &[u8]) -> usize {
+    // Arrow functions with a single bare parameter: `x => x * 2`
+    if function.child_by_field_name("parameter").is_some() {
+        return 1;
+    }
+    let Some(parameters) = function.child_by_field_name("parameters") else {
+        return 0;
+    };
+    let mut cursor = parameters.walk();
+    let count = parameters
+        .named_children(&mut cursor)
+        .filter(|p| !is_comment(*p) && p.kind() != "self_parameter")
+        .filter(|p| !matches!(p.utf8_text(source), Ok("self") | Ok("cls")))
+        .count();
+    count
+}
+
+/// Classifies every line spanned by `node` as code, comment or blank. A line
+/// holding both code and a trailing comment counts as code.
+pub fn line_counts(node: Node) -> LineCounts {
+    let start = node.start_position().row;
+    let mut end = node.end_position().row;
+    // A node ending with a newline reports the following row at column 0
+    if end > start && node.end_position().column == 0 {
+        end -= 1;
+    }
+
+    let len = end - start + 1;
+    let mut code = vec![false; len];
+    let mut comment = vec![false; len];
+    mark_lines(node, start, &mut code, &mut comment);
+
+    let mut counts = LineCounts {
+        total: len,
+        ..Default::default()
+    };
+    for row in 0..len {
+        if code[row] {
+            counts.code += 1;
+        } else if comment[row] {
+            counts.comment += 1;
+        } else {
+            counts.blank += 1;
+        }
+    }
+    counts
+}
+
+fn mark_lines(node: Node, offset: usize, code: &mut [bool], comment: &mut [bool]) {
+    let is_comment = is_comment(node);
+    if node.child_count() == 0 || is_comment {
+        let lines = if is_comment { comment } else { code };
+        let first = node.start_position().row.saturating_sub(offset);
+        let last = (node.end_position().row - offset).min(lines.len() - 1);
+        for flag in lines.iter_mut().take(last + 1).skip(first) {
+            *flag = true;
+        }
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        mark_lines(child, offset, code, comment);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::{Language, Parser as TSParser, Tree};
+
+    fn parse(source: &str, language: Language) -> Tree {
+        let mut parser = TSParser::new();
+        parser.set_language(language).unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    #[test]
+    fn test_function_metrics() {
+        let source = r#"
+fn classify(values: &[i32], limit: i32) -> usize {
+    // count the large values
+    let mut count = 0;
+
+    for v in values {
+        if *v > limit && *v != 0 {
+            count += 1;
+        } else if *v < 0 {
+            count -= 1;
+        }
+    }
+    count
+}
+"#;
+        let tree = parse(source, tree_sitter_rust::language());
+        let function = tree.root_node().named_child(0).unwrap();
+        let metrics = compute(function, source.as_bytes());
+
+        // 1 + for + if + && + else if
+        assert_eq!(metrics.cyclomatic_complexity, 5);
+        // for (1) + if (2, nested) + && (1) + else if (1)
+        assert_eq!(metrics.cognitive_complexity, 5);
+        assert_eq!(metrics.max_nesting_depth, 2);
+        assert_eq!(metrics.parameter_count, 2);
+        assert_eq!(metrics.lines.total, 13);
+        assert_eq!(metrics.lines.comment, 1);
+        assert_eq!(metrics.lines.blank, 1);
+        assert_eq!(metrics.lines.code, 11);
+    }
+
+    #[test]
+    fn test_javascript_function_metrics() {
+        let source = r#"function pick(items, fallback) {
+  // first truthy item
+  for (const item of items) {
+    if (item || fallback) {
+      return item;
+    }
+  }
+  return fallback ? fallback : null;
+}
+"#;
+        let tree = parse(source, tree_sitter_javascript::language());
+        let function = tree.root_node().named_child(0).unwrap();
+        let metrics = compute(function, source.as_bytes());
+
+        // 1 + for + if + || + ternary
+        assert_eq!(metrics.cyclomatic_complexity, 5);
+        // for (1) + if (2, nested) + || (1) + ternary (1)
+        assert_eq!(metrics.cognitive_complexity, 5);
+        assert_eq!(metrics.max_nesting_depth, 2);
+        assert_eq!(metrics.statement_count, 4);
+        assert_eq!(metrics.parameter_count, 2);
+        assert_eq!(metrics.lines.total, 9);
+        assert_eq!(metrics.lines.comment, 1);
+        assert_eq!(metrics.lines.code, 8);
+    }
+
+    #[test]
+    fn test_typescript_arrow_function_metrics() {
+        let source = r#"const visible = (items: Item[], limit = 10) =>
+  items.filter((item) => item.shown && item.rank < limit);
+"#;
+        let tree = parse(source, tree_sitter_typescript::language_typescript());
+        // Arrow functions are captured as their `variable_declarator`
+        let declarator = tree
+            .root_node()
+            .named_child(0)
+            .and_then(|declaration| declaration.named_child(0))
+            .unwrap();
+        let metrics = compute(declarator, source.as_bytes());
+
+        assert_eq!(metrics.cyclomatic_complexity, 2);
+        // The nested callback adds nesting but no increment of its own
+        assert_eq!(metrics.cognitive_complexity, 1);
+        assert_eq!(metrics.max_nesting_depth, 0);
+        assert_eq!(metrics.parameter_count, 2);
+        assert_eq!(metrics.lines.total, 2);
+        assert_eq!(metrics.lines.code, 2);
+    }
+
+    #[test]
+    fn test_python_method_metrics() {
+        let source = r#"def grade(self, score, curve=0):
+    """Letter grade for a score."""
+    # apply the curve first
+    score += curve
+
+    if score >= 90 and not self.strict:
+        return "A"
+    elif score >= 80:
+        return "B"
+    return "C"
+"#;
+        let tree = parse(source, tree_sitter_python::language());
+        let function = tree.root_node().named_child(0).unwrap();
+        let metrics = compute(function, source.as_bytes());
+
+        // 1 + if + and + elif
+        assert_eq!(metrics.cyclomatic_complexity, 4);
+        // if (1) + and (1) + elif (1)
+        assert_eq!(metrics.cognitive_complexity, 3);
+        assert_eq!(metrics.max_nesting_depth, 1);
+        // `self` is not a parameter
+        assert_eq!(metrics.parameter_count, 2);
+        assert_eq!(metrics.lines.total, 10);
+        assert_eq!(metrics.lines.comment, 1);
+        assert_eq!(metrics.lines.blank, 1);
+        assert_eq!(metrics.lines.code, 8);
+    }
+}
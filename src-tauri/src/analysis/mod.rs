@@ -0,0 +1,12 @@
+pub mod call_graph;
+pub mod classification;
+pub mod clones;
+pub mod hashing;
+pub mod import_cycles;
+pub mod metrics;
+pub mod python_modules;
+pub mod rust_semantics;
+pub mod signatures;
+pub mod test_discovery;
+pub mod todos;
+pub mod workspaces;
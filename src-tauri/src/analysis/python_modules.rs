@@ -0,0 +1,376 @@
+//! Python module resolution.
+//!
+//! Files get a dotted `module_name` relative to their source root. Source roots
+//! are the walked directory, every directory holding a `pyproject.toml`,
+//! `setup.cfg` or `setup.py`, the package directories those manifests declare
+//! (setuptools `where`/`package-dir`, poetry `from`) and conventional `src/`
+//! layouts. Directories without `__init__.py` are treated as namespace
+//! packages, so any directory below a root can be imported.
+//!
+//! Imports are resolved once the whole directory is parsed: resolved modules
+//! become `IMPORTS` edges between files, anything else an `ExternalLibrary`.
+
+use crate::analysis::import_cycles::{self, TYPE_ONLY_PROPERTY};
+use crate::models::{
+    CodeEntity, CodeLanguage, EntityType, LinkEntity, LinkType, LINK_ALIAS_PROPERTY,
+    LINK_LINE_PROPERTY,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tree_sitter::Node;
+
+/// Property holding a Python file's dotted module path
+pub const MODULE_NAME_PROPERTY: &str = "module_name";
+/// Property on Import entities holding the imported module, as written
+pub const MODULE_PROPERTY: &str = "module";
+/// Property on `from ... import` entities listing the imported names
+pub const NAMES_PROPERTY: &str = "names";
+/// Property on Import nodes holding the name of `import module as alias`
+pub const ALIAS_PROPERTY: &str = "alias";
+
+const MANIFESTS: &[&str] = &["pyproject.toml", "setup.cfg", "setup.py"];
+const SKIPPED_DIRS: &[&str] = &[
+    ".git",
+    "node_modules",
+    "target",
+    "__pycache__",
+    ".venv",
+    "venv",
+];
+
+/// Source roots of a directory tree
+#[derive(Clone, Debug, Default)]
+pub struct PythonLayout {
+    roots: Vec<PathBuf>,
+}
+
+impl PythonLayout {
+    /// Finds the source roots below `directory`
+    pub fn discover(directory: &Path) -> Self {
+        let mut roots = vec![directory.to_path_buf()];
+        let mut pending = vec![directory.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            if MANIFESTS.iter().any(|m| dir.join(m).is_file()) {
+                roots.push(dir.clone());
+                roots.extend(declared_roots(&dir));
+                let src = dir.join("src");
+                if src.is_dir() && !src.join("__init__.py").exists() {
+                    roots.push(src);
+                }
+            }
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let skipped = path
+                    .file_name()
+                    .map(|n| SKIPPED_DIRS.contains(&n.to_string_lossy().as_ref()))
+                    .unwrap_or(true);
+                if path.is_dir() && !skipped {
+                    pending.push(path);
+                }
+            }
+        }
+        roots.sort();
+        roots.dedup();
+        PythonLayout { roots }
+    }
+
+    /// Dotted module path of a `.py` file relative to its deepest source root,
+    /// e.g. `src/pear/cli/__init__.py` -> `pear.cli`
+    pub fn module_name(&self, file: &Path) -> Option<String> {
+        let root = self
+            .roots
+            .iter()
+            .filter(|root| file.starts_with(root))
+            .max_by_key(|root| root.components().count())?;
+        let relative = file.strip_prefix(root).ok()?.with_extension("");
+        let mut parts: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        if parts.last().map(|p| p == "__init__").unwrap_or(false) {
+            parts.pop();
+        }
+        if parts.is_empty() {
+            return None;
+        }
+        Some(parts.join("."))
+    }
+}
+
+/// Package directories declared by `pyproject.toml` or `setup.cfg`
+fn declared_roots(dir: &Path) -> Vec<PathBuf> {
+    let mut declared = Vec::new();
+
+    if let Some(pyproject) = std::fs::read_to_string(dir.join("pyproject.toml"))
+        .ok()
+        .and_then(|c| c.parse::<toml::Value>().ok())
+    {
+        let tool = pyproject.get("tool");
+        let setuptools = tool.and_then(|t| t.get("setuptools"));
+        // [tool.setuptools.packages.find] where = ["src"]
+        if let Some(wheres) = setuptools
+            .and_then(|s| s.get("packages"))
+            .and_then(|p| p.get("find"))
+            .and_then(|f| f.get("where"))
+            .and_then(|w| w.as_array())
+        {
+            declared.extend(
+                wheres
+                    .iter()
+                    .filter_map(|w| w.as_str())
+                    .map(|w| dir.join(w)),
+            );
+        }
+        // [tool.setuptools] package-dir = { "" = "src" }
+        if let Some(root) = setuptools
+            .and_then(|s| s.get("package-dir"))
+            .and_then(|p| p.get(""))
+            .and_then(|r| r.as_str())
+        {
+            declared.push(dir.join(root));
+        }
+        // [tool.poetry] packages = [{ include = "pear", from = "src" }]
+        if let Some(packages) = tool
+            .and_then(|t| t.get("poetry"))
+            .and_then(|p| p.get("packages"))
+            .and_then(|p| p.as_array())
+        {
+            declared.extend(
+                packages
+                    .iter()
+                    .filter_map(|p| p.get("from").and_then(|f| f.as_str()))
+                    .map(|from| dir.join(from)),
+            );
+        }
+    }
+
+    // setup.cfg: `package_dir = =src` and `[options.packages.find] where = src`
+    if let Ok(setup_cfg) = std::fs::read_to_string(dir.join("setup.cfg")) {
+        for line in setup_cfg.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "where" => declared.push(dir.join(value.trim())),
+                "package_dir" => {
+                    if let Some(root) = value.trim().strip_prefix('=') {
+                        declared.push(dir.join(root.trim()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    declared.into_iter().filter(|d| d.is_dir()).collect()
+}
+
+/// Records the imported module and, for `from ... import`, the imported names
+/// or otherwise the alias
+pub fn write_import_properties(
+    node: Node,
+    name: &str,
+    source: &[u8],
+    properties: &mut HashMap<String, String>,
+) {
+    let module = name.split(" as ").next().unwrap_or(name).trim();
+    properties.insert(MODULE_PROPERTY.to_string(), module.to_string());
+
+    if node.kind() == "import_from_statement" {
+        let mut cursor = node.walk();
+        let names: Vec<&str> = node
+            .children_by_field_name("name", &mut cursor)
+            .filter_map(|n| n.utf8_text(source).ok())
+            .map(|n| n.split(" as ").next().unwrap_or(n).trim())
+            .collect();
+        let names = if names.is_empty() && source[node.byte_range()].ends_with(b"*") {
+            vec!["*"]
+        } else {
+            names
+        };
+        properties.insert(NAMES_PROPERTY.to_string(), names.join(","));
+    } else if let Some((_, alias)) = name.split_once(" as ") {
+        properties.insert(ALIAS_PROPERTY.to_string(), alias.trim().to_string());
+    }
+}
+
+/// Resolves Python imports to files. Returns the `ExternalLibrary` nodes for
+/// unresolved top-level packages and the `IMPORTS` edges from each file.
+pub fn resolve_imports(nodes: &[CodeEntity]) -> (Vec<CodeEntity>, Vec<LinkEntity>) {
+    // Modules map to files; namespace packages map to their directory
+    let mut modules: HashMap<&str, String> = HashMap::new();
+    for file in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::File))
+    {
+        let Some(module) = file.properties.get(MODULE_NAME_PROPERTY) else {
+            continue;
+        };
+        modules.insert(module.as_str(), file.id.clone());
+        let mut dir = Path::new(&file.path).parent();
+        let mut package = module.as_str();
+        if !file.path.ends_with("__init__.py") {
+            package = package.rsplit_once('.').map(|(p, _)| p).unwrap_or("");
+        }
+        // Ancestors without an __init__.py of their own are namespace packages
+        while let (Some(d), false) = (dir, package.is_empty()) {
+            modules
+                .entry(package)
+                .or_insert_with(|| d.to_string_lossy().to_string());
+            package = package.rsplit_once('.').map(|(p, _)| p).unwrap_or("");
+            dir = d.parent();
+        }
+    }
+
+    let file_modules: HashMap<&str, (&str, bool)> = nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::File))
+        .filter_map(|n| {
+            n.properties.get(MODULE_NAME_PROPERTY).map(|m| {
+                (
+                    n.path.as_str(),
+                    (m.as_str(), n.path.ends_with("__init__.py")),
+                )
+            })
+        })
+        .collect();
+
+    let mut externals: HashMap<String, CodeEntity> = HashMap::new();
+    let mut seen = HashMap::new();
+    let mut links = Vec::new();
+    let python = CodeLanguage::Python.to_string();
+
+    for import in nodes.iter().filter(|n| {
+        matches!(n.entity_type, EntityType::Import) && n.properties.get("language") == Some(&python)
+    }) {
+        let Some(spec) = import.properties.get(MODULE_PROPERTY) else {
+            continue;
+        };
+        let Some(&(importer, is_package)) = file_modules.get(import.path.as_str()) else {
+            continue;
+        };
+
+        let relative = spec.starts_with('.');
+        let Some(module) = absolute_module(spec, importer, is_package) else {
+            continue;
+        };
+
+        // `from pkg import sub` may name a submodule rather than an attribute
+        let mut targets = Vec::new();
+        if let Some(names) = import.properties.get(NAMES_PROPERTY) {
+            for name in names.split(',').filter(|n| !n.is_empty() && *n != "*") {
+                let submodule = join_module(&module, name);
+                if let Some(file) = modules.get(submodule.as_str()) {
+                    targets.push(file.clone());
+                }
+            }
+        }
+        if targets.is_empty() {
+            if let Some(file) = modules.get(module.as_str()) {
+                targets.push(file.clone());
+            }
+        }
+
+        if targets.is_empty() && !relative {
+            let top = module.split('.').next().unwrap_or(&module).to_string();
+            let id = format!("external:pypi:{}", top);
+            externals.entry(id.clone()).or_insert_with(|| {
+                let mut properties = HashMap::new();
+                properties.insert("name".to_string(), top.clone());
+                properties.insert("language".to_string(), python.clone());
+                properties.insert("ecosystem".to_string(), "pypi".to_string());
+                CodeEntity {
+                    id: id.clone(),
+                    path: String::new(),
+                    entity_type: EntityType::ExternalLibrary,
+                    start_line: None,
+                    end_line: None,
+                    span: None,
+                    name_span: None,
+                    properties,
+                    children: None,
+                }
+            });
+            targets.push(id);
+        }
+
+        for target in targets {
+            if target != import.path {
+                let mut properties = HashMap::new();
+                if let Some(line) = import.start_line {
+                    properties.insert(LINK_LINE_PROPERTY.to_string(), line.to_string());
+                }
+                if let Some(alias) = import.properties.get(ALIAS_PROPERTY) {
+                    properties.insert(LINK_ALIAS_PROPERTY.to_string(), alias.clone());
+                }
+                if let Some(type_only) = import.properties.get(TYPE_ONLY_PROPERTY) {
+                    properties.insert(TYPE_ONLY_PROPERTY.to_string(), type_only.clone());
+                }
+                let link = LinkEntity {
+                    from_name: import.path.clone(),
+                    to_name: target,
+                    link_type: LinkType::Import,
+                    properties,
+                };
+                import_cycles::merge_import_link(&mut links, &mut seen, link);
+            }
+        }
+    }
+
+    (externals.into_values().collect(), links)
+}
+
+/// Turns `..x.y` into an absolute module relative to the importing module.
+/// Returns None when the dots climb above the top-level package.
+fn absolute_module(spec: &str, importer: &str, importer_is_package: bool) -> Option<String> {
+    let dots = spec.chars().take_while(|c| *c == '.').count();
+    if dots == 0 {
+        return Some(spec.to_string());
+    }
+
+    let mut package: Vec<&str> = importer.split('.').collect();
+    if !importer_is_package {
+        package.pop();
+    }
+    for _ in 1..dots {
+        package.pop()?;
+    }
+    Some(join_module(&package.join("."), &spec[dots..]))
+}
+
+fn join_module(base: &str, name: &str) -> String {
+    match (base.is_empty(), name.is_empty()) {
+        (true, _) => name.to_string(),
+        (_, true) => base.to_string(),
+        _ => format!("{}.{}", base, name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_imports() {
+        assert_eq!(
+            absolute_module("..util", "pear.cli.main", false).as_deref(),
+            Some("pear.util")
+        );
+        assert_eq!(
+            absolute_module(".", "pear.cli", true).as_deref(),
+            Some("pear.cli")
+        );
+        assert_eq!(
+            absolute_module(".models", "pear.cli", true).as_deref(),
+            Some("pear.cli.models")
+        );
+        assert_eq!(absolute_module("...x", "pear.main", false), None);
+        assert_eq!(
+            absolute_module("os.path", "pear.main", false).as_deref(),
+            Some("os.path")
+        );
+    }
+}
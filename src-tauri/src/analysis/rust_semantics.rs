@@ -0,0 +1,462 @@
+//! Rust crate model: crates from `Cargo.toml`, `mod` trees, macro invocations
+//! and `#[cfg(...)]` gating.
+//!
+//! Like test discovery, per-file facts are recorded in properties while files
+//! are parsed (`mods` on File nodes, `macros` on the invoking entity) and are
+//! resolved into edges once the whole directory is known.
+
+use crate::analysis::test_discovery::rust_attributes;
+use crate::error::{AppError, Result};
+use crate::models::{CodeEntity, EntityType, LinkEntity, LinkType, LINK_CONFIDENCE_PROPERTY};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use tree_sitter::Node;
+
+/// Property on File nodes listing out-of-line `mod` declarations as
+/// `name` or `name=path` (from `#[path = "..."]`)
+pub const MODS_PROPERTY: &str = "mods";
+/// Property listing the macros an entity (or file, at top level) invokes
+pub const MACROS_PROPERTY: &str = "macros";
+/// Property holding the `cfg(...)` conditions gating an item, outermost first
+pub const CFG_PROPERTY: &str = "cfg";
+/// Property set to "true" on items that only exist under `cfg(test)`
+pub const TEST_ONLY_PROPERTY: &str = "test_only";
+
+/// Items whose attributes also gate everything declared inside them
+const GATING_KINDS: &[&str] = &["mod_item", "impl_item", "trait_item", "function_item"];
+
+/// Builds the `Crate` nodes (library and binary targets) declared by a manifest.
+/// Workspace-only manifests without a `[package]` yield no crates.
+pub fn crates_from_manifest(manifest: &Path) -> Result<Vec<CodeEntity>> {
+    let content = std::fs::read_to_string(manifest)?;
+    let value: toml::Value = content
+        .parse()
+        .map_err(|e| AppError::Parse(format!("{}: {}", manifest.display(), e)))?;
+
+    let Some(package) = value.get("package") else {
+        return Ok(Vec::new());
+    };
+    let package_name = package
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let version = package
+        .get("version")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let edition = package
+        .get("edition")
+        .and_then(|e| e.as_str())
+        .unwrap_or("2015")
+        .to_string();
+
+    let base = manifest.parent().unwrap_or(Path::new("."));
+    let mut targets: Vec<(&str, String, PathBuf)> = Vec::new();
+
+    // Library: explicit `[lib]` or the conventional `src/lib.rs`
+    let lib = value.get("lib");
+    let lib_path = lib
+        .and_then(|l| l.get("path"))
+        .and_then(|p| p.as_str())
+        .map(|p| base.join(p))
+        .unwrap_or_else(|| base.join("src").join("lib.rs"));
+    if lib.is_some() || lib_path.exists() {
+        let name = lib
+            .and_then(|l| l.get("name"))
+            .and_then(|n| n.as_str())
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| package_name.replace('-', "_"));
+        targets.push(("lib", name, lib_path));
+    }
+
+    // Binaries: `[[bin]]` entries, `src/main.rs` and `src/bin/*.rs`
+    let mut bin_paths = HashSet::new();
+    if let Some(bins) = value.get("bin").and_then(|b| b.as_array()) {
+        for bin in bins {
+            let Some(name) = bin.get("name").and_then(|n| n.as_str()) else {
+                continue;
+            };
+            let path = bin
+                .get("path")
+                .and_then(|p| p.as_str())
+                .map(|p| base.join(p))
+                .unwrap_or_else(|| base.join("src").join("bin").join(format!("{}.rs", name)));
+            bin_paths.insert(path.clone());
+            targets.push(("bin", name.to_string(), path));
+        }
+    }
+    let main_path = base.join("src").join("main.rs");
+    if main_path.exists() && !bin_paths.contains(&main_path) {
+        targets.push(("bin", package_name.clone(), main_path));
+    }
+    if let Ok(entries) = std::fs::read_dir(base.join("src").join("bin")) {
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension().map(|e| e == "rs").unwrap_or(false) && !bin_paths.contains(&path) {
+                let name = path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                targets.push(("bin", name, path));
+            }
+        }
+    }
+
+    let manifest_path = manifest.to_string_lossy().to_string();
+    Ok(targets
+        .into_iter()
+        .map(|(kind, name, root)| {
+            let mut properties = HashMap::new();
+            properties.insert("name".to_string(), name.clone());
+            properties.insert("kind".to_string(), kind.to_string());
+            properties.insert("package".to_string(), package_name.clone());
+            properties.insert("version".to_string(), version.clone());
+            properties.insert("edition".to_string(), edition.clone());
+            properties.insert("root".to_string(), root.to_string_lossy().to_string());
+            CodeEntity {
+                id: format!("{}::{}:{}", manifest_path, kind, name),
+                path: manifest_path.clone(),
+                entity_type: EntityType::Crate,
+                start_line: None,
+                end_line: None,
+                span: None,
+                name_span: None,
+                properties,
+                children: None,
+            }
+        })
+        .collect())
+}
+
+/// `cfg(...)` attributes on `node` and on the modules, impls and traits
+/// around it, outermost first
+pub fn cfg_conditions(node: Node, source: &[u8]) -> Vec<String> {
+    let mut conditions = Vec::new();
+    let mut current = Some(node);
+    while let Some(item) = current {
+        if item == node || GATING_KINDS.contains(&item.kind()) {
+            let mut own: Vec<String> = rust_attributes(item, source)
+                .into_iter()
+                .filter(|attr| attr.starts_with("cfg("))
+                .collect();
+            // Attributes are collected bottom-up; keep source order
+            own.reverse();
+            own.extend(conditions);
+            conditions = own;
+        }
+        current = item.parent();
+    }
+    conditions
+}
+
+/// Stores gating conditions in an entity's property map
+pub fn write_cfg_properties(conditions: &[String], properties: &mut HashMap<String, String>) {
+    if conditions.is_empty() {
+        return;
+    }
+    properties.insert(CFG_PROPERTY.to_string(), conditions.join(" && "));
+    let test_only = conditions
+        .iter()
+        .any(|c| c == "cfg(test)" || c.starts_with("cfg(all(test,"));
+    properties.insert(TEST_ONLY_PROPERTY.to_string(), test_only.to_string());
+}
+
+/// Out-of-line `mod name;` declarations of a file, in `MODS_PROPERTY` form
+pub fn module_declarations(root: Node, source: &[u8]) -> Vec<String> {
+    let mut declarations = Vec::new();
+    collect_mods(root, source, &mut declarations);
+    declarations
+}
+
+fn collect_mods(node: Node, source: &[u8], declarations: &mut Vec<String>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if child.kind() != "mod_item" || child.child_by_field_name("body").is_some() {
+            continue;
+        }
+        let Some(name) = child
+            .child_by_field_name("name")
+            .and_then(|n| n.utf8_text(source).ok())
+        else {
+            continue;
+        };
+        let path = rust_attributes(child, source).into_iter().find_map(|attr| {
+            attr.strip_prefix("path=")
+                .map(|p| p.trim_matches('"').to_string())
+        });
+        declarations.push(match path {
+            Some(path) => format!("{}={}", name, path),
+            None => name.to_string(),
+        });
+    }
+}
+
+/// Records every macro invocation in the `macros` property of the innermost
+/// function, method or test around it. Invocations outside of any of them are
+/// returned for the file itself.
+pub fn record_macro_invocations(
+    root: Node,
+    source: &[u8],
+    entities: &mut [CodeEntity],
+) -> Vec<String> {
+    let mut invocations = Vec::new();
+    collect_macros(root, source, &mut invocations);
+
+    let mut by_entity: HashMap<usize, BTreeSet<String>> = HashMap::new();
+    let mut file_level = BTreeSet::new();
+    for (line, name) in invocations {
+        let enclosing = entities
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| {
+                matches!(
+                    e.entity_type,
+                    EntityType::Function | EntityType::Method | EntityType::Test
+                )
+            })
+            .filter(|(_, e)| {
+                e.start_line.map(|s| s <= line).unwrap_or(false)
+                    && e.end_line.map(|end| line <= end).unwrap_or(false)
+            })
+            .min_by_key(|(_, e)| e.end_line.unwrap_or(0) - e.start_line.unwrap_or(0))
+            .map(|(i, _)| i);
+        match enclosing {
+            Some(i) => {
+                by_entity.entry(i).or_default().insert(name);
+            }
+            None => {
+                file_level.insert(name);
+            }
+        }
+    }
+
+    for (i, names) in by_entity {
+        let names: Vec<String> = names.into_iter().collect();
+        entities[i]
+            .properties
+            .insert(MACROS_PROPERTY.to_string(), names.join(","));
+    }
+    file_level.into_iter().collect()
+}
+
+fn collect_macros(node: Node, source: &[u8], invocations: &mut Vec<(usize, String)>) {
+    if node.kind() == "macro_invocation" {
+        if let Some(name) = node
+            .child_by_field_name("macro")
+            .and_then(|m| m.utf8_text(source).ok())
+        {
+            // `log::info!` is the `info` macro
+            let name = name.rsplit("::").next().unwrap_or(name);
+            invocations.push((node.start_position().row + 1, name.to_string()));
+        }
+    }
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect_macros(child, source, invocations);
+    }
+}
+
+/// Resolves `mod` declarations to files: crates get a `HAS_MODULE` edge to
+/// their root file, and every file to the module files it declares.
+pub fn link_modules(nodes: &[CodeEntity]) -> Vec<LinkEntity> {
+    let files: HashSet<&str> = nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::File))
+        .map(|n| n.path.as_str())
+        .collect();
+
+    let mut links = Vec::new();
+    let mut crate_roots = HashSet::new();
+    for krate in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::Crate))
+    {
+        let Some(root) = krate.properties.get("root") else {
+            continue;
+        };
+        let root = normalize_path(Path::new(root));
+        crate_roots.insert(root.clone());
+        if files.contains(root.as_str()) {
+            links.push(LinkEntity {
+                from_name: krate.id.clone(),
+                to_name: root,
+                link_type: LinkType::Module,
+                properties: HashMap::new(),
+            });
+        }
+    }
+
+    for file in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::File))
+    {
+        let Some(mods) = file.properties.get(MODS_PROPERTY) else {
+            continue;
+        };
+        let path = Path::new(&file.path);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        // `mod.rs`, `lib.rs`, `main.rs` and crate roots own their directory;
+        // any other `foo.rs` declares its children in `foo/`
+        let is_mod_root = crate_roots.contains(&normalize_path(path))
+            || matches!(
+                path.file_name().and_then(|n| n.to_str()),
+                Some("mod.rs" | "lib.rs" | "main.rs")
+            );
+        let child_dir = if is_mod_root {
+            dir.to_path_buf()
+        } else {
+            dir.join(path.file_stem().unwrap_or_default())
+        };
+
+        for declaration in mods.split(',').filter(|m| !m.is_empty()) {
+            let candidates = match declaration.split_once('=') {
+                Some((_, explicit)) => vec![dir.join(explicit)],
+                None => vec![
+                    child_dir.join(format!("{}.rs", declaration)),
+                    child_dir.join(declaration).join("mod.rs"),
+                ],
+            };
+            if let Some(target) = candidates
+                .iter()
+                .map(|c| normalize_path(c))
+                .find(|c| files.contains(c.as_str()))
+            {
+                links.push(LinkEntity {
+                    from_name: file.id.clone(),
+                    to_name: target,
+                    link_type: LinkType::Module,
+                    properties: HashMap::new(),
+                });
+            }
+        }
+    }
+    links
+}
+
+/// Resolves recorded macro invocations to `macro_rules!` definitions,
+/// preferring one in the same file. Macros defined elsewhere (std, external
+/// crates) get a shared `Macro` node flagged `external`.
+pub fn link_macros(nodes: &[CodeEntity]) -> (Vec<CodeEntity>, Vec<LinkEntity>) {
+    let mut definitions: HashMap<&str, Vec<&CodeEntity>> = HashMap::new();
+    for node in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::Macro))
+    {
+        if let Some(name) = node.properties.get("name") {
+            definitions.entry(name.as_str()).or_default().push(node);
+        }
+    }
+
+    let mut external: HashMap<String, CodeEntity> = HashMap::new();
+    let mut links = Vec::new();
+    for node in nodes {
+        let Some(macros) = node.properties.get(MACROS_PROPERTY) else {
+            continue;
+        };
+        for name in macros.split(',').filter(|m| !m.is_empty()) {
+            // Another file's definition is a guess when several share the name
+            let (target, confidence) = match definitions.get(name) {
+                Some(candidates) => match candidates.iter().find(|c| c.path == node.path) {
+                    Some(local) => (local.id.clone(), Some(1.0)),
+                    None => (
+                        candidates[0].id.clone(),
+                        Some(1.0 / candidates.len() as f64),
+                    ),
+                },
+                None => {
+                    let id = format!("macro:{}", name);
+                    external.entry(id.clone()).or_insert_with(|| {
+                        let mut properties = HashMap::new();
+                        properties.insert("name".to_string(), name.to_string());
+                        properties.insert("external".to_string(), "true".to_string());
+                        CodeEntity {
+                            id: id.clone(),
+                            path: String::new(),
+                            entity_type: EntityType::Macro,
+                            start_line: None,
+                            end_line: None,
+                            span: None,
+                            name_span: None,
+                            properties,
+                            children: None,
+                        }
+                    });
+                    (id, None)
+                }
+            };
+            links.push(LinkEntity {
+                from_name: node.id.clone(),
+                to_name: target,
+                link_type: LinkType::InvokesMacro,
+                properties: confidence
+                    .map(|c: f64| (LINK_CONFIDENCE_PROPERTY.to_string(), format!("{:.2}", c)))
+                    .into_iter()
+                    .collect(),
+            });
+        }
+    }
+    (external.into_values().collect(), links)
+}
+
+/// Lexically resolves `.` and `..` so joined paths compare equal to walked ones
+pub(crate) fn normalize_path(path: &Path) -> String {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    #[test]
+    fn test_cfg_and_mod_declarations() {
+        let source = r#"
+mod commands;
+#[path = "legacy/io.rs"]
+mod io;
+mod inline {}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "slow")]
+    fn heavy() {}
+}
+"#;
+        let mut parser = TSParser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+
+        assert_eq!(
+            module_declarations(root, source.as_bytes()),
+            vec!["commands".to_string(), "io=legacy/io.rs".to_string()]
+        );
+
+        let heavy = root
+            .named_descendant_for_point_range(
+                tree_sitter::Point { row: 9, column: 7 },
+                tree_sitter::Point { row: 9, column: 12 },
+            )
+            .unwrap();
+        let heavy = std::iter::successors(Some(heavy), |n| n.parent())
+            .find(|n| n.kind() == "function_item")
+            .unwrap();
+        let conditions = cfg_conditions(heavy, source.as_bytes());
+        assert_eq!(conditions, vec!["cfg(test)", "cfg(feature=\"slow\")"]);
+
+        let mut properties = HashMap::new();
+        write_cfg_properties(&conditions, &mut properties);
+        assert_eq!(properties.get(TEST_ONLY_PROPERTY).unwrap(), "true");
+    }
+}
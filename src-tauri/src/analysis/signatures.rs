@@ -0,0 +1,194 @@
+//! Signatures and doc comments of declarations, stored for full-text search.
+//!
+//! The signature is the declaration up to its body with whitespace collapsed,
+//! e.g. `pub fn add(a: i32, b: i32) -> i32`. The doc is the `///` or `/** */`
+//! comment right above the declaration (past attributes, decorators and
+//! `export`), or a Python docstring, without comment markers.
+
+use tree_sitter::Node;
+
+/// Entity property holding the declaration's signature
+pub const SIGNATURE_PROPERTY: &str = "signature";
+/// Entity property holding the declaration's doc comment or docstring
+pub const DOC_PROPERTY: &str = "doc";
+
+/// Longest signature or doc kept, in characters
+const MAX_TEXT_CHARS: usize = 500;
+
+/// Wrappers whose comments document the declaration inside them
+const WRAPPER_KINDS: &[&str] = &[
+    "export_statement",
+    "lexical_declaration",
+    "variable_declaration",
+    "variable_declarator",
+    "decorated_definition",
+];
+
+/// Writes `SIGNATURE_PROPERTY` and `DOC_PROPERTY` when the declaration has them
+pub fn write_properties(
+    node: Node,
+    source: &[u8],
+    properties: &mut std::collections::HashMap<String, String>,
+) {
+    if let Some(signature) = signature(node, source) {
+        properties.insert(SIGNATURE_PROPERTY.to_string(), signature);
+    }
+    if let Some(doc) = doc_comment(node, source) {
+        properties.insert(DOC_PROPERTY.to_string(), doc);
+    }
+}
+
+pub fn signature(node: Node, source: &[u8]) -> Option<String> {
+    let end = node
+        .child_by_field_name("body")
+        .map(|body| body.start_byte())
+        .unwrap_or_else(|| node.end_byte());
+    let text = std::str::from_utf8(source.get(node.start_byte()..end)?).ok()?;
+    // Python ends the header with `:`, arrow functions with `=>`
+    let text = text.trim_end();
+    let text = text
+        .strip_suffix("=>")
+        .or_else(|| text.strip_suffix(':'))
+        .unwrap_or(text);
+    let signature = collapse(text);
+    (!signature.is_empty()).then(|| truncate(signature))
+}
+
+pub fn doc_comment(node: Node, source: &[u8]) -> Option<String> {
+    docstring(node, source).or_else(|| preceding_comments(node, source))
+}
+
+/// Python: a string as the first statement of the body
+fn docstring(node: Node, source: &[u8]) -> Option<String> {
+    let body = node.child_by_field_name("body")?;
+    let first = body.named_child(0)?;
+    let string = first.named_child(0)?;
+    if body.kind() != "block" || first.kind() != "expression_statement" || string.kind() != "string"
+    {
+        return None;
+    }
+    let text = string.utf8_text(source).ok()?;
+    let text = text
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+        .trim_matches(|c| c == '"' || c == '\'');
+    let doc = collapse(text);
+    (!doc.is_empty()).then(|| truncate(doc))
+}
+
+/// Doc comments directly above the declaration or its wrappers, skipping
+/// attributes and decorators
+fn preceding_comments(node: Node, source: &[u8]) -> Option<String> {
+    let mut anchor = node;
+    while let Some(parent) = anchor.parent() {
+        if !WRAPPER_KINDS.contains(&parent.kind()) {
+            break;
+        }
+        anchor = parent;
+    }
+
+    let mut lines = Vec::new();
+    let mut current = anchor.prev_named_sibling();
+    while let Some(sibling) = current {
+        match sibling.kind() {
+            "attribute_item" | "decorator" => {}
+            "line_comment" | "block_comment" | "comment" => {
+                let text = sibling.utf8_text(source).ok()?;
+                match doc_text(text) {
+                    Some(text) => lines.push(text),
+                    None => break,
+                }
+            }
+            _ => break,
+        }
+        current = sibling.prev_named_sibling();
+    }
+    lines.reverse();
+    let doc = collapse(&lines.join(" "));
+    (!doc.is_empty()).then(|| truncate(doc))
+}
+
+/// The text of a `///` or `/** */` comment; `None` for other comments
+fn doc_text(comment: &str) -> Option<String> {
+    let comment = comment.trim();
+    if let Some(line) = comment.strip_prefix("///") {
+        return Some(line.trim().to_string());
+    }
+    let block = comment.strip_prefix("/**")?.strip_suffix("*/")?;
+    Some(
+        block
+            .lines()
+            .map(|line| line.trim().trim_start_matches('*').trim())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate(text: String) -> String {
+    match text.char_indices().nth(MAX_TEXT_CHARS) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    fn first_declaration(
+        language: tree_sitter::Language,
+        source: &str,
+    ) -> (Option<String>, Option<String>) {
+        let mut parser = TSParser::new();
+        parser.set_language(language).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+        let node = root
+            .named_children(&mut cursor)
+            .find(|n| !n.kind().contains("comment") && n.kind() != "attribute_item")
+            .unwrap();
+        let node = match node.kind() {
+            "export_statement" => node.child_by_field_name("declaration").unwrap(),
+            _ => node,
+        };
+        (
+            signature(node, source.as_bytes()),
+            doc_comment(node, source.as_bytes()),
+        )
+    }
+
+    #[test]
+    fn test_signatures_and_docs() {
+        let rust = "// not a doc\n/// Adds two\n/// numbers.\n#[inline]\npub fn add(a: i32,\n    b: i32) -> i32 {\n    a + b\n}\n";
+        assert_eq!(
+            first_declaration(tree_sitter_rust::language(), rust),
+            (
+                Some("pub fn add(a: i32, b: i32) -> i32".to_string()),
+                Some("Adds two numbers.".to_string())
+            )
+        );
+
+        let ts = "/**\n * Parses the config.\n */\nexport function parse(text: string): Config {\n  return {};\n}\n";
+        assert_eq!(
+            first_declaration(tree_sitter_typescript::language_typescript(), ts),
+            (
+                Some("function parse(text: string): Config".to_string()),
+                Some("Parses the config.".to_string())
+            )
+        );
+
+        let py = "def load(path):\n    \"\"\"Read the file at path.\"\"\"\n    return open(path)\n";
+        assert_eq!(
+            first_declaration(tree_sitter_python::language(), py),
+            (
+                Some("def load(path)".to_string()),
+                Some("Read the file at path.".to_string())
+            )
+        );
+    }
+}
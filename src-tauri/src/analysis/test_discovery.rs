@@ -0,0 +1,588 @@
+//! Test discovery over parsed syntax trees.
+//!
+//! Recognised shapes:
+//! * JS/TS: `describe(...)` suites and `it(...)` / `test(...)` cases
+//! * Rust: `#[test]` functions and `#[cfg(test)] mod` suites
+//! * Python: `test` / `test_*` functions and `unittest.TestCase` (or `Test*`) classes
+//!
+//! Every test case records the names it calls in the `calls` property, and
+//! [`link_tests`] resolves those names to Function/Method nodes as `TESTS` edges
+//! once the whole directory has been parsed. Plain calls between functions
+//! are collected and resolved the same way in `call_graph`.
+
+use crate::analysis::hashing::{self, HashMode, CONTENT_HASH_PROPERTY};
+use crate::models::{
+    CodeEntity, CodeLanguage, EntityType, LinkEntity, LinkType, SourceSpan,
+    LINK_CONFIDENCE_PROPERTY,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tree_sitter::Node;
+
+/// Property holding the comma-separated names called from a test body
+pub const CALLS_PROPERTY: &str = "calls";
+
+const JS_SUITE_FNS: &[&str] = &["describe", "suite", "context"];
+const JS_CASE_FNS: &[&str] = &["it", "test", "specify"];
+const JS_MODIFIERS: &[&str] = &["only", "skip", "todo", "concurrent"];
+const FRAMEWORK_CALLS: &[&str] = &[
+    "describe",
+    "suite",
+    "context",
+    "it",
+    "test",
+    "specify",
+    "expect",
+    "beforeEach",
+    "afterEach",
+    "beforeAll",
+    "afterAll",
+    "setUp",
+    "tearDown",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum TestKind {
+    Suite,
+    Case,
+}
+
+impl TestKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TestKind::Suite => "suite",
+            TestKind::Case => "case",
+        }
+    }
+}
+
+struct FoundTest<'a> {
+    kind: TestKind,
+    name: String,
+    node: Node<'a>,
+    /// Identifier, or the title string of a JS test
+    name_node: Node<'a>,
+    body: Option<Node<'a>>,
+}
+
+/// Finds the test suites and cases declared in a file and returns them as
+/// `Test` entities.
+pub fn discover_tests(
+    root: Node,
+    source: &[u8],
+    path: &str,
+    language: &CodeLanguage,
+    hash_mode: HashMode,
+) -> Vec<CodeEntity> {
+    let mut tests = Vec::new();
+    let mut suites = Vec::new();
+    let context = Context {
+        source,
+        path,
+        language,
+        hash_mode,
+    };
+    visit(root, &context, &mut suites, &mut tests);
+    tests
+}
+
+/// Marks the Function/Method entities that `tests` were found at as not
+/// exported. Test functions are extracted as both, but they aren't API that
+/// `find_untested_functions` should ask tests for.
+pub fn unexport_test_functions(entities: &mut [CodeEntity], tests: &[CodeEntity]) {
+    let test_functions: HashSet<(&str, Option<usize>)> = tests
+        .iter()
+        .filter_map(|test| Some((test.properties.get("name")?.as_str(), test.end_line)))
+        .collect();
+    for entity in entities
+        .iter_mut()
+        .filter(|e| matches!(e.entity_type, EntityType::Function | EntityType::Method))
+    {
+        let is_test = entity
+            .properties
+            .get("name")
+            .is_some_and(|name| test_functions.contains(&(name.as_str(), entity.end_line)));
+        if is_test {
+            entity
+                .properties
+                .insert("exported".to_string(), "false".to_string());
+        }
+    }
+}
+
+/// Per-file inputs shared by every step of the walk
+struct Context<'a> {
+    source: &'a [u8],
+    path: &'a str,
+    language: &'a CodeLanguage,
+    hash_mode: HashMode,
+}
+
+fn visit(node: Node, ctx: &Context, suites: &mut Vec<String>, tests: &mut Vec<CodeEntity>) {
+    let source = ctx.source;
+    let found = match ctx.language {
+        CodeLanguage::Rust => rust_test(node, source),
+        CodeLanguage::Python => python_test(node, source),
+        CodeLanguage::Unknown => None,
+        _ => js_test(node, source),
+    };
+
+    if let Some(found) = found {
+        tests.push(make_test_entity(&found, ctx, suites));
+        if found.kind == TestKind::Case {
+            return;
+        }
+        suites.push(found.name);
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            visit(child, ctx, suites, tests);
+        }
+        suites.pop();
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        visit(child, ctx, suites, tests);
+    }
+}
+
+fn make_test_entity(found: &FoundTest, ctx: &Context, suites: &[String]) -> CodeEntity {
+    let mut qualified: Vec<&str> = suites.iter().map(String::as_str).collect();
+    qualified.push(&found.name);
+    let qualified = qualified.join("::");
+
+    let mut properties = HashMap::new();
+    properties.insert("name".to_string(), found.name.clone());
+    properties.insert("qualified_name".to_string(), qualified.clone());
+    properties.insert("language".to_string(), ctx.language.to_string());
+    properties.insert("kind".to_string(), found.kind.as_str().to_string());
+    properties.insert(
+        CONTENT_HASH_PROPERTY.to_string(),
+        hashing::entity_hash(found.node, ctx.source, ctx.hash_mode),
+    );
+    if let Some(suite) = suites.last() {
+        properties.insert("suite".to_string(), suite.clone());
+    }
+
+    if found.kind == TestKind::Case {
+        let mut calls = BTreeMap::new();
+        if let Some(body) = found.body {
+            collect_calls(body, ctx.source, &mut calls);
+        }
+        let calls: Vec<String> = calls.into_keys().collect();
+        properties.insert(CALLS_PROPERTY.to_string(), calls.join(","));
+    }
+
+    CodeEntity {
+        id: format!("{}::test:{}", ctx.path, qualified),
+        path: ctx.path.to_string(),
+        entity_type: EntityType::Test,
+        start_line: Some(found.node.start_position().row + 1),
+        end_line: Some(found.node.end_position().row + 1),
+        span: Some(SourceSpan::from_node(found.node)),
+        name_span: Some(SourceSpan::from_node(found.name_node)),
+        properties,
+        children: None,
+    }
+}
+
+// ── Rust ────────────────────────────────────────────────────────────────
+
+/// Collects the attributes written directly above an item, e.g. `test`,
+/// `tokio::test` or `cfg(test)`.
+pub(crate) fn rust_attributes(node: Node, source: &[u8]) -> Vec<String> {
+    let mut attributes = Vec::new();
+    let mut current = node.prev_named_sibling();
+    while let Some(sibling) = current {
+        match sibling.kind() {
+            "attribute_item" => {
+                if let Some(attr) = sibling.named_child(0) {
+                    let text = attr.utf8_text(source).unwrap_or_default();
+                    attributes.push(text.split_whitespace().collect::<String>());
+                }
+            }
+            "line_comment" | "block_comment" => {}
+            _ => break,
+        }
+        current = sibling.prev_named_sibling();
+    }
+    attributes
+}
+
+fn rust_test<'a>(node: Node<'a>, source: &[u8]) -> Option<FoundTest<'a>> {
+    let kind = match node.kind() {
+        "function_item" => {
+            let is_test = rust_attributes(node, source)
+                .iter()
+                .any(|attr| attr == "test" || attr.ends_with("::test") || attr == "rstest");
+            if !is_test {
+                return None;
+            }
+            TestKind::Case
+        }
+        "mod_item" => {
+            node.child_by_field_name("body")?;
+            let is_test_mod = rust_attributes(node, source)
+                .iter()
+                .any(|attr| attr == "cfg(test)");
+            if !is_test_mod {
+                return None;
+            }
+            TestKind::Suite
+        }
+        _ => return None,
+    };
+
+    let name_node = node.child_by_field_name("name")?;
+    let name = name_node.utf8_text(source).ok()?;
+    Some(FoundTest {
+        kind,
+        name: name.to_string(),
+        node,
+        name_node,
+        body: node.child_by_field_name("body"),
+    })
+}
+
+// ── Python ──────────────────────────────────────────────────────────────
+
+fn python_test<'a>(node: Node<'a>, source: &[u8]) -> Option<FoundTest<'a>> {
+    let name_node = node.child_by_field_name("name")?;
+    let name = name_node.utf8_text(source).ok()?;
+    let kind = match node.kind() {
+        "class_definition" => {
+            let extends_test_case = node
+                .child_by_field_name("superclasses")
+                .and_then(|s| s.utf8_text(source).ok())
+                .map(|s| s.contains("TestCase"))
+                .unwrap_or(false);
+            if !extends_test_case && !name.starts_with("Test") {
+                return None;
+            }
+            TestKind::Suite
+        }
+        "function_definition" if name == "test" || name.starts_with("test_") => TestKind::Case,
+        _ => return None,
+    };
+
+    Some(FoundTest {
+        kind,
+        name: name.to_string(),
+        node,
+        name_node,
+        body: node.child_by_field_name("body"),
+    })
+}
+
+// ── JavaScript / TypeScript ─────────────────────────────────────────────
+
+fn js_test<'a>(node: Node<'a>, source: &[u8]) -> Option<FoundTest<'a>> {
+    if node.kind() != "call_expression" {
+        return None;
+    }
+
+    // `describe(...)`, `it(...)` and modifiers such as `it.only(...)`
+    let function = node.child_by_field_name("function")?;
+    let callee = match function.kind() {
+        "identifier" => function.utf8_text(source).ok()?,
+        "member_expression" => {
+            let modifier = function
+                .child_by_field_name("property")?
+                .utf8_text(source)
+                .ok()?;
+            if !JS_MODIFIERS.contains(&modifier) {
+                return None;
+            }
+            function
+                .child_by_field_name("object")?
+                .utf8_text(source)
+                .ok()?
+        }
+        _ => return None,
+    };
+
+    let kind = if JS_SUITE_FNS.contains(&callee) {
+        TestKind::Suite
+    } else if JS_CASE_FNS.contains(&callee) {
+        TestKind::Case
+    } else {
+        return None;
+    };
+
+    let arguments = node.child_by_field_name("arguments")?;
+    let title = arguments.named_child(0)?;
+    if !matches!(title.kind(), "string" | "template_string") {
+        return None;
+    }
+    let name = title
+        .utf8_text(source)
+        .ok()?
+        .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+        .to_string();
+
+    let body = arguments
+        .named_child(1)
+        .and_then(|callback| callback.child_by_field_name("body"));
+
+    Some(FoundTest {
+        kind,
+        name,
+        node,
+        name_node: title,
+        body,
+    })
+}
+
+// ── Call collection ─────────────────────────────────────────────────────
+
+/// Counts the names called below `node`, leaving out test framework calls
+pub(crate) fn collect_calls(node: Node, source: &[u8], calls: &mut BTreeMap<String, usize>) {
+    match node.kind() {
+        "call_expression" | "call" => {
+            let function = node.child_by_field_name("function");
+            if let Some(name) = function
+                .filter(|f| !is_matcher(*f, source))
+                .and_then(|f| callee_name(f, source))
+            {
+                if !is_framework_call(&name) {
+                    *calls.entry(name).or_default() += 1;
+                }
+            }
+        }
+        // Macro arguments are not parsed as expressions, so look for
+        // `ident(...)` token pairs, e.g. `assert_eq!(read_file_content(p), ..)`.
+        "token_tree" => {
+            let mut cursor = node.walk();
+            let tokens: Vec<Node> = node.children(&mut cursor).collect();
+            for pair in tokens.windows(2) {
+                let is_call = pair[0].kind() == "identifier"
+                    && pair[1].kind() == "token_tree"
+                    && pair[1]
+                        .utf8_text(source)
+                        .unwrap_or_default()
+                        .starts_with('(');
+                if is_call {
+                    if let Ok(name) = pair[0].utf8_text(source) {
+                        *calls.entry(name.to_string()).or_default() += 1;
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect_calls(child, source, calls);
+    }
+}
+
+fn callee_name(function: Node, source: &[u8]) -> Option<String> {
+    let target = match function.kind() {
+        "identifier" => function,
+        "scoped_identifier" => function.child_by_field_name("name")?,
+        "field_expression" => function.child_by_field_name("field")?,
+        "member_expression" => function.child_by_field_name("property")?,
+        "attribute" => function.child_by_field_name("attribute")?,
+        "generic_function" => {
+            return callee_name(function.child_by_field_name("function")?, source)
+        }
+        _ => return None,
+    };
+    Some(target.utf8_text(source).ok()?.to_string())
+}
+
+/// Whether `function` hangs off a framework call, e.g. the `toBe` of
+/// `expect(x).not.toBe(y)`
+fn is_matcher(function: Node, source: &[u8]) -> bool {
+    let mut node = function;
+    while let Some(inner) = match node.kind() {
+        "member_expression" => node.child_by_field_name("object"),
+        "call_expression" => node.child_by_field_name("function"),
+        _ => None,
+    } {
+        node = inner;
+    }
+    node != function
+        && node.kind() == "identifier"
+        && node.utf8_text(source).is_ok_and(is_framework_call)
+}
+
+fn is_framework_call(name: &str) -> bool {
+    FRAMEWORK_CALLS.contains(&name) || name.starts_with("assert")
+}
+
+// ── Linking ─────────────────────────────────────────────────────────────
+
+/// Function/Method nodes by their plain name
+pub(crate) fn callables_by_name(nodes: &[CodeEntity]) -> HashMap<&str, Vec<&CodeEntity>> {
+    let mut by_name: HashMap<&str, Vec<&CodeEntity>> = HashMap::new();
+    for node in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::Function | EntityType::Method))
+    {
+        if let Some(name) = node.properties.get("name") {
+            by_name.entry(name.as_str()).or_default().push(node);
+        }
+    }
+    by_name
+}
+
+/// Definitions a call from `caller` resolves to, with the confidence of each:
+/// a definition in the caller's own file wins; otherwise every definition
+/// with the called name is a candidate.
+pub(crate) fn resolve_call<'a>(
+    by_name: &HashMap<&str, Vec<&'a CodeEntity>>,
+    caller: &CodeEntity,
+    call: &str,
+) -> (Vec<&'a CodeEntity>, String) {
+    let Some(candidates) = by_name.get(call) else {
+        return (Vec::new(), String::new());
+    };
+    let local: Vec<&CodeEntity> = candidates
+        .iter()
+        .copied()
+        .filter(|c| c.path == caller.path)
+        .collect();
+    let targets = if local.is_empty() {
+        candidates.clone()
+    } else {
+        local
+    };
+    // Name matching can't tell several remote definitions apart
+    let confidence = format!("{:.2}", 1.0 / targets.len() as f64);
+    (targets, confidence)
+}
+
+/// Resolves the calls recorded on test cases to Function/Method nodes and
+/// returns `TESTS` links. A definition in the test's own file wins; otherwise
+/// every definition with the called name is linked.
+pub fn link_tests(nodes: &[CodeEntity]) -> Vec<LinkEntity> {
+    let by_name = callables_by_name(nodes);
+
+    let mut links = Vec::new();
+    for test in nodes
+        .iter()
+        .filter(|n| matches!(n.entity_type, EntityType::Test))
+    {
+        let Some(calls) = test.properties.get(CALLS_PROPERTY) else {
+            continue;
+        };
+        for call in calls.split(',').filter(|c| !c.is_empty()) {
+            let (targets, confidence) = resolve_call(&by_name, test, call);
+            for target in targets {
+                links.push(LinkEntity {
+                    from_name: test.id.clone(),
+                    to_name: target.id.clone(),
+                    link_type: LinkType::Tests,
+                    properties: HashMap::from([(
+                        LINK_CONFIDENCE_PROPERTY.to_string(),
+                        confidence.clone(),
+                    )]),
+                });
+            }
+        }
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    fn discover(source: &str, language: CodeLanguage) -> Vec<CodeEntity> {
+        let mut parser = TSParser::new();
+        let lang = match language {
+            CodeLanguage::Rust => tree_sitter_rust::language(),
+            CodeLanguage::Python => tree_sitter_python::language(),
+            _ => tree_sitter_typescript::language_typescript(),
+        };
+        parser.set_language(lang).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        discover_tests(
+            tree.root_node(),
+            source.as_bytes(),
+            "file",
+            &language,
+            HashMode::Normalized,
+        )
+    }
+
+    #[test]
+    fn test_rust_tests_record_calls_inside_macros() {
+        let source = r#"
+            #[cfg(test)]
+            mod tests {
+                #[test]
+                fn test_read() {
+                    let content = read_file_content(path()).unwrap();
+                    assert!(content.is_empty());
+                    assert_eq!(helper(1), 2);
+                }
+            }
+        "#;
+        let tests = discover(source, CodeLanguage::Rust);
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].properties["kind"], "suite");
+        assert_eq!(tests[1].properties["qualified_name"], "tests::test_read");
+        let calls = &tests[1].properties[CALLS_PROPERTY];
+        assert!(calls.contains("read_file_content"));
+        assert!(calls.contains("helper"));
+    }
+
+    #[test]
+    fn test_js_and_python_tests() {
+        let js = discover(
+            "describe('math', () => { it('adds', () => { expect(add(1, 2)).toBe(3); }); });",
+            CodeLanguage::TypeScript,
+        );
+        assert_eq!(js.len(), 2);
+        assert_eq!(js[1].properties[CALLS_PROPERTY], "add");
+
+        let py = discover(
+            "class MathTest(unittest.TestCase):\n    def test_add(self):\n        self.assertEqual(add(1, 2), 3)\n",
+            CodeLanguage::Python,
+        );
+        assert_eq!(py.len(), 2);
+        assert_eq!(py[1].properties[CALLS_PROPERTY], "add");
+        let name_span = py[1].name_span.unwrap();
+        assert_eq!((name_span.start_line, name_span.start_column), (2, 8));
+        assert_eq!(name_span.end_byte - name_span.start_byte, "test_add".len());
+    }
+
+    #[test]
+    fn test_link_prefers_local_definition() {
+        let entities = [
+            CodeEntity::fixture("a.rs::add", "a.rs", EntityType::Function).with_name("add"),
+            CodeEntity::fixture("b.rs::add", "b.rs", EntityType::Function).with_name("add"),
+            CodeEntity::fixture("a.rs::test:t", "a.rs", EntityType::Test)
+                .with_property(CALLS_PROPERTY, "add"),
+        ];
+        let links = link_tests(&entities);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].to_name, "a.rs::add");
+        assert_eq!(links[0].properties[LINK_CONFIDENCE_PROPERTY], "1.00");
+    }
+
+    #[test]
+    fn test_python_test_functions_are_not_exported() {
+        let source = "def add(a, b):\n    return a + b\n\ndef test_add():\n    assert add(1, 2) == 3\n\ndef testing_helper():\n    pass\n";
+        let mut functions: Vec<CodeEntity> = [("add", 1), ("test_add", 4), ("testing_helper", 7)]
+            .into_iter()
+            .map(|(name, line)| {
+                CodeEntity::fixture(&format!("file::{}", name), "file", EntityType::Function)
+                    .with_name(name)
+                    .with_property("exported", "true")
+                    .with_lines(line, line + 1)
+            })
+            .collect();
+
+        let tests = discover(source, CodeLanguage::Python);
+        assert_eq!(tests.len(), 1);
+        unexport_test_functions(&mut functions, &tests);
+        assert_eq!(functions[0].properties["exported"], "true");
+        assert_eq!(functions[1].properties["exported"], "false");
+        assert_eq!(functions[2].properties["exported"], "true");
+    }
+}
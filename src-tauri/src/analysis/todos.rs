@@ -0,0 +1,361 @@
+//! Inventory of TODO/FIXME/HACK/XXX markers found in comments.
+//!
+//! A marker may carry an owner (`TODO(alice): ...` or `TODO @alice ...`) and
+//! the text may reference an issue (`#123` or `PEAR-42`). Each hit becomes a
+//! `Todo` entity whose `in` property names the innermost enclosing entity.
+
+use crate::analysis::hashing::CONTENT_HASH_PROPERTY;
+use crate::env_utils;
+use crate::models::{CodeEntity, CodeLanguage, EntityType, SourceSpan};
+use std::collections::HashMap;
+use tree_sitter::Node;
+
+/// Property holding the id of the entity a TODO belongs to
+pub const IN_PROPERTY: &str = "in";
+
+const DEFAULT_MARKERS: &[&str] = &["TODO", "FIXME", "HACK", "XXX"];
+
+/// Which comment markers are collected
+#[derive(Clone, Debug)]
+pub struct TodoConfig {
+    pub markers: Vec<String>,
+}
+
+impl Default for TodoConfig {
+    fn default() -> Self {
+        TodoConfig {
+            markers: DEFAULT_MARKERS.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+}
+
+impl TodoConfig {
+    /// Reads a comma-separated marker list from `PEAR_TODO_MARKERS`, falling
+    /// back to the defaults.
+    pub fn from_env() -> Self {
+        match env_utils::get("PEAR_TODO_MARKERS") {
+            Some(markers) => Self::from_markers(&markers),
+            None => Self::default(),
+        }
+    }
+
+    /// Parses a comma-separated marker list, falling back to the defaults
+    /// when it names no markers.
+    pub fn from_markers(markers: &str) -> Self {
+        let markers: Vec<String> = markers
+            .split(',')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect();
+        if markers.is_empty() {
+            Self::default()
+        } else {
+            TodoConfig { markers }
+        }
+    }
+}
+
+/// A marker parsed out of a single comment line
+#[derive(Debug, PartialEq)]
+struct TodoMatch {
+    marker: String,
+    owner: Option<String>,
+    issue: Option<String>,
+    text: String,
+    column: usize,
+}
+
+/// Scans every comment in the tree and returns `Todo` entities attached to
+/// the innermost of `entities` that encloses them.
+pub fn scan_todos(
+    root: Node,
+    source: &[u8],
+    path: &str,
+    language: &CodeLanguage,
+    config: &TodoConfig,
+    entities: &[CodeEntity],
+) -> Vec<CodeEntity> {
+    let mut comments = Vec::new();
+    collect_comments(root, &mut comments);
+
+    let mut todos = Vec::new();
+    for comment in comments {
+        let text = comment.utf8_text(source).unwrap_or_default();
+        for (offset, line) in text.lines().enumerate() {
+            let Some(found) = match_marker(line, &config.markers) else {
+                continue;
+            };
+            let line_number = comment.start_position().row + offset + 1;
+
+            let mut properties = HashMap::new();
+            properties.insert("name".to_string(), found.marker.clone());
+            properties.insert("marker".to_string(), found.marker.clone());
+            properties.insert("text".to_string(), found.text.clone());
+            properties.insert("language".to_string(), language.to_string());
+            properties.insert(
+                CONTENT_HASH_PROPERTY.to_string(),
+                blake3::hash(line.trim().as_bytes()).to_hex().to_string(),
+            );
+            if let Some(owner) = found.owner {
+                properties.insert("owner".to_string(), owner);
+            }
+            if let Some(issue) = found.issue {
+                properties.insert("issue".to_string(), issue);
+            }
+            if let Some(enclosing) = enclosing_entity(entities, line_number) {
+                properties.insert(IN_PROPERTY.to_string(), enclosing.id.clone());
+            }
+
+            // The span runs from the marker to the end of the line, the name
+            // span covers the marker itself
+            let line_start =
+                comment.start_byte() + (line.as_ptr() as usize - text.as_ptr() as usize);
+            let line_column = if offset == 0 {
+                comment.start_position().column
+            } else {
+                0
+            };
+            let marker_span = |len: usize| SourceSpan {
+                start_line: line_number,
+                start_column: line_column + found.column - 1,
+                end_line: line_number,
+                end_column: line_column + found.column - 1 + len,
+                start_byte: line_start + found.column - 1,
+                end_byte: line_start + found.column - 1 + len,
+            };
+            let span = marker_span(line.len() - (found.column - 1));
+            let name_span = marker_span(found.marker.len());
+
+            todos.push(CodeEntity {
+                id: format!("{}::todo:{}:{}", path, line_number, found.column),
+                path: path.to_string(),
+                entity_type: EntityType::Todo,
+                start_line: Some(line_number),
+                end_line: Some(line_number),
+                span: Some(span),
+                name_span: Some(name_span),
+                properties,
+                children: None,
+            });
+        }
+    }
+    todos
+}
+
+fn collect_comments<'a>(node: Node<'a>, comments: &mut Vec<Node<'a>>) {
+    if node.kind().contains("comment") {
+        comments.push(node);
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_comments(child, comments);
+    }
+}
+
+/// Smallest declaration whose line span contains `line`
+fn enclosing_entity(entities: &[CodeEntity], line: usize) -> Option<&CodeEntity> {
+    entities
+        .iter()
+        .filter(|e| {
+            matches!(
+                e.entity_type,
+                EntityType::Function
+                    | EntityType::Method
+                    | EntityType::Class
+                    | EntityType::Interface
+                    | EntityType::Test
+            )
+        })
+        .filter(|e| {
+            e.start_line.map(|s| s <= line).unwrap_or(false)
+                && e.end_line.map(|end| line <= end).unwrap_or(false)
+        })
+        .min_by_key(|e| e.end_line.unwrap_or(0) - e.start_line.unwrap_or(0))
+}
+
+fn match_marker(line: &str, markers: &[String]) -> Option<TodoMatch> {
+    let (column, marker) = markers
+        .iter()
+        .filter_map(|marker| find_word(line, marker).map(|col| (col, marker)))
+        .min_by_key(|(col, _)| *col)?;
+
+    let mut rest = &line[column + marker.len()..];
+    let mut owner = None;
+
+    // `TODO(owner)` form
+    if let Some(stripped) = rest.strip_prefix('(') {
+        if let Some(close) = stripped.find(')') {
+            let name = stripped[..close].trim();
+            if !name.is_empty() {
+                owner = Some(name.trim_start_matches('@').to_string());
+            }
+            rest = &stripped[close + 1..];
+        }
+    }
+
+    rest = rest.trim_start_matches(|c: char| c == ':' || c == '-' || c.is_whitespace());
+
+    // `TODO @owner text` form
+    if owner.is_none() {
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let end = stripped
+                .find(|c: char| c.is_whitespace() || c == ':')
+                .unwrap_or(stripped.len());
+            owner = Some(stripped[..end].to_string());
+            rest = stripped[end..]
+                .trim_start_matches(|c: char| c == ':' || c == '-' || c.is_whitespace());
+        }
+    }
+
+    let text = rest
+        .trim_end_matches(|c: char| c == '/' || c == '*' || c.is_whitespace())
+        .to_string();
+
+    Some(TodoMatch {
+        marker: marker.clone(),
+        owner,
+        issue: find_issue(&text),
+        text,
+        column: column + 1,
+    })
+}
+
+/// Byte offset of `word` in `line` where it is not part of a longer identifier
+fn find_word(line: &str, word: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut start = 0;
+    while let Some(found) = line[start..].find(word) {
+        let at = start + found;
+        let before = line[..at].chars().next_back();
+        let after = line[at + word.len()..].chars().next();
+        if !before.map(is_ident).unwrap_or(false) && !after.map(is_ident).unwrap_or(false) {
+            return Some(at);
+        }
+        start = at + word.len();
+    }
+    None
+}
+
+/// Finds `#123` or tracker keys such as `PEAR-42` in the text
+fn find_issue(text: &str) -> Option<String> {
+    text.split(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '(' | ')' | '[' | ']'))
+        .map(|token| token.trim_end_matches(['.', ':']))
+        .find(|token| {
+            if let Some(number) = token.strip_prefix('#') {
+                return !number.is_empty() && number.chars().all(|c| c.is_ascii_digit());
+            }
+            match token.rsplit_once('-') {
+                Some((key, number)) => {
+                    key.len() >= 2
+                        && key.starts_with(|c: char| c.is_ascii_uppercase())
+                        && key
+                            .chars()
+                            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                        && !number.is_empty()
+                        && number.chars().all(|c| c.is_ascii_digit())
+                }
+                None => false,
+            }
+        })
+        .map(|token| token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree_sitter::Parser as TSParser;
+
+    fn markers() -> Vec<String> {
+        TodoConfig::default().markers
+    }
+
+    #[test]
+    fn test_marker_must_be_a_whole_word_in_upper_case() {
+        assert!(match_marker("// TODOS are done", &markers()).is_none());
+        assert!(match_marker("// see MY_TODO list", &markers()).is_none());
+        assert!(match_marker("// todo: lower case", &markers()).is_none());
+
+        let found = match_marker("// TODO: fix this", &markers()).unwrap();
+        assert_eq!(found.marker, "TODO");
+        assert_eq!(found.text, "fix this");
+        assert_eq!(found.column, 4);
+        // The earliest marker on the line wins
+        let found = match_marker("/* XXX then TODO */", &markers()).unwrap();
+        assert_eq!(found.marker, "XXX");
+        assert_eq!(found.text, "then TODO");
+    }
+
+    #[test]
+    fn test_owner_forms() {
+        let found = match_marker("// TODO(alice): handle #12", &markers()).unwrap();
+        assert_eq!(found.owner.as_deref(), Some("alice"));
+        assert_eq!(found.text, "handle #12");
+        assert_eq!(found.issue.as_deref(), Some("#12"));
+
+        let found = match_marker("# FIXME(@bob) flaky", &markers()).unwrap();
+        assert_eq!(found.owner.as_deref(), Some("bob"));
+
+        let found = match_marker("# HACK @carol: PEAR-42 retry", &markers()).unwrap();
+        assert_eq!(found.owner.as_deref(), Some("carol"));
+        assert_eq!(found.text, "PEAR-42 retry");
+        assert_eq!(found.issue.as_deref(), Some("PEAR-42"));
+
+        let found = match_marker("// TODO() nobody", &markers()).unwrap();
+        assert_eq!(found.owner, None);
+        assert_eq!(found.text, "nobody");
+    }
+
+    #[test]
+    fn test_find_issue() {
+        assert_eq!(find_issue("fixed in #123.").as_deref(), Some("#123"));
+        assert_eq!(find_issue("see (JIRA-7)").as_deref(), Some("JIRA-7"));
+        assert_eq!(
+            find_issue("blocked by A2-9, later").as_deref(),
+            Some("A2-9")
+        );
+        assert_eq!(find_issue("#abc or # or #"), None);
+        assert_eq!(find_issue("pear-42 and X-1 and UTF-8x"), None);
+    }
+
+    #[test]
+    fn test_custom_markers() {
+        let config = TodoConfig::from_markers(" NOTE, ,REVIEW ");
+        let fallback = TodoConfig::from_markers(" , ");
+
+        assert_eq!(config.markers, ["NOTE", "REVIEW"]);
+        assert!(match_marker("// REVIEW: naming", &config.markers).is_some());
+        assert!(match_marker("// TODO: naming", &config.markers).is_none());
+        assert_eq!(fallback.markers, markers());
+    }
+
+    #[test]
+    fn test_todos_attach_to_innermost_entity() {
+        let source = "impl Shape {\n    fn area(&self) {\n        // TODO: cache\n    }\n    // HACK(dan): later\n}\n// FIXME top level\n";
+        let mut parser = TSParser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let entities = [
+            CodeEntity::fixture("file::Shape", "file", EntityType::Class).with_lines(1, 6),
+            CodeEntity::fixture("file::Shape::area", "file", EntityType::Method).with_lines(2, 4),
+        ];
+
+        let todos = scan_todos(
+            tree.root_node(),
+            source.as_bytes(),
+            "file",
+            &CodeLanguage::Rust,
+            &TodoConfig::default(),
+            &entities,
+        );
+        assert_eq!(todos.len(), 3);
+        assert_eq!(todos[0].properties[IN_PROPERTY], "file::Shape::area");
+        assert_eq!(todos[0].id, "file::todo:3:4");
+        let name_span = todos[0].name_span.unwrap();
+        assert_eq!((name_span.start_column, name_span.end_column), (11, 15));
+        assert_eq!(&source[name_span.start_byte..name_span.end_byte], "TODO");
+        assert_eq!(todos[1].properties[IN_PROPERTY], "file::Shape");
+        assert_eq!(todos[1].properties["owner"], "dan");
+        assert!(!todos[2].properties.contains_key(IN_PROPERTY));
+    }
+}
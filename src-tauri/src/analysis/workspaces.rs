@@ -0,0 +1,622 @@
+//! Package and workspace detection for monorepos.
+//!
+//! Every `package.json`, `Cargo.toml` with a `[package]` and `pyproject.toml`
+//! with a `[project]` below the walked directory is a package root; a nested
+//! root (e.g. `src-tauri` inside a JS app) owns its own subtree. Workspace
+//! members come from `package.json` `workspaces` (npm, yarn, bun) and Cargo
+//! `[workspace] members`.
+//!
+//! Imports naming a local package resolve to files in that package instead of
+//! becoming `ExternalLibrary` nodes.
+
+use crate::analysis::import_cycles::{self, TYPE_ONLY_PROPERTY};
+use crate::analysis::rust_semantics::normalize_path;
+use crate::models::{
+    CodeEntity, CodeLanguage, EntityType, LinkEntity, LinkType, LINK_LINE_PROPERTY,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const SKIPPED_DIRS: &[&str] = &[
+    ".git",
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    "__pycache__",
+    ".venv",
+    "venv",
+];
+
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+/// Conventional entry points tried when a package.json doesn't name one
+const JS_ENTRY_FILES: &[&str] = &["src/index", "index", "src/main", "src/lib"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ecosystem {
+    Npm,
+    Cargo,
+    Python,
+}
+
+impl Ecosystem {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Ecosystem::Npm => "npm",
+            Ecosystem::Cargo => "cargo",
+            Ecosystem::Python => "pypi",
+        }
+    }
+}
+
+/// A package root found on disk
+#[derive(Clone, Debug)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    pub ecosystem: Ecosystem,
+    pub manifest: PathBuf,
+    pub root: PathBuf,
+    /// Entry file declared by the manifest (`main`, `module`, `exports`...)
+    pub entry: Option<PathBuf>,
+    /// Member roots declared by a workspace manifest
+    pub members: Vec<PathBuf>,
+}
+
+impl PackageInfo {
+    pub fn id(&self) -> String {
+        format!("{}::package", self.manifest.to_string_lossy())
+    }
+}
+
+/// All packages below a directory
+#[derive(Clone, Debug, Default)]
+pub struct Workspace {
+    pub packages: Vec<PackageInfo>,
+}
+
+impl Workspace {
+    pub fn discover(directory: &Path) -> Self {
+        let mut packages = Vec::new();
+        let mut pending = vec![directory.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            packages.extend(read_package_json(&dir));
+            packages.extend(read_cargo_toml(&dir));
+            packages.extend(read_pyproject(&dir));
+
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let skipped = path
+                    .file_name()
+                    .map(|n| SKIPPED_DIRS.contains(&n.to_string_lossy().as_ref()))
+                    .unwrap_or(true);
+                if path.is_dir() && !skipped {
+                    pending.push(path);
+                }
+            }
+        }
+        packages.sort_by(|a, b| a.manifest.cmp(&b.manifest));
+        Workspace { packages }
+    }
+
+    /// The innermost package whose root contains `path`. When one directory
+    /// holds several manifests, the first by name (`Cargo.toml`) wins.
+    pub fn owner(&self, path: &Path) -> Option<&PackageInfo> {
+        self.packages
+            .iter()
+            .rev()
+            .filter(|p| path.starts_with(&p.root))
+            .max_by_key(|p| p.root.components().count())
+    }
+
+    fn find(&self, ecosystem: Ecosystem, name: &str) -> Option<&PackageInfo> {
+        self.packages
+            .iter()
+            .find(|p| p.ecosystem == ecosystem && p.name == name)
+    }
+
+    /// `Package` nodes for every discovered package
+    pub fn package_nodes(&self) -> Vec<CodeEntity> {
+        self.packages
+            .iter()
+            .map(|package| {
+                let mut properties = HashMap::new();
+                properties.insert("name".to_string(), package.name.clone());
+                properties.insert("version".to_string(), package.version.clone());
+                properties.insert(
+                    "ecosystem".to_string(),
+                    package.ecosystem.as_str().to_string(),
+                );
+                properties.insert(
+                    "manifest".to_string(),
+                    package.manifest.to_string_lossy().to_string(),
+                );
+                properties.insert(
+                    "root".to_string(),
+                    package.root.to_string_lossy().to_string(),
+                );
+                if let Some(workspace) = self.workspace_of(package) {
+                    properties.insert("workspace".to_string(), workspace.name.clone());
+                }
+                CodeEntity {
+                    id: package.id(),
+                    path: package.manifest.to_string_lossy().to_string(),
+                    entity_type: EntityType::Package,
+                    start_line: None,
+                    end_line: None,
+                    span: None,
+                    name_span: None,
+                    properties,
+                    children: None,
+                }
+            })
+            .collect()
+    }
+
+    fn workspace_of(&self, package: &PackageInfo) -> Option<&PackageInfo> {
+        self.packages.iter().find(|w| {
+            w.ecosystem == package.ecosystem
+                && w.manifest != package.manifest
+                && w.members.contains(&package.root)
+        })
+    }
+
+    /// Edges from each Directory and File to its owning package, from
+    /// workspaces to their members and from Cargo packages to their crates
+    pub fn link_packages(&self, nodes: &[CodeEntity]) -> Vec<LinkEntity> {
+        let mut links = Vec::new();
+        for node in nodes {
+            match node.entity_type {
+                EntityType::Directory | EntityType::File => {
+                    if let Some(owner) = self.owner(Path::new(&node.path)) {
+                        links.push(LinkEntity {
+                            from_name: node.id.clone(),
+                            to_name: owner.id(),
+                            link_type: LinkType::BelongsTo,
+                            properties: HashMap::new(),
+                        });
+                    }
+                }
+                EntityType::Crate => {
+                    if let Some(package) = self
+                        .packages
+                        .iter()
+                        .find(|p| p.manifest.to_string_lossy() == node.path)
+                    {
+                        links.push(LinkEntity {
+                            from_name: package.id(),
+                            to_name: node.id.clone(),
+                            link_type: LinkType::Has,
+                            properties: HashMap::new(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for package in &self.packages {
+            if let Some(workspace) = self.workspace_of(package) {
+                links.push(LinkEntity {
+                    from_name: workspace.id(),
+                    to_name: package.id(),
+                    link_type: LinkType::Has,
+                    properties: HashMap::new(),
+                });
+            }
+        }
+        links
+    }
+
+    /// Resolves JS/TS and Rust imports to files: relative specifiers and local
+    /// packages become file-to-file `IMPORTS` edges, other packages
+    /// `ExternalLibrary` nodes.
+    pub fn resolve_imports(&self, nodes: &[CodeEntity]) -> (Vec<CodeEntity>, Vec<LinkEntity>) {
+        let files: HashSet<&str> = nodes
+            .iter()
+            .filter(|n| matches!(n.entity_type, EntityType::File))
+            .map(|n| n.path.as_str())
+            .collect();
+        let crate_roots: HashMap<&str, &str> = nodes
+            .iter()
+            .filter(|n| matches!(n.entity_type, EntityType::Crate))
+            .filter(|n| {
+                n.properties
+                    .get("kind")
+                    .map(|k| k == "lib")
+                    .unwrap_or(false)
+            })
+            .filter_map(|n| {
+                Some((
+                    n.properties.get("name")?.as_str(),
+                    n.properties.get("root")?.as_str(),
+                ))
+            })
+            .collect();
+
+        let mut externals: HashMap<String, CodeEntity> = HashMap::new();
+        let mut seen = HashMap::new();
+        let mut links = Vec::new();
+
+        for import in nodes
+            .iter()
+            .filter(|n| matches!(n.entity_type, EntityType::Import))
+        {
+            let Some(spec) = import.properties.get("name") else {
+                continue;
+            };
+            let language = import.properties.get("language").map(|l| l.as_str());
+            let target = match language {
+                Some("rust") => rust_target(spec, &crate_roots),
+                Some("javascript" | "jsx" | "typescript" | "tsx") => {
+                    self.js_target(spec, Path::new(&import.path), &files)
+                }
+                _ => continue,
+            };
+
+            let to_name = match target {
+                Target::File(path) => path,
+                Target::External(ecosystem, name) => {
+                    let id = format!("external:{}:{}", ecosystem.as_str(), name);
+                    externals.entry(id.clone()).or_insert_with(|| {
+                        external_library(&id, &name, ecosystem, language.unwrap_or_default())
+                    });
+                    id
+                }
+                Target::Unresolved => continue,
+            };
+            if to_name != import.path {
+                let mut properties = HashMap::new();
+                if let Some(line) = import.start_line {
+                    properties.insert(LINK_LINE_PROPERTY.to_string(), line.to_string());
+                }
+                if let Some(type_only) = import.properties.get(TYPE_ONLY_PROPERTY) {
+                    properties.insert(TYPE_ONLY_PROPERTY.to_string(), type_only.clone());
+                }
+                let link = LinkEntity {
+                    from_name: import.path.clone(),
+                    to_name,
+                    link_type: LinkType::Import,
+                    properties,
+                };
+                import_cycles::merge_import_link(&mut links, &mut seen, link);
+            }
+        }
+
+        (externals.into_values().collect(), links)
+    }
+
+    fn js_target(&self, spec: &str, importer: &Path, files: &HashSet<&str>) -> Target {
+        if spec.starts_with('.') {
+            let base = importer.parent().unwrap_or(Path::new("")).join(spec);
+            return find_js_file(&base, files)
+                .map(Target::File)
+                .unwrap_or(Target::Unresolved);
+        }
+        // Path aliases (`@/lib/x`, `~/x`) are project-specific
+        if spec.starts_with("@/") || spec.starts_with("~/") || spec.starts_with('/') {
+            return Target::Unresolved;
+        }
+
+        let spec = spec.strip_prefix("node:").unwrap_or(spec);
+        let mut segments = spec.splitn(3, '/');
+        let name = match (segments.next(), spec.starts_with('@')) {
+            (Some(scope), true) => match segments.next() {
+                Some(package) => format!("{}/{}", scope, package),
+                None => scope.to_string(),
+            },
+            (Some(package), false) => package.to_string(),
+            (None, _) => return Target::Unresolved,
+        };
+        let subpath = spec[name.len()..].trim_start_matches('/');
+
+        let Some(package) = self.find(Ecosystem::Npm, &name) else {
+            return Target::External(Ecosystem::Npm, name);
+        };
+        let found = if subpath.is_empty() {
+            package
+                .entry
+                .as_ref()
+                .and_then(|entry| find_js_file(entry, files))
+                .or_else(|| {
+                    JS_ENTRY_FILES
+                        .iter()
+                        .find_map(|entry| find_js_file(&package.root.join(entry), files))
+                })
+        } else {
+            find_js_file(&package.root.join(subpath), files)
+                .or_else(|| find_js_file(&package.root.join("src").join(subpath), files))
+        };
+        found.map(Target::File).unwrap_or(Target::Unresolved)
+    }
+}
+
+enum Target {
+    File(String),
+    External(Ecosystem, String),
+    Unresolved,
+}
+
+/// `crate::`, `self::` and `super::` paths stay inside the crate (see the
+/// module tree); a workspace crate resolves to its root file.
+fn rust_target(spec: &str, crate_roots: &HashMap<&str, &str>) -> Target {
+    let first = spec
+        .trim_start_matches("::")
+        .split(|c: char| c == ':' || c == '{' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+    match first {
+        "" | "crate" | "self" | "super" | "Self" => Target::Unresolved,
+        name => match crate_roots.get(name) {
+            Some(root) => Target::File(normalize_path(Path::new(root))),
+            None => Target::External(Ecosystem::Cargo, name.to_string()),
+        },
+    }
+}
+
+/// Tries `base` as written, with each JS/TS extension, as a directory index,
+/// and with a `.js` suffix swapped for its TypeScript source
+fn find_js_file(base: &Path, files: &HashSet<&str>) -> Option<String> {
+    let base_str = normalize_path(base);
+    let stem = base_str
+        .strip_suffix(".js")
+        .or_else(|| base_str.strip_suffix(".jsx"))
+        .unwrap_or(&base_str);
+
+    let mut candidates = vec![base_str.clone()];
+    for ext in JS_EXTENSIONS {
+        candidates.push(format!("{}.{}", stem, ext));
+    }
+    for ext in JS_EXTENSIONS {
+        candidates.push(
+            Path::new(&base_str)
+                .join(format!("index.{}", ext))
+                .to_string_lossy()
+                .to_string(),
+        );
+    }
+    candidates.into_iter().find(|c| files.contains(c.as_str()))
+}
+
+fn external_library(id: &str, name: &str, ecosystem: Ecosystem, language: &str) -> CodeEntity {
+    let mut properties = HashMap::new();
+    properties.insert("name".to_string(), name.to_string());
+    properties.insert("ecosystem".to_string(), ecosystem.as_str().to_string());
+    let language = match ecosystem {
+        Ecosystem::Cargo => CodeLanguage::Rust.to_string(),
+        _ => language.to_string(),
+    };
+    properties.insert("language".to_string(), language);
+    CodeEntity {
+        id: id.to_string(),
+        path: String::new(),
+        entity_type: EntityType::ExternalLibrary,
+        start_line: None,
+        end_line: None,
+        span: None,
+        name_span: None,
+        properties,
+        children: None,
+    }
+}
+
+fn read_package_json(dir: &Path) -> Option<PackageInfo> {
+    let manifest = dir.join("package.json");
+    let content = std::fs::read_to_string(&manifest).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&content).ok()?;
+
+    let entry = ["source", "module", "main", "types"]
+        .iter()
+        .find_map(|key| json.get(*key).and_then(|v| v.as_str()))
+        .or_else(|| match json.get("exports") {
+            Some(serde_json::Value::String(entry)) => Some(entry.as_str()),
+            Some(exports) => exports.get(".").and_then(|e| match e {
+                serde_json::Value::String(entry) => Some(entry.as_str()),
+                other => ["import", "default", "require"]
+                    .iter()
+                    .find_map(|k| other.get(*k).and_then(|v| v.as_str())),
+            }),
+            None => None,
+        })
+        .map(|entry| dir.join(entry));
+
+    // `"workspaces": [...]` or `"workspaces": { "packages": [...] }`
+    let patterns: Vec<&str> = match json.get("workspaces") {
+        Some(serde_json::Value::Array(patterns)) => {
+            patterns.iter().filter_map(|p| p.as_str()).collect()
+        }
+        Some(workspaces) => workspaces
+            .get("packages")
+            .and_then(|p| p.as_array())
+            .map(|p| p.iter().filter_map(|p| p.as_str()).collect())
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    Some(PackageInfo {
+        name: json
+            .get("name")
+            .and_then(|n| n.as_str())
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| dir_name(dir)),
+        version: json
+            .get("version")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        ecosystem: Ecosystem::Npm,
+        manifest,
+        root: dir.to_path_buf(),
+        entry,
+        members: expand_members(dir, &patterns),
+    })
+}
+
+fn read_cargo_toml(dir: &Path) -> Option<PackageInfo> {
+    let manifest = dir.join("Cargo.toml");
+    let content = std::fs::read_to_string(&manifest).ok()?;
+    let value: toml::Value = content.parse().ok()?;
+
+    let patterns: Vec<&str> = value
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(|m| m.as_array())
+        .map(|m| m.iter().filter_map(|p| p.as_str()).collect())
+        .unwrap_or_default();
+
+    let package = value.get("package");
+    // A virtual manifest (`[workspace]` only) is still a package root
+    if package.is_none() && value.get("workspace").is_none() {
+        return None;
+    }
+    let field = |key: &str| {
+        package
+            .and_then(|p| p.get(key))
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+    };
+
+    Some(PackageInfo {
+        name: field("name").unwrap_or_else(|| dir_name(dir)),
+        version: field("version").unwrap_or_default(),
+        ecosystem: Ecosystem::Cargo,
+        manifest,
+        root: dir.to_path_buf(),
+        entry: None,
+        members: expand_members(dir, &patterns),
+    })
+}
+
+fn read_pyproject(dir: &Path) -> Option<PackageInfo> {
+    let manifest = dir.join("pyproject.toml");
+    let content = std::fs::read_to_string(&manifest).ok()?;
+    let value: toml::Value = content.parse().ok()?;
+    let project = value
+        .get("project")
+        .or_else(|| value.get("tool").and_then(|t| t.get("poetry")))?;
+    let field = |key: &str| {
+        project
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+    };
+
+    Some(PackageInfo {
+        name: field("name").unwrap_or_else(|| dir_name(dir)),
+        version: field("version").unwrap_or_default(),
+        ecosystem: Ecosystem::Python,
+        manifest,
+        root: dir.to_path_buf(),
+        entry: None,
+        members: Vec::new(),
+    })
+}
+
+/// Expands workspace member globs (`packages/*`) to directories
+fn expand_members(dir: &Path, patterns: &[&str]) -> Vec<PathBuf> {
+    patterns
+        .iter()
+        .filter(|p| !p.starts_with('!'))
+        .flat_map(|pattern| {
+            let full = dir.join(pattern).to_string_lossy().to_string();
+            glob::glob(&full)
+                .map(|paths| paths.flatten().collect::<Vec<_>>())
+                .unwrap_or_default()
+        })
+        .filter(|p| p.is_dir())
+        .collect()
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn package(name: &str, root: &str) -> PackageInfo {
+        PackageInfo {
+            name: name.to_string(),
+            version: "0.1.0".to_string(),
+            ecosystem: Ecosystem::Npm,
+            manifest: Path::new(root).join("package.json"),
+            root: PathBuf::from(root),
+            entry: None,
+            members: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_js_imports_resolve_to_workspace_members() {
+        let workspace = Workspace {
+            packages: vec![
+                package("app", "/repo"),
+                package("@acme/ui", "/repo/packages/ui"),
+            ],
+        };
+        let files: HashSet<&str> = [
+            "/repo/src/main.tsx",
+            "/repo/src/lib/types.ts",
+            "/repo/packages/ui/src/index.ts",
+            "/repo/packages/ui/src/button.tsx",
+        ]
+        .into_iter()
+        .collect();
+        let importer = Path::new("/repo/src/main.tsx");
+
+        let resolved = |spec: &str| match workspace.js_target(spec, importer, &files) {
+            Target::File(path) => path,
+            Target::External(_, name) => format!("external:{}", name),
+            Target::Unresolved => "unresolved".to_string(),
+        };
+
+        assert_eq!(resolved("./lib/types"), "/repo/src/lib/types.ts");
+        assert_eq!(resolved("@acme/ui"), "/repo/packages/ui/src/index.ts");
+        assert_eq!(
+            resolved("@acme/ui/button"),
+            "/repo/packages/ui/src/button.tsx"
+        );
+        assert_eq!(
+            resolved("@radix-ui/react-tabs"),
+            "external:@radix-ui/react-tabs"
+        );
+        assert_eq!(resolved("react-dom/client"), "external:react-dom");
+        assert_eq!(resolved("@/lib/utils"), "unresolved");
+        assert_eq!(
+            workspace
+                .owner(Path::new("/repo/packages/ui/src/x.ts"))
+                .map(|p| p.name.as_str()),
+            Some("@acme/ui")
+        );
+    }
+
+    #[test]
+    fn test_first_manifest_in_a_directory_owns_it() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"core\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        fs::write(root.join("package.json"), r#"{"name": "web"}"#).unwrap();
+        fs::write(root.join("pyproject.toml"), "[project]\nname = \"tools\"\n").unwrap();
+        fs::create_dir_all(root.join("packages/ui")).unwrap();
+        fs::write(root.join("packages/ui/package.json"), r#"{"name": "ui"}"#).unwrap();
+
+        let workspace = Workspace::discover(root);
+        assert_eq!(workspace.packages.len(), 4);
+        let owner = |path: &str| workspace.owner(&root.join(path)).map(|p| p.name.clone());
+        assert_eq!(owner("src/main.rs").as_deref(), Some("core"));
+        assert_eq!(owner("index.ts").as_deref(), Some("core"));
+        assert_eq!(owner("packages/ui/src/button.tsx").as_deref(), Some("ui"));
+    }
+}
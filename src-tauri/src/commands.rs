@@ -1,12 +1,22 @@
+use crate::analysis::clones::{CloneConfig, CloneGroup};
+use crate::analysis::import_cycles::{self, CycleGroup, ImportEdge};
 use crate::env_utils;
 use crate::error::Result;
-use crate::file_manager::{neo4j::NeoDB, AppState};
+use crate::file_manager::export::{self, ExportFormat, ExportSummary};
+use crate::file_manager::graph_store::{GraphBackend, GraphFilter, GraphHealth};
+use crate::file_manager::import::{self, ImportMode, ImportSummary};
+use crate::file_manager::memory_store::MemoryGraph;
+use crate::file_manager::neo4j::NeoDB;
+use crate::file_manager::profiles::{self, ConnectionProfile, ProfileList, ProfileView, Profiles};
+use crate::file_manager::queries::{self, CodeGraphData, EntityDetails, OutlineItem, SearchResult};
+use crate::file_manager::search::{self, SearchFilter, SearchHit};
+use crate::file_manager::AppState;
+use crate::models::{EntityReference, EntityType, LinkType, Repository, TodoItem};
 use crate::parser::Parser;
 
-use tauri::{AppHandle, Emitter, Manager};
-use uuid::Uuid;
+use tauri::{AppHandle, Emitter, Manager, State};
 
-/// Parse a codebase and ingest it into Neo4j
+/// Parse a codebase and ingest it into the graph store
 #[tauri::command]
 pub async fn parse_and_ingest_codebase(app_handle: AppHandle, directory: String) -> Result<String> {
     // Emit event that parsing has started
@@ -14,19 +24,14 @@ pub async fn parse_and_ingest_codebase(app_handle: AppHandle, directory: String)
         .emit("parse_progress", "Starting codebase analysis")
         .unwrap();
     env_utils::init()?;
-    let uri = env_utils::get_required("NEO4J_URI")?;
-    let username = env_utils::get_required("NEO4J_USER")?;
-    let password = env_utils::get_required("NEO4J_PASSWORD")?;
-
-    // Create Neo4j connection
-    let neo_db = NeoDB::new_simple(uri, username, password).await?;
+    let graph = app_handle.state::<AppState>().graph_store().await?;
 
     // Create parser
     let mut parser = Parser::new();
 
     // Parse and ingest
     let (nodes, links) = parser
-        .parse_and_ingest_directory(&app_handle, &neo_db, &directory)
+        .parse_and_ingest_directory(&app_handle, graph.as_ref(), &directory)
         .await?;
 
     // Emit event that parsing is complete
@@ -41,26 +46,22 @@ pub async fn parse_and_ingest_codebase(app_handle: AppHandle, directory: String)
 }
 
 #[tauri::command]
-pub async fn track_repository(path: String, owner_id: String) -> Result<String> {
-    // Generate a unique repository ID
-    let repository_id = Uuid::new_v4().to_string();
+pub async fn track_repository(
+    state: State<'_, AppState>,
+    path: String,
+    owner_id: String,
+) -> Result<String> {
+    // The same checkout always gets the same repository ID
+    let repository_id = Repository::id_for(std::path::Path::new(&path));
 
-    // Initialize file tracker
     // Initialize environment
     env_utils::init()?;
 
-    // Get Neo4j connection details
-    let neo4j_updater = NeoDB::new(
-        &env_utils::get_required("NEO4J_URI")?,
-        &env_utils::get_required("NEO4J_USER")?,
-        &env_utils::get_required("NEO4J_PASSWORD")?,
-        repository_id.clone(),
-        owner_id,
-    )
-    .await?;
-
-    // Register repository in Neo4j
-    neo4j_updater.register_repository(&path).await?;
+    // Register repository in the graph
+    let graph = state.graph_store().await?;
+    graph
+        .register_repository(&path, &repository_id, &owner_id)
+        .await?;
 
     // Return the repository ID to the frontend
     Ok(repository_id)
@@ -70,13 +71,14 @@ pub async fn track_repository(path: String, owner_id: String) -> Result<String>
 pub async fn start_watching_directory(
     app_handle: tauri::AppHandle,
     directory: String,
+    owner_id: Option<String>,
 ) -> Result<String> {
     // Get app state
     let state = app_handle.state::<AppState>();
 
     // Start watching the directory
     state
-        .start_watching(std::path::PathBuf::from(directory))
+        .start_watching(std::path::PathBuf::from(directory), owner_id)
         .await?;
 
     Ok("Started watching directory".to_string())
@@ -92,3 +94,350 @@ pub async fn stop_watching_directory(app_handle: tauri::AppHandle) -> Result<Str
 
     Ok("Stopped watching directory".to_string())
 }
+
+/// Report whether the graph store can be reached, and for Neo4j the server
+/// version, database and schema version. Failures are reported, not raised.
+#[tauri::command]
+pub async fn graph_health(state: State<'_, AppState>) -> Result<GraphHealth> {
+    let backend = state.graph_backend();
+    let graph = match state.graph_store().await {
+        Ok(graph) => graph,
+        Err(e) => return Ok(GraphHealth::unreachable(backend, &e)),
+    };
+    Ok(graph
+        .health()
+        .await
+        .unwrap_or_else(|e| GraphHealth::unreachable(backend, &e)))
+}
+
+/// List the saved Neo4j connection profiles, without their passwords
+#[tauri::command]
+pub async fn list_connection_profiles(state: State<'_, AppState>) -> Result<ProfileList> {
+    env_utils::init()?;
+    Ok(Profiles::load(state.config_dir())?.list())
+}
+
+/// Save a connection profile, replacing the one of the same name. Without a
+/// password the saved one is kept, as long as the URI and user are unchanged.
+#[tauri::command]
+pub async fn create_connection_profile(
+    state: State<'_, AppState>,
+    profile: ConnectionProfile,
+) -> Result<ProfileView> {
+    let mut profiles = Profiles::load(state.config_dir())?;
+    let view = profiles.upsert(profile)?;
+    profiles.save(state.config_dir())?;
+    Ok(view)
+}
+
+/// Try to connect with a profile, saved or not, without migrating the
+/// database. Failures are reported, not raised.
+#[tauri::command]
+pub async fn test_connection_profile(
+    state: State<'_, AppState>,
+    profile: ConnectionProfile,
+) -> Result<GraphHealth> {
+    env_utils::init()?;
+    let profile = Profiles::load(state.config_dir())?.with_saved_password(profile);
+    let health = match profile.and_then(|p| p.settings()) {
+        Ok(settings) => NeoDB::probe(settings).await,
+        Err(e) => Err(e),
+    };
+    Ok(health.unwrap_or_else(|e| GraphHealth::unreachable(GraphBackend::Neo4j, &e)))
+}
+
+/// Make a saved profile the active one and connect with it. `NEO4J_URI` in
+/// the environment still takes precedence.
+#[tauri::command]
+pub async fn switch_connection_profile(
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<GraphHealth> {
+    env_utils::init()?;
+    let mut profiles = Profiles::load(state.config_dir())?;
+    profiles.activate(&name)?;
+    profiles.save(state.config_dir())?;
+    if profiles::env_override() {
+        log::warn!(
+            "NEO4J_URI is set; profile '{}' is used once it is removed",
+            name
+        );
+    }
+    state.reconnect(GraphBackend::Neo4j).await;
+    graph_health(state).await
+}
+
+/// Search the symbols of the repository at `directory` by name or path, best
+/// matches first. `types` are labels such as `Function` or `Class`.
+#[tauri::command]
+pub async fn find_symbols(
+    state: State<'_, AppState>,
+    directory: String,
+    query: String,
+    types: Option<Vec<String>>,
+    limit: Option<usize>,
+) -> Result<Vec<SearchResult>> {
+    let graph = state.graph_store().await?;
+    queries::find_symbols(
+        graph.as_ref(),
+        &Repository::id_for(std::path::Path::new(&directory)),
+        &query,
+        &types.unwrap_or_default(),
+        limit.unwrap_or(20),
+    )
+    .await
+}
+
+/// Full-text search over the names, signatures and doc comments of the
+/// repository at `directory`, best matches first, with the matching words
+/// highlighted
+#[tauri::command]
+pub async fn search_code(
+    state: State<'_, AppState>,
+    directory: String,
+    query: String,
+    filter: Option<SearchFilter>,
+    limit: Option<usize>,
+) -> Result<Vec<SearchHit>> {
+    let graph = state.graph_store().await?;
+    search::search_code(
+        graph.as_ref(),
+        &Repository::id_for(std::path::Path::new(&directory)),
+        &query,
+        &filter.unwrap_or_default(),
+        limit.unwrap_or(20),
+    )
+    .await
+}
+
+/// Look up one entity by id with all its properties
+#[tauri::command]
+pub async fn get_entity(
+    state: State<'_, AppState>,
+    directory: String,
+    id: String,
+) -> Result<Option<EntityDetails>> {
+    let graph = state.graph_store().await?;
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    queries::get_entity(graph.as_ref(), &repository_id, &id).await
+}
+
+/// List what calls an entity
+#[tauri::command]
+pub async fn get_callers(
+    state: State<'_, AppState>,
+    directory: String,
+    id: String,
+) -> Result<Vec<SearchResult>> {
+    let graph = state.graph_store().await?;
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    queries::get_callers(graph.as_ref(), &repository_id, &id).await
+}
+
+/// List what an entity calls
+#[tauri::command]
+pub async fn get_callees(
+    state: State<'_, AppState>,
+    directory: String,
+    id: String,
+) -> Result<Vec<SearchResult>> {
+    let graph = state.graph_store().await?;
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    queries::get_callees(graph.as_ref(), &repository_id, &id).await
+}
+
+/// Classes, functions and other entities declared in a file, nested
+#[tauri::command]
+pub async fn get_file_outline(
+    state: State<'_, AppState>,
+    directory: String,
+    path: String,
+) -> Result<Vec<OutlineItem>> {
+    let graph = state.graph_store().await?;
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    queries::get_file_outline(graph.as_ref(), &repository_id, &path).await
+}
+
+/// The graph around an entity, `depth` hops out (1 by default), optionally
+/// following only some relationship types
+#[tauri::command]
+pub async fn get_neighbors(
+    state: State<'_, AppState>,
+    directory: String,
+    id: String,
+    depth: Option<usize>,
+    rel_types: Option<Vec<String>>,
+) -> Result<CodeGraphData> {
+    let graph = state.graph_store().await?;
+    queries::get_neighbors(
+        graph.as_ref(),
+        &Repository::id_for(std::path::Path::new(&directory)),
+        &id,
+        depth.unwrap_or(1),
+        &rel_types.unwrap_or_default(),
+    )
+    .await
+}
+
+/// The graph of the repository at `directory`, or the part matching `filter`
+#[tauri::command]
+pub async fn get_project_graph(
+    state: State<'_, AppState>,
+    directory: String,
+    filter: Option<GraphFilter>,
+) -> Result<CodeGraphData> {
+    let graph = state.graph_store().await?;
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    queries::get_project_graph(graph.as_ref(), &repository_id, &filter.unwrap_or_default()).await
+}
+
+/// List the tests that exercise a function or method in the repository at
+/// `directory`; `name` is its entity id or qualified name
+#[tauri::command]
+pub async fn find_tests_for_function(
+    state: State<'_, AppState>,
+    directory: String,
+    name: String,
+) -> Result<Vec<EntityReference>> {
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    let graph = state.graph_store().await?;
+    graph.find_tests_for(&repository_id, &name).await
+}
+
+/// List exported functions and methods of the repository at `directory`
+/// without any test calling them
+#[tauri::command]
+pub async fn find_untested_functions(
+    state: State<'_, AppState>,
+    directory: String,
+) -> Result<Vec<EntityReference>> {
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    let graph = state.graph_store().await?;
+    graph.find_untested_functions(&repository_id).await
+}
+
+/// List TODO/FIXME/HACK comments of the repository at `directory`, filtered
+/// by marker, path prefix and owner
+#[tauri::command]
+pub async fn list_todos(
+    state: State<'_, AppState>,
+    directory: String,
+    marker: Option<String>,
+    path_prefix: Option<String>,
+    owner: Option<String>,
+) -> Result<Vec<TodoItem>> {
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    let graph = state.graph_store().await?;
+    graph
+        .list_todos(&repository_id, marker, path_prefix, owner)
+        .await
+}
+
+/// Group duplicated and near-duplicate functions and blocks under a directory.
+/// Thresholds default to `PEAR_CLONE_MIN_TOKENS` / `PEAR_CLONE_MIN_SIMILARITY`.
+#[tauri::command]
+pub async fn find_duplicate_code(
+    directory: String,
+    min_tokens: Option<usize>,
+    min_similarity: Option<f64>,
+) -> Result<Vec<CloneGroup>> {
+    env_utils::init()?;
+    let mut config = CloneConfig::from_env();
+    if let Some(min_tokens) = min_tokens {
+        config.min_tokens = min_tokens;
+    }
+    if let Some(min_similarity) = min_similarity {
+        config.min_similarity = min_similarity;
+    }
+
+    let mut parser = Parser::new();
+    parser.find_clones(&directory, &config).await
+}
+
+/// Find the files of the repository at `directory` that import each other,
+/// with the shortest loop and the import lines of each group, and mark them
+/// in the graph with `CycleGroup` nodes. Type-only imports count unless
+/// `ignore_type_only` is set.
+#[tauri::command]
+pub async fn find_import_cycles(
+    state: State<'_, AppState>,
+    directory: String,
+    ignore_type_only: Option<bool>,
+) -> Result<Vec<CycleGroup>> {
+    let graph = state.graph_store().await?;
+    let repository_id = Repository::id_for(std::path::Path::new(&directory));
+    let filter = GraphFilter {
+        labels: vec![EntityType::File.to_string()],
+        rel_types: vec![LinkType::Import.rel_type().to_string()],
+        path_prefix: None,
+    };
+    let (_, edges) = graph.subgraph(&repository_id, &filter).await?;
+    let imports: Vec<ImportEdge> = edges
+        .iter()
+        .flat_map(|e| ImportEdge::all(&e.from, &e.to, &e.properties))
+        .collect();
+
+    let groups = import_cycles::detect(&imports, ignore_type_only.unwrap_or(false));
+    graph.replace_cycle_groups(&repository_id, &groups).await?;
+    Ok(groups)
+}
+
+/// Write the graph of the repository at `directory`, optionally narrowed to
+/// some labels, relationship types or a path prefix, to `output_path` as
+/// GraphML, GEXF, DOT or JSON. The format defaults to the file extension.
+#[tauri::command]
+pub async fn export_graph(
+    state: State<'_, AppState>,
+    directory: String,
+    output_path: String,
+    format: Option<ExportFormat>,
+    filter: Option<GraphFilter>,
+) -> Result<ExportSummary> {
+    let graph = state.graph_store().await?;
+    export::export_graph(
+        graph.as_ref(),
+        &directory,
+        &filter.unwrap_or_default(),
+        format,
+        std::path::Path::new(&output_path),
+    )
+    .await
+}
+
+/// Load a JSON graph export into the graph store, replacing or merging into
+/// what is stored for its repository (merging by default). With `root` the
+/// exported paths are moved to that checkout.
+#[tauri::command]
+pub async fn import_graph(
+    state: State<'_, AppState>,
+    input_path: String,
+    root: Option<String>,
+    mode: Option<ImportMode>,
+) -> Result<ImportSummary> {
+    let graph = state.graph_store().await?;
+    import::import_graph(
+        graph.as_ref(),
+        std::path::Path::new(&input_path),
+        root.as_deref(),
+        mode.unwrap_or_default(),
+    )
+    .await
+}
+
+/// Copy the local on-disk graph into the Neo4j database configured by
+/// `NEO4J_URI`, `NEO4J_USER` and `NEO4J_PASSWORD` or the active profile
+#[tauri::command]
+pub async fn export_graph_to_neo4j(state: State<'_, AppState>) -> Result<String> {
+    env_utils::init()?;
+    let graph_dir = state.graph_dir();
+    let local = MemoryGraph::load(&graph_dir)?;
+    let neo_db = GraphBackend::Neo4j
+        .open(&graph_dir, state.config_dir())
+        .await?;
+
+    let (nodes, edges) = local.export_into(neo_db.as_ref()).await?;
+    Ok(format!(
+        "Exported {} nodes and {} edges to Neo4j.",
+        nodes, edges
+    ))
+}
@@ -20,6 +20,12 @@ pub enum AppError {
     #[error("Unsupported language: {0}")]
     UnsupportedLanguage(String),
 
+    #[error("Failed to ingest {0}: {1}")]
+    Ingest(String, String),
+
+    #[error("Graph storage error: {0}")]
+    Storage(String),
+
     #[error("Configuration error: {0}")]
     Config(String),
 
@@ -0,0 +1,315 @@
+//! On-disk persistence for `MemoryGraph`, so the app works without a Neo4j
+//! server.
+//!
+//! The graph lives in `<app data>/graph/`. `snapshot.json` holds every node
+//! and edge as of the last compaction and `journal.jsonl` every batch
+//! committed since, one JSON array of `GraphOp`s per line. Opening loads the
+//! snapshot and replays the journal; a torn last line left by a crash is
+//! dropped and cut from the file before anything is appended, so a batch is
+//! either fully persisted or not at all. A complete line that doesn't parse
+//! fails the load instead, keeping the batches after it. Replaying a journal
+//! over a snapshot that already contains it yields the same graph, which
+//! makes an interrupted compaction harmless.
+
+use crate::error::{AppError, Result};
+use crate::file_manager::graph_store::StoredEdge;
+use crate::file_manager::memory_store::{GraphData, GraphOp};
+use crate::models::CodeEntity;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Directory below the app data directory holding the graph
+pub const GRAPH_DIR: &str = "graph";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const JOURNAL_FILE: &str = "journal.jsonl";
+/// Journal batches after which the snapshot is rewritten
+const COMPACT_AFTER_BATCHES: usize = 500;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub nodes: Vec<CodeEntity>,
+    pub edges: Vec<StoredEdge>,
+}
+
+impl Snapshot {
+    pub fn from_data(data: &GraphData) -> Self {
+        Snapshot {
+            nodes: data.nodes.values().cloned().collect(),
+            edges: data.stored_edges(),
+        }
+    }
+
+    pub fn into_data(self) -> GraphData {
+        let mut data = GraphData::default();
+        for node in self.nodes {
+            data.apply(GraphOp::PutNode(node));
+        }
+        for edge in self.edges {
+            data.apply(GraphOp::PutEdge(edge));
+        }
+        data
+    }
+}
+
+/// Append-only log of committed batches
+pub struct Journal {
+    dir: PathBuf,
+    file: File,
+    batches: usize,
+    /// Length to cut the file back to before the next batch, when cutting
+    /// off a failed one failed as well
+    cut_to: Option<u64>,
+}
+
+impl Journal {
+    /// Loads the graph stored in `dir`, creating the directory if needed, and
+    /// opens the journal for appending
+    pub fn open(dir: &Path) -> Result<(GraphData, Journal)> {
+        fs::create_dir_all(dir)?;
+        let (data, batches, valid_len) = read_graph(dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))?;
+        // Later batches would otherwise be written onto the end of a torn line
+        if file.metadata()?.len() > valid_len {
+            warn!("Truncating graph journal to {} bytes", valid_len);
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+
+        let mut journal = Journal {
+            dir: dir.to_path_buf(),
+            file,
+            batches,
+            cut_to: None,
+        };
+        if journal.needs_compaction() {
+            journal.compact(&data)?;
+        }
+        info!(
+            "Opened graph store in {}: {} nodes, {} edges",
+            dir.display(),
+            data.nodes.len(),
+            data.edges.len()
+        );
+        Ok((data, journal))
+    }
+
+    /// Writes one batch as a single line and flushes it to disk. A batch
+    /// that fails to write is cut off again, so the next one doesn't end up
+    /// behind a torn line where replaying would never reach it.
+    pub fn append(&mut self, ops: &[GraphOp]) -> Result<()> {
+        let mut line = serde_json::to_vec(ops).map_err(|e| AppError::Storage(e.to_string()))?;
+        line.push(b'\n');
+        if let Some(len) = self.cut_to {
+            self.file.set_len(len)?;
+            self.cut_to = None;
+        }
+
+        let len = self.file.metadata()?.len();
+        if let Err(e) = self
+            .file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data())
+        {
+            if let Err(cut) = self.file.set_len(len) {
+                warn!(
+                    "Failed to cut a failed batch from the graph journal: {}",
+                    cut
+                );
+                self.cut_to = Some(len);
+            }
+            return Err(e.into());
+        }
+        self.batches += 1;
+        Ok(())
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.batches >= COMPACT_AFTER_BATCHES
+    }
+
+    /// Replaces the snapshot with `data` and empties the journal
+    pub fn compact(&mut self, data: &GraphData) -> Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut writer, &Snapshot::from_data(data))
+                .map_err(|e| AppError::Storage(e.to_string()))?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp, self.dir.join(SNAPSHOT_FILE))?;
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+
+        info!(
+            "Compacted graph store after {} batches ({} nodes, {} edges)",
+            self.batches,
+            data.nodes.len(),
+            data.edges.len()
+        );
+        self.batches = 0;
+        Ok(())
+    }
+}
+
+/// Loads the graph stored in `dir` without opening it for writing
+pub fn load(dir: &Path) -> Result<GraphData> {
+    read_graph(dir).map(|(data, _, _)| data)
+}
+
+// Snapshot plus replayed journal, the number of journal batches and the
+// length of the journal up to the end of its last complete line
+fn read_graph(dir: &Path) -> Result<(GraphData, usize, u64)> {
+    let mut data = match File::open(dir.join(SNAPSHOT_FILE)) {
+        Ok(file) => serde_json::from_reader::<_, Snapshot>(BufReader::new(file))
+            .map_err(|e| AppError::Storage(format!("Unreadable graph snapshot: {}", e)))?
+            .into_data(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => GraphData::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let journal = match File::open(dir.join(JOURNAL_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((data, 0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+    let mut reader = BufReader::new(journal);
+    let mut batches = 0;
+    let mut valid_len = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        // Every batch is written with its newline, so only a line without
+        // one is torn, and then it's the last one
+        let Some(json) = line.strip_suffix(b"\n") else {
+            warn!("Dropping incomplete graph journal entry");
+            break;
+        };
+        let ops = serde_json::from_slice::<Vec<GraphOp>>(json).map_err(|e| {
+            AppError::Storage(format!(
+                "Unreadable graph journal entry {} in {}: {}",
+                batches + 1,
+                dir.join(JOURNAL_FILE).display(),
+                e
+            ))
+        })?;
+        for op in ops {
+            data.apply(op);
+        }
+        batches += 1;
+        valid_len += read as u64;
+    }
+    Ok((data, batches, valid_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EntityType;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn node(id: &str) -> CodeEntity {
+        CodeEntity::fixture(id, id, EntityType::File)
+    }
+
+    #[test]
+    fn test_replay_compact_and_torn_line() {
+        let dir = tempdir().unwrap();
+        let (_, mut journal) = Journal::open(dir.path()).unwrap();
+        journal
+            .append(&[GraphOp::PutNode(node("a")), GraphOp::PutNode(node("b"))])
+            .unwrap();
+        journal
+            .append(&[GraphOp::PutEdge(StoredEdge {
+                repository_id: String::new(),
+                from: "a".to_string(),
+                rel_type: "IMPORTS".to_string(),
+                to: "b".to_string(),
+                properties: HashMap::new(),
+            })])
+            .unwrap();
+
+        let data = load(dir.path()).unwrap();
+        assert_eq!((data.nodes.len(), data.edges.len()), (2, 1));
+
+        journal.compact(&data).unwrap();
+        journal
+            .append(&[GraphOp::DeleteNodes(vec![(String::new(), "b".to_string())])])
+            .unwrap();
+        // A crash in the middle of writing a batch
+        journal.file.write_all(b"[{\"PutNode\":").unwrap();
+
+        let data = load(dir.path()).unwrap();
+        assert_eq!((data.nodes.len(), data.edges.len()), (1, 0));
+
+        // Reopening cuts the torn line, so later batches survive
+        drop(journal);
+        let (_, mut journal) = Journal::open(dir.path()).unwrap();
+        journal.append(&[GraphOp::PutNode(node("c"))]).unwrap();
+        journal.append(&[GraphOp::PutNode(node("d"))]).unwrap();
+        drop(journal);
+
+        let (data, _) = Journal::open(dir.path()).unwrap();
+        let mut ids: Vec<&str> = data.nodes.values().map(|n| n.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c", "d"]);
+    }
+
+    #[test]
+    fn test_unreadable_entry_keeps_later_batches() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(JOURNAL_FILE);
+        let (_, mut journal) = Journal::open(dir.path()).unwrap();
+        journal.append(&[GraphOp::PutNode(node("a"))]).unwrap();
+        // A complete line this version can't read, e.g. from a newer one
+        journal.file.write_all(b"[{\"Renamed\":{}}]\n").unwrap();
+        journal.append(&[GraphOp::PutNode(node("b"))]).unwrap();
+        drop(journal);
+        let len = fs::metadata(&path).unwrap().len();
+
+        assert!(matches!(load(dir.path()), Err(AppError::Storage(_))));
+        assert!(matches!(
+            Journal::open(dir.path()),
+            Err(AppError::Storage(_))
+        ));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_failed_append_is_cut_off() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(JOURNAL_FILE);
+        let (_, mut journal) = Journal::open(dir.path()).unwrap();
+        journal.append(&[GraphOp::PutNode(node("a"))]).unwrap();
+
+        // Neither the batch nor cutting it off can be written
+        journal.file = File::open(&path).unwrap();
+        assert!(journal.append(&[GraphOp::PutNode(node("b"))]).is_err());
+        assert_eq!(journal.batches, 1);
+        assert_eq!(journal.cut_to, Some(fs::metadata(&path).unwrap().len()));
+
+        // Once the file is writable again, the part that did get written goes
+        // before the next batch
+        journal.file = OpenOptions::new().append(true).open(&path).unwrap();
+        journal.file.write_all(b"[{\"PutNode\":").unwrap();
+        journal.append(&[GraphOp::PutNode(node("c"))]).unwrap();
+        drop(journal);
+
+        let (data, _) = Journal::open(dir.path()).unwrap();
+        let mut ids: Vec<&str> = data.nodes.values().map(|n| n.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c"]);
+    }
+}
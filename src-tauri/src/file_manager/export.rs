@@ -0,0 +1,580 @@
+//! Exports a repository's graph, or part of it, to a file.
+//!
+//! GraphML and GEXF open in Gephi and yEd, DOT in Graphviz. Every node carries
+//! its label (`type`), `path`, line range and stored properties; edges carry
+//! their relationship type and properties. The repository id is left out.
+//!
+//! The JSON format is our own and meant for notebooks and for importing back:
+//!
+//! ```json
+//! {
+//!   "format": "pear-graph",
+//!   "version": 1,
+//!   "repository_id": "3f2a9c0d1b7e4a55",
+//!   "root": "/home/me/project",
+//!   "nodes": [
+//!     {"id": "/home/me/project/src/a.rs::add", "type": "Function",
+//!      "path": "/home/me/project/src/a.rs", "start_line": 3, "end_line": 5,
+//!      "span": {"start_line": 3, "start_column": 0, ...},
+//!      "name_span": {...}, "properties": {"name": "add", ...}}
+//!   ],
+//!   "edges": [
+//!     {"from": "...::test:t", "type": "TESTS", "to": "...::add", "properties": {}}
+//!   ]
+//! }
+//! ```
+//!
+//! Paths are absolute and start with `root`. `span`, `name_span` and the line
+//! numbers are omitted when unknown. Files are written record by record
+//! straight to disk, so nothing but the summary goes through Tauri IPC.
+
+use crate::error::{AppError, Result};
+use crate::file_manager::graph_store::StoredEdge;
+use crate::file_manager::graph_store::{GraphFilter, GraphStore, REPOSITORY_ID_PROPERTY};
+use crate::models::{CodeEntity, Repository, SourceSpan};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Value of `format` in JSON exports
+pub const JSON_FORMAT: &str = "pear-graph";
+/// Version of the JSON layout, bumped on incompatible changes
+pub const JSON_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    GraphMl,
+    Gexf,
+    Dot,
+    Json,
+}
+
+impl ExportFormat {
+    /// Guesses the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "graphml" => Some(ExportFormat::GraphMl),
+            "gexf" => Some(ExportFormat::Gexf),
+            "dot" | "gv" => Some(ExportFormat::Dot),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A node in the JSON format
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotNode {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub entity_type: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_span: Option<SourceSpan>,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+}
+
+impl From<&CodeEntity> for SnapshotNode {
+    fn from(entity: &CodeEntity) -> Self {
+        SnapshotNode {
+            id: entity.id.clone(),
+            entity_type: entity.entity_type.to_string(),
+            path: entity.path.clone(),
+            start_line: entity.start_line,
+            end_line: entity.end_line,
+            span: entity.span,
+            name_span: entity.name_span,
+            properties: exported_properties(&entity.properties),
+        }
+    }
+}
+
+/// An edge in the JSON format
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotEdge {
+    pub from: String,
+    #[serde(rename = "type")]
+    pub rel_type: String,
+    pub to: String,
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+}
+
+/// A whole JSON export as read back
+#[derive(Debug, Deserialize)]
+pub struct Snapshot {
+    pub format: String,
+    pub version: u32,
+    pub repository_id: String,
+    pub root: String,
+    pub nodes: Vec<SnapshotNode>,
+    pub edges: Vec<SnapshotEdge>,
+}
+
+impl From<&StoredEdge> for SnapshotEdge {
+    fn from(edge: &StoredEdge) -> Self {
+        SnapshotEdge {
+            from: edge.from.clone(),
+            rel_type: edge.rel_type.clone(),
+            to: edge.to.clone(),
+            properties: exported_properties(&edge.properties),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSummary {
+    pub format: ExportFormat,
+    pub path: String,
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+/// Writes the graph of the repository checked out at `root`, narrowed by
+/// `filter`, to `path`. The format defaults to the one matching the file
+/// extension.
+pub async fn export_graph(
+    graph: &dyn GraphStore,
+    root: &str,
+    filter: &GraphFilter,
+    format: Option<ExportFormat>,
+    path: &Path,
+) -> Result<ExportSummary> {
+    let format = format
+        .or_else(|| ExportFormat::from_path(path))
+        .ok_or_else(|| {
+            AppError::Config(format!(
+                "Cannot tell the export format of {}, expected .graphml, .gexf, .dot or .json",
+                path.display()
+            ))
+        })?;
+    let repository_id = Repository::id_for(Path::new(root));
+    let (nodes, edges) = graph.subgraph(&repository_id, filter).await?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        ExportFormat::GraphMl => write_graphml(&mut out, &nodes, &edges)?,
+        ExportFormat::Gexf => write_gexf(&mut out, &nodes, &edges)?,
+        ExportFormat::Dot => write_dot(&mut out, &nodes, &edges)?,
+        ExportFormat::Json => write_json(&mut out, &repository_id, root, &nodes, &edges)?,
+    }
+    out.flush()?;
+
+    info!(
+        "Exported {} nodes and {} edges of {} to {}",
+        nodes.len(),
+        edges.len(),
+        root,
+        path.display()
+    );
+    Ok(ExportSummary {
+        format,
+        path: path.to_string_lossy().to_string(),
+        nodes: nodes.len(),
+        edges: edges.len(),
+    })
+}
+
+fn exported_properties(properties: &HashMap<String, String>) -> BTreeMap<String, String> {
+    properties
+        .iter()
+        .filter(|(key, _)| key.as_str() != REPOSITORY_ID_PROPERTY)
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Attributes written for a node in GraphML, GEXF and DOT, in a fixed order
+fn node_attributes(node: &CodeEntity) -> Vec<(String, String)> {
+    let mut attributes = vec![
+        ("type".to_string(), node.entity_type.to_string()),
+        ("path".to_string(), node.path.clone()),
+    ];
+    if let Some(start_line) = node.start_line {
+        attributes.push(("start_line".to_string(), start_line.to_string()));
+    }
+    if let Some(end_line) = node.end_line {
+        attributes.push(("end_line".to_string(), end_line.to_string()));
+    }
+    attributes.extend(exported_properties(&node.properties));
+    attributes
+}
+
+fn node_label(node: &CodeEntity) -> &str {
+    node.properties
+        .get("name")
+        .map(String::as_str)
+        .unwrap_or(&node.id)
+}
+
+/// Attribute names used by any of the nodes and edges, in order of first use
+/// for the fixed ones and alphabetically for the rest
+fn attribute_names(nodes: &[CodeEntity], edges: &[StoredEdge]) -> (Vec<String>, Vec<String>) {
+    let mut node_names: Vec<String> = ["type", "path", "start_line", "end_line"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let properties: BTreeSet<String> = nodes
+        .iter()
+        .flat_map(|node| exported_properties(&node.properties).into_keys())
+        .filter(|key| !node_names.contains(key))
+        .collect();
+    node_names.extend(properties);
+
+    let edge_names: BTreeSet<String> = edges
+        .iter()
+        .flat_map(|edge| exported_properties(&edge.properties).into_keys())
+        .collect();
+    (node_names, edge_names.into_iter().collect())
+}
+
+fn xml_escape(value: &str) -> Cow<'_, str> {
+    if !value.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+fn dot_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => {}
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn write_graphml(out: &mut impl Write, nodes: &[CodeEntity], edges: &[StoredEdge]) -> Result<()> {
+    let (node_names, edge_names) = attribute_names(nodes, edges);
+    let node_keys: HashMap<&str, String> = node_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), format!("n{}", i)))
+        .collect();
+    let edge_keys: HashMap<&str, String> = edge_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), format!("e{}", i + 1)))
+        .collect();
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    for name in &node_names {
+        let attr_type = match name.as_str() {
+            "start_line" | "end_line" => "int",
+            _ => "string",
+        };
+        writeln!(
+            out,
+            r#"  <key id="{}" for="node" attr.name="{}" attr.type="{}"/>"#,
+            node_keys[name.as_str()],
+            xml_escape(name),
+            attr_type
+        )?;
+    }
+    writeln!(
+        out,
+        r#"  <key id="e0" for="edge" attr.name="type" attr.type="string"/>"#
+    )?;
+    for name in &edge_names {
+        writeln!(
+            out,
+            r#"  <key id="{}" for="edge" attr.name="{}" attr.type="string"/>"#,
+            edge_keys[name.as_str()],
+            xml_escape(name)
+        )?;
+    }
+
+    writeln!(out, r#"  <graph id="G" edgedefault="directed">"#)?;
+    for node in nodes {
+        writeln!(out, r#"    <node id="{}">"#, xml_escape(&node.id))?;
+        for (name, value) in node_attributes(node) {
+            writeln!(
+                out,
+                r#"      <data key="{}">{}</data>"#,
+                node_keys[name.as_str()],
+                xml_escape(&value)
+            )?;
+        }
+        writeln!(out, "    </node>")?;
+    }
+    for edge in edges {
+        writeln!(
+            out,
+            r#"    <edge source="{}" target="{}">"#,
+            xml_escape(&edge.from),
+            xml_escape(&edge.to)
+        )?;
+        writeln!(
+            out,
+            r#"      <data key="e0">{}</data>"#,
+            xml_escape(&edge.rel_type)
+        )?;
+        for (name, value) in exported_properties(&edge.properties) {
+            writeln!(
+                out,
+                r#"      <data key="{}">{}</data>"#,
+                edge_keys[name.as_str()],
+                xml_escape(&value)
+            )?;
+        }
+        writeln!(out, "    </edge>")?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    Ok(())
+}
+
+fn write_gexf(out: &mut impl Write, nodes: &[CodeEntity], edges: &[StoredEdge]) -> Result<()> {
+    let (node_names, edge_names) = attribute_names(nodes, edges);
+    let node_ids: HashMap<&str, usize> = node_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect();
+    let edge_ids: HashMap<&str, usize> = edge_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect();
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#)?;
+    writeln!(out, r#"  <graph defaultedgetype="directed">"#)?;
+    writeln!(out, r#"    <attributes class="node">"#)?;
+    for name in &node_names {
+        let attr_type = match name.as_str() {
+            "start_line" | "end_line" => "integer",
+            _ => "string",
+        };
+        writeln!(
+            out,
+            r#"      <attribute id="{}" title="{}" type="{}"/>"#,
+            node_ids[name.as_str()],
+            xml_escape(name),
+            attr_type
+        )?;
+    }
+    writeln!(out, "    </attributes>")?;
+    writeln!(out, r#"    <attributes class="edge">"#)?;
+    for name in &edge_names {
+        writeln!(
+            out,
+            r#"      <attribute id="{}" title="{}" type="string"/>"#,
+            edge_ids[name.as_str()],
+            xml_escape(name)
+        )?;
+    }
+    writeln!(out, "    </attributes>")?;
+
+    writeln!(out, "    <nodes>")?;
+    for node in nodes {
+        writeln!(
+            out,
+            r#"      <node id="{}" label="{}">"#,
+            xml_escape(&node.id),
+            xml_escape(node_label(node))
+        )?;
+        writeln!(out, "        <attvalues>")?;
+        for (name, value) in node_attributes(node) {
+            writeln!(
+                out,
+                r#"          <attvalue for="{}" value="{}"/>"#,
+                node_ids[name.as_str()],
+                xml_escape(&value)
+            )?;
+        }
+        writeln!(out, "        </attvalues>")?;
+        writeln!(out, "      </node>")?;
+    }
+    writeln!(out, "    </nodes>")?;
+
+    writeln!(out, "    <edges>")?;
+    for (i, edge) in edges.iter().enumerate() {
+        writeln!(
+            out,
+            r#"      <edge id="{}" source="{}" target="{}" label="{}">"#,
+            i,
+            xml_escape(&edge.from),
+            xml_escape(&edge.to),
+            xml_escape(&edge.rel_type)
+        )?;
+        writeln!(out, "        <attvalues>")?;
+        for (name, value) in exported_properties(&edge.properties) {
+            writeln!(
+                out,
+                r#"          <attvalue for="{}" value="{}"/>"#,
+                edge_ids[name.as_str()],
+                xml_escape(&value)
+            )?;
+        }
+        writeln!(out, "        </attvalues>")?;
+        writeln!(out, "      </edge>")?;
+    }
+    writeln!(out, "    </edges>")?;
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</gexf>")?;
+    Ok(())
+}
+
+fn write_dot(out: &mut impl Write, nodes: &[CodeEntity], edges: &[StoredEdge]) -> Result<()> {
+    writeln!(out, "digraph code {{")?;
+    for node in nodes {
+        let attributes: Vec<String> =
+            std::iter::once(("label".to_string(), node_label(node).to_string()))
+                .chain(node_attributes(node))
+                .map(|(name, value)| format!("{}={}", dot_quote(&name), dot_quote(&value)))
+                .collect();
+        writeln!(
+            out,
+            "  {} [{}];",
+            dot_quote(&node.id),
+            attributes.join(", ")
+        )?;
+    }
+    for edge in edges {
+        let attributes: Vec<String> = std::iter::once(("label".to_string(), edge.rel_type.clone()))
+            .chain(exported_properties(&edge.properties))
+            .map(|(name, value)| format!("{}={}", dot_quote(&name), dot_quote(&value)))
+            .collect();
+        writeln!(
+            out,
+            "  {} -> {} [{}];",
+            dot_quote(&edge.from),
+            dot_quote(&edge.to),
+            attributes.join(", ")
+        )?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn write_json(
+    out: &mut impl Write,
+    repository_id: &str,
+    root: &str,
+    nodes: &[CodeEntity],
+    edges: &[StoredEdge],
+) -> Result<()> {
+    let json_error = |e: serde_json::Error| AppError::Storage(e.to_string());
+
+    writeln!(out, "{{")?;
+    writeln!(
+        out,
+        "  \"format\": {},",
+        serde_json::to_string(JSON_FORMAT).map_err(json_error)?
+    )?;
+    writeln!(out, "  \"version\": {},", JSON_VERSION)?;
+    writeln!(
+        out,
+        "  \"repository_id\": {},",
+        serde_json::to_string(repository_id).map_err(json_error)?
+    )?;
+    writeln!(
+        out,
+        "  \"root\": {},",
+        serde_json::to_string(root).map_err(json_error)?
+    )?;
+
+    // One record per line keeps the file easy to stream back in
+    writeln!(out, "  \"nodes\": [")?;
+    for (i, node) in nodes.iter().enumerate() {
+        write!(out, "    ")?;
+        serde_json::to_writer(&mut *out, &SnapshotNode::from(node)).map_err(json_error)?;
+        writeln!(out, "{}", if i + 1 < nodes.len() { "," } else { "" })?;
+    }
+    writeln!(out, "  ],")?;
+    writeln!(out, "  \"edges\": [")?;
+    for (i, edge) in edges.iter().enumerate() {
+        write!(out, "    ")?;
+        serde_json::to_writer(&mut *out, &SnapshotEdge::from(edge)).map_err(json_error)?;
+        writeln!(out, "{}", if i + 1 < edges.len() { "," } else { "" })?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EntityType;
+
+    fn node(id: &str, name: &str) -> CodeEntity {
+        let mut node = CodeEntity::fixture(id, "src/a.rs", EntityType::Function)
+            .with_property("name", name)
+            .with_property(REPOSITORY_ID_PROPERTY, "r1");
+        node.start_line = Some(3);
+        node
+    }
+
+    fn edges() -> Vec<StoredEdge> {
+        vec![StoredEdge {
+            repository_id: "r1".to_string(),
+            from: "a::lt".to_string(),
+            rel_type: "CALLS".to_string(),
+            to: "a::quote".to_string(),
+            properties: HashMap::new(),
+        }]
+    }
+
+    #[test]
+    fn test_formats_escape_ids_and_values() {
+        let nodes = vec![node("a::lt", "<"), node("a::quote", "say \"hi\"")];
+
+        let mut graphml = Vec::new();
+        write_graphml(&mut graphml, &nodes, &edges()).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert!(graphml.contains(r#"<data key="n4">&lt;</data>"#));
+        assert!(graphml.contains(r#"<edge source="a::lt" target="a::quote">"#));
+        assert!(!graphml.contains("r1"));
+
+        let mut dot = Vec::new();
+        write_dot(&mut dot, &nodes, &edges()).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains(r#""a::quote" ["label"="say \"hi\"""#));
+        assert!(dot.contains(r#""a::lt" -> "a::quote" ["label"="CALLS"];"#));
+
+        let mut json = Vec::new();
+        write_json(&mut json, "r1", "/repo", &nodes, &edges()).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["format"], JSON_FORMAT);
+        assert_eq!(json["nodes"][1]["properties"]["name"], "say \"hi\"");
+        assert_eq!(json["edges"][0]["type"], "CALLS");
+        assert!(json["nodes"][0].get("end_line").is_none());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use blake3::Hash;
+use crate::file_manager::reader::FileReader;
 use log::{info, warn};
 use std::collections::HashMap;
 use std::fs;
@@ -10,14 +10,14 @@ use std::time::{SystemTime, UNIX_EPOCH};
 pub struct FileTracker {
     repository_path: PathBuf,
     repository_id: String,
-    owner_id: String,
+    owner_id: Option<String>,
     file_hashes: HashMap<PathBuf, String>,
     file_timestamps: HashMap<PathBuf, SystemTime>,
 }
 
 impl FileTracker {
     /// Creates a new file tracker for a repository
-    pub fn new(repository_path: PathBuf, repository_id: String, owner_id: String) -> Self {
+    pub fn new(repository_path: PathBuf, repository_id: String, owner_id: Option<String>) -> Self {
         FileTracker {
             repository_path,
             repository_id,
@@ -123,8 +123,6 @@ impl FileTracker {
 
     /// Calculate hash for a file
     fn calculate_file_hash(&self, path: &Path) -> Result<String> {
-        let content = fs::read(path)?;
-        let hash = blake3::hash(&content);
-        Ok(hash.to_hex().to_string())
+        FileReader::from_env().hash(path)
     }
 }
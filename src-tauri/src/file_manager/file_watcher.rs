@@ -1,6 +1,10 @@
+use crate::analysis::classification::{FileClass, FileClassifier};
+use crate::analysis::metrics::FileMetrics;
 use crate::error::{AppError, Result as AppResult};
 use crate::file_manager::file_tracker::FileTracker;
-use crate::file_manager::neo4j::NeoDB;
+use crate::file_manager::graph_store::GraphStore;
+use crate::file_manager::reader::FileReader;
+use crate::models::FileStructure;
 use crate::parser::Parser;
 
 use log::{error, info};
@@ -17,14 +21,24 @@ use uuid::Error;
 
 const DEBOUNCE_TIME: Duration = Duration::from_millis(300);
 
+/// Handles shared with the background task that processes watcher events
+struct WatchContext {
+    repository_id: String,
+    graph: Arc<dyn GraphStore>,
+    parser: Arc<Mutex<Parser>>,
+    file_tracker: Arc<Mutex<FileTracker>>,
+    classifier: Arc<FileClassifier>,
+    pending_changes: Arc<Mutex<HashMap<PathBuf, Instant>>>,
+}
+
 pub struct FileWatcherSystem {
     watcher: RecommendedWatcher,
     // rx: std::sync::mpsc::Receiver<std::result::Result<notify::Event, notify::Error>>, // Remove this
-    neo_db: Arc<NeoDB>,
+    graph: Arc<dyn GraphStore>,
     parser: Arc<Mutex<Parser>>,
     file_tracker: Arc<Mutex<FileTracker>>,
     repository_path: PathBuf,
-    ignore_patterns: Vec<String>,
+    classifier: Arc<FileClassifier>,
     pending_changes: Arc<Mutex<HashMap<PathBuf, Instant>>>,
     // Add a flag to signal if watching is active
     is_watching: Arc<AtomicBool>,
@@ -33,7 +47,8 @@ pub struct FileWatcherSystem {
 impl FileWatcherSystem {
     pub fn new(
         repository_path: PathBuf,
-        neo_db: Arc<NeoDB>,
+        repository_id: String,
+        graph: Arc<dyn GraphStore>,
         parser: Arc<Mutex<Parser>>,
         file_tracker: Arc<Mutex<FileTracker>>,
     ) -> Result<Self, Error> {
@@ -55,73 +70,51 @@ impl FileWatcherSystem {
         .map_err(|e| AppError::Config(format!("Failed to create file watcher: {}", e)))
         .unwrap();
 
-        // Default ignore patterns
-        let ignore_patterns = vec![
-            "node_modules".to_string(),
-            ".git".to_string(),
-            "target".to_string(),
-            "dist".to_string(),
-            "build".to_string(),
-            "__pycache__".to_string(),
-            ".next".to_string(),
-            ".nuxt".to_string(),
-        ];
-
-        let neo_db_clone = neo_db.clone();
-        let parser_clone = parser.clone();
-        let file_tracker_clone = file_tracker.clone();
-        let repository_path_clone = repository_path.clone();
-        let ignore_patterns_clone = ignore_patterns.clone();
+        // Ignored directories and generated/vendored files, per repository
+        let classifier = Arc::new(FileClassifier::load(&repository_path));
 
         let system = FileWatcherSystem {
             watcher,
-            neo_db: neo_db_clone,
-            parser: parser_clone,
-            file_tracker: file_tracker_clone,
-            repository_path: repository_path_clone,
-            ignore_patterns: ignore_patterns_clone,
+            graph: graph.clone(),
+            parser: parser.clone(),
+            file_tracker: file_tracker.clone(),
+            repository_path,
+            classifier: classifier.clone(),
             pending_changes: pending_changes.clone(),
             is_watching: is_watching.clone(),
         };
 
+        let context = WatchContext {
+            repository_id,
+            graph,
+            parser,
+            file_tracker,
+            classifier,
+            pending_changes,
+        };
+
         tokio::spawn(async move {
             // Process events while the watcher is active
             while is_watching.load(std::sync::atomic::Ordering::SeqCst) {
                 match rx.recv_timeout(std::time::Duration::from_millis(100)) {
                     Ok(Ok(event)) => {
                         // Process the event (implementation details...)
-                        Self::process_event(
-                            &event,
-                            &neo_db,
-                            &parser,
-                            &file_tracker,
-                            &repository_path,
-                            &ignore_patterns,
-                            &pending_changes,
-                        )
-                        .await
-                        .unwrap_or_else(|e| {
-                            error!("Error processing event: {}", e);
-                        });
+                        Self::process_event(&event, &context)
+                            .await
+                            .unwrap_or_else(|e| {
+                                error!("Error processing event: {}", e);
+                            });
                     }
                     Ok(Err(e)) => {
                         error!("Watch error: {:?}", e);
                     }
                     Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
                         // No events, check for debounced files
-                        let neoclone = neo_db.clone();
-                        let parserclone = parser.clone();
-                        let filetrackerclone = file_tracker.clone();
-                        Self::process_debounced_files(
-                            &neoclone,
-                            &parserclone,
-                            &filetrackerclone,
-                            &pending_changes,
-                        )
-                        .await
-                        .unwrap_or_else(|e| {
-                            error!("Error processing debounced files: {}", e);
-                        });
+                        Self::process_debounced_files(&context)
+                            .await
+                            .unwrap_or_else(|e| {
+                                error!("Error processing debounced files: {}", e);
+                            });
 
                         // Sleep a bit to avoid busy waiting
                         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -174,20 +167,13 @@ impl FileWatcherSystem {
     }
 
     // Process a single event (static method)
-    async fn process_event(
-        event: &Event,
-        neo_db: &Arc<NeoDB>,
-        parser: &Arc<Mutex<Parser>>,
-        file_tracker: &Arc<Mutex<FileTracker>>,
-        repository_path: &PathBuf,
-        ignore_patterns: &Vec<String>,
-        pending_changes: &Arc<Mutex<HashMap<PathBuf, Instant>>>,
-    ) -> Result<(), String> {
+    async fn process_event(event: &Event, context: &WatchContext) -> Result<(), String> {
+        let classifier = &context.classifier;
         // Extract the paths affected by this event
         let filtered_paths: Vec<PathBuf> = event
             .paths
             .iter()
-            .filter(|path| !Self::should_ignore(path, ignore_patterns))
+            .filter(|path| !Self::should_ignore(path, classifier))
             .cloned()
             .collect();
 
@@ -197,11 +183,38 @@ impl FileWatcherSystem {
 
         // Handle the event based on its kind
         match event.kind {
+            // A move within the repository. The From and To events that come
+            // with it are queued below; the old path is dropped from the queue
+            // here and the new one re-ingests the moved nodes in place.
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() >= 2 => {
+                let from_path = &event.paths[0];
+                let to_path = &event.paths[1];
+
+                if !Self::should_ignore(to_path, classifier) {
+                    info!(
+                        "Detected rename from: {} to: {}",
+                        from_path.display(),
+                        to_path.display()
+                    );
+                    Self::handle_file_rename(
+                        from_path,
+                        to_path,
+                        &context.repository_id,
+                        &context.graph,
+                        &context.file_tracker,
+                    )
+                    .await?;
+                    context.pending_changes.lock().await.remove(from_path);
+                }
+            }
             EventKind::Create(_) | EventKind::Modify(_) => {
+                // A path renamed away is queued too and removed once it's
+                // still gone after the debounce period
+                let renamed = matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
                 for path in filtered_paths {
-                    if path.is_file() {
+                    if path.is_file() || renamed {
                         // Update the pending changes with the current time
-                        let mut changes = pending_changes.lock().await;
+                        let mut changes = context.pending_changes.lock().await;
                         changes.insert(path.clone(), Instant::now());
                         info!("Detected change in file: {}", path.display());
                     }
@@ -211,23 +224,13 @@ impl FileWatcherSystem {
                 for path in filtered_paths {
                     info!("Detected removal of: {}", path.display());
                     // Handle file removal by updating the database
-                    Self::handle_file_removal(&path, neo_db, file_tracker).await?;
-                }
-            }
-            EventKind::Modify(ModifyKind::Name(_)) => {
-                // Handle rename events specially
-                if event.paths.len() >= 2 {
-                    let from_path = &event.paths[0];
-                    let to_path = &event.paths[1];
-
-                    if !Self::should_ignore(to_path, ignore_patterns) {
-                        info!(
-                            "Detected rename from: {} to: {}",
-                            from_path.display(),
-                            to_path.display()
-                        );
-                        Self::handle_file_rename(from_path, to_path, neo_db, file_tracker).await?;
-                    }
+                    Self::handle_file_removal(
+                        &path,
+                        &context.repository_id,
+                        &context.graph,
+                        &context.file_tracker,
+                    )
+                    .await?;
                 }
             }
             _ => {} // Ignore other event kinds
@@ -237,12 +240,15 @@ impl FileWatcherSystem {
     }
 
     // Process debounced files (static method)
-    async fn process_debounced_files(
-        neo_db: &Arc<NeoDB>,
-        parser: &Arc<Mutex<Parser>>,
-        file_tracker: &Arc<Mutex<FileTracker>>,
-        pending_changes: &Arc<Mutex<HashMap<PathBuf, Instant>>>,
-    ) -> Result<(), String> {
+    async fn process_debounced_files(context: &WatchContext) -> Result<(), String> {
+        let WatchContext {
+            repository_id,
+            graph,
+            parser,
+            file_tracker,
+            classifier,
+            pending_changes,
+        } = context;
         let now = Instant::now();
         let mut files_to_process = Vec::new();
 
@@ -259,6 +265,13 @@ impl FileWatcherSystem {
             });
         }
 
+        // Paths renamed away and not back
+        let (files_to_process, gone): (Vec<PathBuf>, Vec<PathBuf>) =
+            files_to_process.into_iter().partition(|path| path.exists());
+        for path in &gone {
+            Self::handle_file_removal(path, repository_id, graph, file_tracker).await?;
+        }
+
         // Process the stable files
         if !files_to_process.is_empty() {
             info!("Processing {} debounced files", files_to_process.len());
@@ -279,14 +292,31 @@ impl FileWatcherSystem {
                         None => continue,
                     };
 
+                    let class = classifier.classify(path);
+                    if classifier.skips(class) {
+                        info!("Skipping {} file: {}", class.as_str(), path.display());
+                        continue;
+                    }
+                    if class != FileClass::Source {
+                        let structure = Self::flagged_file_structure(path, class);
+                        if let Err(e) = graph
+                            .process_file_structure(repository_id, &structure)
+                            .await
+                        {
+                            error!("Failed to update {}: {}", path.display(), e);
+                        }
+                        continue;
+                    }
+
                     // Parse the file
                     match parser_guard.parse_single_file(path, &extension).await {
-                        Ok(entities) => {
-                            for entity in entities.items {
-                                neo_db.ingest_entity(&entity).await;
-                            }
-                            info!("Updated file in graph: {}", path.display());
-                        }
+                        Ok(structure) => match graph
+                            .process_file_structure(repository_id, &structure)
+                            .await
+                        {
+                            Ok(()) => info!("Updated file in graph: {}", path.display()),
+                            Err(e) => error!("Failed to update {}: {}", path.display(), e),
+                        },
                         Err(e) => {
                             error!("Failed to parse file {}: {}", path.display(), e);
                         }
@@ -298,14 +328,25 @@ impl FileWatcherSystem {
         Ok(())
     }
 
+    // A File node without entities for generated, vendored or minified files
+    fn flagged_file_structure(path: &Path, class: FileClass) -> FileStructure {
+        let mut properties = HashMap::new();
+        class.write_properties(&mut properties);
+        FileStructure {
+            structure_type: "file_structure".into(),
+            file_path: path.to_string_lossy().into_owned(),
+            items: Vec::new(),
+            file_hash: FileReader::from_env().hash(path).unwrap_or_default(),
+            metrics: FileMetrics::default(),
+            properties,
+        }
+    }
+
     // Static helper methods
-    fn should_ignore(path: &Path, ignore_patterns: &Vec<String>) -> bool {
-        // Check if the path contains any ignored pattern
-        let path_str = path.to_string_lossy();
-        for pattern in ignore_patterns {
-            if path_str.contains(pattern) {
-                return true;
-            }
+    fn should_ignore(path: &Path, classifier: &FileClassifier) -> bool {
+        // Check if the path is inside an ignored directory
+        if classifier.is_ignored(path) {
+            return true;
         }
 
         // Check if it's a hidden file or directory
@@ -321,12 +362,16 @@ impl FileWatcherSystem {
 
     async fn handle_file_removal(
         path: &Path,
-        neo_db: &Arc<NeoDB>,
+        repository_id: &str,
+        graph: &Arc<dyn GraphStore>,
         file_tracker: &Arc<Mutex<FileTracker>>,
     ) -> Result<(), String> {
         // Remove file and its entities from the database
         info!("Removing file from graph: {}", path.display());
-        neo_db.remove_file(path).await;
+        graph
+            .remove_file(repository_id, path)
+            .await
+            .map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
 
         // Update file tracker
         let mut tracker = file_tracker.lock().await;
@@ -338,7 +383,8 @@ impl FileWatcherSystem {
     async fn handle_file_rename(
         from_path: &Path,
         to_path: &Path,
-        neo_db: &Arc<NeoDB>,
+        repository_id: &str,
+        graph: &Arc<dyn GraphStore>,
         file_tracker: &Arc<Mutex<FileTracker>>,
     ) -> Result<(), String> {
         // Update the path in the database
@@ -347,7 +393,17 @@ impl FileWatcherSystem {
             from_path.display(),
             to_path.display()
         );
-        neo_db.update_file_path(from_path, to_path).await;
+        graph
+            .update_file_path(repository_id, from_path, to_path)
+            .await
+            .map_err(|e| {
+                format!(
+                    "Failed to move {} to {}: {}",
+                    from_path.display(),
+                    to_path.display(),
+                    e
+                )
+            })?;
 
         // Update file tracker
         let mut tracker = file_tracker.lock().await;
@@ -0,0 +1,712 @@
+//! Storage-agnostic graph operations.
+//!
+//! Ingestion, the file watcher and the query commands only talk to a
+//! `GraphStore`, so the graph can live in Neo4j, in memory or on disk in the
+//! app data directory. The backend is chosen at startup from
+//! `PEAR_GRAPH_STORE` and switches to Neo4j when a connection profile is
+//! activated.
+
+use crate::analysis::clones::ClonePair;
+use crate::analysis::hashing::{CONTENT_HASH_PROPERTY, FILE_HASH_PROPERTY};
+use crate::analysis::import_cycles::CycleGroup;
+use crate::env_utils;
+use crate::error::{AppError, Result};
+use crate::file_manager::memory_store::{write_edges, MemoryGraph, COPY_BATCH_SIZE};
+use crate::file_manager::neo4j::NeoDB;
+use crate::file_manager::profiles;
+use crate::file_manager::search::SearchFilter;
+use crate::models::{
+    CodeEntity, EntityReference, EntityType, FileStructure, LinkEntity, LinkType, TodoItem,
+};
+use crate::parser;
+
+use async_trait::async_trait;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+/// `(repository_id, from, rel_type, to)`; edges never cross repositories
+pub type EdgeKey = (String, String, String, String);
+
+/// An edge as kept by the in-memory store
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredEdge {
+    #[serde(default)]
+    pub repository_id: String,
+    pub from: String,
+    pub rel_type: String,
+    pub to: String,
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+}
+
+/// Node and edge property holding the id of the repository they belong to
+pub const REPOSITORY_ID_PROPERTY: &str = "repository_id";
+
+/// Node property listing, comma-separated, the property keys the parser last
+/// wrote. Updates only replace those, so properties users add survive.
+pub const PARSER_KEYS_PROPERTY: &str = "parser_keys";
+
+/// Value of `PARSER_KEYS_PROPERTY` for a node written with `properties`.
+/// Nodes read back from a store keep the list they carry.
+pub fn parser_keys(properties: &HashMap<String, String>) -> String {
+    if let Some(keys) = properties.get(PARSER_KEYS_PROPERTY) {
+        return keys.clone();
+    }
+    let mut keys: Vec<&str> = properties
+        .keys()
+        .map(String::as_str)
+        .filter(|k| *k != REPOSITORY_ID_PROPERTY)
+        .collect();
+    keys.sort();
+    keys.join(",")
+}
+
+/// What a fresh parse of one file changes in the stored entities. `stored`
+/// maps the ids of the entities a store holds for the file to their content
+/// hash and start byte; both stores plan file updates this way.
+#[derive(Debug, Default)]
+pub struct FileSyncPlan {
+    /// New entities and entities whose content hash changed, rewritten in full
+    pub changed: Vec<CodeEntity>,
+    /// Entities with the same content at another position, e.g. below an
+    /// insertion; only their lines, columns and offsets are updated
+    pub moved: Vec<CodeEntity>,
+    /// Ids of stored entities that are no longer in the file
+    pub vanished: Vec<String>,
+}
+
+impl FileSyncPlan {
+    pub fn new(
+        stored: &HashMap<String, (Option<String>, Option<i64>)>,
+        entities: &[CodeEntity],
+    ) -> Self {
+        let current: HashSet<&str> = entities.iter().map(|e| e.id.as_str()).collect();
+        let mut plan = FileSyncPlan {
+            vanished: stored
+                .keys()
+                .filter(|id| !current.contains(id.as_str()))
+                .cloned()
+                .collect(),
+            ..FileSyncPlan::default()
+        };
+
+        for entity in entities {
+            let start_byte = entity.span.map(|span| span.start_byte as i64);
+            match (
+                stored.get(&entity.id),
+                entity.properties.get(CONTENT_HASH_PROPERTY),
+            ) {
+                (Some((Some(old), old_start)), Some(new)) if old == new => {
+                    if *old_start != start_byte {
+                        plan.moved.push(entity.clone());
+                    }
+                }
+                _ => plan.changed.push(entity.clone()),
+            }
+        }
+        plan
+    }
+
+    /// Entities written one way or another
+    pub fn written(&self) -> usize {
+        self.changed.len() + self.moved.len()
+    }
+}
+
+/// Selects part of a repository's graph. Empty lists and a missing prefix
+/// select everything; edges are kept only when both ends are selected.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GraphFilter {
+    /// Entity labels such as `Function` or `File`
+    pub labels: Vec<String>,
+    /// Relationship types such as `CALLS` or `IMPORTS`
+    pub rel_types: Vec<String>,
+    pub path_prefix: Option<String>,
+}
+
+impl GraphFilter {
+    pub fn matches_node(&self, entity: &CodeEntity) -> bool {
+        (self.labels.is_empty() || self.labels.contains(&entity.entity_type.to_string()))
+            && self
+                .path_prefix
+                .as_ref()
+                .map(|prefix| entity.path.starts_with(prefix.as_str()))
+                .unwrap_or(true)
+    }
+
+    pub fn matches_rel(&self, rel_type: &str) -> bool {
+        self.rel_types.is_empty() || self.rel_types.iter().any(|r| r == rel_type)
+    }
+}
+
+/// Which edges of a node `entity_edges` follows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+    #[default]
+    Both,
+}
+
+/// How well a node matches a symbol search for `text` (lowercase), lower is
+/// better: exact name, name prefix, name substring, path substring. `None`
+/// when it doesn't match. Both stores order `find_entities` this way.
+pub fn symbol_rank(name: &str, path: &str, text: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    if name == text {
+        Some(0)
+    } else if name.starts_with(text) {
+        Some(1)
+    } else if name.contains(text) {
+        Some(2)
+    } else if path.to_lowercase().contains(text) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Connectivity of the graph store as reported by `graph_health`
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphHealth {
+    /// `neo4j`, `memory` or `disk`
+    pub backend: String,
+    pub connected: bool,
+    pub server_version: Option<String>,
+    pub database: Option<String>,
+    pub schema_version: Option<i64>,
+    /// Why the store can't be reached
+    pub error: Option<String>,
+}
+
+impl GraphHealth {
+    pub fn unreachable(backend: GraphBackend, error: &AppError) -> Self {
+        GraphHealth {
+            backend: backend.name().to_string(),
+            connected: false,
+            error: Some(error.to_string()),
+            ..GraphHealth::default()
+        }
+    }
+}
+
+/// Every node and edge belongs to one repository (see `Repository::id_for`).
+/// Writes, deletions and queries are all scoped by the repository id they
+/// are given.
+#[async_trait]
+pub trait GraphStore: Send + Sync {
+    async fn ingest_entity(&self, repository_id: &str, entity: &CodeEntity) -> Result<()>;
+
+    /// Creates or updates nodes by id. The properties an update last wrote
+    /// (see `PARSER_KEYS_PROPERTY`) are replaced, so ones the parser no longer
+    /// emits don't linger while ones added by users are kept.
+    async fn batch_ingest_entities(
+        &self,
+        repository_id: &str,
+        entities: &[CodeEntity],
+    ) -> Result<()>;
+
+    /// Brings the entities stored for one file in line with a fresh parse,
+    /// deleting the ones that vanished. Entities whose `content_hash` and
+    /// position are unchanged are not written. Returns the number written and
+    /// deleted.
+    async fn sync_file_entities(
+        &self,
+        repository_id: &str,
+        file_path: &str,
+        entities: &[CodeEntity],
+    ) -> Result<(usize, usize)>;
+
+    /// Writes everything produced by parsing one file atomically: the File
+    /// node unless its `hash` is unchanged, its changed entities, the
+    /// deletion of vanished ones and the `LinkType::FILE_LOCAL` edges between
+    /// nodes of the file, which are replaced by `links`. Edges leaving the
+    /// file, such as resolved imports, are kept. On failure nothing is
+    /// written and the error names the file.
+    async fn ingest_file(
+        &self,
+        repository_id: &str,
+        file: &CodeEntity,
+        entities: &[CodeEntity],
+        links: &[LinkEntity],
+    ) -> Result<()>;
+
+    async fn create_link(&self, repository_id: &str, link: &LinkEntity) -> Result<()>;
+
+    /// Creates edges between existing nodes; links to unknown ids are dropped
+    async fn batch_create_links(&self, repository_id: &str, links: &[LinkEntity]) -> Result<()>;
+
+    /// Replaces the edges of a repository that were resolved across files
+    /// (see `LinkType::RESOLVED`) with `links`, so ones that no longer apply go
+    async fn replace_resolved_links(&self, repository_id: &str, links: &[LinkEntity])
+        -> Result<()>;
+
+    /// Replaces the `DUPLICATE_OF` edges of a repository
+    async fn replace_duplicate_links(&self, repository_id: &str, pairs: &[ClonePair])
+        -> Result<()>;
+
+    /// Replaces the `CycleGroup` nodes of a repository and their `IN_CYCLE`
+    /// edges
+    async fn replace_cycle_groups(&self, repository_id: &str, groups: &[CycleGroup]) -> Result<()>;
+
+    /// Creates or updates the `Repository` node and links it to the root
+    /// Directory of the checkout
+    async fn register_repository(
+        &self,
+        repo_path: &str,
+        repository_id: &str,
+        owner_id: &str,
+    ) -> Result<()>;
+
+    /// Removes a file and the entities declared in it
+    async fn remove_file(&self, repository_id: &str, path: &Path) -> Result<()>;
+
+    /// Deletes every node and edge of a repository, including its
+    /// `Repository` node. Returns the number of nodes deleted.
+    async fn remove_repository(&self, repository_id: &str) -> Result<usize>;
+
+    /// Writes the nodes and edges of a repository, e.g. from an import, after
+    /// deleting its stored graph when `replace` is set. Edges are written as
+    /// in `write_edges`. Returns the number of edges written.
+    async fn load_graph(
+        &self,
+        repository_id: &str,
+        nodes: &[CodeEntity],
+        edges: &[StoredEdge],
+        replace: bool,
+    ) -> Result<usize> {
+        if replace {
+            self.remove_repository(repository_id).await?;
+        }
+        for chunk in nodes.chunks(COPY_BATCH_SIZE) {
+            self.batch_ingest_entities(repository_id, chunk).await?;
+        }
+        write_edges(self, repository_id, edges).await
+    }
+
+    /// Removes the files and directories of a repository that are not in
+    /// `present`, with the entities declared in them, e.g. files deleted
+    /// while the app wasn't running. Returns the number of paths removed.
+    async fn prune_missing(&self, repository_id: &str, present: &HashSet<String>) -> Result<usize>;
+
+    /// Moves a renamed file in one batch: the File node and its entities get
+    /// the ids and path of `to_path` (see `moved_id`) and keep their edges,
+    /// and the new parent Directory owns the file instead of the old one.
+    /// Whatever was stored at `to_path` is replaced; nothing happens when
+    /// `from_path` isn't stored.
+    async fn update_file_path(
+        &self,
+        repository_id: &str,
+        from_path: &Path,
+        to_path: &Path,
+    ) -> Result<()>;
+
+    async fn file_exists(&self, repository_id: &str, path: &Path) -> Result<bool>;
+
+    /// Tests that exercise a function or method, given its entity id or its
+    /// `qualified_name`, so same-named functions elsewhere don't match
+    async fn find_tests_for(
+        &self,
+        repository_id: &str,
+        function: &str,
+    ) -> Result<Vec<EntityReference>>;
+
+    /// Exported functions and methods that no test calls
+    async fn find_untested_functions(&self, repository_id: &str) -> Result<Vec<EntityReference>>;
+
+    /// TODO-style comments, optionally filtered by marker, path prefix and owner
+    async fn list_todos(
+        &self,
+        repository_id: &str,
+        marker: Option<String>,
+        path_prefix: Option<String>,
+        owner: Option<String>,
+    ) -> Result<Vec<TodoItem>>;
+
+    /// Checks that the store answers and describes it
+    async fn health(&self) -> Result<GraphHealth>;
+
+    /// Nodes of one repository whose name or path contains `text`, ignoring
+    /// case, ordered by `symbol_rank`, then shorter names, then id
+    async fn find_entities(
+        &self,
+        repository_id: &str,
+        text: &str,
+        labels: &[String],
+        limit: usize,
+    ) -> Result<Vec<CodeEntity>>;
+
+    /// Nodes of one repository matching any of the query words `terms` (see
+    /// `search::tokenize`) in their name, signature or doc, with their
+    /// relevance, best first
+    async fn search_entities(
+        &self,
+        repository_id: &str,
+        terms: &[String],
+        filter: &SearchFilter,
+        limit: usize,
+    ) -> Result<Vec<(CodeEntity, f64)>>;
+
+    /// Nodes of one repository with the given ids; unknown ids are skipped
+    async fn get_entities(&self, repository_id: &str, ids: &[String]) -> Result<Vec<CodeEntity>>;
+
+    /// Edges of one repository leaving or entering the given nodes, of any
+    /// type when `rel_types` is empty, sorted like `subgraph`
+    async fn entity_edges(
+        &self,
+        repository_id: &str,
+        ids: &[String],
+        direction: Direction,
+        rel_types: &[String],
+    ) -> Result<Vec<StoredEdge>>;
+
+    /// Nodes of one repository matching the filter and the matching edges
+    /// between them, sorted by id so exports are stable
+    async fn subgraph(
+        &self,
+        repository_id: &str,
+        filter: &GraphFilter,
+    ) -> Result<(Vec<CodeEntity>, Vec<StoredEdge>)>;
+
+    /// Writes a parsed file: the File node, its entities, the edges from the
+    /// file to them (see `parser::file_links`) and the edge from its directory
+    async fn process_file_structure(
+        &self,
+        repository_id: &str,
+        file_structure: &FileStructure,
+    ) -> Result<()> {
+        // First, create file node
+        let file_path = &file_structure.file_path;
+        let file_name = std::path::Path::new(file_path)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| file_path.clone());
+
+        let file_extension = std::path::Path::new(file_path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        // Create file entity (files are identified by their path, as in the directory walk)
+        let file_id = file_path.clone();
+        let file_entity = CodeEntity {
+            id: file_id.clone(),
+            path: file_path.clone(),
+            entity_type: EntityType::File,
+            start_line: None,
+            end_line: None,
+            span: None,
+            name_span: None,
+            properties: {
+                let mut props = HashMap::new();
+                props.insert("name".to_string(), file_name);
+                props.insert("extension".to_string(), file_extension);
+                props.insert(
+                    FILE_HASH_PROPERTY.to_string(),
+                    file_structure.file_hash.clone(),
+                );
+                file_structure.metrics.write_properties(&mut props);
+                props.extend(file_structure.properties.clone());
+                props
+            },
+            children: None,
+        };
+
+        // Collect all entities and relationships
+        let mut all_entities = Vec::new();
+        let mut all_links = Vec::new();
+
+        // Process items within the file
+        for item in &file_structure.items {
+            // Create entity for each item
+            let item_id = if item.id.is_empty() {
+                let label = item.entity_type.to_string();
+                // name := last path component, or fall back to autogenerated uuid
+                let name = Path::new(&item.path)
+                    .file_name()
+                    .map(|f| f.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                format!("{}:{}", label.to_lowercase(), name)
+            } else {
+                item.id.clone()
+            };
+
+            let entity = CodeEntity {
+                id: item_id.clone(),
+                path: item.path.clone(),
+                entity_type: item.entity_type.clone(),
+                start_line: item.start_line,
+                end_line: item.end_line,
+                span: item.span,
+                name_span: item.name_span,
+                properties: item.properties.clone(),
+                children: None,
+            };
+
+            all_entities.push(entity);
+
+            // Process any children
+            if let Some(children) = &item.children {
+                for child in children {
+                    let child_id = if child.id.is_empty() {
+                        let label = child.entity_type.to_string();
+                        let name = Path::new(&child.path)
+                            .file_name()
+                            .map(|f| f.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                        format!("{}:{}", label.to_lowercase(), name)
+                    } else {
+                        child.id.clone()
+                    };
+
+                    let child_entity = CodeEntity {
+                        id: child_id.clone(),
+                        path: child.path.clone(),
+                        entity_type: child.entity_type.clone(),
+                        start_line: child.start_line,
+                        end_line: child.end_line,
+                        span: child.span,
+                        name_span: child.name_span,
+                        properties: child.properties.clone(),
+                        children: None,
+                    };
+
+                    all_entities.push(child_entity);
+
+                    // Create link from parent to child
+                    let parent_child_link = LinkEntity {
+                        from_name: item_id.clone(),
+                        to_name: child_id,
+                        link_type: LinkType::Has,
+                        properties: HashMap::new(),
+                    };
+
+                    all_links.push(parent_child_link);
+                }
+            }
+        }
+
+        // The same edges from the file to its entities as a directory ingest
+        all_links.extend(parser::file_links(&file_id, &all_entities));
+
+        // Create relationship between file and its directory
+        let dir_path = std::path::Path::new(file_path)
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if !dir_path.is_empty() {
+            let dir_id = dir_path.clone();
+
+            // Create directory entity if it doesn't exist
+            let dir_entity = directory_entity(&dir_path);
+
+            all_entities.push(dir_entity);
+            all_links.push(LinkEntity {
+                from_name: dir_id,
+                to_name: file_id,
+                link_type: LinkType::Owns,
+                properties: HashMap::new(),
+            });
+        }
+
+        // The file, its entities, its directory and their edges are written
+        // together or not at all
+        self.ingest_file(repository_id, &file_entity, &all_entities, &all_links)
+            .await?;
+
+        info!("Processed file structure for {}", file_path);
+        Ok(())
+    }
+
+    /// Writes several parsed files, one after another
+    async fn batch_process_file_structures(
+        &self,
+        repository_id: &str,
+        structures: &[FileStructure],
+    ) -> Result<()> {
+        for structure in structures {
+            self.process_file_structure(repository_id, structure)
+                .await?;
+        }
+
+        info!("Processed {} file structures in batch", structures.len());
+        Ok(())
+    }
+}
+
+/// The Directory node owning the files directly inside `dir_path`
+pub fn directory_entity(dir_path: &str) -> CodeEntity {
+    let name = Path::new(dir_path)
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| dir_path.to_string());
+    CodeEntity {
+        id: dir_path.to_string(),
+        path: dir_path.to_string(),
+        entity_type: EntityType::Directory,
+        start_line: None,
+        end_line: None,
+        span: None,
+        name_span: None,
+        properties: HashMap::from([("name".to_string(), name)]),
+        children: None,
+    }
+}
+
+/// The id a node declared in `from_path` gets when the file moves to
+/// `to_path`: the file's own id is its path and its entities' ids start with
+/// the path and `::`. Other ids are kept.
+pub fn moved_id(id: &str, from_path: &str, to_path: &str) -> String {
+    match id.strip_prefix(from_path) {
+        Some("") => to_path.to_string(),
+        Some(rest) if rest.starts_with("::") => format!("{}{}", to_path, rest),
+        _ => id.to_string(),
+    }
+}
+
+/// The `Repository` node of a checkout, its root Directory and the `HAS` edge
+/// between them. The owner is left as stored when not given.
+pub fn repository_node(
+    repo_path: &str,
+    repository_id: &str,
+    owner_id: Option<&str>,
+) -> (CodeEntity, CodeEntity, LinkEntity) {
+    let name = Path::new(repo_path)
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| repo_path.to_string());
+    let mut repository = CodeEntity {
+        id: format!("repository:{}", repository_id),
+        path: repo_path.to_string(),
+        entity_type: EntityType::Repository,
+        start_line: None,
+        end_line: None,
+        span: None,
+        name_span: None,
+        properties: HashMap::from([("name".to_string(), name.clone())]),
+        children: None,
+    };
+    if let Some(owner_id) = owner_id {
+        repository
+            .properties
+            .insert("ownerId".to_string(), owner_id.to_string());
+    }
+    let root = CodeEntity {
+        id: repo_path.to_string(),
+        path: repo_path.to_string(),
+        entity_type: EntityType::Directory,
+        start_line: None,
+        end_line: None,
+        span: None,
+        name_span: None,
+        properties: HashMap::from([("name".to_string(), name)]),
+        children: None,
+    };
+    let link = LinkEntity {
+        from_name: repository.id.clone(),
+        to_name: root.id.clone(),
+        link_type: LinkType::Has,
+        properties: HashMap::new(),
+    };
+    (repository, root, link)
+}
+
+/// Where the graph is kept
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphBackend {
+    Neo4j,
+    Memory,
+    /// Persistent local graph, no server needed
+    Disk,
+}
+
+impl GraphBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GraphBackend::Neo4j => "neo4j",
+            GraphBackend::Memory => "memory",
+            GraphBackend::Disk => "disk",
+        }
+    }
+
+    /// Reads `PEAR_GRAPH_STORE` (`neo4j`, `memory` or `disk`). Without it,
+    /// Neo4j is used when `NEO4J_URI` is set or a connection profile in
+    /// `config_dir` is active, and the local disk store otherwise.
+    pub fn from_env(config_dir: &Path) -> Result<Self> {
+        match env_utils::get("PEAR_GRAPH_STORE").as_deref() {
+            None if profiles::env_override() => Ok(GraphBackend::Neo4j),
+            None if profiles::Profiles::load(config_dir)?.active().is_some() => {
+                Ok(GraphBackend::Neo4j)
+            }
+            None => Ok(GraphBackend::Disk),
+            Some("neo4j") => Ok(GraphBackend::Neo4j),
+            Some("memory") => Ok(GraphBackend::Memory),
+            Some("disk") => Ok(GraphBackend::Disk),
+            Some(other) => Err(AppError::Config(format!(
+                "Unknown graph store '{}', expected 'neo4j', 'memory' or 'disk'",
+                other
+            ))),
+        }
+    }
+
+    /// Connects to the backend. Neo4j settings come from the environment or
+    /// the active profile in `config_dir` (see `profiles::neo4j_settings`);
+    /// the disk store lives in `graph_dir`.
+    pub async fn open(&self, graph_dir: &Path, config_dir: &Path) -> Result<Arc<dyn GraphStore>> {
+        match self {
+            GraphBackend::Neo4j => {
+                let neo_db = NeoDB::connect(profiles::neo4j_settings(config_dir)?).await?;
+                Ok(Arc::new(neo_db))
+            }
+            GraphBackend::Memory => Ok(Arc::new(MemoryGraph::new())),
+            GraphBackend::Disk => Ok(Arc::new(MemoryGraph::open(graph_dir)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::EntityType;
+
+    #[test]
+    fn test_sync_plan_only_moves_shifted_entities() {
+        let stored = HashMap::from([
+            ("f".to_string(), (Some("h1".to_string()), Some(0))),
+            ("g".to_string(), (Some("h2".to_string()), Some(10))),
+            ("h".to_string(), (Some("h3".to_string()), Some(20))),
+            ("gone".to_string(), (Some("h4".to_string()), Some(30))),
+            ("unhashed".to_string(), (None, Some(40))),
+        ]);
+        // A line inserted above g shifts g and h; only h's content changed
+        let entities: Vec<CodeEntity> = [
+            ("f", "h1", 0),
+            ("g", "h2", 15),
+            ("h", "h3b", 25),
+            ("unhashed", "h5", 45),
+            ("new", "h6", 50),
+        ]
+        .into_iter()
+        .map(|(id, hash, start_byte)| {
+            CodeEntity::fixture(id, "src/lib.rs", EntityType::Function)
+                .with_property(CONTENT_HASH_PROPERTY, hash)
+                .with_start_byte(start_byte)
+        })
+        .collect();
+        let plan = FileSyncPlan::new(&stored, &entities);
+        let ids = |entities: &[CodeEntity]| -> Vec<String> {
+            entities.iter().map(|e| e.id.clone()).collect()
+        };
+        assert_eq!(ids(&plan.changed), ["h", "unhashed", "new"]);
+        assert_eq!(ids(&plan.moved), ["g"]);
+        assert_eq!(plan.vanished, ["gone"]);
+        assert_eq!(plan.written(), 4);
+    }
+}
@@ -0,0 +1,362 @@
+//! Loads a JSON export (see `export`) into the configured graph store.
+//!
+//! The snapshot can replace what is stored for the repository or be merged
+//! into it, and can be moved to another checkout: paths and ids under the
+//! exported root are rewritten to start with the new one, and the repository
+//! id is recomputed from it.
+
+use crate::error::{AppError, Result};
+use crate::file_manager::export::{Snapshot, SnapshotNode, JSON_FORMAT, JSON_VERSION};
+use crate::file_manager::graph_store::{GraphStore, StoredEdge};
+use crate::file_manager::memory_store::is_known_rel_type;
+use crate::models::{CodeEntity, EntityType, Repository};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Deletes the repository's stored graph first
+    Replace,
+    /// Adds to the stored graph, updating nodes and edges present in both
+    #[default]
+    Merge,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportSummary {
+    pub repository_id: String,
+    pub root: String,
+    pub nodes: usize,
+    pub edges: usize,
+    /// Nodes with an unknown label and edges of an unknown type
+    pub skipped: usize,
+}
+
+/// Reads the JSON export at `path` and writes it to `graph`. With `root` the
+/// graph is moved to that checkout, otherwise it keeps the exported root.
+pub async fn import_graph(
+    graph: &dyn GraphStore,
+    path: &Path,
+    root: Option<&str>,
+    mode: ImportMode,
+) -> Result<ImportSummary> {
+    let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| AppError::Parse(format!("{}: {}", path.display(), e)))?;
+    if snapshot.format != JSON_FORMAT || snapshot.version > JSON_VERSION {
+        return Err(AppError::Parse(format!(
+            "{} is not a graph export this app can read ({} version {})",
+            path.display(),
+            snapshot.format,
+            snapshot.version
+        )));
+    }
+
+    let from = snapshot.root.trim_end_matches(['/', '\\']);
+    let to = root.unwrap_or(&snapshot.root).trim_end_matches(['/', '\\']);
+    let repository_id = Repository::id_for(Path::new(to));
+    let old_repository = format!("repository:{}", snapshot.repository_id);
+    let new_repository = format!("repository:{}", repository_id);
+    let remap_id = |id: &str| {
+        if id == old_repository {
+            new_repository.clone()
+        } else {
+            remap(id, from, to)
+        }
+    };
+
+    // Everything is converted before writing, so nothing is deleted for a
+    // snapshot that turns out to be unusable
+    let mut skipped = 0;
+    let mut nodes = Vec::with_capacity(snapshot.nodes.len());
+    for node in snapshot.nodes {
+        match entity(node, remap_id, from, to) {
+            Some(entity) => nodes.push(entity),
+            None => skipped += 1,
+        }
+    }
+    skipped += snapshot
+        .edges
+        .iter()
+        .filter(|edge| !is_known_rel_type(&edge.rel_type))
+        .count();
+    let edges: Vec<StoredEdge> = snapshot
+        .edges
+        .into_iter()
+        .filter(|edge| is_known_rel_type(&edge.rel_type))
+        .map(|edge| StoredEdge {
+            repository_id: repository_id.clone(),
+            from: remap_id(&edge.from),
+            rel_type: edge.rel_type,
+            to: remap_id(&edge.to),
+            properties: edge.properties.into_iter().collect(),
+        })
+        .collect();
+
+    let written = graph
+        .load_graph(&repository_id, &nodes, &edges, mode == ImportMode::Replace)
+        .await?;
+    if skipped > 0 {
+        warn!(
+            "Skipped {} nodes and edges of unknown types in {}",
+            skipped,
+            path.display()
+        );
+    }
+
+    info!(
+        "Imported {} nodes and {} edges from {} into {}",
+        nodes.len(),
+        written,
+        path.display(),
+        to
+    );
+    Ok(ImportSummary {
+        repository_id,
+        root: to.to_string(),
+        nodes: nodes.len(),
+        edges: written,
+        skipped,
+    })
+}
+
+/// `value` with its leading `from` root replaced by `to`. Only whole path
+/// components match, and an entity id's `::` suffix is kept.
+fn remap(value: &str, from: &str, to: &str) -> String {
+    if from == to {
+        return value.to_string();
+    }
+    match value.strip_prefix(from) {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\', ':']) => {
+            format!("{}{}", to, rest)
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn entity(
+    node: SnapshotNode,
+    remap_id: impl Fn(&str) -> String,
+    from: &str,
+    to: &str,
+) -> Option<CodeEntity> {
+    let entity_type = EntityType::from_label(&node.entity_type)?;
+    Some(CodeEntity {
+        id: remap_id(&node.id),
+        path: remap(&node.path, from, to),
+        entity_type,
+        start_line: node.start_line,
+        end_line: node.end_line,
+        span: node.span,
+        name_span: node.name_span,
+        properties: node
+            .properties
+            .into_iter()
+            .map(|(key, value)| (key, remap(&value, from, to)))
+            .collect(),
+        children: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::export::{export_graph, ExportFormat};
+    use crate::file_manager::graph_store::{repository_node, GraphFilter};
+    use crate::file_manager::memory_store::MemoryGraph;
+    use crate::models::{LinkEntity, LinkType};
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_remap_whole_components() {
+        assert_eq!(remap("/a/repo/x.rs::f", "/a/repo", "/b"), "/b/x.rs::f");
+        assert_eq!(remap("/a/repo", "/a/repo", "/b"), "/b");
+        assert_eq!(
+            remap("/a/repository/x.rs", "/a/repo", "/b"),
+            "/a/repository/x.rs"
+        );
+        assert_eq!(remap("main", "/a/repo", "/b"), "main");
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import_to_new_root() {
+        let dir = tempdir().unwrap();
+        let old_root = "/old/repo";
+        let old_id = Repository::id_for(Path::new(old_root));
+        let (repository, root_dir, has) = repository_node(old_root, &old_id, None);
+        let source = MemoryGraph::new();
+        source
+            .batch_ingest_entities(
+                &old_id,
+                &[
+                    repository,
+                    root_dir,
+                    CodeEntity::fixture(
+                        "/old/repo/a.rs::caller",
+                        "/old/repo/a.rs",
+                        EntityType::Function,
+                    )
+                    .with_name("caller")
+                    .with_lines(1, 2),
+                    CodeEntity::fixture(
+                        "/old/repo/a.rs::callee",
+                        "/old/repo/a.rs",
+                        EntityType::Function,
+                    )
+                    .with_name("callee")
+                    .with_lines(1, 2),
+                ],
+            )
+            .await
+            .unwrap();
+        let tests = LinkEntity {
+            from_name: "/old/repo/a.rs::caller".to_string(),
+            to_name: "/old/repo/a.rs::callee".to_string(),
+            link_type: LinkType::Tests,
+            properties: HashMap::new(),
+        };
+        source
+            .batch_create_links(&old_id, &[has, tests])
+            .await
+            .unwrap();
+
+        let file = dir.path().join("graph.json");
+        let exported = export_graph(
+            &source,
+            old_root,
+            &GraphFilter::default(),
+            Some(ExportFormat::Json),
+            &file,
+        )
+        .await
+        .unwrap();
+        assert_eq!((exported.nodes, exported.edges), (4, 2));
+
+        let target = MemoryGraph::new();
+        let imported = import_graph(&target, &file, Some("/new/repo/"), ImportMode::Replace)
+            .await
+            .unwrap();
+        assert_eq!(
+            (imported.nodes, imported.edges, imported.skipped),
+            (4, 2, 0)
+        );
+        assert_eq!(imported.root, "/new/repo");
+
+        let (nodes, edges) = target
+            .subgraph(&imported.repository_id, &GraphFilter::default())
+            .await
+            .unwrap();
+        assert!(nodes.iter().all(|n| n.path.starts_with("/new/repo")));
+        assert!(nodes
+            .iter()
+            .any(|n| n.id == format!("repository:{}", imported.repository_id)));
+        assert!(edges
+            .iter()
+            .any(|e| e.from == "/new/repo/a.rs::caller" && e.rel_type == "TESTS"));
+
+        // Importing again merges into the same nodes
+        import_graph(&target, &file, Some("/new/repo"), ImportMode::Merge)
+            .await
+            .unwrap();
+        assert_eq!(target.read().unwrap().nodes.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_replace_is_one_batch_after_conversion() {
+        let dir = tempdir().unwrap();
+        let root = "/repo";
+        let repository_id = Repository::id_for(Path::new(root));
+        let source = MemoryGraph::new();
+        source
+            .batch_ingest_entities(
+                &repository_id,
+                &[
+                    CodeEntity::fixture("/repo/a.rs::f", "/repo/a.rs", EntityType::Function)
+                        .with_name("f")
+                        .with_lines(1, 2),
+                    CodeEntity::fixture("/repo/a.rs::g", "/repo/a.rs", EntityType::Function)
+                        .with_name("g")
+                        .with_lines(1, 2),
+                ],
+            )
+            .await
+            .unwrap();
+        let calls = LinkEntity {
+            from_name: "/repo/a.rs::f".to_string(),
+            to_name: "/repo/a.rs::g".to_string(),
+            link_type: LinkType::Calls,
+            properties: HashMap::new(),
+        };
+        source
+            .batch_create_links(&repository_id, &[calls])
+            .await
+            .unwrap();
+        let file = dir.path().join("graph.json");
+        export_graph(
+            &source,
+            root,
+            &GraphFilter::default(),
+            Some(ExportFormat::Json),
+            &file,
+        )
+        .await
+        .unwrap();
+
+        // A label and a relationship type this version doesn't know
+        let mut json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        json["nodes"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({
+                "id": "/repo/a.rs::x", "type": "Hologram", "path": "/repo/a.rs"
+            }));
+        json["edges"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({
+                "from": "/repo/a.rs::f", "type": "HAUNTS", "to": "/repo/a.rs::g"
+            }));
+        std::fs::write(&file, json.to_string()).unwrap();
+
+        let store = dir.path().join("store");
+        let target = MemoryGraph::open(&store).unwrap();
+        target
+            .batch_ingest_entities(
+                &repository_id,
+                &[
+                    CodeEntity::fixture("/repo/old.rs::h", "/repo/old.rs", EntityType::Function)
+                        .with_name("h")
+                        .with_lines(1, 2),
+                ],
+            )
+            .await
+            .unwrap();
+        let journal = || {
+            std::fs::read_to_string(store.join("journal.jsonl"))
+                .unwrap()
+                .lines()
+                .count()
+        };
+        assert_eq!(journal(), 1);
+
+        let imported = import_graph(&target, &file, None, ImportMode::Replace)
+            .await
+            .unwrap();
+        assert_eq!(
+            (imported.nodes, imported.edges, imported.skipped),
+            (2, 1, 2)
+        );
+        assert_eq!(journal(), 2);
+        let data = target.read().unwrap();
+        let mut ids: Vec<&str> = data.nodes.values().map(|n| n.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, ["/repo/a.rs::f", "/repo/a.rs::g"]);
+        assert_eq!(data.edges.len(), 1);
+    }
+}
@@ -0,0 +1,1582 @@
+//! In-memory `GraphStore`, optionally persisted to disk.
+//!
+//! Every write is expressed as a list of `GraphOp`s that are applied to
+//! `GraphData` under one lock, so a batch is either applied whole or not at
+//! all. A persistent graph appends each batch to its journal before applying
+//! it (see `disk_store`). Reads go straight to the maps. Nodes are keyed by
+//! repository and id, and carry their repository in `repository_id`; they
+//! are also indexed by path, and edges by the nodes they connect.
+
+use crate::analysis::clones::ClonePair;
+use crate::analysis::hashing::{CONTENT_HASH_PROPERTY, FILE_HASH_PROPERTY};
+use crate::analysis::import_cycles::{self, CycleGroup};
+use crate::error::{AppError, Result};
+use crate::file_manager::disk_store::{self, Journal};
+use crate::file_manager::graph_store::{
+    directory_entity, moved_id, parser_keys, repository_node, symbol_rank, Direction, EdgeKey,
+    FileSyncPlan, GraphFilter, GraphHealth, GraphStore, StoredEdge, PARSER_KEYS_PROPERTY,
+    REPOSITORY_ID_PROPERTY,
+};
+use crate::file_manager::search::{self, SearchFilter};
+use crate::models::{CodeEntity, EntityReference, EntityType, LinkEntity, LinkType, TodoItem};
+
+use async_trait::async_trait;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Nodes or edges sent per call when copying into another store
+pub const COPY_BATCH_SIZE: usize = 500;
+
+/// `(repository_id, id)`
+pub type NodeKey = (String, String);
+
+/// A single change to the graph
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum GraphOp {
+    /// Creates a node or updates it, replacing the properties it was last
+    /// written with (see `PARSER_KEYS_PROPERTY`) by the entity's
+    PutNode(CodeEntity),
+    /// Updates only the lines and source positions of a stored node, for
+    /// entities whose content didn't change; ignored if the node is missing
+    MoveNode(CodeEntity),
+    /// Deletes nodes together with their edges
+    DeleteNodes(Vec<NodeKey>),
+    /// Creates or updates an edge; ignored if either end is missing
+    PutEdge(StoredEdge),
+    DeleteEdges(Vec<EdgeKey>),
+}
+
+#[derive(Debug, Default)]
+pub struct GraphData {
+    pub nodes: HashMap<NodeKey, CodeEntity>,
+    pub edges: HashMap<EdgeKey, HashMap<String, String>>,
+    /// Node ids per repository and path
+    by_path: HashMap<(String, String), HashSet<String>>,
+    /// Keys of the edges leaving or entering each node
+    adjacency: HashMap<NodeKey, HashSet<EdgeKey>>,
+}
+
+impl GraphData {
+    pub fn apply(&mut self, op: GraphOp) {
+        match op {
+            GraphOp::PutNode(entity) => {
+                let key = node_key(&entity);
+                let old_path = self.nodes.get(&key).map(|node| node.path.clone());
+                if old_path.as_ref() != Some(&entity.path) {
+                    if let Some(old_path) = old_path {
+                        self.unindex(&key, old_path);
+                    }
+                    self.index(&key, entity.path.clone());
+                }
+                match self.nodes.get_mut(&key) {
+                    Some(node) => {
+                        node.path = entity.path;
+                        node.entity_type = entity.entity_type;
+                        node.start_line = entity.start_line;
+                        node.end_line = entity.end_line;
+                        node.span = entity.span;
+                        node.name_span = entity.name_span;
+                        if let Some(keys) = node.properties.get(PARSER_KEYS_PROPERTY).cloned() {
+                            for key in keys.split(',') {
+                                if !entity.properties.contains_key(key) {
+                                    node.properties.remove(key);
+                                }
+                            }
+                        }
+                        node.properties.extend(entity.properties);
+                    }
+                    None => {
+                        self.nodes.insert(
+                            key,
+                            CodeEntity {
+                                children: None,
+                                ..entity
+                            },
+                        );
+                    }
+                }
+            }
+            GraphOp::MoveNode(entity) => {
+                if let Some(node) = self.nodes.get_mut(&node_key(&entity)) {
+                    node.start_line = entity.start_line;
+                    node.end_line = entity.end_line;
+                    node.span = entity.span;
+                    node.name_span = entity.name_span;
+                }
+            }
+            GraphOp::DeleteNodes(keys) => {
+                for key in keys {
+                    let Some(node) = self.nodes.remove(&key) else {
+                        continue;
+                    };
+                    self.unindex(&key, node.path);
+                    for edge in self.adjacency.remove(&key).unwrap_or_default() {
+                        self.remove_edge(&edge);
+                    }
+                }
+            }
+            GraphOp::PutEdge(edge) => {
+                let from = (edge.repository_id.clone(), edge.from.clone());
+                let to = (edge.repository_id.clone(), edge.to.clone());
+                if !self.nodes.contains_key(&from) || !self.nodes.contains_key(&to) {
+                    return;
+                }
+                let key = (edge.repository_id, edge.from, edge.rel_type, edge.to);
+                self.adjacency.entry(from).or_default().insert(key.clone());
+                self.adjacency.entry(to).or_default().insert(key.clone());
+                self.edges.entry(key).or_default().extend(edge.properties);
+            }
+            GraphOp::DeleteEdges(keys) => {
+                for key in keys {
+                    self.remove_edge(&key);
+                }
+            }
+        }
+    }
+
+    fn index(&mut self, (repository_id, id): &NodeKey, path: String) {
+        self.by_path
+            .entry((repository_id.clone(), path))
+            .or_default()
+            .insert(id.clone());
+    }
+
+    fn unindex(&mut self, (repository_id, id): &NodeKey, path: String) {
+        let path_key = (repository_id.clone(), path);
+        if let Some(ids) = self.by_path.get_mut(&path_key) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.by_path.remove(&path_key);
+            }
+        }
+    }
+
+    // Removes an edge and its entries in the adjacency of both ends
+    fn remove_edge(&mut self, key: &EdgeKey) {
+        if self.edges.remove(key).is_none() {
+            return;
+        }
+        let (repository_id, from, _, to) = key;
+        for id in [from, to] {
+            let node = (repository_id.clone(), id.clone());
+            if let Some(edges) = self.adjacency.get_mut(&node) {
+                edges.remove(key);
+                if edges.is_empty() {
+                    self.adjacency.remove(&node);
+                }
+            }
+        }
+    }
+
+    /// All edges as stored records
+    pub fn stored_edges(&self) -> Vec<StoredEdge> {
+        self.edges.iter().map(stored_edge).collect()
+    }
+
+    fn node(&self, repository_id: &str, id: &str) -> Option<&CodeEntity> {
+        self.nodes.get(&(repository_id.to_string(), id.to_string()))
+    }
+
+    /// Nodes of one repository
+    fn nodes_in<'a>(&'a self, repository_id: &'a str) -> impl Iterator<Item = &'a CodeEntity> + 'a {
+        self.nodes
+            .iter()
+            .filter(move |((repo, _), _)| repo == repository_id)
+            .map(|(_, node)| node)
+    }
+
+    /// Nodes of one repository at `path`, e.g. a file and its entities
+    fn nodes_at<'a>(
+        &'a self,
+        repository_id: &'a str,
+        path: &str,
+    ) -> impl Iterator<Item = &'a CodeEntity> + 'a {
+        self.by_path
+            .get(&(repository_id.to_string(), path.to_string()))
+            .into_iter()
+            .flatten()
+            .filter_map(move |id| self.node(repository_id, id))
+    }
+
+    /// Edges leaving or entering a node
+    fn edges_of(&self, repository_id: &str, id: &str) -> impl Iterator<Item = &EdgeKey> {
+        self.adjacency
+            .get(&(repository_id.to_string(), id.to_string()))
+            .into_iter()
+            .flatten()
+    }
+
+    fn edges_of_type<'a>(
+        &'a self,
+        repository_id: &'a str,
+        rel_type: &'a str,
+    ) -> impl Iterator<Item = (&'a CodeEntity, &'a CodeEntity)> + 'a {
+        self.edges
+            .keys()
+            .filter(move |(repo, _, rel, _)| rel == rel_type && repo == repository_id)
+            .filter_map(|(repo, from, _, to)| Some((self.node(repo, from)?, self.node(repo, to)?)))
+    }
+
+    fn reference(node: &CodeEntity) -> EntityReference {
+        EntityReference {
+            name: node
+                .properties
+                .get("qualified_name")
+                .cloned()
+                .unwrap_or_default(),
+            path: node.path.clone(),
+            start_line: node.start_line,
+        }
+    }
+}
+
+fn node_key(entity: &CodeEntity) -> NodeKey {
+    (repository_of(entity).to_string(), entity.id.clone())
+}
+
+fn repository_of(entity: &CodeEntity) -> &str {
+    entity
+        .properties
+        .get(REPOSITORY_ID_PROPERTY)
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+// The entity as stored: without children, tagged with its repository and
+// the keys of its properties
+fn scoped(repository_id: &str, entity: &CodeEntity) -> CodeEntity {
+    let mut entity = CodeEntity {
+        children: None,
+        ..entity.clone()
+    };
+    entity.properties.insert(
+        PARSER_KEYS_PROPERTY.to_string(),
+        parser_keys(&entity.properties),
+    );
+    entity.properties.insert(
+        REPOSITORY_ID_PROPERTY.to_string(),
+        repository_id.to_string(),
+    );
+    entity
+}
+
+fn stored_edge(
+    ((repository_id, from, rel_type, to), properties): (&EdgeKey, &HashMap<String, String>),
+) -> StoredEdge {
+    StoredEdge {
+        repository_id: repository_id.clone(),
+        from: from.clone(),
+        rel_type: rel_type.clone(),
+        to: to.clone(),
+        properties: properties.clone(),
+    }
+}
+
+fn sort_edges(edges: &mut [StoredEdge]) {
+    edges.sort_by(|a, b| (&a.from, &a.rel_type, &a.to).cmp(&(&b.from, &b.rel_type, &b.to)));
+}
+
+fn edge(repository_id: &str, link: &LinkEntity) -> StoredEdge {
+    StoredEdge {
+        repository_id: repository_id.to_string(),
+        from: link.from_name.clone(),
+        rel_type: link.link_type.rel_type().to_string(),
+        to: link.to_name.clone(),
+        properties: link.properties.clone(),
+    }
+}
+
+/// Whether `write_edges` writes edges of this relationship type
+pub fn is_known_rel_type(rel_type: &str) -> bool {
+    LinkType::from_rel_type(rel_type).is_some()
+}
+
+/// Writes stored edges into a store in batches, replacing its `DUPLICATE_OF`
+/// edges when there are any. Edges of unknown types are skipped. Returns the
+/// number written.
+pub async fn write_edges<S: GraphStore + ?Sized>(
+    target: &S,
+    repository_id: &str,
+    edges: &[StoredEdge],
+) -> Result<usize> {
+    let mut links = Vec::new();
+    let mut duplicates = Vec::new();
+    for edge in edges {
+        if edge.rel_type == LinkType::DuplicateOf.rel_type() {
+            duplicates.push(ClonePair {
+                from_id: edge.from.clone(),
+                to_id: edge.to.clone(),
+                similarity: edge
+                    .properties
+                    .get("similarity")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(1.0),
+                group: edge.properties.get("group").cloned().unwrap_or_default(),
+            });
+        } else if let Some(link_type) = LinkType::from_rel_type(&edge.rel_type) {
+            links.push(LinkEntity {
+                from_name: edge.from.clone(),
+                to_name: edge.to.clone(),
+                link_type,
+                properties: edge.properties.clone(),
+            });
+        }
+    }
+
+    for chunk in links.chunks(COPY_BATCH_SIZE) {
+        target.batch_create_links(repository_id, chunk).await?;
+    }
+    if !duplicates.is_empty() {
+        target
+            .replace_duplicate_links(repository_id, &duplicates)
+            .await?;
+    }
+    Ok(links.len() + duplicates.len())
+}
+
+fn sort_references(references: &mut Vec<EntityReference>) {
+    references.sort_by(|a, b| (&a.path, a.start_line).cmp(&(&b.path, b.start_line)));
+    references
+        .dedup_by(|a, b| a.name == b.name && a.path == b.path && a.start_line == b.start_line);
+}
+
+#[derive(Default)]
+pub struct MemoryGraph {
+    data: RwLock<GraphData>,
+    journal: Option<Mutex<Journal>>,
+}
+
+impl MemoryGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the persistent graph stored in `dir`
+    pub fn open(dir: &Path) -> Result<Self> {
+        let (data, journal) = Journal::open(dir)?;
+        Ok(MemoryGraph {
+            data: RwLock::new(data),
+            journal: Some(Mutex::new(journal)),
+        })
+    }
+
+    /// Loads a copy of the graph stored in `dir`; changes are not persisted
+    pub fn load(dir: &Path) -> Result<Self> {
+        Ok(MemoryGraph {
+            data: RwLock::new(disk_store::load(dir)?),
+            journal: None,
+        })
+    }
+
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, GraphData>> {
+        self.data
+            .read()
+            .map_err(|_| AppError::Storage("Graph store lock poisoned".to_string()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, GraphData>> {
+        self.data
+            .write()
+            .map_err(|_| AppError::Storage("Graph store lock poisoned".to_string()))
+    }
+
+    // Entities of a fresh parse to write, move or delete
+    fn sync_plan(
+        data: &GraphData,
+        repository_id: &str,
+        file_path: &str,
+        entities: &[CodeEntity],
+    ) -> FileSyncPlan {
+        let stored: HashMap<String, (Option<String>, Option<i64>)> = data
+            .nodes_at(repository_id, file_path)
+            .filter(|n| !matches!(n.entity_type, EntityType::File | EntityType::Directory))
+            .map(|n| {
+                (
+                    n.id.clone(),
+                    (
+                        n.properties.get(CONTENT_HASH_PROPERTY).cloned(),
+                        n.span.map(|span| span.start_byte as i64),
+                    ),
+                )
+            })
+            .collect();
+        FileSyncPlan::new(&stored, entities)
+    }
+
+    fn sync_ops(repository_id: &str, plan: &FileSyncPlan) -> Vec<GraphOp> {
+        let mut ops = vec![GraphOp::DeleteNodes(
+            plan.vanished
+                .iter()
+                .map(|id| (repository_id.to_string(), id.clone()))
+                .collect(),
+        )];
+        ops.extend(
+            plan.changed
+                .iter()
+                .map(|e| GraphOp::PutNode(scoped(repository_id, e))),
+        );
+        ops.extend(
+            plan.moved
+                .iter()
+                .map(|e| GraphOp::MoveNode(scoped(repository_id, e))),
+        );
+        ops
+    }
+
+    // Whether the stored File node has the same whole-file hash
+    fn file_unchanged(data: &GraphData, repository_id: &str, file: &CodeEntity) -> bool {
+        match (
+            data.node(repository_id, &file.id),
+            file.properties.get(FILE_HASH_PROPERTY),
+        ) {
+            (Some(stored), Some(hash)) => stored.properties.get(FILE_HASH_PROPERTY) == Some(hash),
+            _ => false,
+        }
+    }
+
+    /// Applies a batch of changes under one lock, journaling it first when
+    /// the graph is persistent
+    pub fn commit(&self, ops: Vec<GraphOp>) -> Result<()> {
+        self.commit_with(|_| (ops, ()))
+    }
+
+    /// Like `commit`, but builds the batch from the graph under the same
+    /// lock, so nothing written in between is missed or overwritten
+    pub fn commit_with<T>(&self, build: impl FnOnce(&GraphData) -> (Vec<GraphOp>, T)) -> Result<T> {
+        let mut data = self.write()?;
+        let (ops, result) = build(&data);
+        if ops.is_empty() {
+            return Ok(result);
+        }
+        let Some(journal) = &self.journal else {
+            for op in ops {
+                data.apply(op);
+            }
+            return Ok(result);
+        };
+
+        let mut journal = journal
+            .lock()
+            .map_err(|_| AppError::Storage("Graph journal lock poisoned".to_string()))?;
+        journal.append(&ops)?;
+        for op in ops {
+            data.apply(op);
+        }
+        // The batch is journaled and applied, so a failed compaction only
+        // means a longer journal to replay
+        if journal.needs_compaction() {
+            if let Err(e) = journal.compact(&data) {
+                error!("Failed to compact the graph store: {}", e);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Copies every node and edge into another store, e.g. a Neo4j database,
+    /// replacing its `DUPLICATE_OF` edges. Returns the number of nodes and
+    /// edges written.
+    pub async fn export_into(&self, target: &dyn GraphStore) -> Result<(usize, usize)> {
+        let (nodes, edges) = {
+            let data = self.read()?;
+            let mut nodes: HashMap<String, Vec<CodeEntity>> = HashMap::new();
+            for ((repository_id, _), node) in &data.nodes {
+                nodes
+                    .entry(repository_id.clone())
+                    .or_default()
+                    .push(node.clone());
+            }
+            (nodes, data.stored_edges())
+        };
+
+        let mut node_count = 0;
+        for (repository_id, nodes) in &nodes {
+            for chunk in nodes.chunks(COPY_BATCH_SIZE) {
+                target.batch_ingest_entities(repository_id, chunk).await?;
+            }
+            node_count += nodes.len();
+        }
+
+        let mut by_repository: HashMap<String, Vec<StoredEdge>> = HashMap::new();
+        for edge in edges {
+            by_repository
+                .entry(edge.repository_id.clone())
+                .or_default()
+                .push(edge);
+        }
+        let mut edge_count = 0;
+        for (repository_id, edges) in &by_repository {
+            edge_count += write_edges(target, repository_id, edges).await?;
+        }
+
+        info!("Exported {} nodes and {} edges", node_count, edge_count);
+        Ok((node_count, edge_count))
+    }
+}
+
+#[async_trait]
+impl GraphStore for MemoryGraph {
+    async fn ingest_entity(&self, repository_id: &str, entity: &CodeEntity) -> Result<()> {
+        self.commit(vec![GraphOp::PutNode(scoped(repository_id, entity))])
+    }
+
+    async fn batch_ingest_entities(
+        &self,
+        repository_id: &str,
+        entities: &[CodeEntity],
+    ) -> Result<()> {
+        self.commit(
+            entities
+                .iter()
+                .map(|e| GraphOp::PutNode(scoped(repository_id, e)))
+                .collect(),
+        )
+    }
+
+    async fn sync_file_entities(
+        &self,
+        repository_id: &str,
+        file_path: &str,
+        entities: &[CodeEntity],
+    ) -> Result<(usize, usize)> {
+        let plan = self.commit_with(|data| {
+            let plan = Self::sync_plan(data, repository_id, file_path, entities);
+            (Self::sync_ops(repository_id, &plan), plan)
+        })?;
+
+        let written = plan.written();
+        let deleted = plan.vanished.len();
+        info!(
+            "Synced {}: {} written, {} deleted, {} unchanged",
+            file_path,
+            written,
+            deleted,
+            entities.len() - written
+        );
+        Ok((written, deleted))
+    }
+
+    /// Commits the whole file as one batch, so the journal holds all of it or
+    /// none of it
+    async fn ingest_file(
+        &self,
+        repository_id: &str,
+        file: &CodeEntity,
+        entities: &[CodeEntity],
+        links: &[LinkEntity],
+    ) -> Result<()> {
+        let rel_types: HashSet<&str> = LinkType::FILE_LOCAL.iter().map(|t| t.rel_type()).collect();
+        let plan = self
+            .commit_with(|data| {
+                let plan = Self::sync_plan(data, repository_id, &file.path, entities);
+                let in_file = |id: &str| {
+                    data.node(repository_id, id)
+                        .is_some_and(|n| n.path == file.path)
+                };
+                let stale_edges: HashSet<EdgeKey> = data
+                    .nodes_at(repository_id, &file.path)
+                    .flat_map(|n| data.edges_of(repository_id, &n.id))
+                    .filter(|(_, from, rel, to)| {
+                        rel_types.contains(rel.as_str()) && in_file(from) && in_file(to)
+                    })
+                    .cloned()
+                    .collect();
+
+                let mut ops = Vec::new();
+                if !Self::file_unchanged(data, repository_id, file) {
+                    ops.push(GraphOp::PutNode(scoped(repository_id, file)));
+                }
+                ops.push(GraphOp::DeleteEdges(stale_edges.into_iter().collect()));
+                ops.extend(Self::sync_ops(repository_id, &plan));
+                ops.extend(
+                    links
+                        .iter()
+                        .map(|l| GraphOp::PutEdge(edge(repository_id, l))),
+                );
+                (ops, plan)
+            })
+            .map_err(|e| AppError::Ingest(file.path.clone(), e.to_string()))?;
+
+        info!(
+            "Ingested {}: {} written, {} moved, {} deleted, {} unchanged, {} links",
+            file.path,
+            plan.changed.len(),
+            plan.moved.len(),
+            plan.vanished.len(),
+            entities.len() - plan.written(),
+            links.len()
+        );
+        Ok(())
+    }
+
+    async fn create_link(&self, repository_id: &str, link: &LinkEntity) -> Result<()> {
+        self.commit(vec![GraphOp::PutEdge(edge(repository_id, link))])
+    }
+
+    async fn batch_create_links(&self, repository_id: &str, links: &[LinkEntity]) -> Result<()> {
+        self.commit(
+            links
+                .iter()
+                .map(|l| GraphOp::PutEdge(edge(repository_id, l)))
+                .collect(),
+        )?;
+        info!("Created {} relationships in batch", links.len());
+        Ok(())
+    }
+
+    async fn replace_resolved_links(
+        &self,
+        repository_id: &str,
+        links: &[LinkEntity],
+    ) -> Result<()> {
+        let resolved: HashSet<&str> = LinkType::RESOLVED.iter().map(|t| t.rel_type()).collect();
+        self.commit_with(|data| {
+            let stale: Vec<EdgeKey> = data
+                .edges
+                .keys()
+                .filter(|(repo, _, rel, to)| {
+                    repo == repository_id
+                        && (resolved.contains(rel.as_str())
+                            || (rel == LinkType::Import.rel_type()
+                                && !data
+                                    .node(repo, to)
+                                    .is_some_and(|n| matches!(n.entity_type, EntityType::Import))))
+                })
+                .cloned()
+                .collect();
+
+            let mut ops = vec![GraphOp::DeleteEdges(stale)];
+            ops.extend(
+                links
+                    .iter()
+                    .map(|l| GraphOp::PutEdge(edge(repository_id, l))),
+            );
+            (ops, ())
+        })?;
+        info!("Replaced resolved links with {} relationships", links.len());
+        Ok(())
+    }
+
+    async fn replace_duplicate_links(
+        &self,
+        repository_id: &str,
+        pairs: &[ClonePair],
+    ) -> Result<()> {
+        self.commit_with(|data| {
+            let stale: Vec<EdgeKey> = data
+                .edges
+                .keys()
+                .filter(|(repo, _, rel, _)| {
+                    repo == repository_id && rel == LinkType::DuplicateOf.rel_type()
+                })
+                .cloned()
+                .collect();
+
+            let mut ops = vec![GraphOp::DeleteEdges(stale)];
+            ops.extend(pairs.iter().map(|p| {
+                GraphOp::PutEdge(StoredEdge {
+                    repository_id: repository_id.to_string(),
+                    from: p.from_id.clone(),
+                    rel_type: LinkType::DuplicateOf.rel_type().to_string(),
+                    to: p.to_id.clone(),
+                    properties: HashMap::from([
+                        ("similarity".to_string(), p.similarity.to_string()),
+                        ("group".to_string(), p.group.clone()),
+                    ]),
+                })
+            }));
+            (ops, ())
+        })?;
+
+        info!("Created {} duplicate code links", pairs.len());
+        Ok(())
+    }
+
+    async fn replace_cycle_groups(&self, repository_id: &str, groups: &[CycleGroup]) -> Result<()> {
+        let (nodes, links) = import_cycles::marker_nodes(groups);
+        self.commit_with(|data| {
+            let stale: Vec<NodeKey> = data
+                .nodes_in(repository_id)
+                .filter(|n| matches!(n.entity_type, EntityType::CycleGroup))
+                .map(node_key)
+                .collect();
+
+            let mut ops = vec![GraphOp::DeleteNodes(stale)];
+            ops.extend(
+                nodes
+                    .iter()
+                    .map(|n| GraphOp::PutNode(scoped(repository_id, n))),
+            );
+            ops.extend(
+                links
+                    .iter()
+                    .map(|l| GraphOp::PutEdge(edge(repository_id, l))),
+            );
+            (ops, ())
+        })?;
+
+        info!("Marked {} import cycles", groups.len());
+        Ok(())
+    }
+
+    async fn register_repository(
+        &self,
+        repo_path: &str,
+        repository_id: &str,
+        owner_id: &str,
+    ) -> Result<()> {
+        let (repository, root, link) = repository_node(repo_path, repository_id, Some(owner_id));
+        self.commit(vec![
+            GraphOp::PutNode(scoped(repository_id, &repository)),
+            GraphOp::PutNode(scoped(repository_id, &root)),
+            GraphOp::PutEdge(edge(repository_id, &link)),
+        ])?;
+
+        info!("Registered repository: {}", repo_path);
+        Ok(())
+    }
+
+    /// Applies the whole graph as one batch, so a failure leaves the stored
+    /// one as it was
+    async fn load_graph(
+        &self,
+        repository_id: &str,
+        nodes: &[CodeEntity],
+        edges: &[StoredEdge],
+        replace: bool,
+    ) -> Result<usize> {
+        let edges: Vec<&StoredEdge> = edges
+            .iter()
+            .filter(|e| is_known_rel_type(&e.rel_type))
+            .collect();
+        let written = edges.len();
+        self.commit_with(|data| {
+            let mut ops = Vec::new();
+            if replace {
+                ops.push(GraphOp::DeleteNodes(
+                    data.nodes_in(repository_id).map(node_key).collect(),
+                ));
+            } else if edges
+                .iter()
+                .any(|e| e.rel_type == LinkType::DuplicateOf.rel_type())
+            {
+                ops.push(GraphOp::DeleteEdges(
+                    data.edges
+                        .keys()
+                        .filter(|(repo, _, rel, _)| {
+                            repo == repository_id && rel == LinkType::DuplicateOf.rel_type()
+                        })
+                        .cloned()
+                        .collect(),
+                ));
+            }
+            ops.extend(
+                nodes
+                    .iter()
+                    .map(|n| GraphOp::PutNode(scoped(repository_id, n))),
+            );
+            ops.extend(edges.iter().map(|e| {
+                GraphOp::PutEdge(StoredEdge {
+                    repository_id: repository_id.to_string(),
+                    ..(*e).clone()
+                })
+            }));
+            (ops, ())
+        })?;
+
+        info!(
+            "Loaded {} nodes and {} edges into {}",
+            nodes.len(),
+            written,
+            repository_id
+        );
+        Ok(written)
+    }
+
+    async fn remove_file(&self, repository_id: &str, path: &Path) -> Result<()> {
+        let file_path = path.to_string_lossy().to_string();
+        self.commit_with(|data| {
+            let keys: Vec<NodeKey> = data
+                .nodes_at(repository_id, &file_path)
+                .filter(|n| {
+                    !matches!(
+                        n.entity_type,
+                        EntityType::Directory | EntityType::Repository
+                    )
+                })
+                .map(node_key)
+                .collect();
+            (vec![GraphOp::DeleteNodes(keys)], ())
+        })?;
+
+        info!("Removed file from graph: {}", file_path);
+        Ok(())
+    }
+
+    async fn prune_missing(&self, repository_id: &str, present: &HashSet<String>) -> Result<usize> {
+        let gone = self.commit_with(|data| {
+            let gone: HashSet<&str> = data
+                .nodes_in(repository_id)
+                .filter(|n| matches!(n.entity_type, EntityType::File | EntityType::Directory))
+                .filter(|n| !present.contains(&n.path))
+                .map(|n| n.path.as_str())
+                .collect();
+            let keys: Vec<NodeKey> = gone
+                .iter()
+                .flat_map(|path| data.nodes_at(repository_id, path))
+                .filter(|n| !matches!(n.entity_type, EntityType::Repository))
+                .map(node_key)
+                .collect();
+            (vec![GraphOp::DeleteNodes(keys)], gone.len())
+        })?;
+
+        info!("Pruned {} missing paths from {}", gone, repository_id);
+        Ok(gone)
+    }
+
+    async fn remove_repository(&self, repository_id: &str) -> Result<usize> {
+        let deleted = self.commit_with(|data| {
+            let keys: Vec<NodeKey> = data.nodes_in(repository_id).map(node_key).collect();
+            let deleted = keys.len();
+            (vec![GraphOp::DeleteNodes(keys)], deleted)
+        })?;
+
+        info!("Removed {} nodes of repository {}", deleted, repository_id);
+        Ok(deleted)
+    }
+
+    async fn update_file_path(
+        &self,
+        repository_id: &str,
+        from_path: &Path,
+        to_path: &Path,
+    ) -> Result<()> {
+        let old_path = from_path.to_string_lossy().to_string();
+        let new_path = to_path.to_string_lossy().to_string();
+
+        let name = to_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let parent_path = to_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_string_lossy()
+            .to_string();
+        let moved = self.commit_with(|data| {
+            let declared: Vec<&CodeEntity> = data
+                .nodes_at(repository_id, &old_path)
+                .filter(|n| {
+                    !matches!(
+                        n.entity_type,
+                        EntityType::Directory | EntityType::Repository
+                    )
+                })
+                .collect();
+            if !declared
+                .iter()
+                .any(|n| matches!(n.entity_type, EntityType::File))
+            {
+                return (Vec::new(), false);
+            }
+            let ids: HashMap<&str, String> = declared
+                .iter()
+                .map(|n| (n.id.as_str(), moved_id(&n.id, &old_path, &new_path)))
+                .collect();
+            let new_id = |id: &str| ids.get(id).cloned().unwrap_or_else(|| id.to_string());
+
+            // Edges are carried over to the new ids, except the ones from the
+            // old parent directory
+            let owns = LinkType::Owns.rel_type();
+            let has = LinkType::Has.rel_type();
+            let edges: HashSet<&EdgeKey> = declared
+                .iter()
+                .flat_map(|n| data.edges_of(repository_id, &n.id))
+                .collect();
+            let kept: Vec<StoredEdge> = edges
+                .into_iter()
+                .filter(|(_, from, rel_type, _)| {
+                    ids.contains_key(from.as_str())
+                        || !(rel_type == owns || rel_type == has)
+                        || !matches!(
+                            data.node(repository_id, from).map(|n| &n.entity_type),
+                            Some(EntityType::Directory)
+                        )
+                })
+                .map(|key| {
+                    let mut edge = stored_edge((key, &data.edges[key]));
+                    edge.from = new_id(&edge.from);
+                    edge.to = new_id(&edge.to);
+                    edge
+                })
+                .collect();
+
+            let mut deleted: Vec<NodeKey> = declared.iter().map(|n| node_key(n)).collect();
+            deleted.extend(
+                data.nodes_at(repository_id, &new_path)
+                    .filter(|n| {
+                        !matches!(
+                            n.entity_type,
+                            EntityType::Directory | EntityType::Repository
+                        )
+                    })
+                    .map(node_key),
+            );
+            let mut ops = vec![GraphOp::DeleteNodes(deleted)];
+            let mut file_id = new_path.clone();
+            for node in &declared {
+                let mut node = (*node).clone();
+                node.id = new_id(&node.id);
+                node.path = new_path.clone();
+                if matches!(node.entity_type, EntityType::File) {
+                    node.properties.insert("name".to_string(), name.clone());
+                    file_id = node.id.clone();
+                }
+                ops.push(GraphOp::PutNode(node));
+            }
+            ops.extend(kept.into_iter().map(GraphOp::PutEdge));
+            if !parent_path.is_empty() {
+                if data.node(repository_id, &parent_path).is_none() {
+                    ops.push(GraphOp::PutNode(scoped(
+                        repository_id,
+                        &directory_entity(&parent_path),
+                    )));
+                }
+                ops.push(GraphOp::PutEdge(StoredEdge {
+                    repository_id: repository_id.to_string(),
+                    from: parent_path.clone(),
+                    rel_type: owns.to_string(),
+                    to: file_id,
+                    properties: HashMap::new(),
+                }));
+            }
+            (ops, true)
+        })?;
+        if !moved {
+            return Ok(());
+        }
+
+        info!(
+            "Updated file path in graph from {} to {}",
+            old_path, new_path
+        );
+        Ok(())
+    }
+
+    async fn file_exists(&self, repository_id: &str, path: &Path) -> Result<bool> {
+        let file_path = path.to_string_lossy();
+        Ok(self
+            .read()?
+            .nodes_at(repository_id, &file_path)
+            .any(|n| matches!(n.entity_type, EntityType::File)))
+    }
+
+    async fn find_tests_for(
+        &self,
+        repository_id: &str,
+        function: &str,
+    ) -> Result<Vec<EntityReference>> {
+        let data = self.read()?;
+        let mut references: Vec<EntityReference> = data
+            .edges_of_type(repository_id, LinkType::Tests.rel_type())
+            .filter(|(_, f)| {
+                matches!(f.entity_type, EntityType::Function | EntityType::Method)
+                    && (f.id == function
+                        || f.properties.get("qualified_name").map(String::as_str) == Some(function))
+            })
+            .map(|(t, _)| GraphData::reference(t))
+            .collect();
+        sort_references(&mut references);
+        Ok(references)
+    }
+
+    async fn find_untested_functions(&self, repository_id: &str) -> Result<Vec<EntityReference>> {
+        let data = self.read()?;
+        let tested: HashSet<NodeKey> = data
+            .edges_of_type(repository_id, LinkType::Tests.rel_type())
+            .map(|(_, f)| node_key(f))
+            .collect();
+        let mut references: Vec<EntityReference> = data
+            .nodes_in(repository_id)
+            .filter(|f| {
+                matches!(f.entity_type, EntityType::Function | EntityType::Method)
+                    && f.properties.get("exported").map(String::as_str) == Some("true")
+                    && !tested.contains(&node_key(f))
+            })
+            .map(GraphData::reference)
+            .collect();
+        sort_references(&mut references);
+        Ok(references)
+    }
+
+    async fn list_todos(
+        &self,
+        repository_id: &str,
+        marker: Option<String>,
+        path_prefix: Option<String>,
+        owner: Option<String>,
+    ) -> Result<Vec<TodoItem>> {
+        let data = self.read()?;
+        let enclosing: HashMap<NodeKey, &CodeEntity> = data
+            .edges_of_type(repository_id, LinkType::In.rel_type())
+            .map(|(t, e)| (node_key(t), e))
+            .collect();
+
+        let mut todos: Vec<TodoItem> = data
+            .nodes_in(repository_id)
+            .filter(|t| matches!(t.entity_type, EntityType::Todo))
+            .filter(|t| marker.is_none() || t.properties.get("marker") == marker.as_ref())
+            .filter(|t| {
+                path_prefix
+                    .as_ref()
+                    .map(|prefix| t.path.starts_with(prefix.as_str()))
+                    .unwrap_or(true)
+            })
+            .filter(|t| owner.is_none() || t.properties.get("owner") == owner.as_ref())
+            .map(|t| TodoItem {
+                marker: t.properties.get("marker").cloned().unwrap_or_default(),
+                text: t.properties.get("text").cloned().unwrap_or_default(),
+                owner: t.properties.get("owner").cloned(),
+                issue: t.properties.get("issue").cloned(),
+                path: t.path.clone(),
+                line: t.start_line,
+                enclosing: enclosing
+                    .get(&node_key(t))
+                    .and_then(|e| e.properties.get("qualified_name").cloned()),
+            })
+            .collect();
+        todos.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        Ok(todos)
+    }
+
+    async fn health(&self) -> Result<GraphHealth> {
+        drop(self.read()?);
+        Ok(GraphHealth {
+            backend: if self.journal.is_some() {
+                "disk"
+            } else {
+                "memory"
+            }
+            .to_string(),
+            connected: true,
+            ..GraphHealth::default()
+        })
+    }
+
+    async fn subgraph(
+        &self,
+        repository_id: &str,
+        filter: &GraphFilter,
+    ) -> Result<(Vec<CodeEntity>, Vec<StoredEdge>)> {
+        let data = self.read()?;
+        let mut nodes: Vec<CodeEntity> = data
+            .nodes_in(repository_id)
+            .filter(|node| filter.matches_node(node))
+            .cloned()
+            .collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let ids: HashSet<&str> = nodes.iter().map(|node| node.id.as_str()).collect();
+        let mut edges: Vec<StoredEdge> = data
+            .edges
+            .iter()
+            .filter(|((repo, from, rel_type, to), _)| {
+                repo == repository_id
+                    && filter.matches_rel(rel_type)
+                    && ids.contains(from.as_str())
+                    && ids.contains(to.as_str())
+            })
+            .map(stored_edge)
+            .collect();
+        sort_edges(&mut edges);
+        Ok((nodes, edges))
+    }
+
+    async fn find_entities(
+        &self,
+        repository_id: &str,
+        text: &str,
+        labels: &[String],
+        limit: usize,
+    ) -> Result<Vec<CodeEntity>> {
+        let text = text.to_lowercase();
+        let filter = GraphFilter {
+            labels: labels.to_vec(),
+            ..GraphFilter::default()
+        };
+        let data = self.read()?;
+        let mut matches: Vec<(u8, &str, &CodeEntity)> = data
+            .nodes_in(repository_id)
+            .filter(|node| filter.matches_node(node))
+            .filter_map(|node| {
+                let name = node
+                    .properties
+                    .get("name")
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                Some((symbol_rank(name, &node.path, &text)?, name, node))
+            })
+            .collect();
+        matches.sort_by(|a, b| (a.0, a.1.len(), &a.2.id).cmp(&(b.0, b.1.len(), &b.2.id)));
+        Ok(matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, node)| node.clone())
+            .collect())
+    }
+
+    async fn search_entities(
+        &self,
+        repository_id: &str,
+        terms: &[String],
+        filter: &SearchFilter,
+        limit: usize,
+    ) -> Result<Vec<(CodeEntity, f64)>> {
+        let data = self.read()?;
+        let mut hits: Vec<(&CodeEntity, f64)> = data
+            .nodes_in(repository_id)
+            .filter(|node| filter.matches(node))
+            .map(|node| (node, search::score(node, terms)))
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.id.cmp(&b.0.id)));
+        Ok(hits
+            .into_iter()
+            .take(limit)
+            .map(|(node, score)| (node.clone(), score))
+            .collect())
+    }
+
+    async fn get_entities(&self, repository_id: &str, ids: &[String]) -> Result<Vec<CodeEntity>> {
+        let data = self.read()?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| data.node(repository_id, id).cloned())
+            .collect())
+    }
+
+    async fn entity_edges(
+        &self,
+        repository_id: &str,
+        ids: &[String],
+        direction: Direction,
+        rel_types: &[String],
+    ) -> Result<Vec<StoredEdge>> {
+        let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        let data = self.read()?;
+        let mut edges: Vec<StoredEdge> = data
+            .edges
+            .iter()
+            .filter(|((repo, from, rel_type, to), _)| {
+                let outgoing = direction != Direction::Incoming && ids.contains(from.as_str());
+                let incoming = direction != Direction::Outgoing && ids.contains(to.as_str());
+                repo == repository_id
+                    && (rel_types.is_empty() || rel_types.contains(rel_type))
+                    && (outgoing || incoming)
+            })
+            .map(stored_edge)
+            .collect();
+        sort_edges(&mut edges);
+        Ok(edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_ingest_query_and_sync() {
+        let graph = MemoryGraph::new();
+        graph
+            .batch_ingest_entities(
+                "r1",
+                &[
+                    CodeEntity::fixture("a.rs", "a.rs", EntityType::File).with_name("a.rs"),
+                    CodeEntity::fixture("a.rs::add", "a.rs", EntityType::Function)
+                        .with_name("add")
+                        .with_property("exported", "true"),
+                    CodeEntity::fixture("a.rs::sub", "a.rs", EntityType::Function)
+                        .with_name("sub")
+                        .with_property("exported", "true"),
+                    CodeEntity::fixture("a.rs::test:t", "a.rs", EntityType::Test).with_name("t"),
+                ],
+            )
+            .await
+            .unwrap();
+        graph
+            .batch_create_links(
+                "r1",
+                &[
+                    LinkEntity {
+                        from_name: "a.rs::test:t".to_string(),
+                        to_name: "a.rs::add".to_string(),
+                        link_type: LinkType::Tests,
+                        properties: HashMap::new(),
+                    },
+                    LinkEntity {
+                        from_name: "a.rs::test:t".to_string(),
+                        to_name: "missing".to_string(),
+                        link_type: LinkType::Tests,
+                        properties: HashMap::new(),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        let tests = graph.find_tests_for("r1", "add").await.unwrap();
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].name, "t");
+        let by_id = graph.find_tests_for("r1", "a.rs::add").await.unwrap();
+        assert_eq!(by_id.len(), 1);
+        assert_eq!(by_id[0].name, "t");
+        let untested = graph.find_untested_functions("r1").await.unwrap();
+        assert_eq!(untested.len(), 1);
+        assert_eq!(untested[0].name, "sub");
+        // Queries only see their own repository
+        assert!(graph.find_tests_for("r2", "add").await.unwrap().is_empty());
+        assert!(graph
+            .find_untested_functions("r2")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Edges are only exported when both ends pass the filter
+        let filter = GraphFilter {
+            labels: vec!["Function".to_string(), "Test".to_string()],
+            ..GraphFilter::default()
+        };
+        let (nodes, edges) = graph.subgraph("r1", &filter).await.unwrap();
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(edges.len(), 1);
+        let filter = GraphFilter {
+            labels: vec!["Function".to_string()],
+            ..GraphFilter::default()
+        };
+        let (_, edges) = graph.subgraph("r1", &filter).await.unwrap();
+        assert!(edges.is_empty());
+
+        // Re-ingesting without the test removes it and its edge
+        let (_, deleted) = graph
+            .sync_file_entities(
+                "r1",
+                "a.rs",
+                &[
+                    CodeEntity::fixture("a.rs::add", "a.rs", EntityType::Function).with_name("add"),
+                    CodeEntity::fixture("a.rs::sub", "a.rs", EntityType::Function).with_name("sub"),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+        assert!(graph.read().unwrap().edges.is_empty());
+        assert!(graph.file_exists("r1", Path::new("a.rs")).await.unwrap());
+
+        graph.remove_file("r1", Path::new("a.rs")).await.unwrap();
+        assert!(graph.read().unwrap().nodes.is_empty());
+
+        // Files deleted while the app wasn't running are pruned with their
+        // entities, and only in their own repository
+        let files = [
+            CodeEntity::fixture("src/gone.rs", "src/gone.rs", EntityType::File)
+                .with_name("gone.rs"),
+            CodeEntity::fixture("src/gone.rs::f", "src/gone.rs", EntityType::Function)
+                .with_name("f"),
+            CodeEntity::fixture("src/kept.rs", "src/kept.rs", EntityType::File)
+                .with_name("kept.rs"),
+        ];
+        graph.batch_ingest_entities("r1", &files).await.unwrap();
+        graph.batch_ingest_entities("r2", &files).await.unwrap();
+        assert_eq!(graph.read().unwrap().nodes.len(), 6);
+
+        let present = HashSet::from(["src/kept.rs".to_string()]);
+        assert_eq!(graph.prune_missing("r2", &present).await.unwrap(), 1);
+        assert_eq!(graph.read().unwrap().nodes.len(), 4);
+        assert!(graph
+            .file_exists("r1", Path::new("src/gone.rs"))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sync_skips_unchanged_entities() {
+        let graph = MemoryGraph::new();
+        let hashed = |entities: &[(&str, &str, &str, usize)]| -> Vec<CodeEntity> {
+            entities
+                .iter()
+                .map(|&(id, name, hash, start_byte)| {
+                    CodeEntity::fixture(id, "a.rs", EntityType::Function)
+                        .with_name(name)
+                        .with_property(CONTENT_HASH_PROPERTY, hash)
+                        .with_start_byte(start_byte)
+                })
+                .collect()
+        };
+        let first = hashed(&[
+            ("a.rs::f", "f", "h1", 0),
+            ("a.rs::g", "g", "h2", 10),
+            ("a.rs::h", "h", "h3", 20),
+        ]);
+        assert_eq!(
+            graph
+                .sync_file_entities("r1", "a.rs", &first)
+                .await
+                .unwrap(),
+            (3, 0)
+        );
+
+        // f is unchanged, g changed, h moved and k is new. The name isn't part
+        // of the fixture hashes, so only rewritten entities pick up the new
+        // one; h only gets its new position.
+        let second = hashed(&[
+            ("a.rs::f", "f2", "h1", 0),
+            ("a.rs::g", "g2", "h2b", 10),
+            ("a.rs::h", "h2", "h3", 25),
+            ("a.rs::k", "k", "h4", 40),
+        ]);
+        assert_eq!(
+            graph
+                .sync_file_entities("r1", "a.rs", &second)
+                .await
+                .unwrap(),
+            (3, 0)
+        );
+        let data = graph.read().unwrap();
+        let name = |id: &str| data.node("r1", id).unwrap().properties["name"].clone();
+        assert_eq!(name("a.rs::f"), "f");
+        assert_eq!(name("a.rs::g"), "g2");
+        assert_eq!(name("a.rs::h"), "h");
+        assert_eq!(name("a.rs::k"), "k");
+        let start_byte = data.node("r1", "a.rs::h").unwrap().span.unwrap().start_byte;
+        assert_eq!(start_byte, 25);
+    }
+
+    #[tokio::test]
+    async fn test_sync_drops_properties_the_parse_no_longer_has() {
+        let graph = MemoryGraph::new();
+        let first = CodeEntity::fixture("a.rs::f", "a.rs", EntityType::Function)
+            .with_name("f")
+            .with_property(CONTENT_HASH_PROPERTY, "h1")
+            .with_start_byte(0)
+            .with_property("async", "true");
+        graph
+            .sync_file_entities("r1", "a.rs", &[first])
+            .await
+            .unwrap();
+        assert!(graph
+            .read()
+            .unwrap()
+            .node("r1", "a.rs::f")
+            .unwrap()
+            .properties
+            .contains_key("async"));
+
+        let second = CodeEntity::fixture("a.rs::f", "a.rs", EntityType::Function)
+            .with_name("f")
+            .with_property(CONTENT_HASH_PROPERTY, "h2")
+            .with_start_byte(0);
+        graph
+            .sync_file_entities("r1", "a.rs", &[second])
+            .await
+            .unwrap();
+        let data = graph.read().unwrap();
+        assert!(!data
+            .node("r1", "a.rs::f")
+            .unwrap()
+            .properties
+            .contains_key("async"));
+        assert_eq!(
+            data.node("r1", "a.rs::f").unwrap().properties[CONTENT_HASH_PROPERTY],
+            "h2"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reingest_keeps_user_properties() {
+        let graph = MemoryGraph::new();
+        let file = |hash: &str, lines: &str| {
+            CodeEntity::fixture("a.rs", "a.rs", EntityType::File)
+                .with_name("a.rs")
+                .with_property(FILE_HASH_PROPERTY, hash)
+                .with_property("lines", lines)
+        };
+        let first = CodeEntity::fixture("a.rs::f", "a.rs", EntityType::Function)
+            .with_name("f")
+            .with_property(CONTENT_HASH_PROPERTY, "h1")
+            .with_start_byte(0)
+            .with_property("async", "true");
+        graph
+            .ingest_file("r1", &file("x1", "10"), &[first], &[])
+            .await
+            .unwrap();
+        {
+            let mut data = graph.write().unwrap();
+            for id in ["a.rs", "a.rs::f"] {
+                let key = ("r1".to_string(), id.to_string());
+                let node = data.nodes.get_mut(&key).unwrap();
+                node.properties
+                    .insert("owner".to_string(), "ana".to_string());
+            }
+        }
+
+        // The file's hash is unchanged, so its node isn't rewritten; the
+        // changed function loses the property the parser dropped only
+        let second = CodeEntity::fixture("a.rs::f", "a.rs", EntityType::Function)
+            .with_name("f")
+            .with_property(CONTENT_HASH_PROPERTY, "h2")
+            .with_start_byte(0);
+        graph
+            .ingest_file("r1", &file("x1", "12"), std::slice::from_ref(&second), &[])
+            .await
+            .unwrap();
+        {
+            let data = graph.read().unwrap();
+            let stored = data.node("r1", "a.rs").unwrap();
+            assert_eq!(stored.properties["lines"], "10");
+            assert_eq!(stored.properties["owner"], "ana");
+            let function = data.node("r1", "a.rs::f").unwrap();
+            assert!(!function.properties.contains_key("async"));
+            assert_eq!(function.properties["owner"], "ana");
+        }
+
+        graph
+            .ingest_file("r1", &file("x2", "12"), &[second], &[])
+            .await
+            .unwrap();
+        let data = graph.read().unwrap();
+        let stored = data.node("r1", "a.rs").unwrap();
+        assert_eq!(stored.properties["lines"], "12");
+        assert_eq!(stored.properties["owner"], "ana");
+    }
+
+    #[tokio::test]
+    async fn test_ingest_file_rebuilds_edges_atomically() {
+        let link = |from: &str, to: &str, link_type: LinkType| LinkEntity {
+            from_name: from.to_string(),
+            to_name: to.to_string(),
+            link_type,
+            properties: HashMap::new(),
+        };
+        let file = CodeEntity::fixture("a.py", "a.py", EntityType::File).with_name("a.py");
+        let functions = [
+            CodeEntity::fixture("a.py::f", "a.py", EntityType::Function).with_name("f"),
+            CodeEntity::fixture("a.py::g", "a.py", EntityType::Function).with_name("g"),
+        ];
+
+        let dir = tempdir().unwrap();
+        let graph = MemoryGraph::open(dir.path()).unwrap();
+        graph
+            .batch_ingest_entities(
+                "r1",
+                &[CodeEntity::fixture("b.py", "b.py", EntityType::File).with_name("b.py")],
+            )
+            .await
+            .unwrap();
+        graph
+            .ingest_file(
+                "r1",
+                &file,
+                &functions,
+                &[
+                    link("a.py", "a.py::f", LinkType::Uses),
+                    link("a.py", "a.py::g", LinkType::Uses),
+                    link("a.py", "b.py", LinkType::Uses),
+                ],
+            )
+            .await
+            .unwrap();
+
+        // Edges within the file are rebuilt, the edge leaving it is kept
+        graph
+            .ingest_file(
+                "r1",
+                &file,
+                &functions,
+                &[link("a.py", "a.py::f", LinkType::Uses)],
+            )
+            .await
+            .unwrap();
+        let mut edges: Vec<String> = graph
+            .read()
+            .unwrap()
+            .stored_edges()
+            .into_iter()
+            .map(|e| e.to)
+            .collect();
+        edges.sort();
+        assert_eq!(edges, ["a.py::f", "b.py"]);
+
+        // A full ingest that no longer resolves an import removes it
+        graph
+            .batch_create_links("r1", &[link("a.py", "b.py", LinkType::Import)])
+            .await
+            .unwrap();
+        assert_eq!(graph.read().unwrap().edges.len(), 3);
+        graph.replace_resolved_links("r1", &[]).await.unwrap();
+        assert_eq!(graph.read().unwrap().edges.len(), 2);
+
+        // A failed commit writes nothing and names the file
+        let journal = graph.journal.as_ref().unwrap();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = journal.lock().unwrap();
+            panic!("crash while journaling");
+        }));
+        let error = graph
+            .ingest_file("r1", &file, &functions[..1], &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::Ingest(ref path, _) if path == "a.py"));
+        assert_eq!(graph.read().unwrap().nodes.len(), 4);
+        assert_eq!(graph.read().unwrap().edges.len(), 2);
+        assert_eq!(disk_store::load(dir.path()).unwrap().nodes.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_indices_follow_renames_and_deletes() {
+        let graph = MemoryGraph::new();
+        let file = CodeEntity::fixture("a.py", "a.py", EntityType::File).with_name("a.py");
+        let function = CodeEntity::fixture("a.py::f", "a.py", EntityType::Function).with_name("f");
+        let uses = LinkEntity {
+            from_name: "a.py".to_string(),
+            to_name: "a.py::f".to_string(),
+            link_type: LinkType::Uses,
+            properties: HashMap::new(),
+        };
+        graph
+            .ingest_file("r1", &file, std::slice::from_ref(&function), &[uses])
+            .await
+            .unwrap();
+
+        graph
+            .update_file_path("r1", Path::new("a.py"), Path::new("b.py"))
+            .await
+            .unwrap();
+        assert!(!graph.file_exists("r1", Path::new("a.py")).await.unwrap());
+        assert!(graph.file_exists("r1", Path::new("b.py")).await.unwrap());
+        {
+            let data = graph.read().unwrap();
+            let mut ids: Vec<&str> = data.nodes_at("r1", "b.py").map(|n| n.id.as_str()).collect();
+            ids.sort();
+            assert_eq!(ids, ["b.py", "b.py::f"]);
+            assert_eq!(data.nodes_at("r1", "a.py").count(), 0);
+            let edge = (
+                "r1".to_string(),
+                "b.py".to_string(),
+                "USES".to_string(),
+                "b.py::f".to_string(),
+            );
+            assert!(data.edges.contains_key(&edge));
+        }
+
+        graph.remove_file("r1", Path::new("b.py")).await.unwrap();
+        let data = graph.read().unwrap();
+        assert!(data.nodes.is_empty());
+        assert!(data.edges.is_empty());
+        assert!(data.adjacency.is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+//! Versioned Neo4j schema.
+//!
+//! The database records the last migration applied to it in a single
+//! `SchemaVersion` node. On connect every newer migration in `MIGRATIONS` is
+//! applied in order and the version bumped after each one, so an interrupted
+//! upgrade resumes where it stopped. A database migrated by a newer app is
+//! left alone and opened read-only.
+
+use crate::error::{AppError, Result};
+use crate::models::Repository;
+
+use log::{info, warn};
+use neo4rs::{query, Graph};
+use std::path::Path;
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub steps: &'static [Step],
+}
+
+pub enum Step {
+    /// Any statement, e.g. creating or dropping an index or relabelling nodes
+    Cypher(&'static str),
+    /// Moves a property to a new name on every node with the label
+    RenameProperty {
+        label: &'static str,
+        from: &'static str,
+        to: &'static str,
+    },
+    /// Gives repositories registered as directories the `Repository` node and
+    /// id `Repository::id_for` computes for their path, then assigns every
+    /// unscoped node to the innermost repository containing it
+    ScopeToRepositories,
+}
+
+/// Assigns unscoped nodes under `$path` to `$repository_id`, unless the
+/// repository already has a node with the same id
+const ASSIGN_TO_REPOSITORY: &str =
+    "MATCH (n) WHERE (n:Code OR n:Entity) AND n.repository_id IS NULL AND n.path IS NOT NULL \
+     AND (n.path = $path OR n.path STARTS WITH $path + '/') \
+     AND NOT EXISTS { MATCH (m:Code {repository_id: $repository_id, id: n.id}) } \
+     SET n.repository_id = $repository_id, n:Code:Entity";
+
+impl Step {
+    /// The statement the step runs; `ScopeToRepositories` runs it once per
+    /// repository
+    pub fn cypher(&self) -> String {
+        match self {
+            Step::Cypher(statement) => statement.to_string(),
+            Step::RenameProperty { label, from, to } => format!(
+                "MATCH (n:{label}) WHERE n.{from} IS NOT NULL \
+                 SET n.{to} = n.{from} REMOVE n.{from}"
+            ),
+            Step::ScopeToRepositories => ASSIGN_TO_REPOSITORY.to_string(),
+        }
+    }
+
+    async fn apply(&self, graph: &Graph) -> Result<()> {
+        match self {
+            Step::ScopeToRepositories => scope_to_repositories(graph).await,
+            step => graph
+                .run(query(&step.cypher()))
+                .await
+                .map_err(AppError::Neo4j),
+        }
+    }
+}
+
+/// See `Step::ScopeToRepositories`. Nodes outside every repository are left
+/// as they are; the next ingest of their checkout writes scoped copies.
+async fn scope_to_repositories(graph: &Graph) -> Result<()> {
+    // The directory registered as a repository becomes its root Directory,
+    // owned by a `Repository` node keyed like the ones ingests write
+    let legacy = column(
+        graph,
+        "MATCH (d:Directory:Repository) WHERE d.path IS NOT NULL RETURN d.path AS path",
+        "path",
+    )
+    .await?;
+    for path in legacy {
+        let repository_id = Repository::id_for(Path::new(&path));
+        let name = Path::new(&path)
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.clone());
+        let rekey = query(
+            "MATCH (d:Directory:Repository {path: $path}) \
+             MERGE (r:Code {repository_id: $repository_id, id: $id}) \
+             SET r:Entity:Repository, r.path = $path, r.name = $name, \
+                 r.ownerId = coalesce(d.ownerId, r.ownerId), r.updated_at = datetime() \
+             MERGE (r)-[h:HAS]->(d) \
+             SET h.repository_id = $repository_id \
+             REMOVE d:Repository, d.repository_id, d.ownerId",
+        )
+        .param("path", path.as_str())
+        .param("repository_id", repository_id.as_str())
+        .param("id", format!("repository:{}", repository_id))
+        .param("name", name);
+        graph.run(rekey).await.map_err(AppError::Neo4j)?;
+    }
+
+    // Innermost repositories first, so nested checkouts keep their nodes
+    let mut repositories = graph
+        .execute(query(
+            "MATCH (r:Repository) WHERE r.repository_id IS NOT NULL AND r.path IS NOT NULL \
+             RETURN r.repository_id AS repository_id, r.path AS path ORDER BY size(r.path) DESC",
+        ))
+        .await
+        .map_err(AppError::Neo4j)?;
+    let mut scoped = Vec::new();
+    while let Some(row) = repositories.next().await.map_err(AppError::Neo4j)? {
+        let repository_id: String = row.get("repository_id").unwrap_or_default();
+        let path: String = row.get("path").unwrap_or_default();
+        scoped.push((repository_id, path));
+    }
+    for (repository_id, path) in scoped {
+        let assign = query(ASSIGN_TO_REPOSITORY)
+            .param("repository_id", repository_id)
+            .param("path", path);
+        graph.run(assign).await.map_err(AppError::Neo4j)?;
+    }
+
+    let unscoped = column(
+        graph,
+        "MATCH (n) WHERE (n:Code OR n:Entity) AND n.repository_id IS NULL \
+         RETURN toString(count(n)) AS count",
+        "count",
+    )
+    .await?;
+    if let Some(count) = unscoped.first().filter(|c| c.as_str() != "0") {
+        warn!(
+            "{} graph nodes are outside every registered repository and were left unscoped; \
+             re-ingest their repositories to query them",
+            count
+        );
+    }
+    Ok(())
+}
+
+/// One string column of every row
+async fn column(graph: &Graph, statement: &str, name: &str) -> Result<Vec<String>> {
+    let mut result = graph
+        .execute(query(statement))
+        .await
+        .map_err(AppError::Neo4j)?;
+    let mut values = Vec::new();
+    while let Some(row) = result.next().await.map_err(AppError::Neo4j)? {
+        values.extend(row.get::<String>(name).ok());
+    }
+    Ok(values)
+}
+
+/// Applied in order; versions must increase and released ones never change
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Unique file and directory paths, name indexes",
+        steps: &[
+            Step::Cypher(
+                "CREATE CONSTRAINT file_path IF NOT EXISTS FOR (f:File) REQUIRE f.path IS UNIQUE",
+            ),
+            Step::Cypher("CREATE CONSTRAINT directory_path IF NOT EXISTS FOR (d:Directory) REQUIRE d.path IS UNIQUE"),
+            Step::Cypher("CREATE INDEX function_name IF NOT EXISTS FOR (f:Function) ON (f.name)"),
+            Step::Cypher("CREATE INDEX class_name IF NOT EXISTS FOR (c:Class) ON (c.name)"),
+            Step::Cypher("CREATE INDEX file_language IF NOT EXISTS FOR (f:File) ON (f.language)"),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "Scope nodes by repository",
+        steps: &[
+            Step::Cypher("DROP CONSTRAINT file_path IF EXISTS"),
+            Step::Cypher("DROP CONSTRAINT directory_path IF EXISTS"),
+            Step::Cypher("CREATE CONSTRAINT code_repository_id IF NOT EXISTS FOR (n:Code) REQUIRE (n.repository_id, n.id) IS UNIQUE"),
+            Step::Cypher("CREATE CONSTRAINT file_repository_path IF NOT EXISTS FOR (f:File) REQUIRE (f.repository_id, f.path) IS UNIQUE"),
+            Step::Cypher("CREATE CONSTRAINT directory_repository_path IF NOT EXISTS FOR (d:Directory) REQUIRE (d.repository_id, d.path) IS UNIQUE"),
+            Step::Cypher("CREATE INDEX entity_repository_path IF NOT EXISTS FOR (n:Entity) ON (n.repository_id, n.path)"),
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "Repository nodes for repositories registered as directories",
+        steps: &[
+            Step::RenameProperty {
+                label: "Directory",
+                from: "repositoryId",
+                to: "repository_id",
+            },
+            Step::Cypher(
+                "MATCH (d:Directory) WHERE d.isRepository = 'true' SET d:Repository REMOVE d.isRepository",
+            ),
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "Assign nodes written before repository scoping to their repository",
+        steps: &[
+            Step::ScopeToRepositories,
+            Step::Cypher(
+                "MATCH (a)-[r]->(b) WHERE r.repository_id IS NULL \
+                 AND a.repository_id IS NOT NULL AND a.repository_id = b.repository_id \
+                 SET r.repository_id = a.repository_id",
+            ),
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "Full-text index over entity names, signatures and docs",
+        steps: &[Step::Cypher(
+            "CREATE FULLTEXT INDEX entity_search IF NOT EXISTS FOR (n:Code) ON EACH [n.search_terms, n.signature, n.doc]",
+        )],
+    },
+];
+
+/// Schema version this build writes
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Fails when the database was migrated by a newer version of the app,
+/// whose data this version could corrupt
+pub fn check_writable(version: i64) -> Result<()> {
+    let latest = latest_version();
+    if version > latest {
+        return Err(AppError::Storage(format!(
+            "Graph schema version {} is newer than this app supports ({}); refusing to write",
+            version, latest
+        )));
+    }
+    Ok(())
+}
+
+/// Version recorded in the database; 0 for a new or pre-versioning database
+pub async fn current_version(graph: &Graph) -> Result<i64> {
+    let mut result = graph
+        .execute(query(
+            "MATCH (v:SchemaVersion) RETURN max(v.version) AS version",
+        ))
+        .await
+        .map_err(AppError::Neo4j)?;
+    let mut version = 0;
+    if let Some(row) = result.next().await.map_err(AppError::Neo4j)? {
+        version = row.get::<i64>("version").unwrap_or(0);
+    }
+    Ok(version)
+}
+
+/// Brings the database up to `latest_version` and returns the version it is
+/// at afterwards, which is larger when a newer app migrated it
+pub async fn migrate(graph: &Graph) -> Result<i64> {
+    let mut version = current_version(graph).await?;
+    let latest = latest_version();
+    if version > latest {
+        warn!(
+            "Graph schema version {} is newer than this app supports ({}); opening read-only",
+            version, latest
+        );
+        return Ok(version);
+    }
+
+    let start = version;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > start) {
+        info!(
+            "Applying graph schema migration {}: {}",
+            migration.version, migration.description
+        );
+        // Schema changes can't share a transaction with data writes, so each
+        // step commits on its own; all of them are safe to repeat
+        for step in migration.steps {
+            step.apply(graph).await?;
+        }
+        graph
+            .run(
+                query(
+                    "MERGE (v:SchemaVersion {id: 'schema'}) SET v.version = $version, v.updated_at = datetime()",
+                )
+                .param("version", migration.version),
+            )
+            .await
+            .map_err(AppError::Neo4j)?;
+        version = migration.version;
+    }
+
+    info!("Graph schema is at version {}", version);
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        assert_eq!(latest_version(), 5);
+
+        let rename = Step::RenameProperty {
+            label: "Directory",
+            from: "repositoryId",
+            to: "repository_id",
+        };
+        assert_eq!(
+            rename.cypher(),
+            "MATCH (n:Directory) WHERE n.repositoryId IS NOT NULL \
+             SET n.repository_id = n.repositoryId REMOVE n.repositoryId"
+        );
+    }
+
+    #[test]
+    fn test_newer_schema_is_read_only() {
+        assert!(check_writable(0).is_ok());
+        assert!(check_writable(latest_version()).is_ok());
+        match check_writable(latest_version() + 1) {
+            Err(AppError::Storage(message)) => assert!(message.contains("refusing to write")),
+            other => panic!("expected a storage error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    /// The single string value a query returns, if any
+    async fn value(graph: &Graph, statement: &str) -> Option<String> {
+        column(graph, statement, "value").await.unwrap().pop()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a disposable Neo4j in NEO4J_URI, NEO4J_USER and NEO4J_PASSWORD; wipes it"]
+    async fn test_backfill_uses_repository_ids_and_keeps_unscoped_nodes() {
+        let graph = Graph::new(
+            std::env::var("NEO4J_URI").unwrap(),
+            std::env::var("NEO4J_USER").unwrap(),
+            std::env::var("NEO4J_PASSWORD").unwrap(),
+        )
+        .await
+        .unwrap();
+        graph.run(query("MATCH (n) DETACH DELETE n")).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let nested = root.join("vendor/lib");
+        std::fs::create_dir_all(&nested).unwrap();
+        let root = root.to_string_lossy().to_string();
+        let nested = nested.to_string_lossy().to_string();
+
+        // The shape the app wrote before repository scoping
+        let seed = query(
+            "CREATE (d:Code:Entity:Directory {id: $root, path: $root, repositoryId: 'uuid-1', \
+                     isRepository: 'true', ownerId: 'me'}) \
+             CREATE (f:Code:Entity:File {id: $root + '/a.rs', path: $root + '/a.rs'}) \
+             CREATE (g:Code:Entity:Function {id: $root + '/a.rs::f', path: $root + '/a.rs'}) \
+             CREATE (d)-[:OWNS]->(f), (f)-[:CONTAINS]->(g) \
+             CREATE (:Code:Entity:Directory {id: $nested, path: $nested, repositoryId: 'uuid-2', \
+                     isRepository: 'true'}) \
+             CREATE (:Code:Entity:File {id: $nested + '/b.rs', path: $nested + '/b.rs'}) \
+             CREATE (:Code:Entity:File {id: $root + '2/c.rs', path: $root + '2/c.rs'}) \
+             CREATE (:Code:Entity:File {id: '/elsewhere/x.rs', path: '/elsewhere/x.rs'})",
+        )
+        .param("root", root.as_str())
+        .param("nested", nested.as_str());
+        graph.run(seed).await.unwrap();
+
+        assert_eq!(migrate(&graph).await.unwrap(), latest_version());
+
+        let root_id = Repository::id_for(Path::new(&root));
+        let nested_id = Repository::id_for(Path::new(&nested));
+        let scope = |id: String| {
+            let graph = &graph;
+            async move {
+                value(
+                    graph,
+                    &format!(
+                        "MATCH (n:Code {{id: '{}'}}) RETURN coalesce(n.repository_id, '') AS value",
+                        id
+                    ),
+                )
+                .await
+            }
+        };
+        assert_eq!(
+            scope(format!("{}/a.rs::f", root)).await,
+            Some(root_id.clone())
+        );
+        assert_eq!(scope(root.clone()).await, Some(root_id.clone()));
+        assert_eq!(scope(format!("{}/b.rs", nested)).await, Some(nested_id));
+        // A sibling sharing the prefix and a file outside every repository
+        // are kept, unscoped
+        assert_eq!(scope(format!("{}2/c.rs", root)).await, Some(String::new()));
+        assert_eq!(
+            scope("/elsewhere/x.rs".to_string()).await,
+            Some(String::new())
+        );
+
+        // The registered directory is now owned by a Repository node keyed
+        // like the ones ingests write
+        let owner = value(
+            &graph,
+            &format!(
+                "MATCH (r:Repository {{repository_id: '{0}', id: 'repository:{0}'}})-[:HAS]->(d:Directory) \
+                 WHERE NOT d:Repository RETURN r.ownerId + ' ' + d.path AS value",
+                root_id
+            ),
+        )
+        .await;
+        assert_eq!(owner, Some(format!("me {}", root)));
+        let scoped_edges = value(
+            &graph,
+            &format!(
+                "MATCH ()-[r]->() WHERE r.repository_id = '{}' RETURN toString(count(r)) AS value",
+                root_id
+            ),
+        )
+        .await;
+        assert_eq!(scoped_edges, Some("3".to_string()));
+    }
+}
@@ -1,6 +1,17 @@
+pub mod disk_store;
+pub mod export;
 pub mod file_tracker;
 pub mod file_watcher;
+pub mod graph_store;
+pub mod import;
+pub mod memory_store;
+pub mod migrations;
 pub mod neo4j;
+pub mod neo4j_settings;
+pub mod profiles;
+pub mod queries;
+pub mod reader;
+pub mod search;
 pub mod state;
 
 pub use file_tracker::FileTracker;
@@ -1,306 +1,778 @@
+use crate::analysis::clones::ClonePair;
+use crate::analysis::hashing::FILE_HASH_PROPERTY;
+use crate::analysis::import_cycles::{self, CycleGroup};
 use crate::error::{AppError, Result};
-use crate::models::{CodeEntity, EntityType, FileStructure, LinkEntity, LinkType};
-
-use log::info;
-use neo4rs::{query, BoltType, Graph, Row};
-use std::collections::HashMap;
-use std::path::Path;
+use crate::file_manager::graph_store::StoredEdge;
+use crate::file_manager::graph_store::{
+    directory_entity, moved_id, parser_keys, repository_node, Direction, FileSyncPlan, GraphFilter,
+    GraphHealth, GraphStore, REPOSITORY_ID_PROPERTY,
+};
+use crate::file_manager::migrations;
+use crate::file_manager::neo4j_settings::Neo4jSettings;
+use crate::file_manager::search::{SearchFilter, FIELD_WEIGHTS};
+use crate::models::{
+    CodeEntity, EntityReference, EntityType, LinkEntity, LinkType, SourceSpan, TodoItem,
+};
+
+use async_trait::async_trait;
+use log::{info, warn};
+use neo4rs::{
+    query, BoltType, ConfigBuilder, Graph, Neo4jClientErrorKind, Neo4jErrorKind, Query, Row, Txn,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+
+/// `RETURN` columns of a node `n` read back by `NeoDB::row_entity`
+const NODE_COLUMNS: &str = "labels(n) AS labels, \
+    [k IN keys(n) WHERE NOT k IN ['created_at', 'updated_at'] | [k, toString(n[k])]] AS properties";
+
+/// `RETURN` column with the properties of an edge `r`, without bookkeeping
+/// ones; needs `$repository_key`
+const EDGE_PROPERTIES: &str =
+    "[k IN keys(r) WHERE NOT k IN ['created_at', 'updated_at', $repository_key] \
+    | [k, toString(r[k])]] AS properties";
 
 pub struct NeoDB {
+    // Connection pool shared by every query
     graph: Graph,
-    repository_id: String,
-    owner_id: String,
+    settings: Neo4jSettings,
+    // Schema version of the database after migrating
+    schema_version: i64,
 }
 
 impl NeoDB {
-    pub async fn new(
-        uri: &str,
-        user: &str,
-        password: &str,
-        repository_id: String,
-        owner_id: String,
-    ) -> Result<Self> {
-        // Connect to Neo4j
-        let graph = Graph::new(uri, user, password)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+    /// Opens the connection pool and brings the schema up to date
+    pub async fn connect(settings: Neo4jSettings) -> Result<Self> {
+        let graph = Self::open_pool(&settings).await?;
 
-        // Setup Neo4j schema constraints and indexes
-        Self::setup_schema(&graph).await?;
+        // Bring constraints, indexes and data up to this app's schema
+        let schema_version = migrations::migrate(&graph).await?;
 
+        info!(
+            "Connected to Neo4j at {} with up to {} connections",
+            settings.uri, settings.max_connections
+        );
         Ok(NeoDB {
             graph,
-            repository_id,
-            owner_id,
+            settings,
+            schema_version,
         })
     }
 
-    pub fn match_entity_type(&self, entity: &EntityType) -> Result<&str> {
-        let label = match entity {
-            EntityType::Project => "Project",
-            EntityType::File => "File",
-            EntityType::Directory => "Directory",
-            EntityType::Function => "Function",
-            EntityType::Method => "Method",
-            EntityType::Class => "Class",
-            EntityType::Interface => "Interface",
-            EntityType::Import => "Import",
-        };
-        Ok(label)
+    /// Connects without migrating and reports the server, to check settings
+    /// before using them
+    pub async fn probe(settings: Neo4jSettings) -> Result<GraphHealth> {
+        let graph = Self::open_pool(&settings).await?;
+        let schema_version = migrations::current_version(&graph).await?;
+        NeoDB {
+            graph,
+            settings,
+            schema_version,
+        }
+        .health()
+        .await
     }
 
-    pub async fn new_simple(uri: String, user: String, password: String) -> Result<Self> {
-        // Generate default IDs if not provided
-        let repository_id = "default-repo".to_string();
-        let owner_id = "default-owner".to_string();
+    async fn open_pool(settings: &Neo4jSettings) -> Result<Graph> {
+        let mut config = ConfigBuilder::new()
+            .uri(settings.uri.as_str())
+            .user(settings.user.as_str())
+            .password(settings.password.as_str())
+            .fetch_size(settings.fetch_size)
+            .max_connections(settings.max_connections);
+        if let Some(database) = &settings.database {
+            config = config.db(database.as_str());
+        }
+        if let Some(certificate) = &settings.ca_certificate {
+            config = config.with_client_certificate(certificate);
+        }
+        let config = config.build().map_err(AppError::Neo4j)?;
+        retrying(settings, OnTimeout::Retry, || {
+            Graph::connect(config.clone())
+        })
+        .await
+    }
 
-        Self::new(&uri, &user, &password, repository_id, owner_id).await
+    /// Runs a read query and collects its rows, retried as in `retrying`
+    async fn rows(&self, q: Query) -> Result<Vec<Row>> {
+        self.fetch(q, OnTimeout::Retry).await
     }
 
-    // Setup Neo4j schema (constraints and indexes)
-    async fn setup_schema(graph: &Graph) -> Result<()> {
-        // Create constraints
-        let mut file_constraint_stream = graph
-            .execute(query(
-                "CREATE CONSTRAINT file_path IF NOT EXISTS FOR (f:File) REQUIRE f.path IS UNIQUE",
-            ))
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+    /// Runs a query and collects its rows, retried as in `retrying`
+    async fn fetch(&self, q: Query, on_timeout: OnTimeout) -> Result<Vec<Row>> {
+        let graph = &self.graph;
+        retrying(&self.settings, on_timeout, || {
+            let q = q.clone();
+            async move {
+                let mut result = graph.execute(q).await?;
+                let mut rows = Vec::new();
+                while let Some(row) = result.next().await? {
+                    rows.push(row);
+                }
+                Ok(rows)
+            }
+        })
+        .await
+    }
 
-        while let Some(_) = file_constraint_stream
-            .next()
-            .await
-            .map_err(|e| AppError::Neo4j(e))?
-        {
-            // We don't need to do anything with the results, just consume them
-        }
+    /// Runs a write whose result isn't needed, retried as in `retrying`
+    async fn run(&self, q: Query) -> Result<()> {
+        let graph = &self.graph;
+        retrying(&self.settings, OnTimeout::Fail, || graph.run(q.clone())).await
+    }
 
-        let mut directory_constraint_stream = graph.execute(query("CREATE CONSTRAINT directory_path IF NOT EXISTS FOR (d:Directory) REQUIRE d.path IS UNIQUE"))
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+    /// Runs `work` in a transaction, committed when it succeeds and rolled
+    /// back otherwise. `work` hands the transaction back with its result; a
+    /// retry replays it from the start, reads included.
+    async fn transaction<T, F, Fut>(&self, label: &str, work: F) -> Result<T>
+    where
+        F: Fn(Txn) -> Fut,
+        Fut: Future<Output = (Txn, neo4rs::Result<T>)>,
+    {
+        let graph = &self.graph;
+        retrying(&self.settings, OnTimeout::Fail, || async {
+            let txn = graph.start_txn().await?;
+            let (txn, result) = work(txn).await;
+            match result {
+                Ok(value) => {
+                    txn.commit().await?;
+                    Ok(value)
+                }
+                Err(e) => {
+                    if let Err(rollback) = txn.rollback().await {
+                        warn!("Rollback for {} failed: {}", label, rollback);
+                    }
+                    Err(e)
+                }
+            }
+        })
+        .await
+    }
 
-        while let Some(_) = directory_constraint_stream
-            .next()
-            .await
-            .map_err(|e| AppError::Neo4j(e))?
-        {
-            // We don't need to do anything with the results, just consume them
+    /// Fails when the database was migrated by a newer version of the app
+    fn check_writable(&self) -> Result<()> {
+        migrations::check_writable(self.schema_version)
+    }
+
+    async fn collect_references(&self, q: neo4rs::Query) -> Result<Vec<EntityReference>> {
+        let result = self.rows(q).await?;
+
+        let mut references = Vec::new();
+        for row in result {
+            references.push(EntityReference {
+                name: row.get("name").unwrap_or_default(),
+                path: row.get("path").unwrap_or_default(),
+                start_line: row.get::<i64>("start_line").ok().map(|l| l as usize),
+            });
         }
-        // Create indexes
-        let mut function_index_stream = graph
-            .execute(query(
-                "CREATE INDEX function_name IF NOT EXISTS FOR (f:Function) ON (f.name)",
-            ))
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+        Ok(references)
+    }
 
-        while let Some(_) = function_index_stream
-            .next()
-            .await
-            .map_err(|e| AppError::Neo4j(e))?
-        {
-            // We don't need to do anything with the results, just consume them
+    // Content hashes and start offsets of the non-file entities currently
+    // stored for a path
+    fn stored_entities_query(repository_id: &str, file_path: &str) -> Query {
+        query(
+            "MATCH (n:Entity {repository_id: $repository_id, path: $path}) \
+             WHERE NOT n:File AND NOT n:Directory \
+             RETURN n.id AS id, n.content_hash AS hash, n.start_byte AS start_byte",
+        )
+        .param("repository_id", repository_id)
+        .param("path", file_path)
+    }
+
+    fn stored_entity_hashes(rows: Vec<Row>) -> HashMap<String, (Option<String>, Option<i64>)> {
+        let mut hashes = HashMap::new();
+        for row in rows {
+            if let Ok(id) = row.get::<String>("id") {
+                hashes.insert(
+                    id,
+                    (
+                        row.get::<String>("hash").ok(),
+                        row.get::<i64>("start_byte").ok(),
+                    ),
+                );
+            }
         }
+        hashes
+    }
 
-        let mut class_index_stream = graph
-            .execute(query(
-                "CREATE INDEX class_name IF NOT EXISTS FOR (c:Class) ON (c.name)",
-            ))
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+    // Whole-file hash of the stored File node at a path. The no-op `SET`
+    // locks the node, so concurrent ingests of the file take turns.
+    fn lock_file_query(repository_id: &str, file_path: &str) -> Query {
+        query(
+            "MATCH (f:File {repository_id: $repository_id, path: $path}) \
+             SET f.hash = f.hash RETURN f.hash AS hash",
+        )
+        .param("repository_id", repository_id)
+        .param("path", file_path)
+    }
 
-        while let Some(_) = class_index_stream
-            .next()
-            .await
-            .map_err(|e| AppError::Neo4j(e))?
-        {
-            // We don't need to do anything with the results, just consume them
+    // Entities of a fresh parse to write, move or delete
+    async fn plan_file_sync(
+        &self,
+        repository_id: &str,
+        file_path: &str,
+        entities: &[CodeEntity],
+    ) -> Result<FileSyncPlan> {
+        let rows = self
+            .rows(Self::stored_entities_query(repository_id, file_path))
+            .await?;
+        Ok(FileSyncPlan::new(
+            &Self::stored_entity_hashes(rows),
+            entities,
+        ))
+    }
+
+    fn delete_entities_query(repository_id: &str, ids: Vec<String>) -> neo4rs::Query {
+        query("MATCH (n:Code {repository_id: $repository_id}) WHERE n.id IN $ids DETACH DELETE n")
+            .param("repository_id", repository_id)
+            .param("ids", ids)
+    }
+
+    // Edges of the given types between the file and its entities; edges
+    // leaving the file come from cross-file resolution and are kept
+    fn delete_file_edges_query(
+        repository_id: &str,
+        file_path: &str,
+        rel_types: Vec<String>,
+    ) -> neo4rs::Query {
+        query(
+            "MATCH (n:Entity {repository_id: $repository_id, path: $path})-[r]->(:Entity {repository_id: $repository_id, path: $path}) WHERE type(r) IN $types DELETE r",
+        )
+        .param("repository_id", repository_id)
+        .param("path", file_path)
+        .param("types", rel_types)
+    }
+
+    // UNWIND query merging the given entities by id
+    fn entities_query(repository_id: &str, entities: &[CodeEntity]) -> neo4rs::Query {
+        // Build UNWIND query for multiple node creation in one transaction
+        let mut query_parts = vec!["UNWIND $entities AS entity"];
+        query_parts.push("MERGE (n:Code {repository_id: $repository_id, id: entity.id})");
+
+        // Drop the properties the parser wrote last time and no longer
+        // produces, keeping ones added by users, then set common properties
+        query_parts.push(
+            "WITH n, entity, [k IN split(coalesce(n.parser_keys, ''), ',') \
+             WHERE k <> '' AND NOT k IN keys(entity.properties)] AS dropped",
+        );
+        query_parts.push("CALL apoc.create.removeProperties(n, dropped) YIELD node AS cleaned");
+        query_parts.push("SET n += entity.properties");
+        query_parts.push("SET n.parser_keys = entity.parser_keys");
+        query_parts.push("SET n.repository_id = $repository_id");
+        query_parts.push("SET n.id = entity.id");
+        query_parts.push("SET n.path = entity.path");
+        query_parts.push("SET n.updated_at = datetime()");
+        query_parts.push("SET n:Entity"); // Base label for all entities
+
+        // Set type-specific label
+        query_parts.push("WITH n, entity");
+        query_parts.push("CALL apoc.create.addLabels(n, [entity.type]) YIELD node");
+
+        // Set other properties
+        query_parts.push("SET node.start_line = entity.start_line");
+        query_parts.push("SET node.end_line = entity.end_line");
+        query_parts.push("SET node.name = coalesce(entity.properties.name, entity.name)");
+        query_parts.push("SET node += entity.positions");
+
+        let cypher = query_parts.join("\n");
+
+        // Prepare entities data
+        let entity_data: Vec<BoltType> = entities
+            .iter()
+            .map(|e| {
+                let mut m: HashMap<String, BoltType> = HashMap::new();
+                m.insert("id".into(), e.id.clone().into());
+                m.insert("path".into(), e.path.clone().into());
+
+                // label
+                m.insert("type".into(), e.entity_type.to_string().into());
+
+                if let Some(sl) = e.start_line {
+                    m.insert("start_line".into(), (sl as i64).into());
+                }
+                if let Some(el) = e.end_line {
+                    m.insert("end_line".into(), (el as i64).into());
+                }
+
+                m.insert("positions".into(), Self::positions(e).into());
+
+                let name = std::path::Path::new(&e.path)
+                    .file_name()
+                    .map(|f| f.to_string_lossy().to_string())
+                    .unwrap_or_else(|| e.id.clone());
+                m.insert("name".into(), name.into());
+
+                let props: HashMap<String, BoltType> = e
+                    .properties
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone().into()))
+                    .collect();
+                m.insert("properties".into(), props.into());
+                m.insert("parser_keys".into(), parser_keys(&e.properties).into());
+
+                m.into() // HashMap<String,BoltType> → BoltType
+            })
+            .collect();
+
+        query(&cypher)
+            .param("repository_id", repository_id)
+            .param("entities", entity_data)
+    }
+
+    // Columns and byte offsets, with `name_` for the identifier
+    fn positions(entity: &CodeEntity) -> HashMap<String, BoltType> {
+        entity
+            .span
+            .iter()
+            .flat_map(|span| span.properties(""))
+            .chain(
+                entity
+                    .name_span
+                    .iter()
+                    .flat_map(|span| span.properties("name_")),
+            )
+            .map(|(k, v)| (k, (v as i64).into()))
+            .collect()
+    }
+
+    // UNWIND query moving stored entities to new lines and offsets without
+    // touching their other properties
+    fn positions_query(repository_id: &str, entities: &[CodeEntity]) -> neo4rs::Query {
+        let cypher = r#"
+        UNWIND $entities AS entity
+        MATCH (n:Code {repository_id: $repository_id, id: entity.id})
+        SET n.start_line = entity.start_line, n.end_line = entity.end_line,
+            n.updated_at = datetime()
+        SET n += entity.positions
+        "#;
+
+        let entity_data: Vec<BoltType> = entities
+            .iter()
+            .map(|e| {
+                let mut m: HashMap<String, BoltType> = HashMap::new();
+                m.insert("id".into(), e.id.clone().into());
+                if let Some(sl) = e.start_line {
+                    m.insert("start_line".into(), (sl as i64).into());
+                }
+                if let Some(el) = e.end_line {
+                    m.insert("end_line".into(), (el as i64).into());
+                }
+                m.insert("positions".into(), Self::positions(e).into());
+                m.into()
+            })
+            .collect();
+
+        query(cypher)
+            .param("repository_id", repository_id)
+            .param("entities", entity_data)
+    }
+
+    /// Links grouped by relationship type, in type order
+    fn links_by_type(links: &[LinkEntity]) -> BTreeMap<&'static str, Vec<&LinkEntity>> {
+        let mut by_type: BTreeMap<&'static str, Vec<&LinkEntity>> = BTreeMap::new();
+        for l in links {
+            by_type.entry(l.link_type.rel_type()).or_default().push(l);
         }
+        by_type
+    }
 
-        let mut language_index_stream = graph
-            .execute(query(
-                "CREATE INDEX file_language IF NOT EXISTS FOR (f:File) ON (f.language)",
-            ))
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+    /// One UNWIND query per relationship type, since the type can't be a
+    /// parameter. Edges are merged on their ends and type; their properties
+    /// are added to the stored ones.
+    fn links_queries(repository_id: &str, links: &[LinkEntity]) -> Vec<neo4rs::Query> {
+        Self::links_by_type(links)
+            .into_iter()
+            .map(|(rel_type, links)| {
+                let link_data: Vec<BoltType> = links
+                    .into_iter()
+                    .map(|l| {
+                        let mut m: HashMap<String, BoltType> = HashMap::new();
+                        m.insert("from_id".into(), l.from_name.clone().into());
+                        m.insert("to_id".into(), l.to_name.clone().into());
+                        let properties: HashMap<String, BoltType> = l
+                            .properties
+                            .iter()
+                            .map(|(k, v)| (k.clone(), v.clone().into()))
+                            .collect();
+                        m.insert("properties".into(), properties.into());
+                        m.into()
+                    })
+                    .collect();
 
-        while let Some(_) = language_index_stream
-            .next()
-            .await
-            .map_err(|e| AppError::Neo4j(e))?
-        {
-            // We don't need to do anything with the results, just consume them
+                // `rel_type` comes from `LinkType::rel_type`, never from input
+                let cypher = format!(
+                    r#"
+                    UNWIND $links AS link
+                    MATCH (source:Code {{repository_id: $repository_id, id: link.from_id}})
+                    MATCH (target:Code {{repository_id: $repository_id, id: link.to_id}})
+                    MERGE (source)-[r:{rel_type} {{repository_id: $repository_id}}]->(target)
+                    ON CREATE SET r.created_at = datetime()
+                    SET r += link.properties, r.updated_at = datetime()
+                    RETURN count(r) as count
+                    "#
+                );
+                query(&cypher)
+                    .param("repository_id", repository_id)
+                    .param("links", link_data)
+            })
+            .collect()
+    }
+
+    /// `WHERE` conditions selecting the nodes bound to `var` that pass a
+    /// `GraphFilter` given as `$labels` and `$path_prefix`
+    fn node_filter(var: &str) -> String {
+        format!(
+            "(size($labels) = 0 OR any(label IN labels({var}) WHERE label IN $labels)) \
+             AND ($path_prefix IS NULL OR {var}.path STARTS WITH $path_prefix)"
+        )
+    }
+
+    /// Lucene query for `entity_search` matching each term as a word or a
+    /// word prefix, boosted like `search::score`. Terms are letters and
+    /// digits only, so they need no escaping.
+    fn lucene_query(terms: &[String]) -> String {
+        let mut clauses = Vec::new();
+        for term in terms {
+            for (field, whole, prefix) in FIELD_WEIGHTS {
+                clauses.push(format!("{field}:{term}^{whole}"));
+                clauses.push(format!("{field}:{term}*^{prefix}"));
+            }
         }
+        clauses.join(" ")
+    }
 
-        Ok(())
+    /// Node returned as `labels` and `properties` by `NODE_COLUMNS`
+    fn row_entity(row: &Row) -> Option<CodeEntity> {
+        let labels: Vec<String> = row.get("labels").unwrap_or_default();
+        let properties: Vec<Vec<String>> = row.get("properties").unwrap_or_default();
+        Self::stored_entity(&labels, properties)
     }
 
-    pub async fn ingest_entity(&self, entity: &CodeEntity) -> Result<()> {
-        let label = match entity.entity_type {
-            EntityType::Project => "Project",
-            EntityType::File => "File",
-            EntityType::Directory => "Directory",
-            EntityType::Function => "Function",
-            EntityType::Method => "Method",
-            EntityType::Class => "Class",
-            EntityType::Interface => "Interface",
-            EntityType::Import => "Import",
-        };
+    /// Edge returned as `from`, `rel_type`, `to` and `properties` by
+    /// `EDGE_PROPERTIES`
+    fn row_edge(repository_id: &str, row: &Row) -> StoredEdge {
+        let properties: Vec<Vec<String>> = row.get("properties").unwrap_or_default();
+        StoredEdge {
+            repository_id: repository_id.to_string(),
+            from: row.get("from").unwrap_or_default(),
+            rel_type: row.get("rel_type").unwrap_or_default(),
+            to: row.get("to").unwrap_or_default(),
+            properties: Self::property_map(properties),
+        }
+    }
 
-        let cypher_query = format!(
-            "MERGE (n:{} {{path: $path, start_line: $start_line, end_line: $end_line}}",
-            label
-        );
+    /// Properties returned as `[key, toString(value)]` pairs
+    fn property_map(pairs: Vec<Vec<String>>) -> HashMap<String, String> {
+        pairs
+            .into_iter()
+            .filter_map(|pair| match <[String; 2]>::try_from(pair) {
+                Ok([key, value]) => Some((key, value)),
+                Err(_) => None,
+            })
+            .collect()
+    }
 
-        let q = query(&cypher_query)
-            .param("path", entity.path.clone())
-            .param("start_line", entity.start_line.unwrap_or(0) as i64)
-            .param("end_line", entity.end_line.unwrap_or(0) as i64);
+    /// Rebuilds an entity from its labels and stringified properties, the
+    /// inverse of `entities_query`
+    fn stored_entity(labels: &[String], properties: Vec<Vec<String>>) -> Option<CodeEntity> {
+        let entity_type = labels.iter().find_map(|l| EntityType::from_label(l))?;
+        let mut properties = Self::property_map(properties);
+        let line = |key: &str| properties.get(key).and_then(|l| l.parse().ok());
+        let (start_line, end_line) = (line("start_line"), line("end_line"));
+        let span = SourceSpan::take_properties(&mut properties, "");
+        let name_span = SourceSpan::take_properties(&mut properties, "name_");
+        properties.remove("start_line");
+        properties.remove("end_line");
+
+        Some(CodeEntity {
+            id: properties.remove("id")?,
+            path: properties.remove("path").unwrap_or_default(),
+            entity_type,
+            start_line,
+            end_line,
+            span,
+            name_span,
+            properties,
+            children: None,
+        })
+    }
+}
 
-        let mut result_stream = self
-            .graph
-            .execute(q)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
-        while let Some(_) = result_stream.next().await.map_err(|e| AppError::Neo4j(e))? {}
-        Ok(())
+/// What `retrying` does with an attempt that runs out of time
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OnTimeout {
+    /// Try again, for reads and connecting
+    Retry,
+    /// Give up: a write that timed out here may still commit on the server,
+    /// and running it again would race with it
+    Fail,
+}
+
+/// Runs a query in a transaction and collects its rows
+async fn rows_in(txn: &mut Txn, q: Query) -> neo4rs::Result<Vec<Row>> {
+    let mut result = txn.execute(q).await?;
+    let mut rows = Vec::new();
+    while let Some(row) = result.next(txn.handle()).await? {
+        rows.push(row);
     }
+    Ok(rows)
+}
 
-    pub async fn create_db_link(&self, link: &LinkEntity) -> Result<()> {
-        let label = match link.link_type {
-            LinkType::Has => "Has",
-            LinkType::Owns => "Owns",
-            LinkType::Uses => "Uses",
-            LinkType::Import => "Imports",
-        };
-        let cypher_query = format!(
-            "MATCH (source {{id: $source_id}})
-        MATCH (target {{id: $target_id}})
-        MERGE (source)-[r:$relationship_type]->(target)
-        ON CREATE SET r.created_at = datetime(), r.property1 = $property1, r.property2 = $property2
-        RETURN r"
-        );
+/// Runs `attempt` within the query timeout, retrying lost connections and
+/// transient server errors (deadlocks, leader changes, ...) with exponential
+/// backoff, and timeouts when `on_timeout` says so
+async fn retrying<T, F, Fut>(
+    settings: &Neo4jSettings,
+    on_timeout: OnTimeout,
+    mut attempt: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = neo4rs::Result<T>>,
+{
+    let mut retry = 0;
+    loop {
+        match tokio::time::timeout(settings.query_timeout, attempt()).await {
+            Err(_) if retry < settings.max_retries && on_timeout == OnTimeout::Retry => {
+                let delay = settings.backoff(retry);
+                warn!(
+                    "Neo4j did not answer within {:?}; retrying in {:?}",
+                    settings.query_timeout, delay
+                );
+                tokio::time::sleep(delay).await;
+                retry += 1;
+            }
+            Err(_) => {
+                return Err(AppError::Storage(format!(
+                    "Neo4j did not answer within {}s",
+                    settings.query_timeout.as_secs()
+                )))
+            }
+            Ok(Ok(value)) => return Ok(value),
+            Ok(Err(e)) if retry < settings.max_retries && is_transient(&e) => {
+                let delay = settings.backoff(retry);
+                warn!("Neo4j request failed ({}); retrying in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                retry += 1;
+            }
+            Ok(Err(e)) => return Err(AppError::Neo4j(e)),
+        }
+    }
+}
 
-        let q = query(&cypher_query)
-            .param("source_id", link.from_name.clone())
-            .param("target_id", link.to_name.clone())
-            .param("relationship_type", label);
+fn is_transient(error: &neo4rs::Error) -> bool {
+    match error {
+        neo4rs::Error::IOError { .. } | neo4rs::Error::ConnectionError => true,
+        neo4rs::Error::Neo4j(e) => matches!(
+            e.kind(),
+            Neo4jErrorKind::Transient
+                | Neo4jErrorKind::Client(Neo4jClientErrorKind::SessionExpired)
+        ),
+        _ => false,
+    }
+}
 
-        let mut result_stream = self
-            .graph
-            .execute(q)
+#[async_trait]
+impl GraphStore for NeoDB {
+    async fn ingest_entity(&self, repository_id: &str, entity: &CodeEntity) -> Result<()> {
+        self.batch_ingest_entities(repository_id, std::slice::from_ref(entity))
             .await
-            .map_err(|e| AppError::Neo4j(e))?;
-        while let Some(_) = result_stream.next().await.map_err(|e| AppError::Neo4j(e))? {}
-        Ok(())
     }
 
-    // Create a relationship between two entities
-    pub async fn create_relationship(
+    /// Writes one parsed file in a single transaction, rolled back if any
+    /// statement fails. Unchanged entities are left untouched as in
+    /// `sync_file_entities`.
+    async fn ingest_file(
         &self,
-        from_path: &str,
-        to_path: &str,
-        rel_type: &str,
+        repository_id: &str,
+        file: &CodeEntity,
+        entities: &[CodeEntity],
+        links: &[LinkEntity],
     ) -> Result<()> {
-        // Determine node types based on the paths
-        let from_type = if from_path.contains('.') {
-            "File"
-        } else {
-            "Directory"
-        };
-        let to_type = if to_path.contains('.') {
-            "File"
-        } else {
-            "Directory"
-        };
-
-        // Create Cypher query
-        let cypher = format!(
-            "MATCH (from:{} {{ path: $from_path }}), (to:{} {{ path: $to_path }}) MERGE (from)-[r:{}]->(to)",
-            from_type, to_type, rel_type
-        );
+        self.check_writable()?;
+        let failed = |e: &dyn std::fmt::Display| AppError::Ingest(file.path.clone(), e.to_string());
 
-        // Create a queryable with parameters
-        let q = query(&cypher)
-            .param("from_path", from_path)
-            .param("to_path", to_path);
+        let rel_types: Vec<String> = LinkType::FILE_LOCAL
+            .iter()
+            .map(|t| t.rel_type().to_string())
+            .collect();
 
-        // Execute query
-        let mut result_stream = self
-            .graph
-            .execute(q)
+        // The plan is read in the transaction that writes it, so a retry or
+        // a concurrent ingest of the same file never writes a stale one
+        let plan = self
+            .transaction(&file.path, |mut txn| {
+                let lock = Self::lock_file_query(repository_id, &file.path);
+                let stored = Self::stored_entities_query(repository_id, &file.path);
+                let rel_types = rel_types.clone();
+                async move {
+                    let result = async {
+                        let stored_hash = rows_in(&mut txn, lock)
+                            .await?
+                            .into_iter()
+                            .next()
+                            .and_then(|row| row.get::<String>("hash").ok());
+                        let stored = Self::stored_entity_hashes(rows_in(&mut txn, stored).await?);
+                        let plan = FileSyncPlan::new(&stored, entities);
+
+                        let file_unchanged = stored_hash.is_some()
+                            && stored_hash.as_ref() == file.properties.get(FILE_HASH_PROPERTY);
+                        let mut queries = Vec::new();
+                        if !file_unchanged {
+                            queries.push(Self::entities_query(
+                                repository_id,
+                                std::slice::from_ref(file),
+                            ));
+                        }
+                        if !plan.vanished.is_empty() {
+                            queries.push(Self::delete_entities_query(
+                                repository_id,
+                                plan.vanished.clone(),
+                            ));
+                        }
+                        queries.push(Self::delete_file_edges_query(
+                            repository_id,
+                            &file.path,
+                            rel_types,
+                        ));
+                        if !plan.changed.is_empty() {
+                            queries.push(Self::entities_query(repository_id, &plan.changed));
+                        }
+                        if !plan.moved.is_empty() {
+                            queries.push(Self::positions_query(repository_id, &plan.moved));
+                        }
+                        queries.extend(Self::links_queries(repository_id, links));
+                        txn.run_queries(queries).await?;
+                        Ok(plan)
+                    }
+                    .await;
+                    (txn, result)
+                }
+            })
             .await
-            .map_err(|e| AppError::Neo4j(e))?;
-
-        while let Some(_) = result_stream.next().await.map_err(|e| AppError::Neo4j(e))? {
-            // We don't need to do anything with the results, just consume them
-        }
+            .map_err(|e| failed(&e))?;
 
+        info!(
+            "Ingested {}: {} written, {} moved, {} deleted, {} unchanged, {} links",
+            file.path,
+            plan.changed.len(),
+            plan.moved.len(),
+            plan.vanished.len(),
+            entities.len() - plan.written(),
+            links.len()
+        );
         Ok(())
     }
 
-    // Register a repository in Neo4j
-    pub async fn register_repository(&self, repo_path: &str) -> Result<()> {
-        use std::collections::HashMap;
-        use std::path::Path;
-
-        let path = Path::new(repo_path);
-        let repo_name = path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-
-        // Create repository entity
-        let repo_entity = CodeEntity {
-            id: repo_path.to_string(),
-            path: repo_path.to_string(),
-            entity_type: EntityType::Directory,
-            start_line: None,
-            end_line: None,
-            properties: HashMap::from([
-                ("repositoryId".to_string(), self.repository_id.clone()),
-                ("ownerId".to_string(), self.owner_id.clone()),
-                ("isRepository".to_string(), "true".to_string()),
-            ]),
-            children: Some(Vec::new()),
-        };
+    async fn create_link(&self, repository_id: &str, link: &LinkEntity) -> Result<()> {
+        self.batch_create_links(repository_id, std::slice::from_ref(link))
+            .await
+    }
 
-        // Ingest the repository entity
-        self.ingest_entity(&repo_entity).await?;
+    // Register a repository in Neo4j
+    async fn register_repository(
+        &self,
+        repo_path: &str,
+        repository_id: &str,
+        owner_id: &str,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let (repository, root, link) = repository_node(repo_path, repository_id, Some(owner_id));
+        self.batch_ingest_entities(repository_id, &[repository, root])
+            .await?;
+        self.batch_create_links(repository_id, &[link]).await?;
 
         info!("Registered repository: {}", repo_path);
         Ok(())
     }
 
     // Remove a file and all its entities from Neo4j
-    pub async fn remove_file(&self, path: &std::path::Path) -> Result<()> {
+    async fn remove_file(&self, repository_id: &str, path: &std::path::Path) -> Result<()> {
+        self.check_writable()?;
         let file_path = path.to_string_lossy().to_string();
 
-        // Delete the file node and all its contained entities
+        // Delete the file node and every entity declared in it
         let cypher = r#"
-        MATCH (f:File {path: $path})
-        OPTIONAL MATCH (f)-[:CONTAINS]->(entity)
-        DETACH DELETE f, entity
+        MATCH (n:Entity {repository_id: $repository_id, path: $path})
+        WHERE NOT n:Directory AND NOT n:Repository
+        DETACH DELETE n
         "#;
 
-        let q = query(cypher).param("path", file_path.clone());
+        let q = query(cypher)
+            .param("repository_id", repository_id)
+            .param("path", file_path.clone());
 
-        self.graph
-            .execute(q)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+        self.run(q).await?;
 
         info!("Removed file from graph: {}", file_path);
         Ok(())
     }
 
+    async fn prune_missing(&self, repository_id: &str, present: &HashSet<String>) -> Result<usize> {
+        self.check_writable()?;
+        let cypher = r#"
+        MATCH (n:Entity {repository_id: $repository_id})
+        WHERE (n:File OR n:Directory) AND NOT n.path IN $present
+        RETURN n.path AS path
+        "#;
+        let q = query(cypher)
+            .param("repository_id", repository_id)
+            .param("present", present.iter().cloned().collect::<Vec<_>>());
+
+        let result = self.rows(q).await?;
+        let mut gone = Vec::new();
+        for row in result {
+            if let Ok(path) = row.get::<String>("path") {
+                gone.push(path);
+            }
+        }
+
+        if !gone.is_empty() {
+            let q = query(
+                r#"
+                MATCH (n:Entity {repository_id: $repository_id})
+                WHERE n.path IN $paths AND NOT n:Repository
+                DETACH DELETE n
+                "#,
+            )
+            .param("repository_id", repository_id)
+            .param("paths", gone.clone());
+            self.run(q).await?;
+        }
+
+        info!("Pruned {} missing paths from {}", gone.len(), repository_id);
+        Ok(gone.len())
+    }
+
+    async fn remove_repository(&self, repository_id: &str) -> Result<usize> {
+        self.check_writable()?;
+        let q = query(
+            r#"
+            MATCH (n:Code {repository_id: $repository_id})
+            DETACH DELETE n
+            RETURN count(n) AS deleted
+            "#,
+        )
+        .param("repository_id", repository_id);
+
+        let result = self.fetch(q, OnTimeout::Fail).await?;
+        let mut deleted = 0;
+        if let Some(row) = result.into_iter().next() {
+            deleted = row.get::<i64>("deleted").unwrap_or(0) as usize;
+        }
+
+        info!("Removed {} nodes of repository {}", deleted, repository_id);
+        Ok(deleted)
+    }
+
     // Update file path when a file is renamed
-    pub async fn update_file_path(
+    async fn update_file_path(
         &self,
+        repository_id: &str,
         from_path: &std::path::Path,
         to_path: &std::path::Path,
     ) -> Result<()> {
+        self.check_writable()?;
         let old_path = from_path.to_string_lossy().to_string();
         let new_path = to_path.to_string_lossy().to_string();
         let new_name = to_path
@@ -308,38 +780,87 @@ impl NeoDB {
             .unwrap_or_default()
             .to_string_lossy()
             .to_string();
-
-        // Update the file node path and name
-        let cypher = r#"
-        MATCH (f:File {path: $old_path})
-        SET f.path = $new_path, f.name = $new_name
-        "#;
-
-        let q = query(cypher)
-            .param("old_path", old_path.clone())
-            .param("new_path", new_path.clone())
-            .param("new_name", new_name);
-
-        let mut result_stream = self
-            .graph
-            .execute(q)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
-
-        while let Some(_) = result_stream.next().await.map_err(|e| AppError::Neo4j(e))? {
-            // We don't need to do anything with the results, just consume them
-        }
-
-        // Update parent relationship if needed
-        let new_parent = to_path
+        let parent = to_path
             .parent()
             .unwrap_or_else(|| std::path::Path::new(""))
             .to_string_lossy()
             .to_string();
+        let (old_path, new_path) = (old_path.as_str(), new_path.as_str());
+        let paths = |q: &str| {
+            query(q)
+                .param("repository_id", repository_id)
+                .param("old_path", old_path)
+                .param("new_path", new_path)
+        };
 
-        if !new_parent.is_empty() {
-            self.create_relationship(&new_parent, &new_path, "CONTAINS")
-                .await?;
+        let moved = self
+            .transaction(old_path, |mut txn| {
+                let stored = paths(
+                    "MATCH (f:File {repository_id: $repository_id, path: $old_path}) RETURN f.id AS id",
+                );
+                let statements = [
+                    // Whatever was stored at the new path is replaced
+                    paths(
+                        "MATCH (n:Entity {repository_id: $repository_id, path: $new_path}) \
+                         WHERE NOT n:Directory AND NOT n:Repository DETACH DELETE n",
+                    ),
+                    paths(
+                        "MATCH (:Directory)-[r:OWNS|HAS]->(:File {repository_id: $repository_id, path: $old_path}) \
+                         DELETE r",
+                    ),
+                    // Same ids as `moved_id`
+                    paths(
+                        "MATCH (n:Entity {repository_id: $repository_id, path: $old_path}) \
+                         WHERE NOT n:Directory AND NOT n:Repository \
+                         SET n.path = $new_path, n.updated_at = datetime(), n.id = CASE \
+                           WHEN n.id = $old_path THEN $new_path \
+                           WHEN n.id STARTS WITH $old_path + '::' THEN $new_path + substring(n.id, size($old_path)) \
+                           ELSE n.id END",
+                    ),
+                    paths(
+                        "MATCH (f:File {repository_id: $repository_id, path: $new_path}) SET f.name = $name",
+                    )
+                    .param("name", new_name.as_str()),
+                ];
+                let parent = parent.as_str();
+                async move {
+                    let result = async {
+                        let Some(row) = rows_in(&mut txn, stored).await?.into_iter().next() else {
+                            return Ok(false);
+                        };
+                        let file_id = row.get::<String>("id").unwrap_or_default();
+                        txn.run_queries(statements).await?;
+                        if !parent.is_empty() {
+                            let directory = directory_entity(parent);
+                            txn.run(
+                                query(
+                                    "MERGE (d:Code {repository_id: $repository_id, id: $id}) \
+                                     ON CREATE SET d:Entity:Directory, d.path = $id, d.name = $name, \
+                                       d.updated_at = datetime()",
+                                )
+                                .param("repository_id", repository_id)
+                                .param("id", parent)
+                                .param("name", directory.properties["name"].as_str()),
+                            )
+                            .await?;
+                            let owns = LinkEntity {
+                                from_name: directory.id,
+                                to_name: moved_id(&file_id, old_path, new_path),
+                                link_type: LinkType::Owns,
+                                properties: HashMap::new(),
+                            };
+                            txn.run_queries(Self::links_queries(repository_id, &[owns]))
+                                .await?;
+                        }
+                        Ok(true)
+                    }
+                    .await;
+                    (txn, result)
+                }
+            })
+            .await?;
+        if !moved {
+            return Ok(());
         }
 
         info!(
@@ -350,19 +871,18 @@ impl NeoDB {
     }
 
     // Check if a file exists in the graph
-    pub async fn file_exists(&self, path: &std::path::Path) -> Result<bool> {
+    async fn file_exists(&self, repository_id: &str, path: &std::path::Path) -> Result<bool> {
         let file_path = path.to_string_lossy().to_string();
 
-        let cypher = "MATCH (f:File {path: $path}) RETURN count(f) as count";
-        let q = query(cypher).param("path", file_path);
+        let cypher =
+            "MATCH (f:File {repository_id: $repository_id, path: $path}) RETURN count(f) as count";
+        let q = query(cypher)
+            .param("repository_id", repository_id)
+            .param("path", file_path);
 
-        let mut result = self
-            .graph
-            .execute(q)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+        let result = self.rows(q).await?;
 
-        if let Some(row) = result.next().await.map_err(|e| AppError::Neo4j(e))? {
+        if let Some(row) = result.into_iter().next() {
             let count: i64 = row.get("count").unwrap_or(0);
             return Ok(count > 0);
         }
@@ -370,331 +890,573 @@ impl NeoDB {
         Ok(false)
     }
 
-    pub async fn batch_ingest_entities(&self, entities: &[CodeEntity]) -> Result<()> {
-        if entities.is_empty() {
-            return Ok(());
-        }
+    // Find the tests that exercise a function or method, by id or qualified name
+    async fn find_tests_for(
+        &self,
+        repository_id: &str,
+        function: &str,
+    ) -> Result<Vec<EntityReference>> {
+        let cypher = r#"
+        MATCH (t:Test {repository_id: $repository_id})-[:TESTS]->(f)
+        WHERE (f:Function OR f:Method) AND (f.id = $function OR f.qualified_name = $function)
+        RETURN DISTINCT t.qualified_name AS name, t.path AS path, t.start_line AS start_line
+        ORDER BY path, start_line
+        "#;
 
-        // Build UNWIND query for multiple node creation in one transaction
-        let mut query_parts = vec!["UNWIND $entities AS entity"];
-        query_parts.push("MERGE (n:Code {id: entity.id})");
+        let q = query(cypher)
+            .param("repository_id", repository_id)
+            .param("function", function);
+        self.collect_references(q).await
+    }
 
-        // Set common properties
-        query_parts.push("SET n.path = entity.path");
-        query_parts.push("SET n.updated_at = datetime()");
-        query_parts.push("SET n:Entity"); // Base label for all entities
+    // Find exported functions and methods that no test calls
+    async fn find_untested_functions(&self, repository_id: &str) -> Result<Vec<EntityReference>> {
+        let cypher = r#"
+        MATCH (f:Code {repository_id: $repository_id})
+        WHERE (f:Function OR f:Method) AND f.exported = "true"
+          AND NOT ()-[:TESTS]->(f)
+        RETURN f.qualified_name AS name, f.path AS path, f.start_line AS start_line
+        ORDER BY path, start_line
+        "#;
 
-        // Set type-specific label
-        query_parts.push("WITH n, entity");
-        query_parts.push("CALL apoc.create.addLabels(n, [entity.type]) YIELD node");
+        let q = query(cypher).param("repository_id", repository_id);
+        self.collect_references(q).await
+    }
 
-        // Set other properties
-        query_parts.push("SET node.start_line = entity.start_line");
-        query_parts.push("SET node.end_line = entity.end_line");
-        query_parts.push("SET node.name = entity.name");
+    // List TODO-style comments, optionally filtered by marker, path prefix and owner
+    async fn list_todos(
+        &self,
+        repository_id: &str,
+        marker: Option<String>,
+        path_prefix: Option<String>,
+        owner: Option<String>,
+    ) -> Result<Vec<TodoItem>> {
+        let cypher = r#"
+        MATCH (t:Todo {repository_id: $repository_id})
+        WHERE ($marker IS NULL OR t.marker = $marker)
+          AND ($path_prefix IS NULL OR t.path STARTS WITH $path_prefix)
+          AND ($owner IS NULL OR t.owner = $owner)
+        OPTIONAL MATCH (t)-[:IN]->(e)
+        RETURN t.marker AS marker, t.text AS text, t.owner AS owner, t.issue AS issue,
+               t.path AS path, t.start_line AS line, e.qualified_name AS enclosing
+        ORDER BY path, line
+        "#;
 
-        // Handle properties map
-        query_parts.push("WITH node, entity");
-        query_parts.push("UNWIND keys(entity.properties) AS key");
-        query_parts.push("SET node[key] = entity.properties[key]");
+        let q = query(cypher)
+            .param("repository_id", repository_id)
+            .param("marker", marker)
+            .param("path_prefix", path_prefix)
+            .param("owner", owner);
+
+        let result = self.rows(q).await?;
+
+        let mut todos = Vec::new();
+        for row in result {
+            todos.push(TodoItem {
+                marker: row.get("marker").unwrap_or_default(),
+                text: row.get("text").unwrap_or_default(),
+                owner: row.get("owner").ok(),
+                issue: row.get("issue").ok(),
+                path: row.get("path").unwrap_or_default(),
+                line: row.get::<i64>("line").ok().map(|l| l as usize),
+                enclosing: row.get("enclosing").ok(),
+            });
+        }
+        Ok(todos)
+    }
 
-        let cypher = query_parts.join("\n");
+    async fn health(&self) -> Result<GraphHealth> {
+        let components = self
+            .rows(query(
+                "CALL dbms.components() YIELD name, versions, edition \
+                 RETURN name + ' ' + versions[0] + ' ' + edition AS version",
+            ))
+            .await?;
+        let database = self
+            .rows(query("CALL db.info() YIELD name RETURN name"))
+            .await?;
+
+        Ok(GraphHealth {
+            backend: "neo4j".to_string(),
+            connected: true,
+            server_version: components
+                .into_iter()
+                .next()
+                .and_then(|row| row.get("version").ok()),
+            database: database
+                .into_iter()
+                .next()
+                .and_then(|row| row.get("name").ok()),
+            schema_version: Some(self.schema_version),
+            error: None,
+        })
+    }
 
-        // Prepare entities data
-        let entity_data: Vec<BoltType> = entities
+    async fn subgraph(
+        &self,
+        repository_id: &str,
+        filter: &GraphFilter,
+    ) -> Result<(Vec<CodeEntity>, Vec<StoredEdge>)> {
+        let nodes_cypher = format!(
+            r#"
+            MATCH (n:Code {{repository_id: $repository_id}})
+            WHERE {}
+            RETURN {}
+            ORDER BY n.id
+            "#,
+            Self::node_filter("n"),
+            NODE_COLUMNS
+        );
+        let edges_cypher = format!(
+            r#"
+            MATCH (a:Code {{repository_id: $repository_id}})-[r]->(b:Code {{repository_id: $repository_id}})
+            WHERE {} AND {}
+              AND (size($rel_types) = 0 OR type(r) IN $rel_types)
+            RETURN a.id AS from, type(r) AS rel_type, b.id AS to, {}
+            ORDER BY from, rel_type, to
+            "#,
+            Self::node_filter("a"),
+            Self::node_filter("b"),
+            EDGE_PROPERTIES
+        );
+        let params = |cypher: &str| {
+            query(cypher)
+                .param("repository_id", repository_id)
+                .param("repository_key", REPOSITORY_ID_PROPERTY)
+                .param("labels", filter.labels.clone())
+                .param("rel_types", filter.rel_types.clone())
+                .param("path_prefix", filter.path_prefix.clone())
+        };
+
+        let nodes: Vec<CodeEntity> = self
+            .rows(params(&nodes_cypher))
+            .await?
             .iter()
-            .map(|e| {
-                let mut m: HashMap<String, BoltType> = HashMap::new();
-                m.insert("id".into(), e.id.clone().into());
-                m.insert("path".into(), e.path.clone().into());
+            .filter_map(Self::row_entity)
+            .collect();
+        let edges: Vec<StoredEdge> = self
+            .rows(params(&edges_cypher))
+            .await?
+            .iter()
+            .map(|row| Self::row_edge(repository_id, row))
+            .collect();
 
-                // label
-                let t = match e.entity_type {
-                    EntityType::Project => "Project",
-                    EntityType::Directory => "Directory",
-                    EntityType::File => "File",
-                    EntityType::Class => "Class",
-                    EntityType::Interface => "Interface",
-                    EntityType::Method => "Method",
-                    EntityType::Function => "Function",
-                    EntityType::Import => "Import",
-                };
-                m.insert("type".into(), t.into());
+        info!(
+            "Read subgraph of {} nodes and {} edges from repository {}",
+            nodes.len(),
+            edges.len(),
+            repository_id
+        );
+        Ok((nodes, edges))
+    }
 
-                if let Some(sl) = e.start_line {
-                    m.insert("start_line".into(), (sl as i64).into());
-                }
-                if let Some(el) = e.end_line {
-                    m.insert("end_line".into(), (el as i64).into());
-                }
+    async fn find_entities(
+        &self,
+        repository_id: &str,
+        text: &str,
+        labels: &[String],
+        limit: usize,
+    ) -> Result<Vec<CodeEntity>> {
+        // Same order as `symbol_rank`
+        let cypher = format!(
+            r#"
+            MATCH (n:Code {{repository_id: $repository_id}})
+            WHERE {}
+            WITH n, toLower(coalesce(n.name, '')) AS name
+            WITH n, name, CASE
+                WHEN name = $text THEN 0
+                WHEN name STARTS WITH $text THEN 1
+                WHEN name CONTAINS $text THEN 2
+                WHEN toLower(coalesce(n.path, '')) CONTAINS $text THEN 3
+            END AS rank
+            WHERE rank IS NOT NULL
+            RETURN {}
+            ORDER BY rank, size(name), n.id
+            LIMIT $limit
+            "#,
+            Self::node_filter("n"),
+            NODE_COLUMNS
+        );
+        let q = query(&cypher)
+            .param("repository_id", repository_id)
+            .param("text", text.to_lowercase())
+            .param("labels", labels.to_vec())
+            .param("path_prefix", None::<String>)
+            .param("limit", limit as i64);
+
+        Ok(self
+            .rows(q)
+            .await?
+            .iter()
+            .filter_map(Self::row_entity)
+            .collect())
+    }
 
-                let name = std::path::Path::new(&e.path)
-                    .file_name()
-                    .map(|f| f.to_string_lossy().to_string())
-                    .unwrap_or_else(|| e.id.clone());
-                m.insert("name".into(), name.into());
+    async fn search_entities(
+        &self,
+        repository_id: &str,
+        terms: &[String],
+        filter: &SearchFilter,
+        limit: usize,
+    ) -> Result<Vec<(CodeEntity, f64)>> {
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        // The index spans repositories, so over-fetch before filtering
+        let cypher = format!(
+            r#"
+            CALL db.index.fulltext.queryNodes('entity_search', $lucene, {{limit: $candidates}})
+            YIELD node AS n, score
+            WHERE n.repository_id = $repository_id AND {}
+              AND ($language IS NULL OR n.language = $language)
+            RETURN {}, score
+            ORDER BY score DESC, n.id
+            LIMIT $limit
+            "#,
+            Self::node_filter("n"),
+            NODE_COLUMNS
+        );
+        let q = query(&cypher)
+            .param("lucene", Self::lucene_query(terms))
+            .param("repository_id", repository_id)
+            .param("labels", filter.labels.clone())
+            .param("path_prefix", filter.path_prefix.clone())
+            .param("language", filter.language.clone())
+            .param("candidates", (limit as i64).saturating_mul(20).max(1000))
+            .param("limit", limit as i64);
+
+        Ok(self
+            .rows(q)
+            .await?
+            .iter()
+            .filter_map(|row| Some((Self::row_entity(row)?, row.get::<f64>("score").ok()?)))
+            .collect())
+    }
 
-                let props: HashMap<String, BoltType> = e
-                    .properties
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone().into()))
-                    .collect();
-                m.insert("properties".into(), props.into());
+    async fn get_entities(&self, repository_id: &str, ids: &[String]) -> Result<Vec<CodeEntity>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let cypher = format!(
+            "MATCH (n:Code {{repository_id: $repository_id}}) WHERE n.id IN $ids RETURN {}",
+            NODE_COLUMNS
+        );
+        let q = query(&cypher)
+            .param("repository_id", repository_id)
+            .param("ids", ids.to_vec());
 
-                m.into() // HashMap<String,BoltType> → BoltType
-            })
-            .collect();
+        Ok(self
+            .rows(q)
+            .await?
+            .iter()
+            .filter_map(Self::row_entity)
+            .collect())
+    }
 
-        // Execute the query
-        let q = query(&cypher).param("entities", entity_data);
-        let mut result = self
-            .graph
-            .execute(q)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+    async fn entity_edges(
+        &self,
+        repository_id: &str,
+        ids: &[String],
+        direction: Direction,
+        rel_types: &[String],
+    ) -> Result<Vec<StoredEdge>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let cypher = format!(
+            r#"
+            MATCH (n:Code {{repository_id: $repository_id}}) WHERE n.id IN $ids
+            MATCH (n)-[r]-(:Code {{repository_id: $repository_id}})
+            WHERE (size($rel_types) = 0 OR type(r) IN $rel_types)
+              AND (($outgoing AND startNode(r) = n) OR ($incoming AND endNode(r) = n))
+            WITH DISTINCT r
+            RETURN startNode(r).id AS from, type(r) AS rel_type, endNode(r).id AS to, {}
+            ORDER BY from, rel_type, to
+            "#,
+            EDGE_PROPERTIES
+        );
+        let q = query(&cypher)
+            .param("repository_id", repository_id)
+            .param("repository_key", REPOSITORY_ID_PROPERTY)
+            .param("ids", ids.to_vec())
+            .param("rel_types", rel_types.to_vec())
+            .param("outgoing", direction != Direction::Incoming)
+            .param("incoming", direction != Direction::Outgoing);
+
+        Ok(self
+            .rows(q)
+            .await?
+            .iter()
+            .map(|row| Self::row_edge(repository_id, row))
+            .collect())
+    }
+
+    async fn batch_ingest_entities(
+        &self,
+        repository_id: &str,
+        entities: &[CodeEntity],
+    ) -> Result<()> {
+        self.check_writable()?;
+        if entities.is_empty() {
+            return Ok(());
+        }
 
-        // Consume the results
-        while let Some(_) = result.next().await.map_err(|e| AppError::Neo4j(e))? {}
+        self.run(Self::entities_query(repository_id, entities))
+            .await?;
 
         info!("Batch ingested {} entities", entities.len());
         Ok(())
     }
 
+    /// Brings the entities stored for one file in line with a fresh parse.
+    ///
+    /// Entities whose `content_hash` and position are unchanged are not written
+    /// at all, so properties added to them by users survive; moved entities
+    /// only get their new position, new or changed ones are rewritten from the
+    /// parse and entities that vanished from the file are deleted with their
+    /// edges. Returns the number of entities written and deleted.
+    async fn sync_file_entities(
+        &self,
+        repository_id: &str,
+        file_path: &str,
+        entities: &[CodeEntity],
+    ) -> Result<(usize, usize)> {
+        self.check_writable()?;
+        let plan = self
+            .plan_file_sync(repository_id, file_path, entities)
+            .await?;
+
+        if !plan.vanished.is_empty() {
+            let q = Self::delete_entities_query(repository_id, plan.vanished.clone());
+            self.run(q).await?;
+        }
+
+        self.batch_ingest_entities(repository_id, &plan.changed)
+            .await?;
+        if !plan.moved.is_empty() {
+            self.run(Self::positions_query(repository_id, &plan.moved))
+                .await?;
+        }
+
+        info!(
+            "Synced {}: {} written, {} moved, {} deleted, {} unchanged",
+            file_path,
+            plan.changed.len(),
+            plan.moved.len(),
+            plan.vanished.len(),
+            entities.len() - plan.written()
+        );
+        Ok((plan.written(), plan.vanished.len()))
+    }
+
     // Batch create multiple relationships at once
-    pub async fn batch_create_links(&self, links: &[LinkEntity]) -> Result<()> {
+    async fn batch_create_links(&self, repository_id: &str, links: &[LinkEntity]) -> Result<()> {
+        self.check_writable()?;
         if links.is_empty() {
             return Ok(());
         }
 
-        // Build UNWIND query for multiple relationship creation
-        let cypher = r#"
-            UNWIND $links AS link
-            MATCH (source {id: link.from_id})
-            MATCH (target {id: link.to_id})
-            CALL apoc.merge.relationship(source, link.type,
-                {created_at: datetime()},
-                {updated_at: datetime()},
-                target)
-            YIELD rel
-            RETURN count(rel) as count
-            "#;
-
-        // Prepare link data
-        let link_data: Vec<BoltType> = links
+        // Get the count of created relationships
+        let mut count = 0;
+        for q in Self::links_queries(repository_id, links) {
+            let result = self.fetch(q, OnTimeout::Fail).await?;
+            if let Some(row) = result.into_iter().next() {
+                count += row.get::<i64>("count").unwrap_or(0);
+            }
+        }
+
+        info!("Created {} relationships in batch", count);
+        Ok(())
+    }
+
+    async fn replace_resolved_links(
+        &self,
+        repository_id: &str,
+        links: &[LinkEntity],
+    ) -> Result<()> {
+        self.check_writable()?;
+        let clear = query(
+            r#"
+            MATCH (:Entity {repository_id: $repository_id})-[r]->(target)
+            WHERE type(r) IN $types OR (type(r) = 'IMPORTS' AND NOT target:Import)
+            DELETE r
+            "#,
+        )
+        .param("repository_id", repository_id)
+        .param(
+            "types",
+            LinkType::RESOLVED
+                .iter()
+                .map(|t| t.rel_type().to_string())
+                .collect::<Vec<_>>(),
+        );
+        self.run(clear).await?;
+        self.batch_create_links(repository_id, links).await
+    }
+
+    /// Replaces the `DUPLICATE_OF` edges of a repository with a fresh
+    /// detection result. Each edge carries its similarity and clone group.
+    async fn replace_duplicate_links(
+        &self,
+        repository_id: &str,
+        pairs: &[ClonePair],
+    ) -> Result<()> {
+        self.check_writable()?;
+        let rel_type = LinkType::DuplicateOf.rel_type();
+        let clear = query(&format!(
+            "MATCH (:Entity {{repository_id: $repository_id}})-[r:{rel_type}]->() DELETE r"
+        ))
+        .param("repository_id", repository_id);
+        self.run(clear).await?;
+
+        if pairs.is_empty() {
+            return Ok(());
+        }
+
+        let cypher = format!(
+            r#"
+            UNWIND $pairs AS pair
+            MATCH (source:Code {{repository_id: $repository_id, id: pair.from_id}})
+            MATCH (target:Code {{repository_id: $repository_id, id: pair.to_id}})
+            MERGE (source)-[r:{rel_type}]->(target)
+            SET r.similarity = pair.similarity, r.group = pair.group,
+                r.repository_id = $repository_id
+            RETURN count(r) as count
+            "#
+        );
+
+        let pair_data: Vec<BoltType> = pairs
             .iter()
-            .map(|l| {
+            .map(|p| {
                 let mut m: HashMap<String, BoltType> = HashMap::new();
-                m.insert("from_id".into(), l.from_name.clone().into());
-                m.insert("to_id".into(), l.to_name.clone().into());
-                let kind = match l.link_type {
-                    LinkType::Has => "HAS",
-                    LinkType::Owns => "OWNS",
-                    LinkType::Uses => "USES",
-                    LinkType::Import => "IMPORTS",
-                };
-                m.insert("rel_type".into(), kind.into());
+                m.insert("from_id".into(), p.from_id.clone().into());
+                m.insert("to_id".into(), p.to_id.clone().into());
+                m.insert("similarity".into(), p.similarity.into());
+                m.insert("group".into(), p.group.clone().into());
                 m.into()
             })
             .collect();
 
-        // Execute the query
-        let q = query(cypher).param("links", link_data);
-        let mut result = self
-            .graph
-            .execute(q)
-            .await
-            .map_err(|e| AppError::Neo4j(e))?;
+        let result = self
+            .rows(
+                query(&cypher)
+                    .param("repository_id", repository_id)
+                    .param("pairs", pair_data),
+            )
+            .await?;
 
-        // Get the count of created relationships
         let mut count = 0;
-        if let Some(row) = result.next().await.map_err(|e| AppError::Neo4j(e))? {
+        if let Some(row) = result.into_iter().next() {
             count = row.get::<i64>("count").unwrap_or(0);
         }
 
-        info!("Created {} relationships in batch", count);
+        info!("Created {} duplicate code links", count);
         Ok(())
     }
 
-    // Process a file and create all necessary nodes and relationships
-    pub async fn process_file_structure(&self, file_structure: &FileStructure) -> Result<()> {
-        // First, create file node
-        let file_path = &file_structure.file_path;
-        let file_name = std::path::Path::new(file_path)
-            .file_name()
-            .map(|f| f.to_string_lossy().to_string())
-            .unwrap_or_else(|| file_path.clone());
-
-        let file_extension = std::path::Path::new(file_path)
-            .extension()
-            .map(|e| e.to_string_lossy().to_string())
-            .unwrap_or_default();
-
-        // Create file entity
-        let file_id = format!("file:{}", file_path);
-        let file_entity = CodeEntity {
-            id: file_id.clone(),
-            path: file_path.clone(),
-            entity_type: EntityType::File,
-            start_line: None,
-            end_line: None,
-            properties: {
-                let mut props = HashMap::new();
-                props.insert("name".to_string(), file_name);
-                props.insert("extension".to_string(), file_extension);
-                props.insert("hash".to_string(), file_structure.file_hash.clone());
-                props
-            },
-            children: None,
-        };
-
-        // Collect all entities and relationships
-        let mut all_entities = vec![file_entity];
-        let mut all_links = Vec::new();
-
-        // Process items within the file
-        for item in &file_structure.items {
-            // Create entity for each item
-            let item_id = if item.id.is_empty() {
-                // unwrap the &str from match_entity_type() or bubble the error up
-                let label = self.match_entity_type(&item.entity_type)?;
-                // name := last path component, or fall back to autogenerated uuid
-                let name = Path::new(&item.path)
-                    .file_name()
-                    .map(|f| f.to_string_lossy().into_owned())
-                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
-                format!("{}:{}", label.to_lowercase(), name)
-            } else {
-                item.id.clone()
-            };
-
-            let entity = CodeEntity {
-                id: item_id.clone(),
-                path: item.path.clone(),
-                entity_type: item.entity_type.clone(),
-                start_line: item.start_line,
-                end_line: item.end_line,
-                properties: item.properties.clone(),
-                children: None,
-            };
-
-            all_entities.push(entity);
-
-            // Create link from file to item
-            let link = LinkEntity {
-                from_name: file_id.clone(),
-                to_name: item_id.clone(),
-                link_type: LinkType::Has,
-            };
-
-            all_links.push(link);
-
-            // If this is an import, create an IMPORTS relationship
-            if matches!(item.entity_type, EntityType::Import) {
-                if let Some(target_path) = item.properties.get("target") {
-                    let import_link = LinkEntity {
-                        from_name: file_id.clone(),
-                        to_name: format!("file:{}", target_path),
-                        link_type: LinkType::Import,
-                    };
-                    all_links.push(import_link);
-                }
-            }
-
-            // Process any children
-            if let Some(children) = &item.children {
-                for child in children {
-                    let child_id = if child.id.is_empty() {
-                        let label = self.match_entity_type(&child.entity_type)?;
-                        let name = Path::new(&child.path)
-                            .file_name()
-                            .map(|f| f.to_string_lossy().into_owned())
-                            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
-                        format!("{}:{}", label.to_lowercase(), name)
-                    } else {
-                        child.id.clone()
-                    };
-
-                    let child_entity = CodeEntity {
-                        id: child_id.clone(),
-                        path: child.path.clone(),
-                        entity_type: child.entity_type.clone(),
-                        start_line: child.start_line,
-                        end_line: child.end_line,
-                        properties: child.properties.clone(),
-                        children: None,
-                    };
-
-                    all_entities.push(child_entity);
-
-                    // Create link from parent to child
-                    let parent_child_link = LinkEntity {
-                        from_name: item_id.clone(),
-                        to_name: child_id,
-                        link_type: LinkType::Has,
-                    };
-
-                    all_links.push(parent_child_link);
-                }
-            }
-        }
+    async fn replace_cycle_groups(&self, repository_id: &str, groups: &[CycleGroup]) -> Result<()> {
+        self.check_writable()?;
+        let clear = query("MATCH (n:CycleGroup {repository_id: $repository_id}) DETACH DELETE n")
+            .param("repository_id", repository_id);
+        self.run(clear).await?;
 
-        // Batch process everything
-        self.batch_ingest_entities(&all_entities).await?;
-        self.batch_create_links(&all_links).await?;
-
-        // Create relationship between file and its directory
-        let dir_path = std::path::Path::new(file_path)
-            .parent()
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_default();
-
-        if !dir_path.is_empty() {
-            let dir_id = format!("directory:{}", dir_path);
-
-            // Create directory entity if it doesn't exist
-            let dir_entity = CodeEntity {
-                id: dir_id.clone(),
-                path: dir_path.clone(),
-                entity_type: EntityType::Directory,
-                start_line: None,
-                end_line: None,
-                properties: {
-                    let mut props = HashMap::new();
-                    props.insert(
-                        "name".to_string(),
-                        std::path::Path::new(&dir_path)
-                            .file_name()
-                            .map(|f| f.to_string_lossy().to_string())
-                            .unwrap_or_else(|| dir_path.clone()),
-                    );
-                    props
-                },
-                children: None,
-            };
-
-            self.batch_ingest_entities(&[dir_entity]).await?;
-
-            // Create CONTAINS relationship
-            let contains_link = LinkEntity {
-                from_name: dir_id,
-                to_name: file_id,
-                link_type: LinkType::Has,
-            };
-
-            self.batch_create_links(&[contains_link]).await?;
-        }
+        let (nodes, links) = import_cycles::marker_nodes(groups);
+        self.batch_ingest_entities(repository_id, &nodes).await?;
+        self.batch_create_links(repository_id, &links).await?;
 
-        info!("Processed file structure for {}", file_path);
+        info!("Marked {} import cycles", groups.len());
         Ok(())
     }
+}
 
-    // Process multiple file structures at once
-    pub async fn batch_process_file_structures(&self, structures: &[FileStructure]) -> Result<()> {
-        for structure in structures {
-            self.process_file_structure(structure).await?;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::LINK_ALIAS_PROPERTY;
+
+    #[tokio::test]
+    async fn test_retrying_transient_errors_and_timeouts() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::time::Duration;
+
+        let mut settings = Neo4jSettings::new("bolt://localhost:7687", "neo4j", "");
+        settings.query_timeout = Duration::from_millis(20);
+        settings.retry_backoff = Duration::from_millis(1);
+        settings.max_retries = 2;
+
+        // Fails with `errors` before answering, each failure made by `fail`
+        let calls = AtomicU32::new(0);
+        let flaky = |errors: u32, fail: fn() -> Option<neo4rs::Error>| {
+            calls.store(0, Ordering::SeqCst);
+            let calls = &calls;
+            move || async move {
+                if calls.fetch_add(1, Ordering::SeqCst) < errors {
+                    match fail() {
+                        Some(e) => return Err(e),
+                        None => tokio::time::sleep(Duration::from_secs(1)).await,
+                    }
+                }
+                Ok(7)
+            }
+        };
+        let lost = || Some(neo4rs::Error::ConnectionError);
+        let slow = || None;
+        let broken = || Some(neo4rs::Error::InvalidConfig);
+
+        let ok = retrying(&settings, OnTimeout::Fail, flaky(2, lost)).await;
+        assert_eq!((ok.unwrap(), calls.load(Ordering::SeqCst)), (7, 3));
+        let err = retrying(&settings, OnTimeout::Fail, flaky(3, lost)).await;
+        assert!(matches!(err, Err(AppError::Neo4j(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let err = retrying(&settings, OnTimeout::Retry, flaky(1, broken)).await;
+        assert!(matches!(err, Err(AppError::Neo4j(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Reads run again after a timeout, writes don't
+        let ok = retrying(&settings, OnTimeout::Retry, flaky(1, slow)).await;
+        assert_eq!((ok.unwrap(), calls.load(Ordering::SeqCst)), (7, 2));
+        let err = retrying(&settings, OnTimeout::Fail, flaky(1, slow)).await;
+        assert!(matches!(err, Err(AppError::Storage(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
 
-        info!("Processed {} file structures in batch", structures.len());
-        Ok(())
+    #[test]
+    fn test_links_are_grouped_per_relationship_type() {
+        let link = |from: &str, to: &str, link_type: LinkType| LinkEntity {
+            from_name: from.to_string(),
+            to_name: to.to_string(),
+            link_type,
+            properties: HashMap::new(),
+        };
+        let mut import = link("a", "b", LinkType::Import);
+        import
+            .properties
+            .insert(LINK_ALIAS_PROPERTY.to_string(), "bee".to_string());
+        let links = [
+            import,
+            link("file", "a", LinkType::Has),
+            link("a", "c", LinkType::Import),
+            link("t", "a", LinkType::Tests),
+        ];
+
+        let by_type = NeoDB::links_by_type(&links);
+        let groups: Vec<(&str, Vec<&str>)> = by_type
+            .iter()
+            .map(|(rel_type, links)| {
+                let targets = links.iter().map(|l| l.to_name.as_str()).collect();
+                (*rel_type, targets)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("HAS", vec!["a"]),
+                ("IMPORTS", vec!["b", "c"]),
+                ("TESTS", vec!["a"])
+            ]
+        );
+        assert_eq!(by_type["IMPORTS"][0].properties[LINK_ALIAS_PROPERTY], "bee");
+        assert_eq!(NeoDB::links_queries("r1", &links).len(), 3);
+        assert!(NeoDB::links_queries("r1", &[]).is_empty());
     }
 }
@@ -0,0 +1,123 @@
+//! How the app connects to Neo4j: server and credentials, connection pool
+//! and how long and how often a query is tried. Settings come from the
+//! environment or from the active connection profile (see `profiles`).
+
+use crate::env_utils;
+use crate::error::{AppError, Result};
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+const DEFAULT_FETCH_SIZE: usize = 500;
+const DEFAULT_QUERY_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 200;
+
+#[derive(Clone)]
+pub struct Neo4jSettings {
+    pub uri: String,
+    pub user: String,
+    pub password: String,
+    /// Server default database when not set
+    pub database: Option<String>,
+    /// Extra CA certificate (PEM) trusted for `+s` and `+ssc` URIs, e.g. for
+    /// a self-signed server
+    pub ca_certificate: Option<PathBuf>,
+    /// Size of the connection pool shared by every command and the watcher
+    pub max_connections: usize,
+    /// Rows fetched per round trip when reading results
+    pub fetch_size: usize,
+    /// Limit for a single attempt of a query or transaction
+    pub query_timeout: Duration,
+    /// Attempts after the first one on connection loss, transient errors and
+    /// timed-out reads
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further one
+    pub retry_backoff: Duration,
+}
+
+impl Neo4jSettings {
+    /// Default pool and retry settings for a server
+    pub fn new(uri: &str, user: &str, password: &str) -> Self {
+        Neo4jSettings {
+            uri: uri.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            database: None,
+            ca_certificate: None,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            fetch_size: DEFAULT_FETCH_SIZE,
+            query_timeout: Duration::from_secs(DEFAULT_QUERY_TIMEOUT_SECS),
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS),
+        }
+    }
+
+    /// Reads `NEO4J_URI`, `NEO4J_USER` and `NEO4J_PASSWORD`, and optionally
+    /// `NEO4J_DATABASE`, `NEO4J_CA_CERT` and the tuning variables of
+    /// `apply_env_tuning`
+    pub fn from_env() -> Result<Self> {
+        let mut settings = Neo4jSettings::new(
+            &env_utils::get_required("NEO4J_URI")?,
+            &env_utils::get_required("NEO4J_USER")?,
+            &env_utils::get_required("NEO4J_PASSWORD")?,
+        );
+        settings.database = env_utils::get("NEO4J_DATABASE").filter(|d| !d.is_empty());
+        settings.ca_certificate = env_utils::get("NEO4J_CA_CERT")
+            .filter(|c| !c.is_empty())
+            .map(PathBuf::from);
+        settings.apply_env_tuning()?;
+        Ok(settings)
+    }
+
+    /// Overrides the pool and retry defaults with `NEO4J_MAX_CONNECTIONS`,
+    /// `NEO4J_FETCH_SIZE`, `NEO4J_QUERY_TIMEOUT_SECS`, `NEO4J_MAX_RETRIES`
+    /// and `NEO4J_RETRY_BACKOFF_MS` where set
+    pub fn apply_env_tuning(&mut self) -> Result<()> {
+        self.max_connections = env_or("NEO4J_MAX_CONNECTIONS", self.max_connections)?;
+        self.fetch_size = env_or("NEO4J_FETCH_SIZE", self.fetch_size)?;
+        self.query_timeout = Duration::from_secs(env_or(
+            "NEO4J_QUERY_TIMEOUT_SECS",
+            self.query_timeout.as_secs(),
+        )?);
+        self.max_retries = env_or("NEO4J_MAX_RETRIES", self.max_retries)?;
+        self.retry_backoff = Duration::from_millis(env_or(
+            "NEO4J_RETRY_BACKOFF_MS",
+            self.retry_backoff.as_millis() as u64,
+        )?);
+        Ok(())
+    }
+
+    /// Delay before retry number `attempt`, counting from 0
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.retry_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+fn env_or<T>(key: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env_utils::get(key) {
+        None => Ok(default),
+        Some(value) => value
+            .parse()
+            .map_err(|e: T::Err| AppError::EnvVarParseError(key.to_string(), e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles() {
+        let settings = Neo4jSettings::new("bolt://localhost:7687", "neo4j", "secret");
+        assert_eq!(settings.backoff(0), Duration::from_millis(200));
+        assert_eq!(settings.backoff(3), Duration::from_millis(1600));
+    }
+}
@@ -0,0 +1,313 @@
+//! Saved Neo4j connection profiles, so a packaged app can connect without a
+//! `.env` file.
+//!
+//! Profiles and the name of the active one are kept in `connections.json` in
+//! the app config directory. `NEO4J_URI` in the environment or `.env` still
+//! wins over the active profile. Passwords are written to that file (readable
+//! only by the user on Unix) and never sent back to the frontend:
+//! commands return `ProfileView`s, which only say whether one is set.
+
+use crate::env_utils;
+use crate::error::{AppError, Result};
+use crate::file_manager::neo4j_settings::Neo4jSettings;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const PROFILES_FILE: &str = "connections.json";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ConnectionProfile {
+    pub name: String,
+    /// `bolt://`, `neo4j://` or their `+s` / `+ssc` variants
+    pub uri: String,
+    pub user: String,
+    /// Left unchanged when saving a profile without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Server default database when not set
+    pub database: Option<String>,
+    /// Connect over TLS even when the URI scheme doesn't ask for it
+    pub encrypted: bool,
+    /// Extra CA certificate (PEM) to trust, e.g. for a self-signed server
+    pub ca_certificate: Option<String>,
+}
+
+impl ConnectionProfile {
+    /// `uri` with `+s` added to its scheme when `encrypted` is set
+    pub fn connection_uri(&self) -> String {
+        match self.uri.split_once("://") {
+            Some((scheme, rest)) if self.encrypted && !scheme.contains('+') => {
+                format!("{}+s://{}", scheme, rest)
+            }
+            _ => self.uri.clone(),
+        }
+    }
+
+    /// Settings for connecting with this profile; pool and retry tuning from
+    /// the environment still applies
+    pub fn settings(&self) -> Result<Neo4jSettings> {
+        let mut settings = Neo4jSettings::new(
+            &self.connection_uri(),
+            &self.user,
+            self.password.as_deref().unwrap_or_default(),
+        );
+        settings.database = self.database.clone().filter(|d| !d.is_empty());
+        settings.ca_certificate = self
+            .ca_certificate
+            .as_ref()
+            .filter(|c| !c.is_empty())
+            .map(PathBuf::from);
+        settings.apply_env_tuning()?;
+        Ok(settings)
+    }
+
+    fn view(&self, active: bool) -> ProfileView {
+        ProfileView {
+            name: self.name.clone(),
+            uri: self.uri.clone(),
+            user: self.user.clone(),
+            database: self.database.clone(),
+            encrypted: self.encrypted || self.connection_uri().contains("+s"),
+            ca_certificate: self.ca_certificate.clone(),
+            has_password: self.password.as_ref().is_some_and(|p| !p.is_empty()),
+            active,
+        }
+    }
+}
+
+/// A profile as shown to the frontend, without its password
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileView {
+    pub name: String,
+    pub uri: String,
+    pub user: String,
+    pub database: Option<String>,
+    pub encrypted: bool,
+    pub ca_certificate: Option<String>,
+    pub has_password: bool,
+    pub active: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileList {
+    pub profiles: Vec<ProfileView>,
+    pub active: Option<String>,
+    /// `NEO4J_URI` is set, so the environment is used instead of the profiles
+    pub env_override: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profiles {
+    pub active: Option<String>,
+    pub profiles: Vec<ConnectionProfile>,
+}
+
+impl Profiles {
+    /// Reads the profiles in `config_dir`; none when the file doesn't exist
+    pub fn load(config_dir: &Path) -> Result<Self> {
+        let path = config_dir.join(PROFILES_FILE);
+        if !path.exists() {
+            return Ok(Profiles::default());
+        }
+        serde_json::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| AppError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Replaces the file in one rename, so a crash leaves the old one intact
+    pub fn save(&self, config_dir: &Path) -> Result<()> {
+        fs::create_dir_all(config_dir)?;
+        let path = config_dir.join(PROFILES_FILE);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::Config(format!("Failed to write profiles: {}", e)))?;
+        fs::write(&tmp, json)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ConnectionProfile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn active(&self) -> Option<&ConnectionProfile> {
+        self.active.as_deref().and_then(|name| self.get(name))
+    }
+
+    /// Adds the profile or replaces the one of the same name, keeping its
+    /// password when the new one has none (see `with_saved_password`)
+    pub fn upsert(&mut self, profile: ConnectionProfile) -> Result<ProfileView> {
+        let mut profile = self.with_saved_password(profile)?;
+        profile.name = profile.name.trim().to_string();
+        if profile.name.is_empty() || profile.uri.trim().is_empty() {
+            return Err(AppError::Config(
+                "A connection profile needs a name and a URI".to_string(),
+            ));
+        }
+        if let Some(existing) = self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            *existing = profile.clone();
+        } else {
+            self.profiles.push(profile.clone());
+        }
+        let active = self.active.as_deref() == Some(profile.name.as_str());
+        Ok(profile.view(active))
+    }
+
+    pub fn activate(&mut self, name: &str) -> Result<()> {
+        if self.get(name).is_none() {
+            return Err(AppError::Config(format!(
+                "No connection profile named '{}'",
+                name
+            )));
+        }
+        self.active = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Fills in the saved password of a profile edited without one. The
+    /// password is only reused for the same URI and user, so it is never sent
+    /// to another server; a profile that changed either needs a new one.
+    pub fn with_saved_password(&self, mut profile: ConnectionProfile) -> Result<ConnectionProfile> {
+        if profile.password.is_some() {
+            return Ok(profile);
+        }
+        let Some(saved) = self.get(profile.name.trim()) else {
+            return Ok(profile);
+        };
+        if saved.password.is_some() && (saved.uri != profile.uri || saved.user != profile.user) {
+            return Err(AppError::Config(format!(
+                "Enter the password for '{}' again: its URI or user changed",
+                saved.name
+            )));
+        }
+        profile.password = saved.password.clone();
+        Ok(profile)
+    }
+
+    pub fn list(&self) -> ProfileList {
+        ProfileList {
+            profiles: self
+                .profiles
+                .iter()
+                .map(|p| p.view(self.active.as_deref() == Some(p.name.as_str())))
+                .collect(),
+            active: self.active.clone(),
+            env_override: env_override(),
+        }
+    }
+}
+
+/// Whether `NEO4J_URI` is set and replaces the active profile
+pub fn env_override() -> bool {
+    env_utils::get("NEO4J_URI").is_some_and(|uri| !uri.is_empty())
+}
+
+/// Neo4j settings from the environment when `NEO4J_URI` is set, otherwise
+/// from the active profile in `config_dir`
+pub fn neo4j_settings(config_dir: &Path) -> Result<Neo4jSettings> {
+    if env_override() {
+        return Neo4jSettings::from_env();
+    }
+    let profiles = Profiles::load(config_dir)?;
+    match profiles.active() {
+        Some(profile) => {
+            info!("Using connection profile '{}'", profile.name);
+            profile.settings()
+        }
+        None => Err(AppError::Config(
+            "No Neo4j connection: set NEO4J_URI or choose a connection profile".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn profile(name: &str, password: Option<&str>) -> ConnectionProfile {
+        ConnectionProfile {
+            name: name.to_string(),
+            uri: "neo4j://db.example.com:7687".to_string(),
+            user: "neo4j".to_string(),
+            password: password.map(str::to_string),
+            ..ConnectionProfile::default()
+        }
+    }
+
+    #[test]
+    fn test_connection_uri_adds_tls() {
+        let mut aura = profile("aura", None);
+        assert_eq!(aura.connection_uri(), "neo4j://db.example.com:7687");
+        aura.encrypted = true;
+        assert_eq!(aura.connection_uri(), "neo4j+s://db.example.com:7687");
+        aura.uri = "bolt+ssc://localhost".to_string();
+        assert_eq!(aura.connection_uri(), "bolt+ssc://localhost");
+    }
+
+    #[test]
+    fn test_profiles_keep_password_out_of_views() {
+        let dir = tempdir().unwrap();
+        let mut profiles = Profiles::default();
+        profiles.upsert(profile("local", Some("secret"))).unwrap();
+        profiles.activate("local").unwrap();
+        assert!(profiles.activate("missing").is_err());
+
+        // Editing without a password keeps the saved one
+        let view = profiles.upsert(profile("local", None)).unwrap();
+        assert!(view.has_password && view.active);
+        profiles.save(dir.path()).unwrap();
+
+        let loaded = Profiles::load(dir.path()).unwrap();
+        assert_eq!(loaded.active().unwrap().password.as_deref(), Some("secret"));
+        let json = serde_json::to_string(&loaded.list()).unwrap();
+        assert!(json.contains("\"hasPassword\":true"));
+        assert!(!json.contains("secret"));
+    }
+
+    #[test]
+    fn test_saved_password_needs_same_uri_and_user() {
+        let mut profiles = Profiles::default();
+        profiles.upsert(profile("local", Some("secret"))).unwrap();
+
+        let same = profiles
+            .with_saved_password(profile("local", None))
+            .unwrap();
+        assert_eq!(same.password.as_deref(), Some("secret"));
+
+        // Pointing the profile elsewhere doesn't send the saved password there
+        let moved = ConnectionProfile {
+            uri: "neo4j://evil.example.com:7687".to_string(),
+            ..profile("local", None)
+        };
+        assert!(profiles.with_saved_password(moved.clone()).is_err());
+        assert!(profiles.upsert(moved.clone()).is_err());
+        let renamed_user = ConnectionProfile {
+            user: "admin".to_string(),
+            ..profile("local", None)
+        };
+        assert!(profiles.with_saved_password(renamed_user).is_err());
+
+        // With an explicit password the edit goes through
+        let moved = ConnectionProfile {
+            password: Some("other".to_string()),
+            ..moved
+        };
+        assert!(profiles.upsert(moved).unwrap().has_password);
+        assert_eq!(
+            profiles.get("local").unwrap().password.as_deref(),
+            Some("other")
+        );
+    }
+}
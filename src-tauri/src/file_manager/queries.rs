@@ -0,0 +1,545 @@
+//! Read-only queries behind the symbol search, call and outline views.
+//!
+//! Results are shaped like the frontend's `GraphNode`, `GraphLink`,
+//! `CodeGraphData` and `SearchResult` (see `src/lib/types.ts`) and are built
+//! on `GraphStore` primitives, so they behave the same for every backend.
+//! Callers and callees follow `CALLS`, `TESTS` and `INVOKES_MACRO`, and all
+//! three are drawn as `calls` links.
+
+use crate::error::Result;
+use crate::file_manager::graph_store::{
+    symbol_rank, Direction, GraphFilter, GraphStore, StoredEdge,
+};
+use crate::models::{CodeEntity, EntityType, LinkType};
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// Relationship types followed by `get_callers` and `get_callees`
+const CALL_REL_TYPES: [LinkType; 3] = [LinkType::Calls, LinkType::Tests, LinkType::InvokesMacro];
+/// Hops `get_neighbors` goes at most, however many are asked for
+const MAX_NEIGHBOR_DEPTH: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphNodeType {
+    Class,
+    Method,
+    Function,
+    File,
+    Directory,
+    Import,
+}
+
+impl GraphNodeType {
+    /// Node type drawn for an entity; comments and cycle markers aren't drawn
+    pub fn of(entity_type: &EntityType) -> Option<Self> {
+        match entity_type {
+            EntityType::Class | EntityType::Interface => Some(GraphNodeType::Class),
+            EntityType::Method => Some(GraphNodeType::Method),
+            EntityType::Function | EntityType::Test | EntityType::Macro => {
+                Some(GraphNodeType::Function)
+            }
+            EntityType::File => Some(GraphNodeType::File),
+            EntityType::Directory
+            | EntityType::Repository
+            | EntityType::Project
+            | EntityType::Crate
+            | EntityType::Package => Some(GraphNodeType::Directory),
+            EntityType::Import | EntityType::ExternalLibrary => Some(GraphNodeType::Import),
+            EntityType::Todo | EntityType::CycleGroup => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphLinkType {
+    Calls,
+    Imports,
+    Contains,
+}
+
+impl GraphLinkType {
+    /// Link type drawn for a stored relationship type
+    pub fn of(rel_type: &str) -> Option<Self> {
+        match LinkType::from_rel_type(rel_type)? {
+            LinkType::Calls | LinkType::Tests | LinkType::InvokesMacro => {
+                Some(GraphLinkType::Calls)
+            }
+            LinkType::Import => Some(GraphLinkType::Imports),
+            LinkType::Has | LinkType::Owns | LinkType::Uses | LinkType::Module => {
+                Some(GraphLinkType::Contains)
+            }
+            LinkType::In | LinkType::BelongsTo | LinkType::InCycle | LinkType::DuplicateOf => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GraphNode {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub node_type: GraphNodeType,
+    /// The entity's label, e.g. `Interface` for a `class` node
+    pub group: String,
+    /// Lines the entity spans
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct GraphLink {
+    pub source: String,
+    pub target: String,
+    #[serde(rename = "type")]
+    pub link_type: GraphLinkType,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CodeGraphData {
+    pub nodes: Vec<GraphNode>,
+    pub links: Vec<GraphLink>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub id: String,
+    pub name: String,
+    pub path: String,
+    /// Lowercase label, e.g. `function`
+    #[serde(rename = "type")]
+    pub result_type: String,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    /// Relevance, higher is better; from 0 to 1 for `find_symbols`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EntityDetails {
+    #[serde(flatten)]
+    pub entity: SearchResult,
+    pub properties: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineItem {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    /// Items declared inside this one, e.g. the methods of a class
+    pub children: Vec<OutlineItem>,
+}
+
+fn name_of(entity: &CodeEntity) -> String {
+    entity.properties.get("name").cloned().unwrap_or_else(|| {
+        entity
+            .id
+            .rsplit(['/', '\\', ':'])
+            .find(|part| !part.is_empty())
+            .unwrap_or(&entity.id)
+            .to_string()
+    })
+}
+
+pub fn search_result(entity: &CodeEntity, score: Option<f64>) -> SearchResult {
+    SearchResult {
+        id: entity.id.clone(),
+        name: name_of(entity),
+        path: entity.path.clone(),
+        result_type: entity.entity_type.to_string().to_lowercase(),
+        start_line: entity.start_line,
+        end_line: entity.end_line,
+        score,
+    }
+}
+
+fn graph_node(entity: &CodeEntity) -> Option<GraphNode> {
+    Some(GraphNode {
+        id: entity.id.clone(),
+        name: name_of(entity),
+        node_type: GraphNodeType::of(&entity.entity_type)?,
+        group: entity.entity_type.to_string(),
+        value: match (entity.start_line, entity.end_line) {
+            (Some(start), Some(end)) if end >= start => Some(end - start + 1),
+            _ => None,
+        },
+    })
+}
+
+/// Nodes that can be drawn and the links between them, one per pair and type
+pub fn graph_data(entities: &[CodeEntity], edges: &[StoredEdge]) -> CodeGraphData {
+    let nodes: Vec<GraphNode> = entities.iter().filter_map(graph_node).collect();
+    let ids: HashSet<&str> = nodes.iter().map(|node| node.id.as_str()).collect();
+    let links: BTreeSet<GraphLink> = edges
+        .iter()
+        .filter(|edge| ids.contains(edge.from.as_str()) && ids.contains(edge.to.as_str()))
+        .filter_map(|edge| {
+            Some(GraphLink {
+                source: edge.from.clone(),
+                target: edge.to.clone(),
+                link_type: GraphLinkType::of(&edge.rel_type)?,
+            })
+        })
+        .collect();
+    CodeGraphData {
+        nodes,
+        links: links.into_iter().collect(),
+    }
+}
+
+/// Symbols whose name or path contains `text`, best first. `labels` (e.g.
+/// `Function`) narrows the search when not empty.
+pub async fn find_symbols(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    text: &str,
+    labels: &[String],
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+    let entities = graph
+        .find_entities(repository_id, &text, labels, limit)
+        .await?;
+    Ok(entities
+        .iter()
+        .map(|entity| {
+            let name = entity.properties.get("name").map(String::as_str);
+            let score = symbol_rank(name.unwrap_or_default(), &entity.path, &text)
+                .map(|rank| f64::from(4 - rank) / 4.0);
+            search_result(entity, score)
+        })
+        .collect())
+}
+
+pub async fn get_entity(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    id: &str,
+) -> Result<Option<EntityDetails>> {
+    let entities = graph.get_entities(repository_id, &[id.to_string()]).await?;
+    Ok(entities.into_iter().next().map(|entity| EntityDetails {
+        entity: search_result(&entity, None),
+        properties: entity.properties.into_iter().collect(),
+    }))
+}
+
+/// Entities at the other end of the call edges of `id`, by path and line
+async fn call_partners(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    id: &str,
+    direction: Direction,
+) -> Result<Vec<SearchResult>> {
+    let rel_types: Vec<String> = CALL_REL_TYPES
+        .iter()
+        .map(|link_type| link_type.rel_type().to_string())
+        .collect();
+    let edges = graph
+        .entity_edges(repository_id, &[id.to_string()], direction, &rel_types)
+        .await?;
+    let partners: BTreeSet<String> = edges
+        .into_iter()
+        .map(|edge| match direction {
+            Direction::Incoming => edge.from,
+            _ => edge.to,
+        })
+        .collect();
+
+    let mut entities = graph
+        .get_entities(repository_id, &partners.into_iter().collect::<Vec<_>>())
+        .await?;
+    entities.sort_by(|a, b| (&a.path, a.start_line, &a.id).cmp(&(&b.path, b.start_line, &b.id)));
+    Ok(entities.iter().map(|e| search_result(e, None)).collect())
+}
+
+/// Functions and tests that call `id`
+pub async fn get_callers(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    id: &str,
+) -> Result<Vec<SearchResult>> {
+    call_partners(graph, repository_id, id, Direction::Incoming).await
+}
+
+/// Functions and macros that `id` calls
+pub async fn get_callees(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    id: &str,
+) -> Result<Vec<SearchResult>> {
+    call_partners(graph, repository_id, id, Direction::Outgoing).await
+}
+
+/// Entities declared in a file, nested by the lines they span
+pub async fn get_file_outline(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    path: &str,
+) -> Result<Vec<OutlineItem>> {
+    let filter = GraphFilter {
+        path_prefix: Some(path.to_string()),
+        ..GraphFilter::default()
+    };
+    let (entities, _) = graph.subgraph(repository_id, &filter).await?;
+    let mut entities: Vec<CodeEntity> = entities
+        .into_iter()
+        .filter(|e| e.path == path)
+        .filter(|e| !matches!(e.entity_type, EntityType::File | EntityType::Todo))
+        .collect();
+    // Outer items first, so each one is seen before what it contains
+    entities.sort_by(|a, b| {
+        (a.start_line, std::cmp::Reverse(a.end_line), &a.id).cmp(&(
+            b.start_line,
+            std::cmp::Reverse(b.end_line),
+            &b.id,
+        ))
+    });
+    Ok(nest(entities))
+}
+
+fn nest(entities: Vec<CodeEntity>) -> Vec<OutlineItem> {
+    fn contains(outer: &OutlineItem, entity: &CodeEntity) -> bool {
+        match (
+            outer.start_line,
+            outer.end_line,
+            entity.start_line,
+            entity.end_line,
+        ) {
+            (Some(start), Some(end), Some(inner_start), Some(inner_end)) => {
+                start <= inner_start && inner_end <= end
+            }
+            _ => false,
+        }
+    }
+    fn close(stack: &mut Vec<OutlineItem>, roots: &mut Vec<OutlineItem>) {
+        if let Some(item) = stack.pop() {
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(item),
+                None => roots.push(item),
+            }
+        }
+    }
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<OutlineItem> = Vec::new();
+    for entity in entities {
+        while stack.last().is_some_and(|open| !contains(open, &entity)) {
+            close(&mut stack, &mut roots);
+        }
+        stack.push(OutlineItem {
+            id: entity.id.clone(),
+            name: name_of(&entity),
+            item_type: entity.entity_type.to_string().to_lowercase(),
+            start_line: entity.start_line,
+            end_line: entity.end_line,
+            children: Vec::new(),
+        });
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+    roots
+}
+
+/// Everything within `depth` hops of `id` (at most `MAX_NEIGHBOR_DEPTH`),
+/// following edges of the given types either way, or of any type
+pub async fn get_neighbors(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    id: &str,
+    depth: usize,
+    rel_types: &[String],
+) -> Result<CodeGraphData> {
+    let mut seen: BTreeSet<String> = BTreeSet::from([id.to_string()]);
+    let mut frontier = vec![id.to_string()];
+    let mut edges = Vec::new();
+    for _ in 0..depth.min(MAX_NEIGHBOR_DEPTH) {
+        if frontier.is_empty() {
+            break;
+        }
+        let found = graph
+            .entity_edges(repository_id, &frontier, Direction::Both, rel_types)
+            .await?;
+        frontier = Vec::new();
+        for edge in found {
+            for end in [&edge.from, &edge.to] {
+                if seen.insert(end.clone()) {
+                    frontier.push(end.clone());
+                }
+            }
+            edges.push(edge);
+        }
+    }
+
+    let entities = graph
+        .get_entities(repository_id, &seen.into_iter().collect::<Vec<_>>())
+        .await?;
+    Ok(graph_data(&entities, &edges))
+}
+
+/// The whole repository graph, or the part matching `filter`
+pub async fn get_project_graph(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    filter: &GraphFilter,
+) -> Result<CodeGraphData> {
+    let (entities, edges) = graph.subgraph(repository_id, filter).await?;
+    Ok(graph_data(&entities, &edges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::memory_store::MemoryGraph;
+    use crate::models::LinkEntity;
+    use std::collections::HashMap;
+
+    fn link(from: &str, to: &str, link_type: LinkType) -> LinkEntity {
+        LinkEntity {
+            from_name: from.to_string(),
+            to_name: to.to_string(),
+            link_type,
+            properties: HashMap::new(),
+        }
+    }
+
+    async fn shapes() -> MemoryGraph {
+        let graph = MemoryGraph::new();
+        let file = "/repo/shapes.rs";
+        let entities: Vec<CodeEntity> = [
+            (file, EntityType::File, "shapes.rs", (1, 40)),
+            ("shapes::Shape", EntityType::Class, "Shape", (1, 20)),
+            ("shapes::Shape::area", EntityType::Method, "area", (3, 8)),
+            (
+                "shapes::area_sum",
+                EntityType::Function,
+                "area_sum",
+                (22, 30),
+            ),
+            ("shapes::test_area", EntityType::Test, "test_area", (32, 40)),
+        ]
+        .into_iter()
+        .map(|(id, entity_type, name, (start, end))| {
+            CodeEntity::fixture(id, file, entity_type)
+                .with_property("name", name)
+                .with_lines(start, end)
+        })
+        .collect();
+        graph.batch_ingest_entities("r1", &entities).await.unwrap();
+        graph
+            .batch_create_links(
+                "r1",
+                &[
+                    link(file, "shapes::Shape", LinkType::Owns),
+                    link(file, "shapes::area_sum", LinkType::Uses),
+                    link("shapes::test_area", "shapes::area_sum", LinkType::Tests),
+                    link("shapes::test_area", "shapes::Shape::area", LinkType::Tests),
+                    link("shapes::area_sum", "shapes::Shape::area", LinkType::Calls),
+                ],
+            )
+            .await
+            .unwrap();
+        graph
+    }
+
+    #[tokio::test]
+    async fn test_symbols_calls_and_outline() {
+        let graph = shapes().await;
+
+        let found = find_symbols(&graph, "r1", "Area", &[], 10).await.unwrap();
+        let names: Vec<&str> = found.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["area", "area_sum", "test_area"]);
+        assert_eq!(found[0].score, Some(1.0));
+        // Path matches rank last
+        let in_file = find_symbols(&graph, "r1", "shapes.rs", &[], 10)
+            .await
+            .unwrap();
+        assert_eq!(in_file[0].name, "shapes.rs");
+        assert_eq!(in_file.len(), 5);
+        assert_eq!(in_file[1].score, Some(0.25));
+        let functions = find_symbols(&graph, "r1", "area", &["Function".to_string()], 10)
+            .await
+            .unwrap();
+        assert_eq!(functions.len(), 1);
+
+        let callers = get_callers(&graph, "r1", "shapes::area_sum").await.unwrap();
+        assert_eq!(callers[0].id, "shapes::test_area");
+        let callees = get_callees(&graph, "r1", "shapes::test_area")
+            .await
+            .unwrap();
+        assert_eq!(callees.len(), 2);
+        // Plain calls and tests both count as callers
+        let callers = get_callers(&graph, "r1", "shapes::Shape::area")
+            .await
+            .unwrap();
+        let ids: Vec<&str> = callers.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["shapes::area_sum", "shapes::test_area"]);
+        let callees = get_callees(&graph, "r1", "shapes::area_sum").await.unwrap();
+        assert_eq!(callees[0].id, "shapes::Shape::area");
+
+        let outline = get_file_outline(&graph, "r1", "/repo/shapes.rs")
+            .await
+            .unwrap();
+        let top: Vec<&str> = outline.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(top, ["Shape", "area_sum", "test_area"]);
+        assert_eq!(outline[0].children[0].name, "area");
+
+        let details = get_entity(&graph, "r1", "shapes::Shape")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(details.entity.result_type, "class");
+        assert!(get_entity(&graph, "r2", "shapes::Shape")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_neighbors_and_project_graph() {
+        let graph = shapes().await;
+
+        let near = get_neighbors(&graph, "r1", "shapes::area_sum", 1, &[])
+            .await
+            .unwrap();
+        let mut ids: Vec<&str> = near.nodes.iter().map(|n| n.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(
+            ids,
+            [
+                "/repo/shapes.rs",
+                "shapes::Shape::area",
+                "shapes::area_sum",
+                "shapes::test_area"
+            ]
+        );
+
+        let all = get_project_graph(&graph, "r1", &GraphFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(all.nodes.len(), 5);
+        let json = serde_json::to_value(&all).unwrap();
+        assert!(json["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|l| l["type"] == "calls" && l["target"] == "shapes::area_sum"));
+        assert!(json["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|n| n["type"] == "class" && n["value"] == 20));
+    }
+}
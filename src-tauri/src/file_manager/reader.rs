@@ -0,0 +1,238 @@
+//! Shared file reading for the parser, the watcher and `read_file_content`.
+//!
+//! Files larger than the configured limit are not read at all, files with NUL
+//! bytes are reported as binary, BOM-marked UTF-8/UTF-16 is decoded, and
+//! anything else that isn't valid UTF-8 is decoded lossily with a warning.
+
+use crate::analysis::hashing;
+use crate::env_utils;
+use crate::error::Result;
+use log::warn;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Default size limit, overridden by `PEAR_MAX_FILE_SIZE` (bytes)
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Bytes inspected for NUL bytes, as git does
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// Invalid UTF-8 decoded with replacement characters
+    Lossy,
+}
+
+impl TextEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "utf-8",
+            TextEncoding::Utf8Bom => "utf-8-bom",
+            TextEncoding::Utf16Le => "utf-16le",
+            TextEncoding::Utf16Be => "utf-16be",
+            TextEncoding::Lossy => "lossy",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FileContent {
+    Text {
+        content: String,
+        encoding: TextEncoding,
+    },
+    Binary,
+    Oversized {
+        size: u64,
+    },
+}
+
+impl FileContent {
+    /// Flags stored on the File node when the content couldn't be parsed
+    /// normally (binary, oversized or lossily decoded)
+    pub fn write_properties(&self, properties: &mut HashMap<String, String>) {
+        match self {
+            FileContent::Text { encoding, .. } => {
+                properties.insert("encoding".to_string(), encoding.as_str().to_string());
+            }
+            FileContent::Binary => {
+                properties.insert("binary".to_string(), "true".to_string());
+            }
+            FileContent::Oversized { size } => {
+                properties.insert("oversized".to_string(), "true".to_string());
+                properties.insert("size".to_string(), size.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FileReader {
+    pub max_size: u64,
+}
+
+impl Default for FileReader {
+    fn default() -> Self {
+        FileReader {
+            max_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+impl FileReader {
+    pub fn from_env() -> Self {
+        FileReader {
+            max_size: env_utils::get_parsed("PEAR_MAX_FILE_SIZE").unwrap_or(DEFAULT_MAX_FILE_SIZE),
+        }
+    }
+
+    /// Hash of the file's bytes, streamed so nothing is buffered. Files over
+    /// the limit aren't read at all: their hash covers only their size and
+    /// modification time.
+    pub fn hash(&self, path: &Path) -> Result<String> {
+        let metadata = std::fs::metadata(path)?;
+        if metadata.len() > self.max_size {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            let stamp = format!("oversized:{}:{}", metadata.len(), modified.as_nanos());
+            return Ok(hashing::file_hash(stamp.as_bytes()));
+        }
+        Ok(hashing::reader_hash(std::fs::File::open(path)?)?)
+    }
+
+    pub fn read(&self, path: &Path) -> Result<FileContent> {
+        let size = std::fs::metadata(path)?.len();
+        if size > self.max_size {
+            return Ok(FileContent::Oversized { size });
+        }
+
+        let mut bytes = Vec::with_capacity(size as usize);
+        std::fs::File::open(path)?
+            .take(self.max_size)
+            .read_to_end(&mut bytes)?;
+
+        Ok(match decode(&bytes) {
+            Some((content, encoding)) => {
+                if encoding == TextEncoding::Lossy {
+                    warn!(
+                        "{} is not valid UTF-8; decoded with replacement characters",
+                        path.display()
+                    );
+                }
+                FileContent::Text { content, encoding }
+            }
+            None => FileContent::Binary,
+        })
+    }
+}
+
+/// Decodes text, or returns None for binary content
+pub fn decode(bytes: &[u8]) -> Option<(String, TextEncoding)> {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return Some((
+            String::from_utf8_lossy(rest).into_owned(),
+            TextEncoding::Utf8Bom,
+        ));
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return Some((
+            decode_utf16(rest, u16::from_le_bytes),
+            TextEncoding::Utf16Le,
+        ));
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return Some((
+            decode_utf16(rest, u16::from_be_bytes),
+            TextEncoding::Utf16Be,
+        ));
+    }
+
+    if bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some((text.to_string(), TextEncoding::Utf8)),
+        Err(_) => Some((
+            String::from_utf8_lossy(bytes).into_owned(),
+            TextEncoding::Lossy,
+        )),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_decode_encodings() {
+        assert_eq!(
+            decode(b"fn main() {}"),
+            Some(("fn main() {}".to_string(), TextEncoding::Utf8))
+        );
+        assert_eq!(
+            decode(b"\xEF\xBB\xBFx = 1"),
+            Some(("x = 1".to_string(), TextEncoding::Utf8Bom))
+        );
+        assert_eq!(
+            decode(b"\xFF\xFEx\x00=\x001\x00"),
+            Some(("x=1".to_string(), TextEncoding::Utf16Le))
+        );
+        assert_eq!(
+            decode(b"caf\xE9"),
+            Some(("caf\u{FFFD}".to_string(), TextEncoding::Lossy))
+        );
+        assert_eq!(decode(b"\x7FELF\x02\x01\x00\x00"), None);
+    }
+
+    #[test]
+    fn test_oversized_file_is_not_read() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("big.js");
+        std::fs::write(&path, "x".repeat(64)).unwrap();
+
+        let reader = FileReader { max_size: 16 };
+        assert!(matches!(
+            reader.read(&path).unwrap(),
+            FileContent::Oversized { size: 64 }
+        ));
+    }
+
+    #[test]
+    fn test_hash_streams_small_files_and_skips_oversized_ones() {
+        let dir = tempdir().unwrap();
+        let small = dir.path().join("small.js");
+        std::fs::write(&small, "let x = 1;").unwrap();
+        let big = dir.path().join("big.js");
+        std::fs::write(&big, "x".repeat(64)).unwrap();
+
+        let reader = FileReader { max_size: 16 };
+        assert_eq!(
+            reader.hash(&small).unwrap(),
+            hashing::file_hash(b"let x = 1;")
+        );
+        assert_ne!(
+            reader.hash(&big).unwrap(),
+            hashing::file_hash("x".repeat(64).as_bytes())
+        );
+        // Growing an oversized file still changes its hash
+        let before = reader.hash(&big).unwrap();
+        std::fs::write(&big, "x".repeat(65)).unwrap();
+        assert_ne!(reader.hash(&big).unwrap(), before);
+    }
+}
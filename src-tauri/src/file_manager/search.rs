@@ -0,0 +1,304 @@
+//! Full-text code search over entity names, signatures and doc comments.
+//!
+//! Identifiers are split into lowercase words at `snake_case`, `camelCase`
+//! and acronym boundaries (`parseHTTPResponse` → `parse http response`). The
+//! parser stores the words of each entity's name and qualified name in
+//! `SEARCH_TERMS_PROPERTY`; Neo4j indexes them with the signature and doc in
+//! the `entity_search` full-text index, and the local store scores the same
+//! fields with `score` using the same weights. Query words also match the
+//! start of stored words, so `conf` finds `ConfigLoader`.
+
+use crate::analysis::signatures::{DOC_PROPERTY, SIGNATURE_PROPERTY};
+use crate::error::Result;
+use crate::file_manager::graph_store::GraphStore;
+use crate::file_manager::queries::{search_result, SearchResult};
+use crate::models::CodeEntity;
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Entity property holding the words of its name and qualified name
+pub const SEARCH_TERMS_PROPERTY: &str = "search_terms";
+
+/// Weight of a term matching a whole word or the start of one, per field.
+/// The Neo4j query uses the same numbers as boosts.
+pub const FIELD_WEIGHTS: [(&str, f64, f64); 3] = [
+    (SEARCH_TERMS_PROPERTY, 4.0, 2.0),
+    (SIGNATURE_PROPERTY, 1.0, 1.0),
+    (DOC_PROPERTY, 0.5, 0.5),
+];
+
+/// Narrows a search; empty lists and missing values select everything
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SearchFilter {
+    /// Entity labels such as `Function` or `Class`
+    pub labels: Vec<String>,
+    pub path_prefix: Option<String>,
+    /// As stored on entities, e.g. `rust` or `typescript`
+    pub language: Option<String>,
+}
+
+impl SearchFilter {
+    pub fn matches(&self, entity: &CodeEntity) -> bool {
+        (self.labels.is_empty() || self.labels.contains(&entity.entity_type.to_string()))
+            && self
+                .path_prefix
+                .as_ref()
+                .map(|prefix| entity.path.starts_with(prefix.as_str()))
+                .unwrap_or(true)
+            && self
+                .language
+                .as_ref()
+                .map(|language| entity.properties.get("language") == Some(language))
+                .unwrap_or(true)
+    }
+}
+
+/// A run of field text, marked when it matched the query
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Fragment {
+    pub text: String,
+    pub matched: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Highlight {
+    /// `name`, `signature` or `doc`
+    pub field: String,
+    pub fragments: Vec<Fragment>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub result: SearchResult,
+    pub highlights: Vec<Highlight>,
+}
+
+/// Byte ranges of the words in `text`
+fn word_spans(text: &str) -> Vec<Range<usize>> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, &(offset, c)) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if let Some(s) = start.take() {
+                spans.push(s..offset);
+            }
+            continue;
+        }
+        let Some(s) = start else {
+            start = Some(offset);
+            continue;
+        };
+        let prev = chars[i - 1].1;
+        let next_lower = chars.get(i + 1).is_some_and(|&(_, n)| n.is_lowercase());
+        // fooBar, foo2Bar and the `R` of HTTPResponse start a new word
+        let boundary = c.is_uppercase()
+            && (prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_lower));
+        if boundary {
+            spans.push(s..offset);
+            start = Some(offset);
+        }
+    }
+    if let Some(s) = start {
+        spans.push(s..text.len());
+    }
+    spans
+}
+
+/// Lowercase words of identifiers or prose, in order, duplicates kept
+pub fn tokenize(text: &str) -> Vec<String> {
+    word_spans(text)
+        .into_iter()
+        .map(|span| text[span].to_lowercase())
+        .collect()
+}
+
+/// `SEARCH_TERMS_PROPERTY` value for an entity with these names
+pub fn search_terms(names: &[&str]) -> String {
+    let mut terms: Vec<String> = Vec::new();
+    for word in names.iter().flat_map(|name| tokenize(name)) {
+        if !terms.contains(&word) {
+            terms.push(word);
+        }
+    }
+    terms.join(" ")
+}
+
+/// Relevance of an entity for the query words `terms`; 0 when none match.
+/// Each term adds, per field, the weight of its best match.
+pub fn score(entity: &CodeEntity, terms: &[String]) -> f64 {
+    let mut total = 0.0;
+    for (field, whole, prefix) in FIELD_WEIGHTS {
+        let Some(text) = entity.properties.get(field) else {
+            continue;
+        };
+        let words = tokenize(text);
+        for term in terms {
+            if words.iter().any(|w| w == term) {
+                total += whole;
+            } else if words.iter().any(|w| w.starts_with(term.as_str())) {
+                total += prefix;
+            }
+        }
+    }
+    total
+}
+
+/// `text` split into fragments, with the words starting with a term marked
+pub fn highlight(text: &str, terms: &[String]) -> Option<Vec<Fragment>> {
+    fn push(fragments: &mut Vec<Fragment>, text: &str, matched: bool) {
+        if text.is_empty() {
+            return;
+        }
+        match fragments.last_mut() {
+            Some(last) if last.matched == matched => last.text.push_str(text),
+            _ => fragments.push(Fragment {
+                text: text.to_string(),
+                matched,
+            }),
+        }
+    }
+
+    let mut fragments = Vec::new();
+    let mut end = 0;
+    let mut any = false;
+    for span in word_spans(text) {
+        let word = text[span.clone()].to_lowercase();
+        if terms.iter().any(|term| word.starts_with(term.as_str())) {
+            push(&mut fragments, &text[end..span.start], false);
+            push(&mut fragments, &text[span.clone()], true);
+            end = span.end;
+            any = true;
+        }
+    }
+    push(&mut fragments, &text[end..], false);
+    any.then_some(fragments)
+}
+
+fn highlights(entity: &CodeEntity, terms: &[String]) -> Vec<Highlight> {
+    ["name", SIGNATURE_PROPERTY, DOC_PROPERTY]
+        .into_iter()
+        .filter_map(|field| {
+            let text = entity.properties.get(field)?;
+            Some(Highlight {
+                field: field.to_string(),
+                fragments: highlight(text, terms)?,
+            })
+        })
+        .collect()
+}
+
+/// Entities of one repository matching `query`, best first, with the
+/// matching words of their name, signature and doc highlighted
+pub async fn search_code(
+    graph: &dyn GraphStore,
+    repository_id: &str,
+    query: &str,
+    filter: &SearchFilter,
+    limit: usize,
+) -> Result<Vec<SearchHit>> {
+    let mut terms = tokenize(query);
+    terms.sort();
+    terms.dedup();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let found = graph
+        .search_entities(repository_id, &terms, filter, limit)
+        .await?;
+    Ok(found
+        .iter()
+        .map(|(entity, score)| SearchHit {
+            result: search_result(entity, Some(*score)),
+            highlights: highlights(entity, &terms),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::memory_store::MemoryGraph;
+    use crate::models::EntityType;
+
+    #[test]
+    fn test_tokenize_identifiers() {
+        assert_eq!(
+            tokenize("parseHTTPResponse_v2"),
+            ["parse", "http", "response", "v2"]
+        );
+        assert_eq!(
+            tokenize("NeoDB.ingest_entity"),
+            ["neo", "db", "ingest", "entity"]
+        );
+        assert_eq!(tokenize("utf8Decode"), ["utf8", "decode"]);
+        assert_eq!(
+            search_terms(&["loadFile", "Loader.loadFile"]),
+            "load file loader"
+        );
+    }
+
+    #[test]
+    fn test_highlight_marks_word_prefixes() {
+        let fragments = highlight("fn read_config(path)", &["conf".to_string()]).unwrap();
+        let marked: Vec<(&str, bool)> = fragments
+            .iter()
+            .map(|f| (f.text.as_str(), f.matched))
+            .collect();
+        assert_eq!(
+            marked,
+            [("fn read_", false), ("config", true), ("(path)", false)]
+        );
+        assert!(highlight("fn main()", &["conf".to_string()]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_and_filters() {
+        let entities: Vec<CodeEntity> = [
+            ("a.rs", "load_config", "rust", None),
+            (
+                "b.ts",
+                "readSettings",
+                "typescript",
+                Some("Loads the config file"),
+            ),
+            ("c.rs", "unrelated", "rust", None),
+        ]
+        .into_iter()
+        .map(|(id, name, language, doc)| {
+            let entity = CodeEntity::fixture(id, &format!("/repo/{}", id), EntityType::Function)
+                .with_property("name", name)
+                .with_property("language", language)
+                .with_property(SEARCH_TERMS_PROPERTY, &search_terms(&[name]))
+                .with_lines(1, 3);
+            match doc {
+                Some(doc) => entity.with_property(DOC_PROPERTY, doc),
+                None => entity,
+            }
+        })
+        .collect();
+        let graph = MemoryGraph::new();
+        graph.batch_ingest_entities("r1", &entities).await.unwrap();
+
+        let hits = search_code(&graph, "r1", "loadConfig", &SearchFilter::default(), 10)
+            .await
+            .unwrap();
+        let names: Vec<&str> = hits.iter().map(|h| h.result.name.as_str()).collect();
+        assert_eq!(names, ["load_config", "readSettings"]);
+        assert!(hits[0].result.score > hits[1].result.score);
+        assert_eq!(hits[1].highlights[0].field, "doc");
+
+        let typescript = SearchFilter {
+            language: Some("typescript".to_string()),
+            ..SearchFilter::default()
+        };
+        let hits = search_code(&graph, "r1", "config", &typescript, 10)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].result.name, "readSettings");
+    }
+}
@@ -1,50 +1,96 @@
+use crate::file_manager::disk_store::GRAPH_DIR;
 use crate::file_manager::file_watcher::FileWatcherSystem;
+use crate::file_manager::graph_store::{GraphBackend, GraphStore};
+use crate::models::Repository;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 pub struct AppState {
     file_watcher: Arc<Mutex<Option<FileWatcherSystem>>>,
-    // Add other app state here
+    data_dir: PathBuf,
+    config_dir: PathBuf,
+    graph_backend: std::sync::Mutex<GraphBackend>,
+    // Opened on first use and shared by every command and the watcher
+    graph_store: Mutex<Option<Arc<dyn GraphStore>>>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    /// `data_dir` is the app data directory, home of the local graph store;
+    /// `config_dir` the app config directory, home of connection profiles
+    pub fn new(data_dir: PathBuf, config_dir: PathBuf) -> Self {
+        let graph_backend = GraphBackend::from_env(&config_dir).unwrap_or_else(|e| {
+            log::error!("{}; falling back to the local graph store", e);
+            GraphBackend::Disk
+        });
         AppState {
             file_watcher: Arc::new(Mutex::new(None)),
-            // Initialize other state
+            data_dir,
+            config_dir,
+            graph_backend: std::sync::Mutex::new(graph_backend),
+            graph_store: Mutex::new(None),
         }
     }
 
-    // Method to start watching a directory
-    pub async fn start_watching(&self, repo_path: std::path::PathBuf) -> crate::error::Result<()> {
-        // Get Neo4j connection details from environment variables
-        let uri = crate::env_utils::get_required("NEO4J_URI")?;
-        let username = crate::env_utils::get_required("NEO4J_USER")?;
-        let password = crate::env_utils::get_required("NEO4J_PASSWORD")?;
+    /// Directory of the local on-disk graph store
+    pub fn graph_dir(&self) -> PathBuf {
+        self.data_dir.join(GRAPH_DIR)
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    pub fn graph_backend(&self) -> GraphBackend {
+        *self.graph_backend.lock().unwrap()
+    }
 
+    /// Drops the open graph store so the next caller connects to `backend`
+    /// with the current settings. A running watcher keeps the store it was
+    /// started with.
+    pub async fn reconnect(&self, backend: GraphBackend) {
+        let mut guard = self.graph_store.lock().await;
+        *self.graph_backend.lock().unwrap() = backend;
+        *guard = None;
+    }
+
+    /// The selected graph store, connecting on first use. A failed
+    /// connection is retried by the next caller.
+    pub async fn graph_store(&self) -> crate::error::Result<Arc<dyn GraphStore>> {
+        let mut guard = self.graph_store.lock().await;
+        if let Some(store) = &*guard {
+            return Ok(store.clone());
+        }
+        let store = self
+            .graph_backend()
+            .open(&self.graph_dir(), &self.config_dir)
+            .await?;
+        *guard = Some(store.clone());
+        Ok(store)
+    }
+
+    // Method to start watching a directory
+    pub async fn start_watching(
+        &self,
+        repo_path: std::path::PathBuf,
+        owner_id: Option<String>,
+    ) -> crate::error::Result<()> {
         // Initialize components
-        let neo_db = Arc::new(
-            crate::file_manager::neo4j::NeoDB::new(
-                &uri,
-                &username,
-                &password,
-                "repo-id".to_string(),
-                "owner-id".to_string(),
-            )
-            .await?,
-        );
+        let graph = self.graph_store().await?;
+        let repository_id = Repository::id_for(&repo_path);
 
         let parser = Arc::new(tokio::sync::Mutex::new(crate::parser::Parser::new()));
         let file_tracker = Arc::new(tokio::sync::Mutex::new(
             crate::file_manager::file_tracker::FileTracker::new(
                 repo_path.clone(),
-                "repo-id".to_string(),
-                "owner-id".to_string(),
+                repository_id.clone(),
+                owner_id,
             ),
         ));
 
         // Create the file watcher
-        let mut watcher = FileWatcherSystem::new(repo_path, neo_db, parser, file_tracker).unwrap();
+        let mut watcher =
+            FileWatcherSystem::new(repo_path, repository_id, graph, parser, file_tracker).unwrap();
 
         // Start watching
         watcher.start();
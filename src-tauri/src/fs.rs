@@ -1,3 +1,4 @@
+use crate::file_manager::reader::{FileContent, FileReader};
 use ignore::{
     gitignore::{Gitignore, GitignoreBuilder},
     Match,
@@ -24,17 +25,25 @@ pub struct FileNodeRust {
 ///
 /// # Returns
 ///
-/// * `Ok(String)` containing the file's content if the file is read successfully and is valid UTF-8.
-/// * `Err(io::Error)` if the file cannot be found, cannot be opened due to permissions,
-///   or if the content is not valid UTF-8.
+/// * `Ok(String)` containing the file's content. UTF-16 and BOM-prefixed files are decoded,
+///   invalid UTF-8 is decoded lossily.
+/// * `Err(String)` if the file cannot be read, is binary, or is larger than
+///   `PEAR_MAX_FILE_SIZE`.
 #[tauri::command]
 pub fn read_file_content(file_path: String) -> Result<String, String> {
     // <-- Return Result<String, String>
     let path = Path::new(&file_path); // Borrow the String to create a Path
 
-    // Read the file to a string and map the error
-    fs::read_to_string(path).map_err(|e| format!("Failed to read file '{}': {}", file_path, e))
-    // <-- Map io::Error to String
+    // Read through the shared reader so encodings and limits match the parser
+    match FileReader::from_env().read(path) {
+        Ok(FileContent::Text { content, .. }) => Ok(content),
+        Ok(FileContent::Binary) => Err(format!("Failed to read file '{}': binary file", file_path)),
+        Ok(FileContent::Oversized { size }) => Err(format!(
+            "Failed to read file '{}': file is too large ({} bytes)",
+            file_path, size
+        )),
+        Err(e) => Err(format!("Failed to read file '{}': {}", file_path, e)),
+    }
 }
 
 // You might want to add some tests below (optional)
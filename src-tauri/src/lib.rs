@@ -1,4 +1,5 @@
 // Modules
+mod analysis;
 mod commands;
 mod env_utils;
 mod error;
@@ -27,16 +28,42 @@ pub fn run() {
     // Load environment variables
     dotenvy::dotenv().ok();
 
-    let app_state = AppState::new();
-
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
-        .manage(app_state)
+        .setup(|app| {
+            // The local graph store lives in the app data directory,
+            // connection profiles in the app config directory
+            let data_dir = app.path().app_data_dir()?;
+            let config_dir = app.path().app_config_dir()?;
+            app.manage(AppState::new(data_dir, config_dir));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             greet,
             parse_and_ingest_codebase,
             track_repository,
+            graph_health,
+            list_connection_profiles,
+            create_connection_profile,
+            test_connection_profile,
+            switch_connection_profile,
+            find_symbols,
+            search_code,
+            get_entity,
+            get_callers,
+            get_callees,
+            get_file_outline,
+            get_neighbors,
+            get_project_graph,
+            find_tests_for_function,
+            find_untested_functions,
+            list_todos,
+            find_duplicate_code,
+            find_import_cycles,
+            export_graph_to_neo4j,
+            export_graph,
+            import_graph,
             read_directory_structure,
             read_file_content
         ])
@@ -1,3 +1,4 @@
+use crate::analysis::metrics::FileMetrics;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
@@ -12,6 +13,16 @@ pub struct Repository {
     pub name: Option<String>,
 }
 
+impl Repository {
+    /// Stable id of the repository checked out at `path`, so every ingest and
+    /// watcher of the same checkout writes to the same part of the graph
+    pub fn id_for(path: &std::path::Path) -> String {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let hash = blake3::hash(path.to_string_lossy().as_bytes()).to_hex();
+        hash[..16].to_string()
+    }
+}
+
 #[derive(Clone)]
 pub struct FileMetadata {
     pub path: PathBuf,
@@ -27,6 +38,31 @@ pub struct FileChangePayload {
     pub event_type: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CodeLanguage {
+    JavaScript,
+    Jsx,
+    TypeScript,
+    Tsx,
+    Rust,
+    Python,
+    Unknown,
+}
+
+impl std::fmt::Display for CodeLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodeLanguage::JavaScript => write!(f, "javascript"),
+            CodeLanguage::Jsx => write!(f, "jsx"),
+            CodeLanguage::TypeScript => write!(f, "typescript"),
+            CodeLanguage::Tsx => write!(f, "tsx"),
+            CodeLanguage::Rust => write!(f, "rust"),
+            CodeLanguage::Python => write!(f, "python"),
+            CodeLanguage::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EntityType {
     Project,
@@ -37,6 +73,14 @@ pub enum EntityType {
     Method,
     Function,
     Import,
+    Test,
+    Todo,
+    Crate,
+    Macro,
+    ExternalLibrary,
+    Package,
+    Repository,
+    CycleGroup,
 }
 
 impl std::fmt::Display for EntityType {
@@ -50,23 +94,141 @@ impl std::fmt::Display for EntityType {
             EntityType::Method => write!(f, "Method"),
             EntityType::Function => write!(f, "Function"),
             EntityType::Import => write!(f, "Import"),
+            EntityType::Test => write!(f, "Test"),
+            EntityType::Todo => write!(f, "Todo"),
+            EntityType::Crate => write!(f, "Crate"),
+            EntityType::Macro => write!(f, "Macro"),
+            EntityType::ExternalLibrary => write!(f, "ExternalLibrary"),
+            EntityType::Package => write!(f, "Package"),
+            EntityType::Repository => write!(f, "Repository"),
+            EntityType::CycleGroup => write!(f, "CycleGroup"),
         }
     }
 }
 
+impl EntityType {
+    /// Inverse of `Display`, i.e. of the node label
+    pub fn from_label(label: &str) -> Option<Self> {
+        [
+            EntityType::Project,
+            EntityType::Directory,
+            EntityType::File,
+            EntityType::Class,
+            EntityType::Interface,
+            EntityType::Method,
+            EntityType::Function,
+            EntityType::Import,
+            EntityType::Test,
+            EntityType::Todo,
+            EntityType::Crate,
+            EntityType::Macro,
+            EntityType::ExternalLibrary,
+            EntityType::Package,
+            EntityType::Repository,
+            EntityType::CycleGroup,
+        ]
+        .into_iter()
+        .find(|entity_type| entity_type.to_string() == label)
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub enum LinkType {
     Has,
     Owns,
     Uses,
     Import,
+    Tests,
+    Calls,
+    In,
+    Module,
+    InvokesMacro,
+    BelongsTo,
+    InCycle,
+    DuplicateOf,
+}
+
+impl LinkType {
+    /// Every relationship type the app writes. Relationship types can't be
+    /// query parameters, so only these `rel_type` strings are ever spliced
+    /// into Cypher.
+    pub const ALL: [LinkType; 12] = [
+        LinkType::Has,
+        LinkType::Owns,
+        LinkType::Uses,
+        LinkType::Import,
+        LinkType::Tests,
+        LinkType::Calls,
+        LinkType::In,
+        LinkType::Module,
+        LinkType::InvokesMacro,
+        LinkType::BelongsTo,
+        LinkType::InCycle,
+        LinkType::DuplicateOf,
+    ];
+
+    /// Types of the edges parsing one file draws between the file and its
+    /// own entities; re-ingesting the file clears and rebuilds them
+    pub const FILE_LOCAL: [LinkType; 5] = [
+        LinkType::Has,
+        LinkType::Owns,
+        LinkType::Uses,
+        LinkType::Import,
+        LinkType::In,
+    ];
+
+    /// Types of the edges resolved across files once a whole directory is
+    /// parsed, replaced on every full ingest. `IMPORTS` edges count too when
+    /// they end at a file or library rather than an `Import` entity.
+    pub const RESOLVED: [LinkType; 5] = [
+        LinkType::Tests,
+        LinkType::Calls,
+        LinkType::InvokesMacro,
+        LinkType::Module,
+        LinkType::BelongsTo,
+    ];
+
+    /// Relationship type stored in the graph
+    pub fn rel_type(&self) -> &'static str {
+        match self {
+            LinkType::Has => "HAS",
+            LinkType::Owns => "OWNS",
+            LinkType::Uses => "USES",
+            LinkType::Import => "IMPORTS",
+            LinkType::Tests => "TESTS",
+            LinkType::Calls => "CALLS",
+            LinkType::In => "IN",
+            LinkType::Module => "HAS_MODULE",
+            LinkType::InvokesMacro => "INVOKES_MACRO",
+            LinkType::BelongsTo => "BELONGS_TO",
+            LinkType::InCycle => "IN_CYCLE",
+            LinkType::DuplicateOf => "DUPLICATE_OF",
+        }
+    }
+
+    pub fn from_rel_type(rel_type: &str) -> Option<Self> {
+        LinkType::ALL
+            .into_iter()
+            .find(|link_type| link_type.rel_type() == rel_type)
+    }
 }
 
+/// 1-based line of the import, call or invocation an edge comes from
+pub const LINK_LINE_PROPERTY: &str = "line";
+/// Name an import is bound to, e.g. `np` in `import numpy as np`
+pub const LINK_ALIAS_PROPERTY: &str = "alias";
+/// How many times the source calls or invokes the target
+pub const LINK_COUNT_PROPERTY: &str = "count";
+/// How sure name-based resolution is of the target, from 0 to 1
+pub const LINK_CONFIDENCE_PROPERTY: &str = "confidence";
+
 #[derive(Clone, Debug)]
 pub struct LinkEntity {
     pub from_name: String,
     pub to_name: String,
     pub link_type: LinkType,
+    /// Stored on the edge, e.g. `LINK_LINE_PROPERTY`
+    pub properties: HashMap<String, String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -77,11 +239,146 @@ pub struct CodeEntity {
     pub entity_type: EntityType,
     pub start_line: Option<usize>,
     pub end_line: Option<usize>,
+    /// Full declaration span
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+    /// Span of the declared identifier, for go-to-definition and highlighting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_span: Option<SourceSpan>,
     pub properties: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub children: Option<Vec<CodeEntity>>,
 }
 
+#[cfg(test)]
+impl CodeEntity {
+    /// Bare entity for tests; everything but the id, path and type is empty.
+    pub(crate) fn fixture(id: &str, path: &str, entity_type: EntityType) -> Self {
+        CodeEntity {
+            id: id.to_string(),
+            path: path.to_string(),
+            entity_type,
+            start_line: None,
+            end_line: None,
+            span: None,
+            name_span: None,
+            properties: HashMap::new(),
+            children: None,
+        }
+    }
+
+    pub(crate) fn with_property(mut self, key: &str, value: &str) -> Self {
+        self.properties.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Names a top-level item, whose qualified name is its name
+    pub(crate) fn with_name(self, name: &str) -> Self {
+        self.with_property("name", name)
+            .with_property("qualified_name", name)
+    }
+
+    pub(crate) fn with_lines(mut self, start_line: usize, end_line: usize) -> Self {
+        self.start_line = Some(start_line);
+        self.end_line = Some(end_line);
+        self
+    }
+
+    pub(crate) fn with_start_byte(mut self, start_byte: usize) -> Self {
+        self.span = Some(SourceSpan {
+            start_byte,
+            ..SourceSpan::default()
+        });
+        self
+    }
+}
+
+/// Exact location of a syntax node. Lines are 1-based like `start_line`;
+/// columns and byte offsets are 0-based and count bytes of the decoded UTF-8
+/// source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceSpan {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub start_byte: usize,
+    pub end_byte: usize,
+}
+
+impl SourceSpan {
+    pub fn from_node(node: tree_sitter::Node) -> Self {
+        let start = node.start_position();
+        let end = node.end_position();
+        SourceSpan {
+            start_line: start.row + 1,
+            start_column: start.column,
+            end_line: end.row + 1,
+            end_column: end.column,
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+        }
+    }
+
+    /// Flat node properties, e.g. `start_column` or `name_start_byte` with a
+    /// `name_` prefix
+    pub fn properties(&self, prefix: &str) -> Vec<(String, usize)> {
+        [
+            ("start_line", self.start_line),
+            ("start_column", self.start_column),
+            ("end_line", self.end_line),
+            ("end_column", self.end_column),
+            ("start_byte", self.start_byte),
+            ("end_byte", self.end_byte),
+        ]
+        .into_iter()
+        .map(|(key, value)| (format!("{}{}", prefix, key), value))
+        .collect()
+    }
+
+    /// Inverse of `properties`: takes the span's keys out of a flat property
+    /// map, or returns None if any of them is missing
+    pub fn take_properties(properties: &mut HashMap<String, String>, prefix: &str) -> Option<Self> {
+        let keys = SourceSpan::default().properties(prefix);
+        let values: Vec<usize> = keys
+            .iter()
+            .map(|(key, _)| properties.get(key)?.parse().ok())
+            .collect::<Option<_>>()?;
+        for (key, _) in &keys {
+            properties.remove(key);
+        }
+        Some(SourceSpan {
+            start_line: values[0],
+            start_column: values[1],
+            end_line: values[2],
+            end_column: values[3],
+            start_byte: values[4],
+            end_byte: values[5],
+        })
+    }
+}
+
+/// Lightweight pointer to an entity returned by graph queries
+#[derive(Clone, Debug, Serialize)]
+pub struct EntityReference {
+    pub name: String,
+    pub path: String,
+    pub start_line: Option<usize>,
+}
+
+/// A TODO/FIXME/HACK comment as returned to the frontend
+#[derive(Clone, Debug, Serialize)]
+pub struct TodoItem {
+    pub marker: String,
+    pub text: String,
+    pub owner: Option<String>,
+    pub issue: Option<String>,
+    pub path: String,
+    pub line: Option<usize>,
+    /// Name of the function, class or test the comment sits in
+    pub enclosing: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct FileStructure {
     #[serde(rename = "type")]
@@ -89,4 +386,7 @@ pub struct FileStructure {
     pub file_path: String,
     pub items: Vec<CodeEntity>,
     pub file_hash: String,
+    pub metrics: FileMetrics,
+    /// File-level facts gathered while parsing (module declarations, ...)
+    pub properties: HashMap<String, String>,
 }
@@ -1,14 +1,33 @@
+use crate::analysis::call_graph;
+use crate::analysis::classification::{FileClass, FileClassifier};
+use crate::analysis::clones::{self, CloneCandidate, CloneConfig, CloneGroup};
+use crate::analysis::hashing::{self, HashMode, CONTENT_HASH_PROPERTY, FILE_HASH_PROPERTY};
+use crate::analysis::import_cycles::{self, TYPE_ONLY_PROPERTY};
+use crate::analysis::metrics::{self, FileMetrics};
+use crate::analysis::python_modules::{self, PythonLayout};
+use crate::analysis::rust_semantics;
+use crate::analysis::signatures;
+use crate::analysis::test_discovery;
+use crate::analysis::todos::{self, TodoConfig};
+use crate::analysis::workspaces::Workspace;
 use crate::error::{AppError, Result};
-use crate::file_manager::neo4j::NeoDB;
-use crate::models::{CodeEntity, CodeLanguage, EntityType, FileStructure, LinkEntity, LinkType};
+use crate::file_manager::graph_store::{repository_node, GraphStore};
+use crate::file_manager::reader::{FileContent, FileReader};
+use crate::file_manager::search::{self, SEARCH_TERMS_PROPERTY};
+use crate::models::{
+    CodeEntity, CodeLanguage, EntityType, FileStructure, LinkEntity, LinkType, Repository,
+    SourceSpan, LINK_LINE_PROPERTY,
+};
 use crate::ts_queries;
 use queues::*;
-use std::fs::{read_to_string, File};
-use std::io::{self, BufRead, BufReader};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use tauri::AppHandle;
-use tree_sitter::{Language, Parser as TSParser};
-use ts_queries::{JS_ENTITY_AND_DEP_QUERY, TS_ENTITY_AND_DEP_QUERY};
+use tauri::{AppHandle, Emitter};
+use tree_sitter::{Language, Node, Parser as TSParser};
+use ts_queries::{
+    JS_ENTITY_AND_DEP_QUERY, PY_ENTITY_AND_DEP_QUERY, RUST_ENTITY_AND_DEP_QUERY,
+    TS_ENTITY_AND_DEP_QUERY,
+};
 
 // Define supported languages
 
@@ -19,6 +38,8 @@ impl CodeLanguage {
             "jsx" => CodeLanguage::Jsx,
             "ts" => CodeLanguage::TypeScript,
             "tsx" => CodeLanguage::Tsx,
+            "rs" => CodeLanguage::Rust,
+            "py" => CodeLanguage::Python,
             _ => CodeLanguage::Unknown,
         }
     }
@@ -29,6 +50,8 @@ impl CodeLanguage {
             CodeLanguage::TypeScript => Ok(tree_sitter_typescript::language_typescript()),
             CodeLanguage::Tsx => Ok(tree_sitter_typescript::language_tsx()),
             CodeLanguage::Jsx => Ok(tree_sitter_javascript::language()),
+            CodeLanguage::Rust => Ok(tree_sitter_rust::language()),
+            CodeLanguage::Python => Ok(tree_sitter_python::language()),
             CodeLanguage::Unknown => Err(AppError::UnsupportedLanguage(
                 "Unknown language".to_string(),
             )),
@@ -36,30 +59,39 @@ impl CodeLanguage {
     }
 }
 
+/// Everything extracted from a single source file
+pub struct ParsedFile {
+    pub entities: Vec<CodeEntity>,
+    pub metrics: FileMetrics,
+    pub clone_candidates: Vec<CloneCandidate>,
+    /// Extra properties for the File node itself
+    pub file_properties: HashMap<String, String>,
+}
+
 // Main Parser struct
 pub struct Parser {
     ts_parser: TSParser,
+    todo_config: TodoConfig,
+    hash_mode: HashMode,
+    clone_config: CloneConfig,
+    reader: FileReader,
 }
 
 impl Parser {
     pub fn new() -> Self {
         Self {
             ts_parser: TSParser::new(),
+            todo_config: TodoConfig::from_env(),
+            hash_mode: HashMode::from_env(),
+            clone_config: CloneConfig::from_env(),
+            reader: FileReader::from_env(),
         }
     }
 
-    fn count_lines_in_file<P: AsRef<Path>>(&mut self, file_path: P) -> io::Result<usize> {
-        let file = File::open(file_path)?;
-        let reader = BufReader::new(file);
-        let mut line_count = 0;
-        for _line in reader.lines() {
-            line_count += 1;
-        }
-        Ok(line_count)
-    }
-
     pub async fn parse_and_ingest_directory(
         &mut self,
+        app_handle: &AppHandle,
+        graph: &dyn GraphStore,
         directory: &str,
     ) -> Result<(Vec<CodeEntity>, Vec<LinkEntity>)> {
         let dir_path = Path::new(directory);
@@ -75,21 +107,18 @@ impl Parser {
         // };
         let mut nodes: Vec<CodeEntity> = Vec::new();
         let mut links: Vec<LinkEntity> = Vec::new();
+        // Links written together with their file
+        let mut ingested_links: Vec<LinkEntity> = Vec::new();
+        let mut clone_candidates: Vec<CloneCandidate> = Vec::new();
+        // Every path the walk reached, to prune the ones that disappeared
+        let mut walked: HashSet<String> = HashSet::new();
+        let python_layout = PythonLayout::discover(dir_path);
+        let workspace = Workspace::discover(dir_path);
+        let repository_id = Repository::id_for(dir_path);
         let mut q: std::collections::VecDeque<PathBuf> = std::collections::VecDeque::new();
         q.push_back(dir_path.to_path_buf());
 
-        let ignore_dirs = [
-            "node_modules",
-            ".git",
-            "target",
-            "dist",
-            "build",
-            ".idea",
-            ".vscode",
-            "__pycache__",
-            ".next",
-            ".nuxt",
-        ];
+        let classifier = FileClassifier::load(dir_path);
 
         let mut gitignore = ignore::gitignore::GitignoreBuilder::new(directory);
         let gitignore_path = Path::new(directory).join(".gitignore");
@@ -106,12 +135,7 @@ impl Parser {
                 Err(_) => &curr_node,
             };
 
-            let path_str = rel_path.to_string_lossy();
-
-            let should_ignore = ignore_dirs.iter().any(|&dir| {
-                path_str.contains(&format!("/{}/", dir))
-                    || path_str.starts_with(&format!("{}/", dir))
-            });
+            let should_ignore = classifier.is_ignored(&curr_node);
 
             let gitignore_matched = gitignore.matched(rel_path, false).is_ignore();
 
@@ -120,11 +144,14 @@ impl Parser {
             }
 
             if curr_node.is_dir() {
+                walked.insert(curr_node.to_string_lossy().to_string());
                 let dir_node = CodeEntity {
                     id: curr_node.to_string_lossy().to_string(),
                     entity_type: EntityType::Directory,
                     start_line: Some(0),
                     end_line: Some(0),
+                    span: None,
+                    name_span: None,
                     path: curr_node.to_string_lossy().to_string(),
                     properties: std::collections::HashMap::new(),
                     children: Some(Vec::new()),
@@ -145,10 +172,7 @@ impl Parser {
                             };
 
                             // Check if this path should be ignored
-                            let should_ignore = ignore_dirs.iter().any(|&dir| {
-                                path_str.contains(&format!("/{}/", dir))
-                                    || path_str.starts_with(&format!("{}/", dir))
-                            });
+                            let should_ignore = classifier.is_ignored(&entry_path);
 
                             // Also check against .gitignore rules
                             let gitignore_matched = gitignore.matched(&rel_path, false).is_ignore();
@@ -165,12 +189,14 @@ impl Parser {
                                     from_name: curr_node.to_string_lossy().to_string(),
                                     to_name: path_str.clone().to_string(),
                                     link_type: LinkType::Owns,
+                                    properties: HashMap::new(),
                                 });
                             } else {
                                 links.push(LinkEntity {
                                     from_name: curr_node.to_string_lossy().to_string(),
                                     to_name: path_str.clone().to_string(),
                                     link_type: LinkType::Has,
+                                    properties: HashMap::new(),
                                 })
                             }
                         }
@@ -180,6 +206,19 @@ impl Parser {
                     }
                 }
             } else if curr_node.is_file() {
+                if curr_node
+                    .file_name()
+                    .map(|n| n == "Cargo.toml")
+                    .unwrap_or(false)
+                {
+                    match rust_semantics::crates_from_manifest(&curr_node) {
+                        Ok(crates) => nodes.extend(crates),
+                        Err(e) => {
+                            log::warn!("failed to read manifest {}: {:?}", curr_node.display(), e)
+                        }
+                    }
+                    continue;
+                }
                 let extension = match curr_node.extension() {
                     Some(ext) => {
                         let ext_str = ext.to_string_lossy().to_string();
@@ -197,6 +236,39 @@ impl Parser {
                     continue;
                 }
 
+                // Generated, vendored and minified files are skipped or kept
+                // as flagged File nodes without their entities
+                let class = classifier.classify(&curr_node);
+                if classifier.skips(class) {
+                    log::info!("Skipping {} file: {}", class.as_str(), curr_node.display());
+                    continue;
+                }
+                // Files skipped above are left out, so pruning removes nodes
+                // stored for them before they were skipped
+                walked.insert(curr_node.to_string_lossy().to_string());
+                if class != FileClass::Source {
+                    let mut properties = HashMap::new();
+                    properties.insert("language".to_string(), language.to_string());
+                    class.write_properties(&mut properties);
+                    let file_node = CodeEntity {
+                        id: curr_node.to_string_lossy().to_string(),
+                        entity_type: EntityType::File,
+                        path: curr_node.to_string_lossy().to_string(),
+                        start_line: Some(0),
+                        end_line: Some(0),
+                        span: None,
+                        name_span: None,
+                        properties,
+                        children: Some(Vec::new()),
+                    };
+                    if let Err(e) = Self::ingest_file(graph, &repository_id, &file_node, &[]).await
+                    {
+                        let _ = app_handle.emit("parse_error", e.to_string());
+                    }
+                    nodes.push(file_node);
+                    continue;
+                }
+
                 // for child in &file_node.children.clone().unwrap() {
                 //     match child.entity_type {
                 //         EntityType::Import => links.push(LinkEntity {
@@ -211,44 +283,45 @@ impl Parser {
                 //     }
                 // }
                 match self.parse_file(&curr_node, &language).await {
-                    Ok(file_breakdown) => {
+                    Ok(parsed) => {
+                        let ParsedFile {
+                            entities: file_breakdown,
+                            metrics: file_metrics,
+                            clone_candidates: file_candidates,
+                            file_properties: mut properties,
+                        } = parsed;
+                        if matches!(language, CodeLanguage::Python) {
+                            if let Some(module) = python_layout.module_name(&curr_node) {
+                                properties.insert(
+                                    python_modules::MODULE_NAME_PROPERTY.to_string(),
+                                    module,
+                                );
+                            }
+                        }
+                        clone_candidates.extend(file_candidates);
                         let file_node = CodeEntity {
                             id: curr_node.to_string_lossy().to_string(),
                             entity_type: EntityType::File,
                             path: curr_node.to_string_lossy().to_string(),
                             start_line: Some(0),
-                            end_line: Some(self.count_lines_in_file(&curr_node).unwrap_or(0)),
-                            properties: std::collections::HashMap::new(),
+                            end_line: Some(file_metrics.lines.total),
+                            span: None,
+                            name_span: None,
+                            properties,
                             children: Some(file_breakdown),
                         };
-                        if let Some(children) = &file_node.children {
-                            for child in children {
-                                match child.entity_type {
-                                    EntityType::Import => links.push(LinkEntity {
-                                        from_name: file_node.id.clone(),
-                                        to_name: child.id.clone(),
-                                        link_type: LinkType::Import,
-                                    }),
-                                    EntityType::Method => links.push(LinkEntity {
-                                        from_name: file_node.id.clone(),
-                                        to_name: child.id.clone(),
-                                        link_type: LinkType::Uses,
-                                    }),
-                                    EntityType::Function => links.push(LinkEntity {
-                                        from_name: file_node.id.clone(),
-                                        to_name: child.id.clone(),
-                                        link_type: LinkType::Uses,
-                                    }),
-                                    EntityType::Class => links.push(LinkEntity {
-                                        from_name: file_node.id.clone(),
-                                        to_name: child.id.clone(),
-                                        link_type: LinkType::Owns,
-                                    }),
-                                    _ => {}
-                                }
-                                nodes.push(child.to_owned());
-                            }
+                        let children = file_node.children.as_deref().unwrap_or_default();
+                        let file_links = file_links(&file_node.id, children);
+                        nodes.extend(children.iter().cloned());
+
+                        let _ = app_handle
+                            .emit("parse_progress", format!("Ingesting {}", file_node.path));
+                        if let Err(e) =
+                            Self::ingest_file(graph, &repository_id, &file_node, &file_links).await
+                        {
+                            let _ = app_handle.emit("parse_error", e.to_string());
                         }
+                        ingested_links.extend(file_links);
                         nodes.push(file_node);
                     }
                     Err(e) => {
@@ -257,16 +330,139 @@ impl Parser {
                 }
             }
         }
+        // Files and directories deleted since the last run, with their entities
+        if let Err(e) = graph.prune_missing(&repository_id, &walked).await {
+            let _ = app_handle.emit(
+                "parse_error",
+                format!("Failed to remove deleted files from the graph: {}", e),
+            );
+        }
+
+        // Tests and calls can reach code in any file, so resolve them once everything is parsed
+        links.extend(test_discovery::link_tests(&nodes));
+        links.extend(call_graph::link_calls(&nodes));
+        links.extend(rust_semantics::link_modules(&nodes));
+        let (external_macros, macro_links) = rust_semantics::link_macros(&nodes);
+        links.extend(macro_links);
+        nodes.extend(external_macros);
+        let (external_libraries, import_links) = python_modules::resolve_imports(&nodes);
+        links.extend(import_links);
+        nodes.extend(external_libraries);
+        let (external_libraries, import_links) = workspace.resolve_imports(&nodes);
+        links.extend(import_links);
+        nodes.extend(external_libraries);
+        links.extend(workspace.link_packages(&nodes));
+        nodes.extend(workspace.package_nodes());
+        let (repository, _, repository_link) = repository_node(directory, &repository_id, None);
+        nodes.push(repository);
+        links.push(repository_link);
+
+        // Nodes that don't belong to a single parsed file are written here
+        let structural: Vec<CodeEntity> = nodes
+            .iter()
+            .filter(|n| {
+                matches!(
+                    n.entity_type,
+                    EntityType::Directory
+                        | EntityType::Crate
+                        | EntityType::ExternalLibrary
+                        | EntityType::Package
+                        | EntityType::Repository
+                ) || (matches!(n.entity_type, EntityType::Macro) && n.path.is_empty())
+            })
+            .map(|n| CodeEntity {
+                children: None,
+                ..n.clone()
+            })
+            .collect();
+        graph
+            .batch_ingest_entities(&repository_id, &structural)
+            .await?;
+        // Resolved edges that no longer apply, e.g. a removed import, go too
+        graph.replace_resolved_links(&repository_id, &links).await?;
+        links.extend(ingested_links);
+
+        let clone_report = clones::detect(&clone_candidates, &self.clone_config);
+        if let Err(e) = graph
+            .replace_duplicate_links(&repository_id, &clone_report.pairs)
+            .await
+        {
+            let _ = app_handle.emit(
+                "parse_error",
+                format!("Failed to store duplicate code links: {}", e),
+            );
+        }
+
         println!("Finished processing");
         Ok((nodes, links))
     }
 
-    async fn parse_file(
+    /// Writes a parsed file, its entities and the links between them in one
+    /// transaction. Only entities whose content hash changed since the last
+    /// ingest are written, so unchanged ones keep anything users stored on them.
+    async fn ingest_file(
+        graph: &dyn GraphStore,
+        repository_id: &str,
+        file_node: &CodeEntity,
+        links: &[LinkEntity],
+    ) -> Result<()> {
+        let file_entity = CodeEntity {
+            children: None,
+            ..file_node.clone()
+        };
+        let children = file_node.children.as_deref().unwrap_or_default();
+        graph
+            .ingest_file(repository_id, &file_entity, children, links)
+            .await
+    }
+
+    /// Parses every supported file under `directory` and groups duplicated
+    /// functions and blocks, without touching the graph.
+    pub async fn find_clones(
         &mut self,
-        path: &Path,
-        language: &CodeLanguage,
-    ) -> Result<Vec<CodeEntity>> {
-        let content = std::fs::read_to_string(path).map_err(|e| AppError::Io(e))?;
+        directory: &str,
+        config: &CloneConfig,
+    ) -> Result<Vec<CloneGroup>> {
+        let mut candidates = Vec::new();
+        for entry in ignore::WalkBuilder::new(directory).build().flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let extension = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let language = CodeLanguage::from_extension(&extension);
+            if matches!(language, CodeLanguage::Unknown) {
+                continue;
+            }
+            match self.parse_file(path, &language).await {
+                Ok(parsed) => candidates.extend(parsed.clone_candidates),
+                Err(e) => log::warn!("failed to parse file {}: {:?}", path.display(), e),
+            }
+        }
+        Ok(clones::detect(&candidates, config).groups)
+    }
+
+    async fn parse_file(&mut self, path: &Path, language: &CodeLanguage) -> Result<ParsedFile> {
+        // Binary and oversized files keep a flagged File node without entities
+        let file = self.reader.read(path)?;
+        let mut file_properties = HashMap::new();
+        file_properties.insert(FILE_HASH_PROPERTY.to_string(), self.reader.hash(path)?);
+        file.write_properties(&mut file_properties);
+        let content = match file {
+            FileContent::Text { content, .. } => content,
+            _ => {
+                log::info!("Not parsing binary or oversized file: {}", path.display());
+                return Ok(ParsedFile {
+                    entities: Vec::new(),
+                    metrics: FileMetrics::default(),
+                    clone_candidates: Vec::new(),
+                    file_properties,
+                });
+            }
+        };
         let mut children: Vec<CodeEntity> = Vec::new();
 
         println!("{}", path.to_string_lossy().to_string());
@@ -282,12 +478,15 @@ impl Parser {
             .ok_or_else(|| AppError::Parse("Failed to parse file".to_string()))?;
         let root = tree.root_node();
         let source = content.as_bytes();
+        let path_str = path.to_string_lossy().to_string();
 
         let query_str = match language {
             CodeLanguage::TypeScript => TS_ENTITY_AND_DEP_QUERY,
-            CodeLanguage::JavaScript => JS_ENTITY_AND_DEP_QUERY, // (define this separately)
+            CodeLanguage::JavaScript => JS_ENTITY_AND_DEP_QUERY,
             CodeLanguage::Tsx => TS_ENTITY_AND_DEP_QUERY,
             CodeLanguage::Jsx => JS_ENTITY_AND_DEP_QUERY,
+            CodeLanguage::Rust => RUST_ENTITY_AND_DEP_QUERY,
+            CodeLanguage::Python => PY_ENTITY_AND_DEP_QUERY,
             _ => {
                 return Err(AppError::UnsupportedLanguage(
                     "No query defined for this language".to_string(),
@@ -299,41 +498,152 @@ impl Parser {
             .map_err(|e| AppError::TreeSitter(e.to_string()))?;
 
         let mut cursor = tree_sitter::QueryCursor::new();
+        let mut file_metrics = FileMetrics::new(root);
+        let mut clone_candidates = Vec::new();
 
-        for (m, capture_idx) in cursor.captures(&query, root, source) {
-            let node = &m.captures[capture_idx].node;
-            let cap_name = &query.capture_names()[capture_idx];
-            let start_line = node.start_position().row + 1;
-            let end_line = node.end_position().row + 1;
-
-            let name = match cap_name.as_str() {
-                "import" => node.utf8_text(source).unwrap_or_default().to_string(),
-                _ => {
-                    // for class.name, function.name, method.name
-                    node.utf8_text(source).unwrap_or_default().to_string()
+        for m in cursor.matches(&query, root, source) {
+            // Each pattern captures the whole declaration as `<kind>.node`
+            // and its identifier as `<kind>.name`.
+            let mut kind = "";
+            let mut decl_node = None;
+            let mut name_node = None;
+            for capture in m.captures {
+                let cap_name = &query.capture_names()[capture.index as usize];
+                match cap_name.split_once('.') {
+                    Some((k, "node")) => {
+                        kind = k;
+                        decl_node = Some(capture.node);
+                    }
+                    Some((_, "name")) => name_node = Some(capture.node),
+                    _ => {}
                 }
+            }
+            let (Some(node), Some(name_node)) = (decl_node, name_node) else {
+                continue;
             };
 
-            let entity_type = match cap_name.as_str() {
+            let name = name_node
+                .utf8_text(source)
+                .unwrap_or_default()
+                .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+                .to_string();
+
+            let entity_type = match kind {
                 "import" => EntityType::Import,
                 "class" => EntityType::Class,
-                "function" => EntityType::Function,
+                "interface" => EntityType::Interface,
                 "method" => EntityType::Method,
+                "macro" => EntityType::Macro,
+                _ if is_method(node) => EntityType::Method,
                 _ => EntityType::Function,
             };
 
+            let mut properties = std::collections::HashMap::new();
+            properties.insert("name".to_string(), name.clone());
+            properties.insert("language".to_string(), language.to_string());
+            properties.insert(
+                CONTENT_HASH_PROPERTY.to_string(),
+                hashing::entity_hash(node, source, self.hash_mode),
+            );
+
+            let id = if matches!(entity_type, EntityType::Import) {
+                if matches!(language, CodeLanguage::Python) {
+                    python_modules::write_import_properties(node, &name, source, &mut properties);
+                }
+                if import_cycles::is_type_only(node, source) {
+                    properties.insert(TYPE_ONLY_PROPERTY.to_string(), "true".to_string());
+                }
+                format!("{}::import:{}", path_str, name)
+            } else {
+                let qualified = qualified_name(node, source, &name);
+                properties.insert(
+                    "exported".to_string(),
+                    is_exported(node, &name, language).to_string(),
+                );
+                properties.insert("qualified_name".to_string(), qualified.clone());
+                signatures::write_properties(node, source, &mut properties);
+                let hash = hashing::with_doc(
+                    &properties[CONTENT_HASH_PROPERTY],
+                    properties.get(signatures::DOC_PROPERTY).map(String::as_str),
+                );
+                properties.insert(CONTENT_HASH_PROPERTY.to_string(), hash);
+                if matches!(language, CodeLanguage::Rust) {
+                    rust_semantics::write_cfg_properties(
+                        &rust_semantics::cfg_conditions(node, source),
+                        &mut properties,
+                    );
+                }
+
+                let entity_metrics = metrics::compute(node, source);
+                entity_metrics.write_properties(&mut properties);
+                let id = format!("{}::{}", path_str, qualified);
+                if matches!(entity_type, EntityType::Function | EntityType::Method) {
+                    call_graph::write_call_properties(node, source, &mut properties);
+                    file_metrics.add_function(&entity_metrics);
+                    clone_candidates.push(clones::function_candidate(
+                        node, source, &path_str, &qualified, &id,
+                    ));
+                }
+                id
+            };
+
+            let mut names = vec![name.as_str()];
+            names.extend(properties.get("qualified_name").map(String::as_str));
+            let terms = search::search_terms(&names);
+            properties.insert(SEARCH_TERMS_PROPERTY.to_string(), terms);
+
             children.push(CodeEntity {
-                id: name,
-                path: path.to_string_lossy().to_string(),
+                id,
+                path: path_str.clone(),
                 entity_type,
-                start_line: Some(start_line),
-                end_line: Some(end_line),
-                properties: std::collections::HashMap::new(),
+                start_line: Some(node.start_position().row + 1),
+                end_line: Some(node.end_position().row + 1),
+                span: Some(SourceSpan::from_node(node)),
+                name_span: Some(SourceSpan::from_node(name_node)),
+                properties,
                 children: None,
             });
         }
 
-        Ok(children)
+        let tests =
+            test_discovery::discover_tests(root, source, &path_str, language, self.hash_mode);
+        test_discovery::unexport_test_functions(&mut children, &tests);
+        children.extend(tests);
+
+        // TODOs attach to the innermost entity, so scan once everything else is known
+        let todo_items = todos::scan_todos(
+            root,
+            source,
+            &path_str,
+            language,
+            &self.todo_config,
+            &children,
+        );
+        children.extend(todo_items);
+
+        clone_candidates.extend(clones::block_candidates(root, source, &path_str));
+
+        file_metrics.write_properties(&mut file_properties);
+        if matches!(language, CodeLanguage::Rust) {
+            let file_macros = rust_semantics::record_macro_invocations(root, source, &mut children);
+            if !file_macros.is_empty() {
+                file_properties.insert(
+                    rust_semantics::MACROS_PROPERTY.to_string(),
+                    file_macros.join(","),
+                );
+            }
+            let mods = rust_semantics::module_declarations(root, source);
+            if !mods.is_empty() {
+                file_properties.insert(rust_semantics::MODS_PROPERTY.to_string(), mods.join(","));
+            }
+        }
+
+        Ok(ParsedFile {
+            entities: children,
+            metrics: file_metrics,
+            clone_candidates,
+            file_properties,
+        })
     }
 
     // Parse a single file with extension
@@ -342,41 +652,9 @@ impl Parser {
         path: &Path,
         extension: &str,
     ) -> Result<FileStructure> {
-        // Check if file is TypeScript/TSX
-        let is_typescript = extension == "ts" || extension == "tsx";
-
-        // For now, only process TypeScript files
-        if !is_typescript {
-            return Err(AppError::UnsupportedLanguage(format!(
-                "Only processing TypeScript files for now. Skipping file with extension: {}",
-                extension
-            )));
-        }
-
         // Determine language from extension
         let language = CodeLanguage::from_extension(extension);
 
-        match extension {
-            "ts" => self
-                .ts_parser
-                .set_language(tree_sitter_typescript::language_typescript())
-                .map_err(|e| AppError::TreeSitter(e.to_string()))?,
-            "tsx" => self
-                .ts_parser
-                .set_language(tree_sitter_typescript::language_tsx())
-                .map_err(|e| AppError::TreeSitter(e.to_string()))?,
-            "js" | "jsx" => self
-                .ts_parser
-                .set_language(tree_sitter_javascript::language())
-                .map_err(|e| AppError::TreeSitter(e.to_string()))?,
-            _ => {
-                return Err(AppError::UnsupportedLanguage(format!(
-                    "Unsupported file extension: {}",
-                    extension
-                )))
-            }
-        }
-
         // Skip unsupported languages
         if matches!(language, CodeLanguage::Unknown) {
             return Err(AppError::UnsupportedLanguage(format!(
@@ -385,21 +663,394 @@ impl Parser {
             )));
         }
 
-        let content = read_to_string(path).map_err(AppError::Io)?;
-        let file_hash = { format!("{:x}", md5::compute(content.as_bytes())) };
-
         // Use the existing parse_file method
-        let parse_result = self.parse_file(path, &language).await.map_err(|e| e)?;
-        let mut items: Vec<CodeEntity> = Vec::new();
-        for ent in parse_result.into_iter() {
-            items.push(ent);
-        }
+        let parsed = self.parse_file(path, &language).await?;
+        let file_hash = parsed.file_properties[FILE_HASH_PROPERTY].clone();
         let fs = FileStructure {
             structure_type: "file_structure".into(),
             file_path: path.to_string_lossy().into_owned(),
-            items,
+            items: parsed.entities,
             file_hash,
+            metrics: parsed.metrics,
+            properties: parsed.file_properties,
         };
         Ok(fs)
     }
 }
+
+/// Edges from a parsed file to the entities declared in it. The directory
+/// ingest and the file watcher both write these, so re-ingesting a file
+/// either way leaves the same edges behind.
+pub fn file_links(file_id: &str, entities: &[CodeEntity]) -> Vec<LinkEntity> {
+    let link = |from: &str, to: &str, link_type: LinkType| LinkEntity {
+        from_name: from.to_string(),
+        to_name: to.to_string(),
+        link_type,
+        properties: HashMap::new(),
+    };
+    entities
+        .iter()
+        .filter_map(|child| match child.entity_type {
+            EntityType::Import => Some(LinkEntity {
+                properties: child
+                    .start_line
+                    .map(|line| (LINK_LINE_PROPERTY.to_string(), line.to_string()))
+                    .into_iter()
+                    .collect(),
+                ..link(file_id, &child.id, LinkType::Import)
+            }),
+            EntityType::Method | EntityType::Function => {
+                Some(link(file_id, &child.id, LinkType::Uses))
+            }
+            EntityType::Macro | EntityType::Test => Some(link(file_id, &child.id, LinkType::Has)),
+            EntityType::Class => Some(link(file_id, &child.id, LinkType::Owns)),
+            EntityType::Todo => Some(link(
+                &child.id,
+                child
+                    .properties
+                    .get(todos::IN_PROPERTY)
+                    .map(String::as_str)
+                    .unwrap_or(file_id),
+                LinkType::In,
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns true when a function-like node is declared directly inside a class,
+/// impl or trait body rather than at module level.
+fn is_method(node: Node) -> bool {
+    let mut current = node.parent();
+    while let Some(parent) = current {
+        match parent.kind() {
+            "class_body" | "class_definition" | "impl_item" | "trait_item" => return true,
+            "function_item"
+            | "function_definition"
+            | "function_declaration"
+            | "arrow_function"
+            | "method_definition"
+            | "source_file"
+            | "program"
+            | "module" => return false,
+            _ => current = parent.parent(),
+        }
+    }
+    false
+}
+
+/// Builds a dotted name from the enclosing classes, impls, traits and modules,
+/// e.g. `NeoDB.ingest_entity` or `tests.test_read_existing_file`.
+fn qualified_name(node: Node, source: &[u8], name: &str) -> String {
+    let mut parts = vec![name.to_string()];
+    let mut current = node.parent();
+    while let Some(parent) = current {
+        let scope = match parent.kind() {
+            "class_declaration"
+            | "abstract_class_declaration"
+            | "class_definition"
+            | "interface_declaration"
+            | "trait_item"
+            | "mod_item" => parent.child_by_field_name("name"),
+            "impl_item" => parent.child_by_field_name("type"),
+            _ => None,
+        };
+        if let Some(scope) = scope {
+            parts.push(scope.utf8_text(source).unwrap_or_default().to_string());
+        }
+        current = parent.parent();
+    }
+    parts.reverse();
+    parts.join(".")
+}
+
+/// Whether a declaration is visible outside its file: `pub` in Rust, an
+/// `export` in JS/TS, and a non-underscore name in Python.
+fn is_exported(node: Node, name: &str, language: &CodeLanguage) -> bool {
+    match language {
+        CodeLanguage::Rust => {
+            let mut cursor = node.walk();
+            let is_pub = node
+                .children(&mut cursor)
+                .any(|child| child.kind() == "visibility_modifier");
+            is_pub
+        }
+        CodeLanguage::Python => !name.starts_with('_'),
+        _ => {
+            // `export function f` wraps the declaration, `export const f = () => {}`
+            // wraps the lexical declaration around the declarator.
+            let mut current = node.parent();
+            for _ in 0..2 {
+                match current {
+                    Some(parent) if parent.kind() == "export_statement" => return true,
+                    Some(parent) => current = parent.parent(),
+                    None => break,
+                }
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::import_cycles::{self, ImportEdge};
+    use crate::file_manager::graph_store::GraphFilter;
+    use crate::file_manager::memory_store::MemoryGraph;
+    use tempfile::tempdir;
+
+    const SHAPES: &str = "import math
+
+# TODO: cache areas
+class Circle:
+    def area(self):
+        # FIXME: use tau
+        return math.pi * self.r ** 2
+
+def total(shapes):
+    return sum(s.area() for s in shapes)
+";
+
+    // Edges leaving the file's nodes, with their properties, in a stable order
+    fn file_edges(graph: &MemoryGraph, file: &str) -> Vec<(String, String, String, Vec<String>)> {
+        let data = graph.read().unwrap();
+        let mut edges: Vec<_> = data
+            .stored_edges()
+            .into_iter()
+            .filter(|e| e.from.starts_with(file))
+            .map(|e| {
+                let mut properties: Vec<String> = e
+                    .properties
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                properties.sort();
+                (e.from, e.rel_type, e.to, properties)
+            })
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reingest_keeps_file_edges() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shapes.py");
+        std::fs::write(&path, SHAPES).unwrap();
+        let file_id = path.to_string_lossy().to_string();
+        let mut parser = Parser::new();
+        let graph = MemoryGraph::new();
+
+        // Written as `parse_and_ingest_directory` does
+        let parsed = parser
+            .parse_file(&path, &CodeLanguage::Python)
+            .await
+            .unwrap();
+        let file = CodeEntity {
+            properties: parsed.file_properties.clone(),
+            ..CodeEntity::fixture(&file_id, &file_id, EntityType::File)
+        };
+        let links = file_links(&file_id, &parsed.entities);
+        graph
+            .ingest_file("r1", &file, &parsed.entities, &links)
+            .await
+            .unwrap();
+        let before = file_edges(&graph, &file_id);
+        let types: HashSet<&str> = before.iter().map(|e| e.1.as_str()).collect();
+        assert_eq!(types, HashSet::from(["IMPORTS", "IN", "OWNS", "USES"]));
+
+        // Saving the file makes the watcher re-ingest it
+        let structure = parser.parse_single_file(&path, "py").await.unwrap();
+        graph
+            .process_file_structure("r1", &structure)
+            .await
+            .unwrap();
+        assert_eq!(file_edges(&graph, &file_id), before);
+        let import = before.iter().find(|e| e.1 == "IMPORTS").unwrap();
+        assert_eq!(import.3, ["line=1"]);
+    }
+
+    #[tokio::test]
+    async fn test_renamed_file_is_reingested_in_place() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("old")).unwrap();
+        std::fs::create_dir_all(dir.path().join("new")).unwrap();
+        let from = dir.path().join("old/shapes.py");
+        let to = dir.path().join("new/shapes.py");
+        std::fs::write(&from, SHAPES).unwrap();
+        let (from_id, to_id) = (
+            from.to_string_lossy().to_string(),
+            to.to_string_lossy().to_string(),
+        );
+        let mut parser = Parser::new();
+        let graph = MemoryGraph::new();
+
+        let structure = parser.parse_single_file(&from, "py").await.unwrap();
+        graph
+            .process_file_structure("r1", &structure)
+            .await
+            .unwrap();
+        let moved: Vec<_> = file_edges(&graph, &from_id)
+            .into_iter()
+            .map(|(from, rel_type, to, properties)| {
+                let rename = |id: String| id.replacen(&from_id, &to_id, 1);
+                (rename(from), rel_type, rename(to), properties)
+            })
+            .collect();
+        // The watcher moves the file, then re-ingests it once it settles
+        std::fs::rename(&from, &to).unwrap();
+        graph.update_file_path("r1", &from, &to).await.unwrap();
+        let structure = parser.parse_single_file(&to, "py").await.unwrap();
+        graph
+            .process_file_structure("r1", &structure)
+            .await
+            .unwrap();
+
+        let data = graph.read().unwrap();
+        assert!(data.nodes.values().all(|n| n.path != from_id));
+        let files: Vec<&str> = data
+            .nodes
+            .values()
+            .filter(|n| matches!(n.entity_type, EntityType::File))
+            .map(|n| n.id.as_str())
+            .collect();
+        assert_eq!(files, [to_id.as_str()]);
+        assert!(data
+            .nodes
+            .contains_key(&("r1".to_string(), format!("{}::total", to_id))));
+        let parents: Vec<(String, String)> = data
+            .stored_edges()
+            .into_iter()
+            .filter(|e| e.to == to_id && !e.from.starts_with(&to_id))
+            .map(|e| (e.from, e.rel_type))
+            .collect();
+        let new_dir = dir.path().join("new").to_string_lossy().to_string();
+        assert_eq!(parents, [(new_dir, "OWNS".to_string())]);
+        drop(data);
+        assert_eq!(file_edges(&graph, &to_id), moved);
+    }
+
+    #[tokio::test]
+    async fn test_cycle_imports_keep_every_line() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("a.py"), "import b\n\nfrom b import g\n").unwrap();
+        std::fs::write(dir.path().join("b.py"), "import a\n").unwrap();
+        let layout = PythonLayout::discover(dir.path());
+        let mut parser = Parser::new();
+        let graph = MemoryGraph::new();
+
+        let mut nodes = Vec::new();
+        for name in ["a.py", "b.py"] {
+            let path = dir.path().join(name);
+            let file_id = path.to_string_lossy().to_string();
+            let mut parsed = parser
+                .parse_file(&path, &CodeLanguage::Python)
+                .await
+                .unwrap();
+            parsed.file_properties.insert(
+                python_modules::MODULE_NAME_PROPERTY.to_string(),
+                layout.module_name(&path).unwrap(),
+            );
+            let file = CodeEntity {
+                properties: parsed.file_properties.clone(),
+                ..CodeEntity::fixture(&file_id, &file_id, EntityType::File)
+            };
+            let links = file_links(&file_id, &parsed.entities);
+            graph
+                .ingest_file("r1", &file, &parsed.entities, &links)
+                .await
+                .unwrap();
+            nodes.extend(parsed.entities);
+            nodes.push(file);
+        }
+        let (_, import_links) = python_modules::resolve_imports(&nodes);
+        graph
+            .replace_resolved_links("r1", &import_links)
+            .await
+            .unwrap();
+
+        // Read back as `find_import_cycles` does
+        let filter = GraphFilter {
+            labels: vec![EntityType::File.to_string()],
+            rel_types: vec![LinkType::Import.rel_type().to_string()],
+            path_prefix: None,
+        };
+        let (_, edges) = graph.subgraph("r1", &filter).await.unwrap();
+        assert_eq!(edges.len(), 2);
+        let imports: Vec<ImportEdge> = edges
+            .iter()
+            .flat_map(|e| ImportEdge::all(&e.from, &e.to, &e.properties))
+            .collect();
+        let groups = import_cycles::detect(&imports, false);
+        assert_eq!(groups.len(), 1);
+        let lines: Vec<(bool, Option<usize>)> = groups[0]
+            .imports
+            .iter()
+            .map(|e| (e.from.ends_with("a.py"), e.line))
+            .collect();
+        assert_eq!(lines, [(true, Some(1)), (true, Some(3)), (false, Some(1))]);
+    }
+
+    #[tokio::test]
+    async fn test_default_exports_and_dynamic_imports() {
+        let dir = tempdir().unwrap();
+        let sources = [
+            (
+                "a.ts",
+                CodeLanguage::TypeScript,
+                "export default function () {\n  return import(\"./b\");\n}\n",
+            ),
+            (
+                "b.js",
+                CodeLanguage::JavaScript,
+                "import run from \"./a\";\nexport default class {}\n",
+            ),
+        ];
+        let mut parser = Parser::new();
+        let mut nodes = Vec::new();
+        for (name, language, source) in sources {
+            let path = dir.path().join(name);
+            std::fs::write(&path, source).unwrap();
+            let parsed = parser.parse_file(&path, &language).await.unwrap();
+            let file_id = path.to_string_lossy().to_string();
+            nodes.push(CodeEntity::fixture(&file_id, &file_id, EntityType::File));
+            nodes.extend(parsed.entities);
+        }
+
+        let defaults: Vec<(&str, String, bool)> = nodes
+            .iter()
+            .filter(|n| {
+                n.properties
+                    .get("name")
+                    .is_some_and(|name| name == "default")
+            })
+            .map(|n| {
+                (
+                    Path::new(&n.path).file_name().unwrap().to_str().unwrap(),
+                    n.entity_type.to_string(),
+                    n.properties["exported"] == "true",
+                )
+            })
+            .collect();
+        assert_eq!(
+            defaults,
+            [
+                ("a.ts", "Function".to_string(), true),
+                ("b.js", "Class".to_string(), true)
+            ]
+        );
+
+        // The dynamic import closes a loop with the static one
+        let (_, links) = Workspace::discover(dir.path()).resolve_imports(&nodes);
+        let imports: Vec<ImportEdge> = links
+            .iter()
+            .filter(|l| matches!(l.link_type, LinkType::Import))
+            .flat_map(|l| ImportEdge::all(&l.from_name, &l.to_name, &l.properties))
+            .collect();
+        let groups = import_cycles::detect(&imports, false);
+        assert_eq!(groups.len(), 1);
+        let lines: Vec<Option<usize>> = groups[0].imports.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [Some(2), Some(1)]);
+    }
+}
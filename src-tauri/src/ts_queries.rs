@@ -1,32 +1,39 @@
 // src/ts_queries.rs
 
-//! Tree-sitter query that extracts TypeScript/TSX code entities and their
-//! dependency edges (imports / exports).
+//! Tree-sitter queries that extract code entities and their dependency
+//! edges (imports / exports) for every supported language.
 //!
 //! Capture names follow the pattern `<kind>.<field>` so the extractor can
 //! match on either the full node (e.g. `@class.node`) or just the identifier
 //! (`@class.name`).
 
-pub const ENTITY_AND_DEP_QUERY: &str = r#"
+pub const TS_ENTITY_AND_DEP_QUERY: &str = r#"
 ; ===== ENTITIES ==========================================================
 
-; ── Class declarations & default export classes --------------------------
+; ── Class declarations ----------------------------------------------------
 (class_declaration
-  name: (identifier) @class.name) @class.node
+  name: (type_identifier) @class.name) @class.node
 
-(export_default_statement
-  declaration: (class_declaration) @class.node)
+(abstract_class_declaration
+  name: (type_identifier) @class.name) @class.node
 
 ; ── Interface declarations ------------------------------------------------
 (interface_declaration
-  name: (identifier) @interface.name) @interface.node
+  name: (type_identifier) @interface.name) @interface.node
 
-; ── Free function declarations & default export functions ----------------
+; ── Free function declarations --------------------------------------------
 (function_declaration
   name: (identifier) @function.name) @function.node
 
-(export_default_statement
-  declaration: (function_declaration) @function.node)
+; ── Default exports of anonymous classes and functions ---------------------
+; (named ones are declarations, matched above); `default` stands in as name
+(export_statement
+  "default" @class.name
+  value: (class) @class.node)
+
+(export_statement
+  "default" @function.name
+  value: [(function) (arrow_function)] @function.node)
 
 ; ── Arrow‐function assignments (const Foo = () => { … }) -------------------
 (variable_declarator
@@ -41,24 +48,109 @@ pub const ENTITY_AND_DEP_QUERY: &str = r#"
 
 ; ── Static import … from "module" ----------------------------------------
 (import_statement
-  source: (string) @import.source) @import.statement
+  source: (string) @import.name) @import.node
 
 ; ── Static export … from "module" ----------------------------------------
 (export_statement
-  source: (string) @export.source) @export.statement
-
-; ── Named exports with clause --------------------------------------------
-(export_statement
-  (export_clause) @export.item
-  source: (string) @export.source) @export.statement
+  source: (string) @import.name) @import.node
 
 ; ── Dynamic import("module") ---------------------------------------------
 (call_expression
-  function: (identifier) @import.func
-  arguments: (arguments (string) @import.source)) @import.dynamic
+  function: (import)
+  arguments: (arguments (string) @import.name)) @import.node
 
 ; ── require("module") calls ----------------------------------------------
 (call_expression
   function: (identifier) @import.func
-  arguments: (arguments (string) @import.source)) @import.require
+  arguments: (arguments (string) @import.name)
+  (#eq? @import.func "require")) @import.node
+"#;
+
+pub const JS_ENTITY_AND_DEP_QUERY: &str = r#"
+; ===== ENTITIES ==========================================================
+
+(class_declaration
+  name: (identifier) @class.name) @class.node
+
+(function_declaration
+  name: (identifier) @function.name) @function.node
+
+(export_statement
+  "default" @class.name
+  value: (class) @class.node)
+
+(export_statement
+  "default" @function.name
+  value: [(function) (arrow_function)] @function.node)
+
+(variable_declarator
+  name: (identifier) @function.name
+  value: (arrow_function)) @function.node
+
+(method_definition
+  name: (property_identifier) @method.name) @method.node
+
+; ===== DEPENDENCIES =====================================================
+
+(import_statement
+  source: (string) @import.name) @import.node
+
+(export_statement
+  source: (string) @import.name) @import.node
+
+(call_expression
+  function: (import)
+  arguments: (arguments (string) @import.name)) @import.node
+
+(call_expression
+  function: (identifier) @import.func
+  arguments: (arguments (string) @import.name)
+  (#eq? @import.func "require")) @import.node
+"#;
+
+pub const RUST_ENTITY_AND_DEP_QUERY: &str = r#"
+; ===== ENTITIES ==========================================================
+
+; ── Structs and enums are stored as classes ------------------------------
+(struct_item
+  name: (type_identifier) @class.name) @class.node
+
+(enum_item
+  name: (type_identifier) @class.name) @class.node
+
+; ── Traits ---------------------------------------------------------------
+(trait_item
+  name: (type_identifier) @interface.name) @interface.node
+
+; ── Functions (methods are detected from the enclosing impl) --------------
+(function_item
+  name: (identifier) @function.name) @function.node
+
+; ── macro_rules! definitions ---------------------------------------------
+(macro_definition
+  name: (identifier) @macro.name) @macro.node
+
+; ===== DEPENDENCIES =====================================================
+
+(use_declaration
+  argument: (_) @import.name) @import.node
+"#;
+
+pub const PY_ENTITY_AND_DEP_QUERY: &str = r#"
+; ===== ENTITIES ==========================================================
+
+(class_definition
+  name: (identifier) @class.name) @class.node
+
+; ── Functions (methods are detected from the enclosing class) -------------
+(function_definition
+  name: (identifier) @function.name) @function.node
+
+; ===== DEPENDENCIES =====================================================
+
+(import_statement
+  name: (_) @import.name) @import.node
+
+(import_from_statement
+  module_name: (_) @import.name) @import.node
 "#;